@@ -0,0 +1,82 @@
+//! Benchmarks adapter websocket message processing
+//!
+//! Set `WS_CAPTURE` to a capture file written by a market data engine with
+//! `capture_dir` configured to benchmark on real traffic. The adapter is
+//! selected by the exchange prefix of the file name. Without it a small FTX
+//! sample is used.
+
+use std::{collections::HashMap, path::Path};
+
+use criterion::*;
+
+use botnode::market_data::{
+    adapter::WsMarketDataAdapter, binance::Binance, capture::CaptureReader, ftx::Ftx, serum::Serum,
+};
+use botvana::market::orderbook::PlainOrderbook;
+
+const FTX_SAMPLE: &[&str] = &[
+    r#"{"channel":"orderbook","market":"BTC/USD","type":"partial","data":{"time":1640995200.0,"checksum":0,"bids":[[41999.0,0.5],[41998.0,1.2],[41997.0,2.0]],"asks":[[42000.0,0.4],[42001.0,1.1],[42002.0,3.0]],"action":"partial"}}"#,
+    r#"{"channel":"orderbook","market":"BTC/USD","type":"update","data":{"time":1640995200.1,"checksum":0,"bids":[[41999.0,0.0]],"asks":[[42000.0,0.7]],"action":"update"}}"#,
+    r#"{"channel":"orderbook","market":"BTC/USD","type":"update","data":{"time":1640995200.2,"checksum":0,"bids":[[41999.0,0.3]],"asks":[],"action":"update"}}"#,
+];
+
+fn load_capture() -> (String, Vec<String>) {
+    let path = match std::env::var("WS_CAPTURE") {
+        Ok(path) => path,
+        Err(_) => {
+            return (
+                "ftx".to_string(),
+                FTX_SAMPLE.iter().map(|msg| msg.to_string()).collect(),
+            )
+        }
+    };
+
+    let exchange = Path::new(&path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('-').next())
+        .expect("Invalid capture file name")
+        .to_string();
+    let msgs = CaptureReader::open(&path)
+        .expect("Failed to open capture")
+        .map(|msg| msg.expect("Failed to read capture").text)
+        .collect();
+
+    (exchange, msgs)
+}
+
+fn bench_adapter<A: WsMarketDataAdapter>(
+    c: &mut Criterion,
+    name: &str,
+    adapter: A,
+    msgs: &[String],
+) {
+    let mut group = c.benchmark_group("process_ws_msg");
+    group.throughput(Throughput::Elements(msgs.len() as u64));
+    group.bench_with_input(BenchmarkId::new(name, msgs.len()), msgs, |b, msgs| {
+        b.iter_batched(
+            HashMap::<Box<str>, PlainOrderbook<f64>>::new,
+            |mut markets| {
+                for msg in msgs {
+                    let _ = black_box(adapter.process_ws_msg(msg, &mut markets));
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn bench_process_ws_msg(c: &mut Criterion) {
+    let (exchange, msgs) = load_capture();
+
+    match exchange.as_str() {
+        "ftx" => bench_adapter(c, "ftx", Ftx::default(), &msgs),
+        "binancespot" => bench_adapter(c, "binance", Binance::default(), &msgs),
+        "serum" => bench_adapter(c, "serum", Serum::default(), &msgs),
+        other => panic!("Unknown capture exchange {other}"),
+    }
+}
+
+criterion_group!(benches, bench_process_ws_msg);
+criterion_main!(benches);
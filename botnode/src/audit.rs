@@ -1,3 +1,5 @@
 //! Audit engine
 
 pub mod engine;
+pub mod journal;
+pub mod latency;
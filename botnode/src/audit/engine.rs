@@ -1,15 +1,37 @@
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    io,
+    path::PathBuf,
+    time::{Instant, SystemTime},
+};
 
 use metered::{clear::Clear, time_source::StdInstant, *};
 
+use super::{
+    journal::{JournalEvent, JournalWriter},
+    latency::LatencyBreakdown,
+};
 use crate::prelude::*;
 
+const JOURNAL_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Journal directory with receivers of configurations and trading events
+type JournalInputs = (
+    PathBuf,
+    spsc_queue::Consumer<BotConfiguration>,
+    spsc_queue::Consumer<(SystemTime, JournalEvent)>,
+);
+
 /// Auditing engine
 #[derive(Debug)]
 pub struct AuditEngine {
     market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    latency_rx: Option<spsc_queue::Consumer<(ExchangeId, LatencyTrace)>>,
+    journal: Option<JournalInputs>,
+    clock: SharedClock,
     status_tx: spsc_queue::Producer<EngineStatus>,
     status_rx: spsc_queue::Consumer<EngineStatus>,
+    heartbeat: Heartbeat,
     metrics: AuditMetrics,
 }
 
@@ -19,17 +41,74 @@ pub struct AuditMetrics {
 }
 
 impl AuditEngine {
-    pub fn new(market_data_rxs: ConsumersMap<Box<str>, MarketEvent>) -> Self {
+    pub fn new(market_data_rxs: ConsumersMap<Box<str>, MarketEvent>, clock: SharedClock) -> Self {
         let (status_tx, status_rx) = spsc_queue::make(1);
         let metrics = AuditMetrics::default();
 
         Self {
             market_data_rxs,
+            latency_rx: None,
+            journal: None,
+            clock,
             status_tx,
             status_rx,
+            heartbeat: Heartbeat::new(),
             metrics,
         }
     }
+
+    /// Reports latency breakdown of traces received on `latency_rx`
+    pub fn with_latency_rx(
+        mut self,
+        latency_rx: spsc_queue::Consumer<(ExchangeId, LatencyTrace)>,
+    ) -> Self {
+        self.latency_rx = Some(latency_rx);
+        self
+    }
+
+    /// Writes audit journal to a new file in `dir`
+    ///
+    /// Market events are journaled along with configurations received on
+    /// `config_rx` and trading engine events received on `events_rx`.
+    pub fn with_journal(
+        mut self,
+        dir: PathBuf,
+        config_rx: spsc_queue::Consumer<BotConfiguration>,
+        events_rx: spsc_queue::Consumer<(SystemTime, JournalEvent)>,
+    ) -> Self {
+        self.journal = Some((dir, config_rx, events_rx));
+        self
+    }
+}
+
+/// Journal being written with receivers of the journaled events
+pub struct AuditJournal {
+    writer: JournalWriter,
+    config_rx: spsc_queue::Consumer<BotConfiguration>,
+    events_rx: spsc_queue::Consumer<(SystemTime, JournalEvent)>,
+    last_flush: Instant,
+}
+
+impl AuditJournal {
+    /// Appends received configuration and trading event, flushing the
+    /// journal every second
+    fn append_received(&mut self, clock: &SharedClock) -> io::Result<()> {
+        if let Some(config) = self.config_rx.try_pop() {
+            self.writer
+                .append(clock.now(), JournalEvent::Config(config))?;
+        }
+
+        if let Some((time, event)) = self.events_rx.try_pop() {
+            self.writer.append(time, event)?;
+        }
+
+        if self.last_flush.elapsed() >= JOURNAL_FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait(?Send)]
@@ -42,49 +121,127 @@ impl Engine for AuditEngine {
         self.status_rx.clone()
     }
 
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        Some(self.heartbeat.rx())
+    }
+
     async fn start(self, shutdown: Shutdown) -> Result<(), EngineError> {
         info!("Starting audit engine");
 
         self.status_tx.try_push(EngineStatus::Booting);
 
-        run_audit_loop(self.status_tx, self.market_data_rxs, self.metrics, shutdown).await
+        let journal = match self.journal {
+            Some((dir, config_rx, events_rx)) => match JournalWriter::create(&dir) {
+                Ok(writer) => {
+                    info!("writing audit journal to {:?}", writer.path());
+
+                    Some(AuditJournal {
+                        writer,
+                        config_rx,
+                        events_rx,
+                        last_flush: Instant::now(),
+                    })
+                }
+                Err(e) => {
+                    error!("Failed to create audit journal in {dir:?}: {e}");
+                    self.status_tx.try_push(EngineStatus::Error);
+
+                    return Err(EngineError::with_source(e));
+                }
+            },
+            None => None,
+        };
+
+        run_audit_loop(
+            (self.status_tx, self.heartbeat),
+            self.market_data_rxs,
+            self.latency_rx,
+            journal,
+            self.clock,
+            self.metrics,
+            shutdown,
+        )
+        .await
     }
 }
 
 /// Audit engine loop
+///
+/// Latency breakdown of each exchange is reported and reset every 5 seconds.
+/// Market events, configurations and trading events are appended to the
+/// journal when it's enabled.
 pub async fn run_audit_loop(
-    status_tx: spsc_queue::Producer<EngineStatus>,
+    (status_tx, heartbeat): (spsc_queue::Producer<EngineStatus>, Heartbeat),
     market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    latency_rx: Option<spsc_queue::Consumer<(ExchangeId, LatencyTrace)>>,
+    mut journal: Option<AuditJournal>,
+    clock: SharedClock,
     audit_metrics: AuditMetrics,
     shutdown: Shutdown,
 ) -> Result<(), EngineError> {
+    // Delay shutdown until the journal is synced
+    let _token = shutdown
+        .delay_shutdown_token()
+        .map_err(EngineError::with_source)?;
+
     status_tx.try_push(EngineStatus::Running);
 
     info!("{:?}", market_data_rxs);
 
     let throughput = &audit_metrics.throughput;
     let mut start = std::time::Instant::now();
+    let mut breakdowns: HashMap<ExchangeId, LatencyBreakdown> = HashMap::new();
 
     loop {
+        heartbeat.tick();
+        glommio::yield_if_needed().await;
+
         measure!(throughput, {
-            for (_, market_data_rx) in market_data_rxs.iter() {
+            for (exchange, market_data_rx) in market_data_rxs.iter() {
                 if let Some(event) = market_data_rx.try_pop() {
-                    let elapsed = event.timestamp.elapsed().unwrap();
+                    heartbeat.event();
+                    let elapsed = clock.elapsed(event.timestamp);
                     trace!("market_event = {event:?}");
 
                     if elapsed > std::time::Duration::from_millis(1) {
                         warn!("Late market event!");
                     }
+
+                    if let Some(journal) = journal.as_mut() {
+                        match exchange.parse::<ExchangeId>() {
+                            Ok(exchange) => {
+                                journal
+                                    .writer
+                                    .append(event.timestamp, JournalEvent::market(exchange, &event))
+                                    .map_err(EngineError::with_source)?;
+                            }
+                            Err(e) => warn!("Not journaling event: {e}"),
+                        }
+                    }
                 }
             }
         });
 
+        if let Some(journal) = journal.as_mut() {
+            journal
+                .append_received(&clock)
+                .map_err(EngineError::with_source)?;
+        }
+
+        if let Some((exchange, trace)) = latency_rx.as_ref().and_then(|rx| rx.try_pop()) {
+            breakdowns.entry(exchange).or_default().record(&trace);
+        }
+
         if start.elapsed().as_secs() >= 5 {
             if shutdown.shutdown_started() {
                 info!("shutting down audit engine");
 
                 status_tx.try_push(EngineStatus::ShuttingDown);
 
+                if let Some(journal) = journal.as_mut() {
+                    journal.writer.sync().map_err(EngineError::with_source)?;
+                }
+
                 return Ok(());
             }
 
@@ -94,6 +251,11 @@ pub async fn run_audit_loop(
                 throughput.0.borrow().hdr_histogram.mean()
             );
             throughput.clear();
+
+            for (exchange, breakdown) in breakdowns.iter_mut() {
+                info!("{exchange} latency: {}", breakdown.summary());
+                breakdown.reset();
+            }
         }
     }
 }
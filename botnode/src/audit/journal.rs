@@ -0,0 +1,545 @@
+//! Audit journal
+//!
+//! The journal is an append-only file starting with a magic header followed
+//! by entries stored as
+//!
+//! ```text
+//! | length (u32 LE) | checksum (u32 LE) | bincode encoded JournalEntry |
+//! ```
+//!
+//! Entries are numbered from zero. The checksum is CRC32 of the previous
+//! entry's checksum followed by the encoded entry, chaining the entries so
+//! any modified, removed or reordered entry fails verification. Each time the
+//! journal is opened a new file is created, existing files are never written
+//! to.
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use flate2::Crc;
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::{order_request::OrderRequest, ExchangeEvent, ExchangeRequest};
+use botvana::{
+    cfg::BotConfiguration,
+    exchange::ExchangeId,
+    market::event::{MarketEvent, MarketEventType},
+};
+
+const MAGIC: &[u8; 6] = b"BVJRN\x01";
+const FILE_EXTENSION: &str = "bvjrn";
+/// Entries larger than this are treated as corruption of the length
+const MAX_ENTRY_LEN: usize = 64 * 1024 * 1024;
+const ENTRY_HEADER_LEN: usize = 8;
+
+/// Single journal entry
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    /// Position of the entry in the journal
+    pub seq: u64,
+    /// Time the event happened
+    pub time: SystemTime,
+    pub event: JournalEvent,
+}
+
+/// Event recorded in the journal
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum JournalEvent {
+    /// Market event received from the market data engine
+    Market(ExchangeId, MarketSummary),
+    /// Request sent by the trading engine to the exchange engine
+    Request(ExchangeRequest),
+    /// Event received by the trading engine from the exchange engine
+    Exchange(ExchangeEvent),
+    /// Configuration applied by the bot
+    Config(BotConfiguration),
+}
+
+impl JournalEvent {
+    /// Creates market event summary
+    pub fn market(exchange: ExchangeId, event: &MarketEvent) -> Self {
+        JournalEvent::Market(exchange, MarketSummary::from(&event.r#type))
+    }
+}
+
+/// Summary of a market event
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MarketSummary {
+    /// Number of markets listed by the exchange
+    Markets(usize),
+    /// Top of the orderbook and number of price levels on each side
+    Orderbook {
+        market: Box<str>,
+        bid: Option<f64>,
+        ask: Option<f64>,
+        bid_levels: usize,
+        ask_levels: usize,
+    },
+    /// Number of trades, their total size and the last price
+    Trades {
+        market: Box<str>,
+        count: usize,
+        volume: f64,
+        last_price: Option<f64>,
+    },
+    MidPrice {
+        market: Box<str>,
+        bid: f64,
+        ask: f64,
+    },
+}
+
+impl From<&MarketEventType> for MarketSummary {
+    fn from(event: &MarketEventType) -> Self {
+        match event {
+            MarketEventType::Markets(markets) => MarketSummary::Markets(markets.len()),
+            MarketEventType::OrderbookUpdate(market, orderbook) => MarketSummary::Orderbook {
+                market: market.clone(),
+                bid: orderbook.bids.price_vec.last().copied(),
+                ask: orderbook.asks.price_vec.first().copied(),
+                bid_levels: orderbook.bids.price_vec.len(),
+                ask_levels: orderbook.asks.price_vec.len(),
+            },
+            MarketEventType::Trades(market, trades) => MarketSummary::Trades {
+                market: market.clone(),
+                count: trades.len(),
+                volume: trades.iter().map(|trade| trade.size).sum(),
+                last_price: trades.last().map(|trade| trade.price),
+            },
+            MarketEventType::MidPriceChange(market, bid, ask) => MarketSummary::MidPrice {
+                market: market.clone(),
+                bid: *bid,
+                ask: *ask,
+            },
+        }
+    }
+}
+
+/// Error reading or verifying the journal
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("Failed to read journal: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a journal file")]
+    InvalidHeader,
+    #[error("Journal ends in the middle of entry {0}")]
+    Truncated(u64),
+    #[error("Entry {seq} has invalid length {len}")]
+    InvalidLength { seq: u64, len: usize },
+    #[error("Checksum mismatch in entry {0}")]
+    ChecksumMismatch(u64),
+    #[error("Expected entry {expected}, found entry {found}")]
+    Sequence { expected: u64, found: u64 },
+    #[error("Failed to decode entry {0}: {1}")]
+    Decode(u64, bincode::Error),
+}
+
+/// Writer appending entries to a new journal file
+pub struct JournalWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    seq: u64,
+    checksum: u32,
+}
+
+impl JournalWriter {
+    /// Creates new journal file in given directory
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        let name = format!(
+            "journal-{}.{}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            FILE_EXTENSION
+        );
+        let path = dir.as_ref().join(name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut writer = BufWriter::new(file);
+
+        writer.write_all(MAGIC)?;
+
+        Ok(Self {
+            path,
+            writer,
+            seq: 0,
+            checksum: 0,
+        })
+    }
+
+    /// Returns path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the event and returns its sequence number
+    pub fn append(&mut self, time: SystemTime, event: JournalEvent) -> io::Result<u64> {
+        let entry = JournalEntry {
+            seq: self.seq,
+            time,
+            event,
+        };
+        let buf = bincode::serialize(&entry)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let checksum = chained_checksum(self.checksum, &buf);
+
+        self.writer.write_all(&(buf.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&checksum.to_le_bytes())?;
+        self.writer.write_all(&buf)?;
+
+        self.checksum = checksum;
+        self.seq += 1;
+
+        Ok(entry.seq)
+    }
+
+    /// Flushes buffered entries to the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flushes buffered entries and waits until they reach the disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+}
+
+impl Drop for JournalWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            tracing::error!("Failed to sync journal {:?}: {e}", self.path);
+        }
+    }
+}
+
+/// Reader verifying entries of a journal file
+///
+/// Iteration stops after the first error.
+pub struct JournalReader {
+    reader: BufReader<File>,
+    seq: u64,
+    checksum: u32,
+    failed: bool,
+}
+
+impl JournalReader {
+    /// Opens the file and validates its header
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, JournalError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 6];
+
+        if read_full(&mut reader, &mut magic)? != magic.len() || &magic != MAGIC {
+            return Err(JournalError::InvalidHeader);
+        }
+
+        Ok(Self {
+            reader,
+            seq: 0,
+            checksum: 0,
+            failed: false,
+        })
+    }
+
+    fn read_entry(&mut self) -> Result<Option<JournalEntry>, JournalError> {
+        let mut header = [0u8; ENTRY_HEADER_LEN];
+
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            ENTRY_HEADER_LEN => {}
+            _ => return Err(JournalError::Truncated(self.seq)),
+        }
+
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        if len > MAX_ENTRY_LEN {
+            return Err(JournalError::InvalidLength { seq: self.seq, len });
+        }
+
+        let mut buf = vec![0u8; len];
+
+        if read_full(&mut self.reader, &mut buf)? != len {
+            return Err(JournalError::Truncated(self.seq));
+        }
+
+        if chained_checksum(self.checksum, &buf) != checksum {
+            return Err(JournalError::ChecksumMismatch(self.seq));
+        }
+
+        let entry: JournalEntry =
+            bincode::deserialize(&buf).map_err(|e| JournalError::Decode(self.seq, e))?;
+
+        if entry.seq != self.seq {
+            return Err(JournalError::Sequence {
+                expected: self.seq,
+                found: entry.seq,
+            });
+        }
+
+        self.checksum = checksum;
+        self.seq += 1;
+
+        Ok(Some(entry))
+    }
+}
+
+impl Iterator for JournalReader {
+    type Item = Result<JournalEntry, JournalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let entry = self.read_entry().transpose();
+        self.failed = matches!(entry, Some(Err(_)));
+        entry
+    }
+}
+
+/// Verifies the journal file and returns number of its entries
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<u64, JournalError> {
+    JournalReader::open(path)?.try_fold(0, |count, entry| entry.map(|_| count + 1))
+}
+
+/// Returns journal files in given directory in chronological order
+pub fn list_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new(FILE_EXTENSION)))
+        .collect();
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Exchange and name of a market
+type MarketKey = (ExchangeId, Box<str>);
+
+/// Bot state reconstructed by replaying journal entries
+#[derive(Debug, Default)]
+pub struct JournalState {
+    /// Last applied configuration
+    pub config: Option<BotConfiguration>,
+    /// Last best bid and ask of each market
+    pub quotes: HashMap<MarketKey, (Option<f64>, Option<f64>)>,
+    /// Orders requested and not yet cancelled or fully filled, by client
+    /// order id
+    pub open_orders: HashMap<u64, OrderRequest>,
+    /// Position of each market built from fills
+    pub positions: HashMap<MarketKey, f64>,
+    /// Number of applied entries
+    pub entries: u64,
+}
+
+impl JournalState {
+    /// Applies the entry to the state
+    pub fn apply(&mut self, entry: &JournalEntry) {
+        match &entry.event {
+            JournalEvent::Market(exchange, summary) => match summary {
+                MarketSummary::Orderbook {
+                    market, bid, ask, ..
+                } => {
+                    self.quotes
+                        .insert((exchange.clone(), market.clone()), (*bid, *ask));
+                }
+                MarketSummary::MidPrice { market, bid, ask } => {
+                    self.quotes
+                        .insert((exchange.clone(), market.clone()), (Some(*bid), Some(*ask)));
+                }
+                MarketSummary::Markets(_) | MarketSummary::Trades { .. } => {}
+            },
+            JournalEvent::Request(request) => match request {
+                ExchangeRequest::PlaceOrder(order) => {
+                    self.open_orders.insert(order.order_id, order.clone());
+                }
+                ExchangeRequest::CancelOrder(order_id) => {
+                    self.open_orders.remove(order_id);
+                }
+                ExchangeRequest::CancelAll => self.open_orders.clear(),
+            },
+            JournalEvent::Exchange(ExchangeEvent::OrderFill(fill)) => {
+                *self
+                    .positions
+                    .entry((fill.exchange.clone(), fill.market.clone()))
+                    .or_default() += fill.side.sign() * fill.size;
+
+                if let Some(order) = self.open_orders.get_mut(&fill.order_id) {
+                    order.size -= fill.size;
+
+                    if order.size <= f64::EPSILON {
+                        self.open_orders.remove(&fill.order_id);
+                    }
+                }
+            }
+            JournalEvent::Exchange(_) => {}
+            JournalEvent::Config(config) => self.config = Some(config.clone()),
+        }
+
+        self.entries += 1;
+    }
+}
+
+/// Returns CRC32 of the previous checksum followed by the data
+fn chained_checksum(previous: u32, data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+
+    crc.update(&previous.to_le_bytes());
+    crc.update(data);
+    crc.sum()
+}
+
+/// Reads until the buffer is full or the reader ends, returns bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botvana::exchange::{Fill, Side};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("botnode-journal-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn order(order_id: u64, size: f64) -> JournalEvent {
+        JournalEvent::Request(ExchangeRequest::PlaceOrder(OrderRequest {
+            order_id,
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            side: Side::Buy,
+            price: 42000.0,
+            size,
+        }))
+    }
+
+    fn fill(order_id: u64, size: f64) -> JournalEvent {
+        JournalEvent::Exchange(ExchangeEvent::OrderFill(Fill {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            order_id,
+            side: Side::Buy,
+            price: 42000.0,
+            size,
+            fee: 0.0,
+            time: SystemTime::UNIX_EPOCH,
+        }))
+    }
+
+    fn write_journal(name: &str) -> PathBuf {
+        let mut writer = JournalWriter::create(test_dir(name)).unwrap();
+
+        writer
+            .append(
+                SystemTime::UNIX_EPOCH,
+                JournalEvent::Market(
+                    ExchangeId::Ftx,
+                    MarketSummary::MidPrice {
+                        market: Box::from("BTC/USD"),
+                        bid: 41999.0,
+                        ask: 42001.0,
+                    },
+                ),
+            )
+            .unwrap();
+        writer
+            .append(SystemTime::UNIX_EPOCH, order(1, 1.0))
+            .unwrap();
+        writer
+            .append(SystemTime::UNIX_EPOCH, order(2, 1.0))
+            .unwrap();
+        writer.append(SystemTime::UNIX_EPOCH, fill(1, 1.0)).unwrap();
+        assert_eq!(
+            writer.append(SystemTime::UNIX_EPOCH, fill(2, 0.5)).unwrap(),
+            4
+        );
+
+        writer.path().to_path_buf()
+    }
+
+    #[test]
+    fn write_verify_and_replay() {
+        let path = write_journal("replay");
+
+        assert_eq!(
+            list_files(path.parent().unwrap()).unwrap(),
+            vec![path.clone()]
+        );
+        assert_eq!(verify(&path).unwrap(), 5);
+
+        let mut state = JournalState::default();
+        for entry in JournalReader::open(&path).unwrap() {
+            state.apply(&entry.unwrap());
+        }
+
+        assert_eq!(state.entries, 5);
+        assert_eq!(
+            state.quotes[&(ExchangeId::Ftx, Box::from("BTC/USD"))],
+            (Some(41999.0), Some(42001.0))
+        );
+        assert_eq!(
+            state.positions[&(ExchangeId::Ftx, Box::from("BTC/USD"))],
+            1.5
+        );
+        assert_eq!(state.open_orders.len(), 1);
+        assert_eq!(state.open_orders[&2].size, 0.5);
+    }
+
+    #[test]
+    fn detect_corruption() {
+        let path = write_journal("corrupt");
+        let data = fs::read(&path).unwrap();
+
+        // Modified entry
+        let mut modified = data.clone();
+        let last = modified.len() - 1;
+        modified[last] ^= 1;
+        fs::write(&path, &modified).unwrap();
+        assert!(matches!(
+            verify(&path),
+            Err(JournalError::ChecksumMismatch(4))
+        ));
+
+        // Removed entry
+        let first_len = u32::from_le_bytes(data[6..10].try_into().unwrap()) as usize;
+        let mut removed = data[..MAGIC.len()].to_vec();
+        removed.extend_from_slice(&data[MAGIC.len() + ENTRY_HEADER_LEN + first_len..]);
+        fs::write(&path, &removed).unwrap();
+        assert!(matches!(
+            verify(&path),
+            Err(JournalError::ChecksumMismatch(0))
+        ));
+
+        // Truncated entry
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(matches!(verify(&path), Err(JournalError::Truncated(4))));
+
+        fs::write(&path, b"BVREC\x01").unwrap();
+        assert!(matches!(verify(&path), Err(JournalError::InvalidHeader)));
+    }
+}
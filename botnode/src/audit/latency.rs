@@ -0,0 +1,107 @@
+//! Tick-to-trade latency breakdown
+
+use hdrhistogram::Histogram;
+
+use crate::{prelude::*, util::*};
+use botvana::report::Percentiles;
+
+/// Latency histograms of market events of one exchange
+///
+/// Each processing stage has a histogram of time spent reaching it from the
+/// previous stage, in microseconds.
+pub struct LatencyBreakdown {
+    stages: Vec<(LatencyStage, Histogram<u64>)>,
+    tick_to_trade: Histogram<u64>,
+}
+
+impl LatencyBreakdown {
+    pub fn new() -> Self {
+        Self {
+            stages: LatencyStage::ALL[1..]
+                .iter()
+                .map(|stage| (*stage, latency_histogram()))
+                .collect(),
+            tick_to_trade: latency_histogram(),
+        }
+    }
+
+    /// Records stages reached by the event
+    pub fn record(&mut self, trace: &LatencyTrace) {
+        for (stage, span) in trace.spans() {
+            if let Some((_, histogram)) = self.stages.iter_mut().find(|(other, _)| *other == stage)
+            {
+                record_latency(histogram, span);
+            }
+        }
+
+        if let Some(latency) = trace.tick_to_trade() {
+            record_latency(&mut self.tick_to_trade, latency);
+        }
+    }
+
+    /// Returns percentiles of each stage followed by tick-to-trade
+    /// percentiles
+    pub fn percentiles(&self) -> Vec<(&'static str, Percentiles)> {
+        self.stages
+            .iter()
+            .map(|(stage, histogram)| (stage.name(), percentiles(histogram)))
+            .chain(Some(("tick_to_trade", percentiles(&self.tick_to_trade))))
+            .collect()
+    }
+
+    /// Returns one line summary of the stages with recorded values
+    pub fn summary(&self) -> String {
+        self.percentiles()
+            .iter()
+            .filter(|(_, percentiles)| percentiles.count > 0)
+            .map(|(name, p)| format!("{name} p50={}us p99={}us max={}us", p.p50, p.p99, p.max))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Starts new reporting window
+    pub fn reset(&mut self) {
+        self.stages
+            .iter_mut()
+            .for_each(|(_, histogram)| histogram.reset());
+        self.tick_to_trade.reset();
+    }
+}
+
+impl Default for LatencyBreakdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_latency_breakdown() {
+        let start = Instant::now();
+        let mut breakdown = LatencyBreakdown::new();
+        let mut trace = LatencyTrace::received_at(start);
+
+        trace.mark_at(LatencyStage::Parsed, start + Duration::from_micros(20));
+        trace.mark_at(LatencyStage::Dequeued, start + Duration::from_micros(30));
+        breakdown.record(&trace);
+
+        trace.mark_at(LatencyStage::OrderSent, start + Duration::from_micros(100));
+        breakdown.record(&trace);
+
+        let percentiles: HashMap<_, _> = breakdown.percentiles().into_iter().collect();
+        assert_eq!(percentiles["parsed"].count, 2);
+        assert_eq!(percentiles["parsed"].max, 20);
+        assert_eq!(percentiles["dequeued"].max, 10);
+        assert_eq!(percentiles["book_updated"].count, 0);
+        assert_eq!(percentiles["order_sent"].count, 1);
+        assert_eq!(percentiles["tick_to_trade"].max, 100);
+        assert!(breakdown.summary().starts_with("parsed p50=20us"));
+
+        breakdown.reset();
+        assert_eq!(breakdown.summary(), "");
+    }
+}
@@ -0,0 +1,167 @@
+//! Backtesting
+//!
+//! Drives a [`Strategy`] with recorded market events, using the event
+//! timestamps as the clock and a simulated exchange for fills. Runs are
+//! deterministic, the same events and configuration always produce the same
+//! report.
+
+pub mod report;
+pub mod sim_exchange;
+pub mod source;
+
+use std::{sync::Arc, time::UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::{prelude::*, trading::strategy::*};
+use botvana::clock::SimulatedClock;
+use report::*;
+use sim_exchange::SimExchange;
+
+/// Backtest configuration
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BacktestConfig {
+    /// Latency between the strategy and the exchange in milliseconds
+    pub latency_ms: u64,
+    /// Fee paid by resting orders in basis points
+    pub maker_fee_bps: f64,
+    /// Fee paid by orders crossing the spread in basis points
+    pub taker_fee_bps: f64,
+}
+
+impl BacktestConfig {
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency_ms)
+    }
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 5,
+            maker_fee_bps: 2.0,
+            taker_fee_bps: 7.0,
+        }
+    }
+}
+
+/// Backtest runner
+pub struct Backtest {
+    config: BacktestConfig,
+    strategy: Box<dyn Strategy>,
+}
+
+impl Backtest {
+    pub fn new(strategy: Box<dyn Strategy>, config: BacktestConfig) -> Self {
+        Self { config, strategy }
+    }
+
+    /// Runs the strategy over given events and returns the report
+    pub fn run<I>(mut self, events: I) -> BacktestReport
+    where
+        I: IntoIterator<Item = (ExchangeId, MarketEvent)>,
+    {
+        let clock = Arc::new(SimulatedClock::new(UNIX_EPOCH));
+        let mut ctx = StrategyContext::new(clock.clone());
+        let mut exchange = SimExchange::new(&self.config);
+        let mut pnl = PnlTracker::default();
+        let mut fills = Vec::new();
+        let mut n_events = 0;
+        let mut start = None;
+        let mut end = None;
+
+        for (exchange_id, event) in events {
+            let now = event.timestamp;
+
+            clock.set(now);
+            start.get_or_insert(now);
+            end = Some(now);
+            n_events += 1;
+
+            // Orders resting before the event are matched first, then the
+            // strategy reacts to fills and to the event itself
+            for fill in exchange.on_market_event(exchange_id, &event) {
+                pnl.on_fill(&fill);
+                self.strategy.on_fill(&mut ctx, &fill);
+                fills.push(fill);
+            }
+
+            pnl.on_market_event(exchange_id, &event);
+            self.strategy.on_market_event(&mut ctx, exchange_id, &event);
+
+            for request in ctx.take_requests() {
+                exchange.submit(now, request);
+            }
+        }
+
+        BacktestReport {
+            strategy: self.strategy.name().to_string(),
+            events: n_events,
+            start,
+            end,
+            orders_placed: exchange.orders_placed(),
+            fills,
+            fees: pnl.fees(),
+            pnl: pnl.pnl(),
+            max_drawdown: pnl.max_drawdown(),
+            latency: self.config.latency(),
+            maker_fee_bps: self.config.maker_fee_bps,
+            taker_fee_bps: self.config.taker_fee_bps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trading::strategy::quote_mid::QuoteMid;
+
+    fn events() -> Vec<(ExchangeId, MarketEvent)> {
+        [(99.0, 101.0), (99.0, 101.0), (98.0, 99.5), (101.0, 102.0)]
+            .iter()
+            .enumerate()
+            .map(|(n, (bid, ask))| {
+                let event = MarketEvent {
+                    r#type: MarketEventType::MidPriceChange(Box::from("BTC/USD"), *bid, *ask),
+                    timestamp: UNIX_EPOCH + Duration::from_millis(n as u64 * 100),
+                    trace: LatencyTrace::default(),
+                };
+
+                (ExchangeId::Ftx, event)
+            })
+            .collect()
+    }
+
+    fn run() -> BacktestReport {
+        let strategy = QuoteMid::new(ExchangeId::Ftx, Box::from("BTC/USD"), 1.0, 10.0);
+
+        Backtest::new(Box::new(strategy), BacktestConfig::default()).run(events())
+    }
+
+    #[test]
+    fn test_backtest_quote_mid() {
+        let report = run();
+
+        assert_eq!(report.events, 4);
+        assert_eq!(report.start, Some(UNIX_EPOCH));
+        assert_eq!(report.orders_placed, 4);
+
+        // Bid quoted at 99.9 is filled when the ask drops to 99.5, then the
+        // requoted ask at 98.848... is filled when the bid jumps to 101
+        assert_eq!(report.fills.len(), 2);
+        assert_eq!(report.fills[0].price, 99.9);
+        assert!(report.fill_ratio() > 0.0);
+        assert!(report.fees > 0.0);
+    }
+
+    #[test]
+    fn test_backtest_is_deterministic() {
+        let first = run();
+        let second = run();
+
+        assert_eq!(first.fills, second.fills);
+        assert_eq!(first.pnl, second.pnl);
+        assert_eq!(first.max_drawdown, second.max_drawdown);
+    }
+}
@@ -0,0 +1,211 @@
+//! Backtest report
+
+use std::{fmt, time::SystemTime};
+
+use crate::prelude::*;
+use botvana::exchange::Fill;
+
+/// Position in a single market
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Position {
+    size: f64,
+    cash: f64,
+    mark: Option<f64>,
+}
+
+/// Tracks positions and mark-to-market PnL
+#[derive(Debug, Default)]
+pub struct PnlTracker {
+    positions: HashMap<(ExchangeId, Box<str>), Position>,
+    fees: f64,
+    peak: f64,
+    max_drawdown: f64,
+}
+
+impl PnlTracker {
+    /// Applies fill to the position
+    pub fn on_fill(&mut self, fill: &Fill) {
+        let position = self
+            .positions
+            .entry((fill.exchange, fill.market.clone()))
+            .or_default();
+
+        position.size += fill.side.sign() * fill.size;
+        position.cash -= fill.side.sign() * fill.size * fill.price;
+        self.fees += fill.fee;
+
+        if position.mark.is_none() {
+            position.mark = Some(fill.price);
+        }
+
+        self.update_drawdown();
+    }
+
+    /// Marks positions in the market to the mid price of the event
+    pub fn on_market_event(&mut self, exchange: ExchangeId, event: &MarketEvent) {
+        let (market, mid) = match &event.r#type {
+            MarketEventType::OrderbookUpdate(market, orderbook) => {
+                match (
+                    orderbook.bids.price_vec.last(),
+                    orderbook.asks.price_vec.first(),
+                ) {
+                    (Some(bid), Some(ask)) => (market, (bid + ask) / 2.0),
+                    _ => return,
+                }
+            }
+            MarketEventType::MidPriceChange(market, bid, ask) => (market, (bid + ask) / 2.0),
+            _ => return,
+        };
+
+        if let Some(position) = self.positions.get_mut(&(exchange, market.clone())) {
+            position.mark = Some(mid);
+            self.update_drawdown();
+        }
+    }
+
+    /// Returns mark-to-market PnL net of fees
+    pub fn pnl(&self) -> f64 {
+        self.positions
+            .values()
+            .map(|position| position.cash + position.size * position.mark.unwrap_or_default())
+            .sum::<f64>()
+            - self.fees
+    }
+
+    /// Returns total fees paid
+    pub fn fees(&self) -> f64 {
+        self.fees
+    }
+
+    /// Returns the largest drop of PnL from its previous peak
+    pub fn max_drawdown(&self) -> f64 {
+        self.max_drawdown
+    }
+
+    fn update_drawdown(&mut self) {
+        let pnl = self.pnl();
+
+        self.peak = self.peak.max(pnl);
+        self.max_drawdown = self.max_drawdown.max(self.peak - pnl);
+    }
+}
+
+/// Result of a backtest run
+#[derive(Clone, Debug)]
+pub struct BacktestReport {
+    pub strategy: String,
+    /// Number of market events processed
+    pub events: u64,
+    pub start: Option<SystemTime>,
+    pub end: Option<SystemTime>,
+    pub orders_placed: u64,
+    pub fills: Vec<Fill>,
+    pub fees: f64,
+    /// Mark-to-market PnL net of fees
+    pub pnl: f64,
+    pub max_drawdown: f64,
+    /// Assumed latency between the strategy and the exchange
+    pub latency: Duration,
+    pub maker_fee_bps: f64,
+    pub taker_fee_bps: f64,
+}
+
+impl BacktestReport {
+    /// Returns ratio of filled orders to orders placed
+    pub fn fill_ratio(&self) -> f64 {
+        if self.orders_placed == 0 {
+            0.0
+        } else {
+            self.fills.len() as f64 / self.orders_placed as f64
+        }
+    }
+}
+
+impl fmt::Display for BacktestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let duration = match (self.start, self.end) {
+            (Some(start), Some(end)) => end.duration_since(start).unwrap_or_default(),
+            _ => Duration::ZERO,
+        };
+
+        writeln!(f, "strategy:      {}", self.strategy)?;
+        writeln!(f, "events:        {} over {duration:?}", self.events)?;
+        writeln!(
+            f,
+            "assumptions:   latency {:?}, maker fee {} bps, taker fee {} bps",
+            self.latency, self.maker_fee_bps, self.taker_fee_bps
+        )?;
+        writeln!(f, "orders placed: {}", self.orders_placed)?;
+        writeln!(
+            f,
+            "fills:         {} (fill ratio {:.2}%)",
+            self.fills.len(),
+            self.fill_ratio() * 100.0
+        )?;
+        writeln!(f, "fees:          {:.6}", self.fees)?;
+        writeln!(f, "pnl:           {:.6}", self.pnl)?;
+        writeln!(f, "max drawdown:  {:.6}", self.max_drawdown)?;
+        writeln!(f)?;
+        writeln!(f, "trades:")?;
+
+        for fill in self.fills.iter() {
+            let time: DateTime<Utc> = fill.time.into();
+
+            writeln!(
+                f,
+                "  {} {} {} {:?} {} @ {} (fee {:.6})",
+                time.to_rfc3339(),
+                fill.exchange,
+                fill.market,
+                fill.side,
+                fill.size,
+                fill.price,
+                fill.fee
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botvana::{clock::RealClock, exchange::Side};
+    use std::time::UNIX_EPOCH;
+
+    fn fill(side: Side, price: f64, fee: f64) -> Fill {
+        Fill {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            order_id: 1,
+            side,
+            price,
+            size: 1.0,
+            fee,
+            time: UNIX_EPOCH,
+        }
+    }
+
+    fn ticker(bid: f64, ask: f64) -> MarketEvent {
+        MarketEvent::mid_price_change(&RealClock, Box::from("BTC/USD"), bid, ask)
+    }
+
+    #[test]
+    fn test_pnl_and_drawdown() {
+        let mut tracker = PnlTracker::default();
+
+        tracker.on_fill(&fill(Side::Buy, 100.0, 0.5));
+        tracker.on_market_event(ExchangeId::Ftx, &ticker(103.0, 105.0));
+        assert_eq!(tracker.pnl(), 3.5);
+
+        tracker.on_market_event(ExchangeId::Ftx, &ticker(97.0, 99.0));
+        assert_eq!(tracker.pnl(), -2.5);
+        assert_eq!(tracker.max_drawdown(), 6.0);
+
+        tracker.on_fill(&fill(Side::Sell, 101.0, 0.5));
+        assert_eq!(tracker.pnl(), 0.0);
+        assert_eq!(tracker.fees(), 1.0);
+        assert_eq!(tracker.max_drawdown(), 6.0);
+    }
+}
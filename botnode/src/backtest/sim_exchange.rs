@@ -0,0 +1,254 @@
+//! Simulated exchange
+//!
+//! Fill model assumptions:
+//!
+//! - requests reach the exchange after a constant latency
+//! - orders crossing the spread on arrival are filled in full at the best
+//!   opposite price and pay the taker fee
+//! - resting orders are filled in full at their price once the opposite side
+//!   of the book or a trade moves through the price and pay the maker fee
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::SystemTime,
+};
+
+use super::BacktestConfig;
+use crate::{
+    exchange::{order_request::OrderRequest, ExchangeRequest},
+    prelude::*,
+};
+use botvana::exchange::{Fill, Side};
+
+/// Best bid and ask of a market
+type TopOfBook = (Option<f64>, Option<f64>);
+
+/// Simulated exchange matching strategy orders against market events
+pub struct SimExchange {
+    latency: Duration,
+    maker_fee: f64,
+    taker_fee: f64,
+    pending: VecDeque<(SystemTime, ExchangeRequest)>,
+    orders: BTreeMap<u64, OrderRequest>,
+    books: HashMap<(ExchangeId, Box<str>), TopOfBook>,
+    orders_placed: u64,
+}
+
+impl SimExchange {
+    pub fn new(config: &BacktestConfig) -> Self {
+        Self {
+            latency: config.latency(),
+            maker_fee: config.maker_fee_bps / 10_000.0,
+            taker_fee: config.taker_fee_bps / 10_000.0,
+            pending: VecDeque::new(),
+            orders: BTreeMap::new(),
+            books: HashMap::new(),
+            orders_placed: 0,
+        }
+    }
+
+    /// Returns number of orders that reached the exchange
+    pub fn orders_placed(&self) -> u64 {
+        self.orders_placed
+    }
+
+    /// Returns number of resting orders
+    pub fn open_orders(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Submits request sent at given time
+    pub fn submit(&mut self, now: SystemTime, request: ExchangeRequest) {
+        self.pending.push_back((now + self.latency, request));
+    }
+
+    /// Advances the exchange to the time of the event and applies the event
+    ///
+    /// Returns fills that happened.
+    pub fn on_market_event(&mut self, exchange: ExchangeId, event: &MarketEvent) -> Vec<Fill> {
+        let now = event.timestamp;
+        let mut fills = Vec::new();
+
+        while let Some((arrival, _)) = self.pending.front() {
+            if *arrival > now {
+                break;
+            }
+
+            let (_, request) = self.pending.pop_front().unwrap();
+            self.process_request(request, now, &mut fills);
+        }
+
+        match &event.r#type {
+            MarketEventType::OrderbookUpdate(market, orderbook) => {
+                let top = (
+                    orderbook.bids.price_vec.last().cloned(),
+                    orderbook.asks.price_vec.first().cloned(),
+                );
+                self.books.insert((exchange, market.clone()), top);
+                self.match_resting(exchange, market, top, now, &mut fills);
+            }
+            MarketEventType::MidPriceChange(market, bid, ask) => {
+                let top = (Some(*bid), Some(*ask));
+                self.books.insert((exchange, market.clone()), top);
+                self.match_resting(exchange, market, top, now, &mut fills);
+            }
+            MarketEventType::Trades(market, trades) => {
+                for trade in trades.iter() {
+                    let top = (Some(trade.price), Some(trade.price));
+                    self.match_resting(exchange, market, top, now, &mut fills);
+                }
+            }
+            MarketEventType::Markets(_) => {}
+        }
+
+        fills
+    }
+
+    fn process_request(
+        &mut self,
+        request: ExchangeRequest,
+        now: SystemTime,
+        fills: &mut Vec<Fill>,
+    ) {
+        match request {
+            ExchangeRequest::PlaceOrder(order) => {
+                self.orders_placed += 1;
+
+                let (bid, ask) = self
+                    .books
+                    .get(&(order.exchange, order.market.clone()))
+                    .cloned()
+                    .unwrap_or_default();
+                let taker_price = match order.side {
+                    Side::Buy => ask.filter(|ask| *ask <= order.price),
+                    Side::Sell => bid.filter(|bid| *bid >= order.price),
+                };
+
+                match taker_price {
+                    Some(price) => fills.push(self.fill(&order, price, self.taker_fee, now)),
+                    None => {
+                        self.orders.insert(order.order_id, order);
+                    }
+                }
+            }
+            ExchangeRequest::CancelOrder(order_id) => {
+                self.orders.remove(&order_id);
+            }
+            ExchangeRequest::CancelAll => {
+                self.orders.clear();
+            }
+        }
+    }
+
+    /// Fills resting orders of the market that the bid and ask moved through
+    fn match_resting(
+        &mut self,
+        exchange: ExchangeId,
+        market: &str,
+        (bid, ask): TopOfBook,
+        now: SystemTime,
+        fills: &mut Vec<Fill>,
+    ) {
+        let filled: Vec<u64> = self
+            .orders
+            .values()
+            .filter(|order| order.exchange == exchange && order.market.as_ref() == market)
+            .filter(|order| match order.side {
+                Side::Buy => ask.map(|ask| ask < order.price).unwrap_or(false),
+                Side::Sell => bid.map(|bid| bid > order.price).unwrap_or(false),
+            })
+            .map(|order| order.order_id)
+            .collect();
+
+        for order_id in filled {
+            let order = self.orders.remove(&order_id).unwrap();
+            fills.push(self.fill(&order, order.price, self.maker_fee, now));
+        }
+    }
+
+    fn fill(&self, order: &OrderRequest, price: f64, fee_rate: f64, time: SystemTime) -> Fill {
+        Fill {
+            exchange: order.exchange,
+            market: order.market.clone(),
+            order_id: order.order_id,
+            side: order.side,
+            price,
+            size: order.size,
+            fee: price * order.size * fee_rate,
+            time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn ticker(ms: u64, bid: f64, ask: f64) -> MarketEvent {
+        MarketEvent {
+            r#type: MarketEventType::MidPriceChange(Box::from("BTC/USD"), bid, ask),
+            timestamp: UNIX_EPOCH + Duration::from_millis(ms),
+            trace: LatencyTrace::default(),
+        }
+    }
+
+    fn order(order_id: u64, side: Side, price: f64) -> ExchangeRequest {
+        ExchangeRequest::PlaceOrder(OrderRequest {
+            order_id,
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            side,
+            price,
+            size: 1.0,
+        })
+    }
+
+    fn sim_exchange() -> SimExchange {
+        SimExchange::new(&BacktestConfig {
+            latency_ms: 10,
+            maker_fee_bps: 0.0,
+            taker_fee_bps: 10.0,
+        })
+    }
+
+    #[test]
+    fn test_order_arrives_after_latency() {
+        let mut exchange = sim_exchange();
+
+        exchange.on_market_event(ExchangeId::Ftx, &ticker(0, 99.0, 101.0));
+        exchange.submit(UNIX_EPOCH, order(1, Side::Buy, 102.0));
+
+        assert!(exchange
+            .on_market_event(ExchangeId::Ftx, &ticker(5, 99.0, 101.0))
+            .is_empty());
+
+        let fills = exchange.on_market_event(ExchangeId::Ftx, &ticker(10, 99.0, 101.0));
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 101.0);
+        assert!((fills[0].fee - 0.101).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_resting_order_fill_and_cancel() {
+        let mut exchange = sim_exchange();
+
+        exchange.submit(UNIX_EPOCH, order(1, Side::Buy, 100.0));
+        exchange.submit(UNIX_EPOCH, order(2, Side::Sell, 102.0));
+        exchange.on_market_event(ExchangeId::Ftx, &ticker(10, 99.0, 101.0));
+        assert_eq!(exchange.open_orders(), 2);
+
+        exchange.submit(
+            UNIX_EPOCH + Duration::from_millis(10),
+            ExchangeRequest::CancelOrder(2),
+        );
+
+        let fills = exchange.on_market_event(ExchangeId::Ftx, &ticker(20, 98.0, 99.5));
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].order_id, 1);
+        assert_eq!(fills[0].price, 100.0);
+        assert_eq!(fills[0].fee, 0.0);
+        assert_eq!(exchange.open_orders(), 0);
+        assert_eq!(exchange.orders_placed(), 2);
+    }
+}
@@ -0,0 +1,184 @@
+//! Market events reconstructed from recorded market data files
+
+use std::{
+    io,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::TimeZone;
+
+use crate::{
+    prelude::*,
+    recorder::{file::*, MarketRecord, RecordData},
+};
+use botvana::market::trade::Trade;
+
+/// Iterator of market events reconstructed from recorded market data
+///
+/// Orderbook deltas are applied to the last snapshot of the market so each
+/// event carries the full orderbook, same as events produced by the market
+/// data engine. Event timestamps are the recorded receive times.
+pub struct RecordedEvents<I> {
+    records: I,
+    books: HashMap<(ExchangeId, Box<str>), PlainOrderbook<f64>>,
+}
+
+impl RecordedEvents<Box<dyn Iterator<Item = io::Result<MarketRecord>>>> {
+    /// Reads all recorded files in given directory in chronological order
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let readers = list_files(dir)?
+            .into_iter()
+            .map(RecordFileReader::open)
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self::new(Box::new(readers.into_iter().flatten())))
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MarketRecord>>> RecordedEvents<I> {
+    pub fn new(records: I) -> Self {
+        Self {
+            records,
+            books: HashMap::new(),
+        }
+    }
+
+    fn market_event(&mut self, record: MarketRecord) -> Option<MarketEvent> {
+        let MarketRecord {
+            exchange,
+            market,
+            exchange_time,
+            received_at,
+            data,
+        } = record;
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(received_at);
+
+        let r#type = match data {
+            RecordData::OrderbookSnapshot { bids, asks } => {
+                let orderbook = PlainOrderbook {
+                    bids,
+                    asks,
+                    time: exchange_time.unwrap_or_default(),
+                };
+                self.books
+                    .insert((exchange, market.clone()), orderbook.clone());
+
+                MarketEventType::OrderbookUpdate(market, Box::new(orderbook))
+            }
+            RecordData::OrderbookDelta { bids, asks } => {
+                let orderbook = match self.books.get_mut(&(exchange, market.clone())) {
+                    Some(orderbook) => orderbook,
+                    None => {
+                        warn!("Skipping orderbook delta without snapshot for {market}");
+                        return None;
+                    }
+                };
+
+                orderbook.bids.update(&bids);
+                orderbook.asks.update(&asks);
+                orderbook.time = exchange_time.unwrap_or(orderbook.time);
+
+                MarketEventType::OrderbookUpdate(market, Box::new(orderbook.clone()))
+            }
+            RecordData::Trades(trades) => {
+                let trades = trades
+                    .iter()
+                    .map(|trade| Trade {
+                        price: trade.price,
+                        size: trade.size,
+                        time: Utc.timestamp_nanos(trade.time),
+                        received_at: timestamp,
+                    })
+                    .collect();
+
+                MarketEventType::Trades(market, trades)
+            }
+            RecordData::Ticker { bid, ask } => MarketEventType::MidPriceChange(market, bid, ask),
+        };
+
+        Some(MarketEvent {
+            r#type,
+            timestamp,
+            trace: LatencyTrace::default(),
+        })
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MarketRecord>>> Iterator for RecordedEvents<I> {
+    type Item = io::Result<(ExchangeId, MarketEvent)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = match self.records.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            let exchange = record.exchange;
+
+            if let Some(event) = self.market_event(record) {
+                return Some(Ok((exchange, event)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(received_at: u64, data: RecordData) -> io::Result<MarketRecord> {
+        Ok(MarketRecord {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            exchange_time: None,
+            received_at,
+            data,
+        })
+    }
+
+    #[test]
+    fn test_recorded_events_apply_deltas() {
+        let records = vec![
+            record(
+                1,
+                RecordData::OrderbookDelta {
+                    bids: PriceLevelsVec::from_tuples_vec(&[(99.0, 1.0)]),
+                    asks: PriceLevelsVec::new(),
+                },
+            ),
+            record(
+                2,
+                RecordData::OrderbookSnapshot {
+                    bids: PriceLevelsVec::from_tuples_vec(&[(99.0, 1.0), (100.0, 1.0)]),
+                    asks: PriceLevelsVec::from_tuples_vec(&[(101.0, 1.0)]),
+                },
+            ),
+            record(
+                3,
+                RecordData::OrderbookDelta {
+                    bids: PriceLevelsVec::from_tuples_vec(&[(100.0, 0.0)]),
+                    asks: PriceLevelsVec::new(),
+                },
+            ),
+        ];
+
+        let events: Vec<_> = RecordedEvents::new(records.into_iter())
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        // Delta without snapshot is skipped
+        assert_eq!(events.len(), 2);
+
+        let (exchange, event) = &events[1];
+        assert_eq!(*exchange, ExchangeId::Ftx);
+        assert_eq!(event.timestamp, UNIX_EPOCH + Duration::from_nanos(3));
+        match &event.r#type {
+            MarketEventType::OrderbookUpdate(_, orderbook) => {
+                assert_eq!(orderbook.bids.price_vec, vec![99.0]);
+                assert_eq!(orderbook.asks.price_vec, vec![101.0]);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::{env::args, fs};
+
+use serde::Deserialize;
+use tracing::error;
+use tracing_subscriber::EnvFilter;
+
+use botnode::{
+    backtest::{source::RecordedEvents, Backtest, BacktestConfig},
+    trading::strategy,
+};
+use botvana::cfg::StrategyConfig;
+
+/// Backtest settings file
+#[derive(Deserialize)]
+struct Settings {
+    strategy: StrategyConfig,
+    #[serde(flatten)]
+    backtest: BacktestConfig,
+}
+
+/// Runs strategy over recorded market data and prints the report
+///
+/// Usage: `backtest <recordings-dir> <settings.json>`
+fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let mut args = args().skip(1);
+    let dir = args.next().expect("Please specify recordings directory");
+    let settings = args.next().expect("Please specify settings file");
+
+    let settings = fs::read_to_string(settings).expect("Failed to read settings");
+    let settings: Settings = serde_json::from_str(&settings).expect("Invalid settings");
+    let strategy = strategy::from_config(&settings.strategy).expect("Invalid strategy");
+    let events = RecordedEvents::from_dir(&dir).expect("Failed to open recordings");
+
+    let report =
+        Backtest::new(strategy, settings.backtest).run(events.map_while(|event| match event {
+            Ok(event) => Some(event),
+            Err(e) => {
+                error!("Failed to read recorded event, stopping: {e}");
+                None
+            }
+        }));
+
+    print!("{report}");
+}
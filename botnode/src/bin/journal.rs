@@ -0,0 +1,61 @@
+use std::{env::args, process};
+
+use chrono::{DateTime, Utc};
+
+use botnode::audit::journal::{self, JournalReader, JournalState};
+
+/// Verifies or replays audit journal
+///
+/// Usage: `journal verify <journal-file>` or `journal replay <journal-file>`
+///
+/// Replay prints every entry followed by the reconstructed state of the bot.
+fn main() {
+    let mut args = args().skip(1);
+    let command = args.next().expect("Please specify verify or replay");
+    let path = args.next().expect("Please specify journal file");
+
+    match command.as_str() {
+        "verify" => match journal::verify(&path) {
+            Ok(entries) => println!("{path}: {entries} entries verified"),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                process::exit(1);
+            }
+        },
+        "replay" => {
+            let reader = JournalReader::open(&path).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                process::exit(1);
+            });
+            let mut state = JournalState::default();
+
+            for entry in reader {
+                match entry {
+                    Ok(entry) => {
+                        println!(
+                            "{} {} {:?}",
+                            entry.seq,
+                            DateTime::<Utc>::from(entry.time).to_rfc3339(),
+                            entry.event
+                        );
+                        state.apply(&entry);
+                    }
+                    Err(e) => {
+                        eprintln!("{path}: {e}");
+                        process::exit(1);
+                    }
+                }
+            }
+
+            println!("entries: {}", state.entries);
+            println!("config: {:?}", state.config);
+            println!("quotes: {:?}", state.quotes);
+            println!("positions: {:?}", state.positions);
+            println!("open orders: {:?}", state.open_orders);
+        }
+        _ => {
+            eprintln!("Unknown command {command}, expected verify or replay");
+            process::exit(1);
+        }
+    }
+}
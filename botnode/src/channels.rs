@@ -1,11 +1,22 @@
-use std::hash::Hash;
+use std::{hash::Hash, sync::Arc};
+
+use once_cell::sync::Lazy;
 
 use crate::prelude::*;
+use botvana::metrics::{self, Counter};
 
 const FAIL_LIMIT: usize = 100;
 
+static QUEUE_FULL_RETRIES: Lazy<Arc<Counter>> = Lazy::new(|| {
+    metrics::registry().counter(
+        "botnode_queue_full_retries_total",
+        "Retries of pushing onto a full inter-engine queue",
+        &[],
+    )
+});
+
 /// Array of producers for inter-engine channel
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ProducersArray<T, const N: usize>(pub(super) ArrayVec<spsc_queue::Producer<T>, N>);
 
 impl<T, const N: usize> ProducersArray<T, N>
@@ -31,6 +42,7 @@ where
 
             while let Some(value) = res {
                 res = tx.try_push(value);
+                QUEUE_FULL_RETRIES.inc();
                 warn!("Retried to push to channel {idx}: {res:?}");
 
                 if fail_cnt > FAIL_LIMIT {
@@ -76,7 +88,7 @@ impl<T, const N: usize> Default for ProducersArray<T, N> {
 }
 
 /// Map of consumers for inter-engine channel
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ConsumersMap<K, V>(HashMap<K, spsc_queue::Consumer<V>>);
 
 impl<K, V> ConsumersMap<K, V>
@@ -1,14 +1,43 @@
-use botvana::exchange::ExchangeId;
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use botvana::{
+    cfg::ExchangeConfig,
+    exchange::ExchangeId,
+    net::{
+        codec::{NegotiatedVersion, DEFAULT_MAX_FRAME_SIZE},
+        msg::{Capabilities, Command, CommandResult},
+        tls::{rustls, ServerName},
+    },
+    report::{EngineReport, MarketDataMetrics, Metrics, StatusReport},
+};
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
 use crate::{
-    audit::engine::*, engine::*, exchange::engine::*, indicator::engine::*, market_data::*,
-    prelude::*, trading::engine::*,
+    audit::engine::*,
+    engine::*,
+    exchange::{engine::*, ExchangeEvent},
+    heartbeat::HeartbeatMonitor,
+    indicator::engine::*,
+    market_data::{adapter::MarketDataAdapter, *},
+    prelude::*,
+    recorder::engine::*,
+    supervisor::*,
+    topology::{online_cpus, Topology},
+    trading::{engine::*, TradingCommand},
 };
 
 use super::BotnodeStatus;
 
 const CONSUMER_LIMIT: usize = 16;
 const QUEUE_LEN: usize = 1024;
+/// Maximum number of engines consuming market data
+const MARKET_DATA_CONSUMERS: usize = 5;
+const COMMAND_QUEUE_LEN: usize = 16;
+/// Restart policy of engines that can be rebuilt without losing state
+const RESTART_POLICY: RestartPolicy = RestartPolicy::OnFailure { max_restarts: 10 };
+
+/// Handle used to replace the log filter at runtime
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
 
 /// Control engine for Botnode
 ///
@@ -16,171 +45,607 @@ const QUEUE_LEN: usize = 1024;
 pub struct ControlEngine {
     pub(super) bot_id: BotId,
     pub(super) server_addr: String,
+    /// Shared secret used to authenticate with botvana-server
+    pub(super) secret: Box<str>,
+    /// TLS configuration and expected name of botvana-server
+    pub(super) tls: Option<(Arc<rustls::ClientConfig>, ServerName)>,
+    /// Capabilities supported by both botnode and botvana-server
+    pub(super) capabilities: Capabilities,
+    /// Protocol version negotiated with botvana-server
+    pub(super) protocol_version: NegotiatedVersion,
+    /// Largest frame accepted from botvana-server
+    pub(super) max_frame_size: usize,
     pub(super) status: BotnodeStatus,
     pub(super) ping_interval: std::time::Duration,
     pub(super) bot_configuration: Option<BotConfiguration>,
     config_txs: ArrayVec<spsc_queue::Producer<BotConfiguration>, CONSUMER_LIMIT>,
     pub(super) status_rxs: HashMap<EngineType, spsc_queue::Consumer<EngineStatus>>,
     pub(super) market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    pub(super) exchange_rx: Option<spsc_queue::Consumer<ExchangeEvent>>,
+    /// Last reported status of each engine
+    pub(super) engine_statuses: HashMap<EngineType, EngineStatus>,
+    trading_command_tx: Option<spsc_queue::Producer<(u64, TradingCommand)>>,
+    pub(super) command_result_rx: Option<spsc_queue::Consumer<(u64, CommandResult)>>,
+    restart_txs: HashMap<EngineType, spsc_queue::Producer<()>>,
+    pub(super) supervisor: Supervisor,
+    pub(super) heartbeats: HeartbeatMonitor,
+    log_filter: Option<LogFilterHandle>,
+    /// Directory the audit journal is written to
+    journal_dir: Option<PathBuf>,
+    pub(super) metrics_rxs: Vec<spsc_queue::Consumer<MarketDataMetrics>>,
+    /// Latest metrics reported by each market data engine
+    pub(super) market_data_metrics: HashMap<ExchangeId, MarketDataMetrics>,
+    /// Number of times botnode reconnected to botvana-server
+    pub(super) server_reconnects: u64,
+    /// Market data or fill that failed to send, resent after reconnecting
+    pub(super) unsent: Option<Message>,
+    pub(super) clock: SharedClock,
 }
 
 impl ControlEngine {
     /// Create new control engine
-    pub fn new<T: ToString>(bot_id: BotId, server_addr: T) -> Self {
+    ///
+    /// The clock is shared with all engines spawned by the control engine.
+    pub fn new<T: ToString>(
+        bot_id: BotId,
+        server_addr: T,
+        secret: Box<str>,
+        clock: SharedClock,
+    ) -> Self {
         Self {
             bot_id,
             server_addr: server_addr.to_string(),
+            secret,
+            tls: None,
+            capabilities: Capabilities::NONE,
+            protocol_version: NegotiatedVersion::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
             status: BotnodeStatus::Offline,
             ping_interval: std::time::Duration::from_secs(5),
             config_txs: ArrayVec::<_, CONSUMER_LIMIT>::new(),
             bot_configuration: None,
             market_data_rxs: ConsumersMap::default(),
             status_rxs: HashMap::new(),
+            exchange_rx: None,
+            engine_statuses: HashMap::new(),
+            trading_command_tx: None,
+            command_result_rx: None,
+            restart_txs: HashMap::new(),
+            supervisor: Supervisor::default(),
+            heartbeats: HeartbeatMonitor::default(),
+            log_filter: None,
+            journal_dir: None,
+            metrics_rxs: Vec::new(),
+            market_data_metrics: HashMap::new(),
+            server_reconnects: 0,
+            unsent: None,
+            clock,
         }
     }
 
-    /// Spawns the engines based on given configuration and wires them up using channels.
+    /// Connects to botvana-server over TLS, verifying it presents
+    /// certificate for `server_name`
+    pub fn with_tls(mut self, config: Arc<rustls::ClientConfig>, server_name: ServerName) -> Self {
+        self.tls = Some((config, server_name));
+        self
+    }
+
+    /// Allows botvana-server to change the log filter using given handle
+    pub fn with_log_filter(mut self, handle: LogFilterHandle) -> Self {
+        self.log_filter = Some(handle);
+        self
+    }
+
+    /// Rejects frames from botvana-server larger than given number of bytes
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Makes the audit engine write its journal to given directory
+    pub fn with_journal(mut self, dir: PathBuf) -> Self {
+        self.journal_dir = Some(dir);
+        self
+    }
+
+    /// Returns capabilities negotiated with botvana-server
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Spawns the engines placed by the topology of given configuration and
+    /// wires them up using channels
+    ///
+    /// Engines placed on the same CPU are spawned in one executor. Nothing is
+    /// spawned when the topology is invalid.
     pub(super) fn spawn_engines(
         &mut self,
         config: BotConfiguration,
         shutdown: Shutdown,
-    ) -> Result<(), ()> {
+    ) -> Result<(), EngineError> {
+        let topology = Topology::from_config(&config)
+            .and_then(|topology| {
+                topology.validate_cpus(&online_cpus()?)?;
+                Ok(topology)
+            })
+            .map_err(|e| {
+                error!("Invalid topology: {e}");
+                EngineError::with_source(e)
+            })?;
+        debug!("topology = {topology:?}");
+
         let n_exchanges = config.exchanges.len();
-        // Build market data receiver hashmap for each client:
+        // Build market data receiver hashmap for the control engine and each
+        // running consumer:
         //  - trading engine
         //  - indicator engine
-        //  - control engine
         //  - audit engine
-        let mut market_data_rxs = vec![
-            ConsumersMap::with_capacity(n_exchanges),
-            ConsumersMap::with_capacity(n_exchanges),
-            ConsumersMap::with_capacity(n_exchanges),
-            ConsumersMap::with_capacity(n_exchanges),
-        ];
-
-        for (i, exchange) in config.exchanges.iter().enumerate() {
+        //  - recorder engine
+        let mut market_data_rxs: HashMap<_, _> = [
+            EngineType::ControlEngine,
+            EngineType::TradingEngine,
+            EngineType::IndicatorEngine,
+            EngineType::AuditEngine,
+            EngineType::RecorderEngine,
+        ]
+        .into_iter()
+        .filter(|engine| *engine == EngineType::ControlEngine || topology.runs(engine))
+        .map(|engine| (engine, ConsumersMap::with_capacity(n_exchanges)))
+        .collect();
+
+        let mut placed = Vec::new();
+
+        for exchange in config.exchanges.iter() {
+            let exchange_id = exchange
+                .parse::<ExchangeId>()
+                .expect("exchange validated by topology");
+            let cpu = topology
+                .cpu(&EngineType::MarketDataEngine(exchange_id))
+                .expect("market data engine placed by topology");
+
             debug!("starting exchange {exchange:?}");
 
-            self.spawn_market_engine(
-                i + 1,
+            placed.push(self.market_engine(
+                cpu,
                 exchange.as_ref(),
-                shutdown.clone(),
+                exchange_id,
+                &config.exchange_config(exchange),
                 &mut market_data_rxs,
-            )
-            .expect(&format!("Failed to start {exchange} market data engine"));
+            ));
         }
 
-        self.market_data_rxs = market_data_rxs.pop().unwrap();
+        if let (Some(cpu), Some(recorder_config)) = (
+            topology.cpu(&EngineType::RecorderEngine),
+            config.recorder.clone(),
+        ) {
+            let recorder_rxs = market_data_rxs
+                .remove(&EngineType::RecorderEngine)
+                .unwrap_or_default();
+
+            placed.push(PlacedEngine::restartable(
+                EngineType::RecorderEngine,
+                cpu,
+                move || RecorderEngine::new(recorder_config.clone(), recorder_rxs.clone()),
+            ));
+        }
+
+        self.market_data_rxs = market_data_rxs
+            .remove(&EngineType::ControlEngine)
+            .unwrap_or_default();
 
         let (exchange_request_tx, exchange_request_rx) = spsc_queue::make(100);
 
-        let mut exchange_engine = ExchangeEngine::new(
-            self.data_rx(),
-            crate::exchange::null_adapter::NullAdapter {},
-            exchange_request_rx,
-        );
+        let mut exchange_engine = topology.cpu(&EngineType::ExchangeEngine).map(|cpu| {
+            let engine = ExchangeEngine::new(
+                self.data_rx(),
+                crate::exchange::null_adapter::NullAdapter {},
+                exchange_request_rx,
+            );
+
+            (cpu, engine)
+        });
+
+        self.exchange_rx = exchange_engine
+            .as_mut()
+            .map(|(_, exchange_engine)| exchange_engine.data_rx());
+
+        let mut indicator_engine = topology
+            .cpu(&EngineType::IndicatorEngine)
+            .zip(market_data_rxs.remove(&EngineType::IndicatorEngine))
+            .map(|(cpu, market_data_rxs)| {
+                let engine =
+                    IndicatorEngine::new(self.data_rx(), market_data_rxs, self.clock.clone());
+
+                (cpu, engine)
+            });
+
+        let mut trading_engine = match (
+            topology.cpu(&EngineType::TradingEngine),
+            market_data_rxs.remove(&EngineType::TradingEngine),
+            exchange_engine.as_mut(),
+        ) {
+            (Some(cpu), Some(market_data_rxs), Some((_, exchange_engine))) => {
+                let (trading_command_tx, trading_command_rx) = spsc_queue::make(COMMAND_QUEUE_LEN);
+                let (command_result_tx, command_result_rx) = spsc_queue::make(COMMAND_QUEUE_LEN);
+                self.trading_command_tx = Some(trading_command_tx);
+                self.command_result_rx = Some(command_result_rx);
+
+                // Without the indicator engine the trading engine runs with
+                // no indicators
+                let indicator_rx = match indicator_engine.as_mut() {
+                    Some((_, indicator_engine)) => indicator_engine.data_rx(),
+                    None => spsc_queue::make(1).1,
+                };
+
+                let engine = TradingEngine::new(
+                    self.data_rx(),
+                    market_data_rxs,
+                    indicator_rx,
+                    exchange_request_tx,
+                    exchange_engine.data_rx(),
+                    trading_command_rx,
+                    command_result_tx,
+                    self.clock.clone(),
+                );
 
-        self.status_rxs
-            .insert(EngineType::ExchangeEngine, exchange_engine.status_rx());
+                Some((cpu, engine))
+            }
+            _ => None,
+        };
+
+        match (
+            topology.cpu(&EngineType::AuditEngine),
+            market_data_rxs.remove(&EngineType::AuditEngine),
+        ) {
+            (Some(cpu), Some(audit_rxs)) => {
+                let latency_rx = trading_engine
+                    .as_mut()
+                    .map(|(_, trading_engine)| trading_engine.latency_rx());
+                let journal = self.journal_dir.clone().map(|dir| {
+                    // Only configuration is journaled without the trading
+                    // engine
+                    let events_rx = match trading_engine.as_mut() {
+                        Some((_, trading_engine)) => trading_engine.journal_rx(),
+                        None => spsc_queue::make(1).1,
+                    };
+
+                    (dir, self.data_rx(), events_rx)
+                });
+                let clock = self.clock.clone();
 
-        let mut indicator_engine =
-            IndicatorEngine::new(self.data_rx(), market_data_rxs.pop().unwrap());
+                placed.push(PlacedEngine::restartable(
+                    EngineType::AuditEngine,
+                    cpu,
+                    move || {
+                        let mut audit_engine = AuditEngine::new(audit_rxs.clone(), clock.clone());
 
-        self.status_rxs
-            .insert(EngineType::IndicatorEngine, indicator_engine.status_rx());
+                        if let Some(latency_rx) = latency_rx.clone() {
+                            audit_engine = audit_engine.with_latency_rx(latency_rx);
+                        }
 
-        let trading_engine = TradingEngine::new(
-            market_data_rxs.pop().unwrap(),
-            indicator_engine.data_rx(),
-            exchange_request_tx,
-            exchange_engine.data_rx(),
-        );
+                        if let Some((dir, config_rx, events_rx)) = journal.clone() {
+                            audit_engine = audit_engine.with_journal(dir, config_rx, events_rx);
+                        }
 
-        self.status_rxs
-            .insert(EngineType::TradingEngine, trading_engine.status_rx());
+                        audit_engine
+                    },
+                ));
+            }
+            _ if self.journal_dir.is_some() => {
+                warn!("Audit engine isn't placed, the journal isn't written")
+            }
+            _ => {}
+        }
 
-        let audit_engine = AuditEngine::new(market_data_rxs.pop().unwrap());
+        // Indicator, trading and exchange engines keep state that would be
+        // lost on restart, so they are only reported when they exit
+        if let Some((cpu, engine)) = indicator_engine {
+            placed.push(PlacedEngine::permanent(
+                EngineType::IndicatorEngine,
+                cpu,
+                engine,
+            ));
+        }
 
-        self.status_rxs
-            .insert(EngineType::AuditEngine, audit_engine.status_rx());
+        if let Some((cpu, engine)) = trading_engine {
+            placed.push(PlacedEngine::permanent(
+                EngineType::TradingEngine,
+                cpu,
+                engine,
+            ));
+        }
 
-        spawn_engine(n_exchanges + 3, indicator_engine, shutdown.clone())
-            .expect("failed to start indicator engine");
+        if let Some((cpu, engine)) = exchange_engine {
+            placed.push(PlacedEngine::permanent(
+                EngineType::ExchangeEngine,
+                cpu,
+                engine,
+            ));
+        }
 
-        spawn_engine(n_exchanges + 4, trading_engine, shutdown.clone())
-            .expect("failed to start trading engine");
+        let mut executors: BTreeMap<usize, Vec<PlacedEngine>> = BTreeMap::new();
+        for engine in placed {
+            executors.entry(engine.cpu).or_default().push(engine);
+        }
 
-        spawn_engine(n_exchanges + 5, exchange_engine, shutdown.clone())
-            .expect("failed to start order engine");
+        for (cpu, engines) in executors {
+            let (boxed, supervised): (Vec<_>, Vec<_>) = engines
+                .into_iter()
+                .map(|placed| (placed.boxed, (placed.engine, placed.policy, placed.respawn)))
+                .unzip();
 
-        spawn_engine(n_exchanges + 6, audit_engine, shutdown.clone())
-            .expect("failed to start audit engine");
+            let spawned =
+                spawn_executor(cpu, boxed, shutdown.clone()).map_err(EngineError::with_source)?;
+
+            for ((engine, policy, respawn), spawned) in supervised.into_iter().zip(spawned) {
+                self.supervise(engine, spawned, policy, respawn);
+            }
+        }
 
         Ok(())
     }
 
-    fn spawn_market_engine(
+    /// Supervises spawned engine and receives its status and heartbeats
+    fn supervise(
         &mut self,
-        cpu: usize,
-        exchange: &str,
-        shutdown: Shutdown,
-        market_data_rxs: &mut Vec<ConsumersMap<Box<str>, MarketEvent>>,
-    ) -> Result<glommio::ExecutorJoinHandle<()>, StartEngineError> {
-        match exchange {
-            "ftx" => {
-                let ftx_adapter = crate::market_data::ftx::Ftx::default();
-                let mut market_data_engine =
-                    MarketDataEngine::<_, 4>::new(self.data_rx(), ftx_adapter);
-
-                self.status_rxs.insert(
-                    EngineType::MarketDataEngine(ExchangeId::Ftx),
-                    market_data_engine.status_rx(),
-                );
+        engine: EngineType,
+        spawned: SpawnedEngine,
+        policy: RestartPolicy,
+        respawn: Option<Respawn>,
+    ) {
+        let receivers = self
+            .supervisor
+            .supervise(engine.clone(), spawned, policy, respawn);
+
+        self.receive_from(engine, receivers);
+    }
 
-                market_data_rxs.iter_mut().for_each(|rx| {
-                    rx.insert(Box::from(exchange), market_data_engine.data_rx());
-                });
+    /// Receives status and monitors heartbeats of the engine
+    pub(super) fn receive_from(&mut self, engine: EngineType, receivers: EngineReceivers) {
+        if let Some(heartbeat_rx) = receivers.heartbeat_rx {
+            self.heartbeats.monitor(engine.clone(), heartbeat_rx);
+        }
+
+        self.status_rxs.insert(engine, receivers.status_rx);
+    }
 
-                spawn_engine(cpu, market_data_engine, shutdown)
+    /// Applies configuration to running engines via the config fan-out
+    ///
+    /// Changes of exchanges take effect only after botnode restarts.
+    pub(super) fn apply_configuration(&mut self, config: BotConfiguration) {
+        if let Some(current) = &self.bot_configuration {
+            if current.exchanges != config.exchanges {
+                warn!(
+                    "Changing exchanges requires restart, running with {:?}",
+                    current.exchanges
+                );
             }
-            "binance" => {
-                let binance_adapter = crate::market_data::binance::Binance::default();
-                let mut market_data_engine =
-                    MarketDataEngine::<_, 4>::new(self.data_rx(), binance_adapter);
+        }
 
-                market_data_rxs.iter_mut().for_each(|rx| {
-                    rx.insert(Box::from(exchange), market_data_engine.data_rx());
-                });
+        debug!("applying config = {config:?}");
 
-                self.status_rxs.insert(
-                    EngineType::MarketDataEngine(ExchangeId::BinanceSpot),
-                    market_data_engine.status_rx(),
-                );
+        self.bot_configuration = Some(config.clone());
+        self.push_value(config);
+    }
 
-                spawn_engine(cpu, market_data_engine, shutdown)
-            }
-            "serum" => {
-                let serum_adapter = crate::market_data::serum::Serum::default();
-                let mut market_data_engine =
-                    MarketDataEngine::<_, 4>::new(self.data_rx(), serum_adapter);
+    /// Executes command sent by botvana-server
+    ///
+    /// Trading commands are forwarded to the trading engine and `None` is
+    /// returned, their result arrives on `command_result_rx`.
+    pub(super) fn execute_command(&mut self, id: u64, command: Command) -> Option<CommandResult> {
+        let trading_command = match command {
+            Command::PauseTrading => TradingCommand::Pause,
+            Command::ResumeTrading => TradingCommand::Resume,
+            Command::CancelAll => TradingCommand::CancelAll,
+            Command::FlattenPositions => TradingCommand::Flatten,
+            Command::SetLogLevel(filter) => return Some(self.set_log_filter(&filter)),
+            Command::RestartEngine(engine) => return Some(self.restart_engine(engine)),
+            Command::StatusDump => return Some(Ok(self.status_dump().into())),
+        };
+
+        match &self.trading_command_tx {
+            Some(tx) => match tx.try_push((id, trading_command)) {
+                None => None,
+                Some(_) => Some(Err(Box::from("Trading engine command queue is full"))),
+            },
+            None => Some(Err(Box::from("Trading engine is not running"))),
+        }
+    }
 
-                market_data_rxs.iter_mut().for_each(|rx| {
-                    rx.insert(Box::from(exchange), market_data_engine.data_rx());
-                });
+    fn set_log_filter(&self, filter: &str) -> CommandResult {
+        let handle = self
+            .log_filter
+            .as_ref()
+            .ok_or("Log filter can't be changed")?;
+        let filter = EnvFilter::try_new(filter).map_err(|e| e.to_string())?;
 
-                self.status_rxs.insert(
-                    EngineType::MarketDataEngine(ExchangeId::Serum),
-                    market_data_engine.status_rx(),
-                );
+        handle.reload(filter).map_err(|e| e.to_string())?;
 
-                spawn_engine(cpu, market_data_engine, shutdown)
-            }
-            _ => {
-                error!("Unknown exchange {exchange}");
-                Err(StartEngineError {
-                    source: "Unknown exchange".into(),
+        Ok(Box::from("log filter changed"))
+    }
+
+    /// Restarts engine, only market data engines can be restarted
+    fn restart_engine(&self, engine: EngineType) -> CommandResult {
+        let tx = self
+            .restart_txs
+            .get(&engine)
+            .ok_or_else(|| format!("Restarting {engine:?} is not supported"))?;
+
+        match tx.try_push(()) {
+            None => Ok(format!("{engine:?} restarting").into()),
+            Some(_) => Err(format!("{engine:?} is already restarting").into()),
+        }
+    }
+
+    /// Returns JSON with status of the bot and its engines
+    fn status_dump(&self) -> String {
+        let engines: Vec<_> = self
+            .engine_statuses
+            .iter()
+            .map(|(engine, status)| serde_json::json!({ "engine": engine, "status": status }))
+            .collect();
+        let config = self.bot_configuration.as_ref();
+
+        serde_json::json!({
+            "bot_id": self.bot_id.0,
+            "capabilities": self.capabilities.0,
+            "exchanges": config.map(|config| &config.exchanges),
+            "markets": config.map(|config| &config.markets),
+            "strategy": config.and_then(|config| config.strategy.as_ref()),
+            "engines": engines,
+            "health": self.heartbeats.health(),
+        })
+        .to_string()
+    }
+
+    /// Returns status report of the bot and its engines
+    pub(super) fn status_report(&self) -> StatusReport {
+        StatusReport {
+            time: Some(DateTime::<Utc>::from(self.clock.now())),
+            engines: self
+                .engine_statuses
+                .iter()
+                .map(|(engine, status)| EngineReport {
+                    engine: engine.clone(),
+                    status: status.clone(),
                 })
-            }
+                .collect(),
+            server_reconnects: self.server_reconnects,
+        }
+    }
+
+    /// Returns the latest metrics reported by the engines
+    pub(super) fn metrics(&self) -> Metrics {
+        Metrics {
+            time: Some(DateTime::<Utc>::from(self.clock.now())),
+            market_data: self.market_data_metrics.values().cloned().collect(),
+        }
+    }
+
+    /// Returns receiver of restart requests for the engine
+    fn restart_rx(&mut self, engine: EngineType) -> spsc_queue::Consumer<()> {
+        let (restart_tx, restart_rx) = spsc_queue::make(1);
+        self.restart_txs.insert(engine, restart_tx);
+        restart_rx
+    }
+
+    /// Builds market data engine of the exchange placed on given CPU
+    fn market_engine(
+        &mut self,
+        cpu: usize,
+        exchange: &str,
+        exchange_id: ExchangeId,
+        exchange_config: &ExchangeConfig,
+        market_data_rxs: &mut HashMap<EngineType, ConsumersMap<Box<str>, MarketEvent>>,
+    ) -> PlacedEngine {
+        let exchange_config = exchange_config.clone();
+        let clock = self.clock.clone();
+
+        match exchange_id {
+            ExchangeId::Ftx => self.market_data_engine(
+                cpu,
+                exchange,
+                exchange_id,
+                move || crate::market_data::ftx::Ftx::new(&exchange_config, clock.clone()),
+                market_data_rxs,
+            ),
+            ExchangeId::BinanceSpot => self.market_data_engine(
+                cpu,
+                exchange,
+                exchange_id,
+                move || crate::market_data::binance::Binance::new(&exchange_config, clock.clone()),
+                market_data_rxs,
+            ),
+            ExchangeId::Serum => self.market_data_engine(
+                cpu,
+                exchange,
+                exchange_id,
+                move || crate::market_data::serum::Serum::new(&exchange_config, clock.clone()),
+                market_data_rxs,
+            ),
+        }
+    }
+
+    /// Builds market data engine running adapter built by `adapter` and
+    /// wires its market data into `market_data_rxs`
+    ///
+    /// Restarted engine pushes onto the queues of the exited one.
+    fn market_data_engine<A, F>(
+        &mut self,
+        cpu: usize,
+        exchange: &str,
+        exchange_id: ExchangeId,
+        mut adapter: F,
+        market_data_rxs: &mut HashMap<EngineType, ConsumersMap<Box<str>, MarketEvent>>,
+    ) -> PlacedEngine
+    where
+        A: MarketDataAdapter<MARKET_DATA_CONSUMERS> + Send + 'static,
+        F: FnMut() -> A + 'static,
+    {
+        let engine_type = EngineType::MarketDataEngine(exchange_id);
+        let config_rx = self.data_rx();
+        let restart_rx = self.restart_rx(engine_type.clone());
+        let mut market_data_engine =
+            MarketDataEngine::<_, MARKET_DATA_CONSUMERS>::new(config_rx.clone(), adapter())
+                .with_restart(restart_rx.clone());
+
+        market_data_rxs.values_mut().for_each(|rx| {
+            rx.insert(Box::from(exchange), market_data_engine.data_rx());
+        });
+        self.metrics_rxs.push(market_data_engine.metrics_rx());
+
+        let outputs = market_data_engine.outputs();
+        let respawn = move || {
+            let market_data_engine = MarketDataEngine::new(config_rx.clone(), adapter())
+                .with_restart(restart_rx.clone())
+                .with_outputs(outputs.clone());
+
+            BoxedEngine::new(market_data_engine)
+        };
+
+        PlacedEngine {
+            engine: engine_type,
+            cpu,
+            boxed: BoxedEngine::new(market_data_engine),
+            policy: RESTART_POLICY,
+            respawn: Some(Box::new(respawn)),
+        }
+    }
+}
+
+/// Engine placed on a CPU waiting to be spawned
+struct PlacedEngine {
+    engine: EngineType,
+    cpu: usize,
+    boxed: BoxedEngine,
+    policy: RestartPolicy,
+    respawn: Option<Respawn>,
+}
+
+impl PlacedEngine {
+    /// Engine restarted on failure, each instance is built by `build`
+    fn restartable<E, F>(engine: EngineType, cpu: usize, mut build: F) -> Self
+    where
+        E: Engine + Send + 'static,
+        F: FnMut() -> E + 'static,
+    {
+        Self {
+            engine,
+            cpu,
+            boxed: BoxedEngine::new(build()),
+            policy: RESTART_POLICY,
+            respawn: Some(Box::new(move || BoxedEngine::new(build()))),
+        }
+    }
+
+    /// Engine that is never restarted
+    fn permanent<E: Engine + Send + 'static>(engine: EngineType, cpu: usize, built: E) -> Self {
+        Self {
+            engine,
+            cpu,
+            boxed: BoxedEngine::new(built),
+            policy: RestartPolicy::Never,
+            respawn: None,
         }
     }
 }
@@ -1,15 +1,23 @@
-use std::time::SystemTime;
-
 use super::engine::*;
 use super::BotnodeStatus;
-use crate::prelude::*;
+use crate::{exchange::ExchangeEvent, prelude::*, supervisor::SupervisorEvent};
+use botvana::{
+    clock::estimate_offset_nanos,
+    net::{
+        msg::Capabilities,
+        tls::{MaybeTlsStream, TlsConnector},
+    },
+};
 
 const BOTVANA_SERVER_READ_TIMEOUT: u64 = 50;
+/// Interval of sending status reports and metrics in seconds
+const REPORT_INTERVAL_SECS: u64 = 5;
 
 /// Runs the Botnode control engine that runs the connection to Botvana
 ///
 /// This connects to Botvana server on a given address, sends the Hello
-/// message and runs the loop.
+/// message and runs the loop. Failing to send to the server returns an error
+/// instead of panicking so the control engine reconnects.
 pub(crate) async fn run_control_loop(
     control: &mut super::engine::ControlEngine,
     shutdown: Shutdown,
@@ -22,10 +30,26 @@ pub(crate) async fn run_control_loop(
 
     // Await the first message expected to be bot configuration
     let msg = framed.next().await;
-    let mut last_activity = SystemTime::now();
+    let mut last_activity = control.clock.now();
+    let mut last_report = control.clock.now();
+    // Local time the last unanswered ping was sent at
+    let mut ping_sent_at = None;
 
     process_bot_configuration(control, msg, shutdown.clone())?;
 
+    if let Some(msg) = control.unsent.take() {
+        if !control.protocol_version.supports(&msg) {
+            warn!("Dropping unsent message unsupported by botvana-server: {msg:?}");
+        } else if let Err(e) = framed.send(msg.clone()).await {
+            error!("Failed to resend message: {e:?}");
+            control.unsent = Some(msg);
+
+            return Err(EngineError::with_source(ControlEngineError {
+                msg: "Failed to resend message to botvana-server",
+            }));
+        }
+    }
+
     loop {
         if shutdown.shutdown_started() {
             info!("shutting down control engine");
@@ -33,56 +57,160 @@ pub(crate) async fn run_control_loop(
             break Ok(());
         }
 
-        let elapsed = last_activity.elapsed().unwrap();
+        let elapsed = control.clock.elapsed(last_activity);
 
         if elapsed > control.ping_interval {
-            if let Err(e) = framed.send(Message::ping()).await {
-                error!("Failed to send ping message: {e:?}");
+            let sent_at = control.clock.unix_nanos();
+
+            match framed.send(Message::Ping(sent_at)).await {
+                Ok(()) => ping_sent_at = Some(sent_at),
+                Err(e) => error!("Failed to send ping message: {e:?}"),
             }
-            last_activity = SystemTime::now();
+            last_activity = control.clock.now();
         }
 
-        for (engine, status_rx) in control.status_rxs.iter() {
-            if status_rx.producer_disconnected() {
-                warn!("Engine {engine:?} disconnected!");
-            }
+        let mut status_changes = Vec::new();
 
+        for (engine, status_rx) in control.status_rxs.iter() {
             let status = status_rx.try_pop();
             if let Some(status) = status {
                 info!("EngineStatus: {engine:?} = {status:?}");
+                control.supervisor.on_status(engine, &status);
+                status_changes.push(Message::engine_status(engine.clone(), status.clone()));
+                control.engine_statuses.insert(engine.clone(), status);
+            }
+        }
+
+        for msg in status_changes {
+            if let Err(e) = framed.send(msg).await {
+                error!("Failed to send engine status: {e:?}");
+            }
+            last_activity = control.clock.now();
+        }
+
+        for event in control.supervisor.poll(&shutdown) {
+            match event {
+                SupervisorEvent::Exited(exit) => {
+                    control.heartbeats.remove(&exit.engine);
+
+                    if let Err(e) = framed.send(Message::engine_exit(exit)).await {
+                        error!("Failed to send engine exit: {e:?}");
+                    }
+                    last_activity = control.clock.now();
+                }
+                SupervisorEvent::Restarted(engine, receivers) => {
+                    control.receive_from(engine, receivers);
+
+                    // Restarted engine awaits configuration, the other
+                    // engines ignore unchanged configuration
+                    if let Some(config) = control.bot_configuration.clone() {
+                        control.apply_configuration(config);
+                    }
+                }
             }
         }
 
+        for health in control.heartbeats.poll() {
+            if let Err(e) = framed.send(Message::engine_health(health)).await {
+                error!("Failed to send engine health: {e:?}");
+            }
+            last_activity = control.clock.now();
+        }
+
         for (exchange, rx) in control.market_data_rxs.iter() {
-            let exchange = exchange.parse::<botvana::exchange::ExchangeId>().unwrap();
-
-            match rx.try_pop() {
-                Some(MarketEvent {
-                    r#type: MarketEventType::Markets(markets),
-                    ..
-                }) => {
-                    framed
-                        .send(Message::market_list(*markets.clone()))
-                        .await
-                        .unwrap();
-                    last_activity = SystemTime::now();
+            let event = match rx.try_pop() {
+                Some(event) => event,
+                None => continue,
+            };
+            let exchange = match exchange.parse::<botvana::exchange::ExchangeId>() {
+                Ok(exchange) => exchange,
+                Err(e) => {
+                    error!("Market event from unknown exchange {exchange}: {e:?}");
+                    continue;
                 }
-                Some(MarketEvent {
-                    r#type: MarketEventType::OrderbookUpdate(market, orderbook),
-                    ..
-                }) => {
-                    let orderbook = Orderbook {
+            };
+
+            let msg = match event.r#type {
+                MarketEventType::Markets(markets) => Message::market_list(*markets),
+                MarketEventType::OrderbookUpdate(market, orderbook) => {
+                    Message::orderbook(Orderbook {
                         exchange,
                         market,
                         bids: orderbook.bids,
                         asks: orderbook.asks,
                         time: orderbook.time,
-                    };
-                    framed.send(Message::orderbook(orderbook)).await.unwrap();
-                    last_activity = SystemTime::now();
+                    })
+                }
+                MarketEventType::Trades(market, trades) => {
+                    Message::trades(exchange, market, trades)
+                }
+                _ => continue,
+            };
+
+            if let Err(e) = framed.send(msg.clone()).await {
+                error!("Failed to send market data: {e:?}");
+                control.unsent = Some(msg);
+
+                return Err(EngineError::with_source(ControlEngineError {
+                    msg: "Failed to send market data to botvana-server",
+                }));
+            }
+            last_activity = control.clock.now();
+        }
+
+        if let Some((id, result)) = control
+            .command_result_rx
+            .as_ref()
+            .and_then(|rx| rx.try_pop())
+        {
+            if let Err(e) = framed.send(Message::command_result(id, result)).await {
+                error!("Failed to send command result: {e:?}");
+            }
+            last_activity = control.clock.now();
+        }
+
+        for metrics_rx in control.metrics_rxs.iter() {
+            while let Some(metrics) = metrics_rx.try_pop() {
+                control
+                    .market_data_metrics
+                    .insert(metrics.exchange, metrics);
+            }
+        }
+
+        if control.capabilities.contains(Capabilities::REPORTS)
+            && control.clock.elapsed(last_report) >= Duration::from_secs(REPORT_INTERVAL_SECS)
+        {
+            let reports = [
+                Message::status_report(control.status_report()),
+                Message::metrics(control.metrics()),
+            ];
+
+            for msg in reports
+                .into_iter()
+                .filter(|msg| control.protocol_version.supports(msg))
+            {
+                if let Err(e) = framed.send(msg).await {
+                    error!("Failed to send report: {e:?}");
                 }
-                Some(_) | None => {}
             }
+            last_report = control.clock.now();
+            last_activity = control.clock.now();
+        }
+
+        if let Some(ExchangeEvent::OrderFill(fill)) =
+            control.exchange_rx.as_ref().and_then(|rx| rx.try_pop())
+        {
+            let msg = Message::fill(fill);
+
+            if let Err(e) = framed.send(msg.clone()).await {
+                error!("Failed to send fill: {e:?}");
+                control.unsent = Some(msg);
+
+                return Err(EngineError::with_source(ControlEngineError {
+                    msg: "Failed to send fill to botvana-server",
+                }));
+            }
+            last_activity = control.clock.now();
         }
 
         let msg =
@@ -94,6 +222,45 @@ pub(crate) async fn run_control_loop(
         // Check if the stream has yielded a value
         match msg {
             Ok(None) => return Ok(()),
+            Ok(Some(Ok(Message::Capabilities(capabilities)))) => {
+                info!("botvana-server capabilities = {capabilities:?}");
+                control.capabilities = capabilities.intersection(Capabilities::SUPPORTED);
+
+                if let Err(e) = framed
+                    .send(Message::capabilities(Capabilities::SUPPORTED))
+                    .await
+                {
+                    error!("Failed to send capabilities: {e:?}");
+                }
+            }
+            Ok(Some(Ok(Message::Pong(server_time)))) => {
+                // Align the clock with botvana-server
+                if let Some(sent_at) = ping_sent_at.take() {
+                    let offset =
+                        estimate_offset_nanos(sent_at, server_time, control.clock.unix_nanos());
+                    control
+                        .clock
+                        .set_offset_nanos(control.clock.offset_nanos() + offset);
+                    debug!("botvana-server clock offset = {offset}ns");
+                }
+            }
+            Ok(Some(Ok(Message::ConfigUpdate(version, bot_config)))) => {
+                info!("received configuration version {version}");
+                control.apply_configuration(bot_config);
+
+                if let Err(e) = framed.send(Message::config_ack(version)).await {
+                    error!("Failed to acknowledge configuration: {e:?}");
+                }
+            }
+            Ok(Some(Ok(Message::Command(id, command)))) => {
+                info!("received command {id} {command:?}");
+
+                if let Some(result) = control.execute_command(id, command) {
+                    if let Err(e) = framed.send(Message::command_result(id, result)).await {
+                        error!("Failed to send command result: {e:?}");
+                    }
+                }
+            }
             Ok(msg) => {
                 debug!("got msg from botvana-server: {msg:?}");
             }
@@ -102,23 +269,51 @@ pub(crate) async fn run_control_loop(
     }
 }
 
-/// Opens a connection to botvana server
+/// Opens a connection to botvana server and authenticates
 async fn connect_botvana_server(
     control: &mut ControlEngine,
-) -> Result<Framed<TcpStream, BotvanaCodec>, EngineError> {
+) -> Result<Framed<MaybeTlsStream<TcpStream>, BotvanaCodec>, EngineError> {
     control.status = BotnodeStatus::Connecting;
 
     let stream = TcpStream::connect(control.server_addr.clone())
         .await
         .map_err(EngineError::with_source)?;
+    let stream = match &control.tls {
+        Some((config, server_name)) => {
+            let stream = TlsConnector::from(config.clone())
+                .connect(server_name.clone(), stream)
+                .await
+                .map_err(EngineError::with_source)?;
 
-    let mut framed = Framed::new(stream, BotvanaCodec);
+            MaybeTlsStream::Client(Box::new(stream))
+        }
+        None => MaybeTlsStream::Plain(stream),
+    };
+
+    let codec = BotvanaCodec::new().with_max_frame_size(control.max_frame_size);
+    control.protocol_version = codec.negotiated_version();
+    let mut framed = Framed::new(stream, codec);
 
     let msg = Message::hello(control.bot_id.clone());
     if let Err(e) = framed.send(msg).await {
         error!("Error framing the message: {e:?}");
     }
 
+    let nonce = match framed.next().await {
+        Some(Ok(Message::AuthChallenge(nonce))) => nonce,
+        Some(Err(e)) => return Err(EngineError::with_source(e)),
+        _ => {
+            return Err(EngineError::with_source(ControlEngineError {
+                msg: "Expected authentication challenge from botvana-server",
+            }))
+        }
+    };
+
+    let msg = Message::auth_response(control.secret.as_bytes(), &control.bot_id, &nonce);
+    if let Err(e) = framed.send(msg).await {
+        error!("Error framing the message: {e:?}");
+    }
+
     Ok(framed)
 }
 
@@ -138,15 +333,15 @@ fn process_bot_configuration<E: 'static + std::error::Error>(
                 control.status = BotnodeStatus::Online;
             }
 
-            debug!("config = {bot_config:?}");
-
-            control.bot_configuration = Some(bot_config.clone());
-
-            control
-                .spawn_engines(bot_config.clone(), shutdown.clone())
-                .unwrap();
+            // Engines keep running across reconnects, the configuration
+            // received after reconnecting is applied like an update
+            if control.bot_configuration.is_none() {
+                control.spawn_engines(bot_config.clone(), shutdown.clone())?;
+            } else {
+                control.server_reconnects += 1;
+            }
 
-            control.push_value(bot_config);
+            control.apply_configuration(bot_config);
         }
         Some(Err(e)) => {
             return Err(EngineError::with_source(e));
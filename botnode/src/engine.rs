@@ -1,35 +1,6 @@
 use crate::prelude::*;
-use botvana::exchange::ExchangeId;
-
-/// Botnode engines type
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
-pub enum EngineType {
-    AuditEngine,
-    ControlEngine,
-    ExchangeEngine,
-    IndicatorEngine,
-    MarketDataEngine(ExchangeId),
-    TradingEngine,
-}
-
-/// Engine status
-#[derive(Clone, Debug)]
-pub enum EngineStatus {
-    /// The engine is booting
-    Booting,
-    /// The engine is running and operating as expected
-    Running,
-    /// The engine is shutting down
-    ShuttingDown,
-    /// Engine has encountered error and shut down
-    Error,
-}
 
-impl Default for EngineStatus {
-    fn default() -> Self {
-        Self::Booting
-    }
-}
+pub use botvana::engine::{EngineStatus, EngineType};
 
 /// Engine trait
 #[async_trait(?Send)]
@@ -40,6 +11,13 @@ pub trait Engine {
     /// Returns engine health receiver
     fn status_rx(&self) -> spsc_queue::Consumer<EngineStatus>;
 
+    /// Returns receiver of the engine loop heartbeats
+    ///
+    /// Engines without heartbeats aren't monitored for stalls.
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        None
+    }
+
     /// Start the engine loop
     async fn start(self, shutdown: Shutdown) -> Result<(), EngineError>;
 }
@@ -121,9 +99,7 @@ pub fn spawn_engine<E: Engine + Send + 'static>(
     engine: E,
     shutdown: Shutdown,
 ) -> Result<glommio::ExecutorJoinHandle<()>, StartEngineError> {
-    LocalExecutorBuilder::new(Placement::Fixed(cpu))
-        .spin_before_park(std::time::Duration::from_micros(250))
-        .name(&engine.name())
+    engine_executor(cpu, &engine.name())
         .spawn(move || async move {
             match engine.start(shutdown).await {
                 Ok(_handle) => {}
@@ -135,12 +111,23 @@ pub fn spawn_engine<E: Engine + Send + 'static>(
         .map_err(StartEngineError::from)
 }
 
+/// Returns builder of executor for the named engine pinned to given CPU
+pub(crate) fn engine_executor(cpu: usize, name: &str) -> LocalExecutorBuilder {
+    LocalExecutorBuilder::new(Placement::Fixed(cpu))
+        .spin_before_park(std::time::Duration::from_micros(250))
+        .name(name)
+}
+
 /// Awaits until a value is produced on a given spsc_queue channel
-pub fn await_value<T>(rx: spsc_queue::Consumer<T>) -> T {
+///
+/// Spins on the queue, yielding to other tasks of the executor when it's due.
+pub async fn await_value<T>(rx: &spsc_queue::Consumer<T>) -> T {
     loop {
         if let Some(config) = rx.try_pop() {
             break config;
         }
+
+        glommio::yield_if_needed().await;
     }
 }
 
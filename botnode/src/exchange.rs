@@ -3,21 +3,29 @@ pub(crate) mod engine;
 pub(crate) mod error;
 pub(crate) mod ftx;
 pub(crate) mod null_adapter;
-pub(crate) mod order_request;
+pub mod order_request;
 pub(crate) mod order_response;
 
+use serde::{Deserialize, Serialize};
+
+use botvana::exchange::Fill;
+
 /// Event generated by an exchange - order or balance related
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum ExchangeEvent {
     BalanceChange,
     OrderRejected,
     OrderAck,
-    OrderFill,
+    OrderFill(Fill),
     OrderCancelled,
 }
 
-#[derive(Clone, Debug)]
+/// Request sent to an exchange engine
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum ExchangeRequest {
     PlaceOrder(order_request::OrderRequest),
-    CancelOrder,
+    /// Cancels order with given client order id
+    CancelOrder(u64),
+    /// Cancels all open orders
+    CancelAll,
 }
@@ -8,6 +8,7 @@ pub struct ExchangeEngine<A> {
     _request_rx: spsc_queue::Consumer<super::ExchangeRequest>,
     status_tx: spsc_queue::Producer<EngineStatus>,
     status_rx: spsc_queue::Consumer<EngineStatus>,
+    heartbeat: Heartbeat,
 }
 
 impl<A: ExchangeAdapter> ExchangeEngine<A> {
@@ -23,6 +24,7 @@ impl<A: ExchangeAdapter> ExchangeEngine<A> {
             _request_rx: request_rx,
             status_tx,
             status_rx,
+            heartbeat: Heartbeat::new(),
         }
     }
 }
@@ -37,15 +39,19 @@ impl<A: ExchangeAdapter> Engine for ExchangeEngine<A> {
         self.status_rx.clone()
     }
 
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        Some(self.heartbeat.rx())
+    }
+
     async fn start(self, shutdown: Shutdown) -> Result<(), EngineError> {
         info!("Starting order engine");
 
         self.status_tx.try_push(EngineStatus::Booting);
 
-        let config = await_value(self.config_rx);
+        let config = await_value(&self.config_rx).await;
         info!("got config = {config:?}");
 
-        run_event_loop(self.status_tx, shutdown)?;
+        run_event_loop(self.config_rx, self.status_tx, self.heartbeat, shutdown).await?;
 
         Ok(())
     }
@@ -57,15 +63,25 @@ impl<A: ExchangeAdapter> EngineData for ExchangeEngine<A> {
 }
 
 /// Runs the order event loop
-fn run_event_loop(
+async fn run_event_loop(
+    config_rx: spsc_queue::Consumer<BotConfiguration>,
     status_tx: spsc_queue::Producer<EngineStatus>,
+    heartbeat: Heartbeat,
     shutdown: Shutdown,
 ) -> Result<(), EngineError> {
     status_tx.try_push(EngineStatus::Running);
 
     loop {
+        heartbeat.tick();
+        glommio::yield_if_needed().await;
+
         if shutdown.shutdown_started() {
             break Ok(());
         }
+
+        if let Some(config) = config_rx.try_pop() {
+            info!("got updated config = {config:?}");
+            heartbeat.event();
+        }
     }
 }
@@ -1,2 +1,15 @@
-#[derive(Clone, Debug)]
-pub struct OrderRequest;
+use serde::{Deserialize, Serialize};
+
+use botvana::exchange::{ExchangeId, Side};
+
+/// Request to place a limit order
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OrderRequest {
+    /// Client assigned order id
+    pub order_id: u64,
+    pub exchange: ExchangeId,
+    pub market: Box<str>,
+    pub side: Side,
+    pub price: f64,
+    pub size: f64,
+}
@@ -0,0 +1,224 @@
+//! Engine heartbeats
+//!
+//! Engine loops tick their [`Heartbeat`] every iteration and mark the events
+//! they process. Heartbeats are sent to the control engine every
+//! [`HEARTBEAT_INTERVAL`] and its [`HeartbeatMonitor`] declares engines that
+//! haven't sent one for [`STALL_TIMEOUT`] stalled. Heartbeats are sent from
+//! the engine loop itself, so engines stuck outside of it, e.g. waiting for
+//! configuration in [`await_value`], stop sending them.
+
+use std::{cell::Cell, time::Instant};
+
+use crate::prelude::*;
+use botvana::engine::EngineHealth;
+
+/// Interval of sending heartbeats
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Engine that hasn't sent heartbeat for this long is stalled
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Heartbeat sent by an engine loop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeartbeatReport {
+    /// Loop iterations since the engine started
+    pub iterations: u64,
+    /// When the engine last processed an event
+    pub last_event: Option<Instant>,
+}
+
+/// Heartbeat of an engine loop
+///
+/// Ticking only takes `&self`, so the heartbeat can be shared by the futures
+/// of the engine.
+#[derive(Debug)]
+pub struct Heartbeat {
+    tx: spsc_queue::Producer<HeartbeatReport>,
+    rx: spsc_queue::Consumer<HeartbeatReport>,
+    iterations: Cell<u64>,
+    last_event: Cell<Option<Instant>>,
+    last_sent: Cell<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        let (tx, rx) = spsc_queue::make(1);
+
+        Self {
+            tx,
+            rx,
+            iterations: Cell::new(0),
+            last_event: Cell::new(None),
+            last_sent: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Returns receiver of the heartbeats
+    pub fn rx(&self) -> spsc_queue::Consumer<HeartbeatReport> {
+        self.rx.clone()
+    }
+
+    /// Counts loop iteration and sends heartbeat when it's due
+    pub fn tick(&self) {
+        let iterations = self.iterations.get() + 1;
+        self.iterations.set(iterations);
+
+        let now = Instant::now();
+        if now.duration_since(self.last_sent.get()) < HEARTBEAT_INTERVAL {
+            return;
+        }
+        self.last_sent.set(now);
+
+        // Full queue means the previous heartbeat wasn't received yet, which
+        // is as good as this one for the monitor
+        self.tx.try_push(HeartbeatReport {
+            iterations,
+            last_event: self.last_event.get(),
+        });
+    }
+
+    /// Records that the engine processed an event
+    pub fn event(&self) {
+        self.last_event.set(Some(Instant::now()));
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Monitored engine
+struct Monitored {
+    heartbeat_rx: spsc_queue::Consumer<HeartbeatReport>,
+    /// When the last heartbeat or monitoring started
+    last_heartbeat: Instant,
+    report: Option<HeartbeatReport>,
+    stalled: bool,
+}
+
+/// Monitor of engine heartbeats
+#[derive(Default)]
+pub struct HeartbeatMonitor {
+    engines: HashMap<EngineType, Monitored>,
+}
+
+impl HeartbeatMonitor {
+    /// Starts monitoring heartbeats received on `heartbeat_rx`, replacing
+    /// the previous receiver of the engine
+    pub fn monitor(
+        &mut self,
+        engine: EngineType,
+        heartbeat_rx: spsc_queue::Consumer<HeartbeatReport>,
+    ) {
+        self.engines.insert(
+            engine,
+            Monitored {
+                heartbeat_rx,
+                last_heartbeat: Instant::now(),
+                report: None,
+                stalled: false,
+            },
+        );
+    }
+
+    /// Stops monitoring the engine
+    pub fn remove(&mut self, engine: &EngineType) {
+        self.engines.remove(engine);
+    }
+
+    /// Receives heartbeats and returns health of engines that stalled or
+    /// recovered since the last poll
+    pub fn poll(&mut self) -> Vec<EngineHealth> {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&mut self, now: Instant) -> Vec<EngineHealth> {
+        let mut changes = Vec::new();
+
+        for (engine, monitored) in self.engines.iter_mut() {
+            if let Some(report) = monitored.heartbeat_rx.try_pop() {
+                monitored.last_heartbeat = now;
+                monitored.report = Some(report);
+            }
+
+            let stalled = now.saturating_duration_since(monitored.last_heartbeat) >= STALL_TIMEOUT;
+            if stalled == monitored.stalled {
+                continue;
+            }
+            monitored.stalled = stalled;
+
+            let health = monitored.health(engine, now);
+            if stalled {
+                error!("{engine:?} stalled: {health:?}");
+            } else {
+                info!("{engine:?} recovered: {health:?}");
+            }
+            changes.push(health);
+        }
+
+        changes
+    }
+
+    /// Returns health of all monitored engines
+    pub fn health(&self) -> Vec<EngineHealth> {
+        let now = Instant::now();
+
+        self.engines
+            .iter()
+            .map(|(engine, monitored)| monitored.health(engine, now))
+            .collect()
+    }
+}
+
+impl Monitored {
+    fn health(&self, engine: &EngineType, now: Instant) -> EngineHealth {
+        let last_event = self.report.and_then(|report| report.last_event);
+
+        EngineHealth {
+            engine: engine.clone(),
+            stalled: self.stalled,
+            iterations: self.report.map_or(0, |report| report.iterations),
+            heartbeat_age_ms: now
+                .saturating_duration_since(self.last_heartbeat)
+                .as_millis() as u64,
+            last_event_age_ms: last_event
+                .map(|last_event| now.saturating_duration_since(last_event).as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_monitor() {
+        let heartbeat = Heartbeat::new();
+        let mut monitor = HeartbeatMonitor::default();
+
+        monitor.monitor(EngineType::TradingEngine, heartbeat.rx());
+        let start = Instant::now();
+        assert!(monitor.poll_at(start).is_empty());
+
+        let stalled = monitor.poll_at(start + STALL_TIMEOUT);
+        assert_eq!(stalled.len(), 1);
+        assert!(stalled[0].stalled);
+        assert_eq!(stalled[0].iterations, 0);
+        assert!(monitor.poll_at(start + STALL_TIMEOUT * 2).is_empty());
+
+        heartbeat.event();
+        heartbeat.last_sent.set(start - HEARTBEAT_INTERVAL);
+        heartbeat.tick();
+
+        let recovered = monitor.poll_at(start + STALL_TIMEOUT * 3);
+        assert_eq!(recovered.len(), 1);
+        assert!(!recovered[0].stalled);
+        assert_eq!(recovered[0].iterations, 1);
+        assert_eq!(recovered[0].heartbeat_age_ms, 0);
+        assert!(recovered[0].last_event_age_ms.is_some());
+
+        monitor.remove(&EngineType::TradingEngine);
+        assert!(monitor.health().is_empty());
+    }
+}
@@ -9,14 +9,17 @@ pub struct IndicatorEngine {
     data_txs: ArrayVec<spsc_queue::Producer<IndicatorEvent>, CONSUMER_LIMIT>,
     indicators_config: Box<[IndicatorConfig]>,
     market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    clock: SharedClock,
     status_tx: spsc_queue::Producer<EngineStatus>,
     status_rx: spsc_queue::Consumer<EngineStatus>,
+    heartbeat: Heartbeat,
 }
 
 impl IndicatorEngine {
     pub fn new(
         config_rx: spsc_queue::Consumer<BotConfiguration>,
         market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+        clock: SharedClock,
     ) -> Self {
         let (status_tx, status_rx) = spsc_queue::make(1);
         Self {
@@ -24,8 +27,10 @@ impl IndicatorEngine {
             data_txs: ArrayVec::<_, CONSUMER_LIMIT>::new(),
             indicators_config: Box::new([]),
             market_data_rxs,
+            clock,
             status_tx,
             status_rx,
+            heartbeat: Heartbeat::new(),
         }
     }
 }
@@ -40,16 +45,29 @@ impl Engine for IndicatorEngine {
         self.status_rx.clone()
     }
 
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        Some(self.heartbeat.rx())
+    }
+
     async fn start(mut self, shutdown: Shutdown) -> Result<(), EngineError> {
         info!("Starting indicator engine");
 
         self.status_tx.try_push(EngineStatus::Booting);
 
-        let config = await_value(self.config_rx);
+        let config = await_value(&self.config_rx).await;
         debug!("config = {config:?}");
         self.indicators_config = config.indicators;
 
-        super::event_loop::run_indicator_loop(self.market_data_rxs, self.status_tx, shutdown).await
+        super::event_loop::run_indicator_loop(
+            self.config_rx,
+            self.indicators_config,
+            self.market_data_rxs,
+            self.clock,
+            self.status_tx,
+            self.heartbeat,
+            shutdown,
+        )
+        .await
     }
 }
 
@@ -24,9 +24,15 @@ impl IndicatorState {
 }
 
 /// Indicator engine loop
+///
+/// Indicators are replaced when updated configuration is received.
 pub async fn run_indicator_loop(
+    config_rx: spsc_queue::Consumer<BotConfiguration>,
+    mut indicators: Box<[IndicatorConfig]>,
     market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    clock: SharedClock,
     status_tx: spsc_queue::Producer<EngineStatus>,
+    heartbeat: Heartbeat,
     shutdown: Shutdown,
 ) -> Result<(), EngineError> {
     let _token = shutdown
@@ -35,19 +41,30 @@ pub async fn run_indicator_loop(
 
     let mut indicator_state = IndicatorState::default();
 
+    debug!("indicators = {indicators:?}");
+
     status_tx.try_push(EngineStatus::Running);
 
     loop {
+        heartbeat.tick();
+        glommio::yield_if_needed().await;
+
         if shutdown.shutdown_started() {
             info!("shutting down indicator engine");
 
             break Ok(());
         }
 
+        if let Some(config) = config_rx.try_pop() {
+            indicators = config.indicators;
+            info!("indicators updated = {indicators:?}");
+        }
+
         for (_, market_data_rx) in market_data_rxs.iter() {
             if let Some(event) = market_data_rx.try_pop() {
+                heartbeat.event();
                 //info!("market_event = {:?}", event);
-                if let Err(e) = process_market_event(event, &mut indicator_state) {
+                if let Err(e) = process_market_event(event, clock.as_ref(), &mut indicator_state) {
                     error!("Failed to process market event: {e}");
                 }
             }
@@ -58,6 +75,7 @@ pub async fn run_indicator_loop(
 /// Handles incoming [`MarketEvent`]
 fn process_market_event(
     event: MarketEvent,
+    clock: &dyn Clock,
     indicator_state: &mut IndicatorState,
 ) -> Result<(), EngineError> {
     match event.r#type {
@@ -66,16 +84,12 @@ fn process_market_event(
         }
         MarketEventType::Trades(market_symbol, trades) => {
             if !trades.is_empty() {
-                let diff = trades[0].received_at.elapsed();
+                let diff = clock.elapsed(trades[0].received_at);
                 trace!("{market_symbol} core latency = {} us", diff.as_micros());
             }
         }
         MarketEventType::OrderbookUpdate(market_symbol, orderbook) => {
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_micros() as f64
-                / 1_000_000.0;
+            let now = (clock.unix_nanos() / 1_000) as f64 / 1_000_000.0;
             let delay = now - orderbook.time;
             let bid = orderbook.bids.price_vec.last().unwrap_or(&0.0);
             let ask = orderbook.asks.price_vec.first().unwrap_or(&0.0);
@@ -1,11 +1,17 @@
 pub mod audit;
+pub mod backtest;
 pub mod channels;
 pub mod control;
 pub mod engine;
 pub mod error;
 pub mod exchange;
+pub mod heartbeat;
 pub mod indicator;
 pub mod market_data;
+pub mod metrics;
+pub mod recorder;
+pub mod supervisor;
+pub mod topology;
 pub mod trading;
 pub mod util;
 
@@ -30,7 +36,9 @@ pub mod prelude {
 
     pub use botvana::{
         cfg::{BotConfiguration, IndicatorConfig},
+        clock::{Clock, SharedClock},
         exchange::ExchangeId,
+        latency::{LatencyStage, LatencyTrace},
         market::{
             event::{MarketEvent, MarketEventType},
             orderbook::*,
@@ -46,6 +54,7 @@ pub mod prelude {
         channels::*,
         engine::*,
         error::{EngineError, StartEngineError},
+        heartbeat::{Heartbeat, HeartbeatReport},
         indicator::IndicatorEvent,
     };
 }
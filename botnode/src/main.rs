@@ -1,4 +1,9 @@
-use std::{env::var, panic};
+use std::{
+    env::var,
+    panic,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use async_shutdown::Shutdown;
 use futures::prelude::*;
@@ -6,21 +11,25 @@ use glommio::LocalExecutor;
 use signal_hook::consts::signal::*;
 use signal_hook_async_std::Signals;
 use tracing::{debug, error, info};
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter};
 
-use botnode::{control::engine::*, engine::*};
-use botvana::net::msg::BotId;
+use botnode::{control::engine::*, engine::*, metrics::spawn_metrics_server, supervisor};
+use botvana::{
+    clock::MonotonicClock,
+    net::{msg::BotId, tls},
+};
 
 #[global_allocator]
 static ALLOC: snmalloc_rs::SnMalloc = snmalloc_rs::SnMalloc;
 
 fn main() {
+    let (filter, log_filter) = reload::Layer::new(EnvFilter::from_default_env());
     tracing_subscriber::registry()
-        .with(EnvFilter::from_default_env())
+        .with(filter)
         .with(fmt::layer().with_thread_names(true))
         .init();
 
-    let (bot_id, server_addr) = load_configuration();
+    let (bot_id, server_addr, secret) = load_configuration();
 
     let shutdown = Shutdown::new();
 
@@ -28,6 +37,12 @@ fn main() {
         let shutdown = shutdown.clone();
 
         panic::set_hook(Box::new(move |p| {
+            // Supervisor restarts engines whose executor panicked
+            if supervisor::is_supervised_thread() {
+                error!("Panic in supervised engine executor: {p}");
+                return;
+            }
+
             error!("Panic coming from one of the threads, exiting");
             debug!("panic = {:?}", p);
             shutdown.shutdown();
@@ -37,9 +52,35 @@ fn main() {
     // Start the control engine that will connect to botvana-server and
     // receive the configuration. Then the control engine spawns other engines
     // based on the configuration it recieves.
-    let control_engine = ControlEngine::new(bot_id, server_addr);
+    let tls = load_tls_configuration(&server_addr);
+    let mut control_engine =
+        ControlEngine::new(bot_id, server_addr, secret, Arc::new(MonotonicClock::new()))
+            .with_log_filter(log_filter);
+
+    if let Some((config, server_name)) = tls {
+        control_engine = control_engine.with_tls(config, server_name);
+    }
+
+    // Write audit journal when JOURNAL_DIR is given
+    if let Ok(dir) = var("JOURNAL_DIR") {
+        control_engine = control_engine.with_journal(PathBuf::from(dir));
+    }
+
+    // Limit size of frames from botvana-server when MAX_FRAME_SIZE is given
+    if let Ok(size) = var("MAX_FRAME_SIZE") {
+        let size = size
+            .parse()
+            .expect("MAX_FRAME_SIZE must be number of bytes");
+        control_engine = control_engine.with_max_frame_size(size);
+    }
+
     spawn_engine(0, control_engine, shutdown.clone()).expect("failed to start control engine");
 
+    // Serve Prometheus metrics when METRICS_ADDR is given
+    if let Ok(addr) = var("METRICS_ADDR") {
+        spawn_metrics_server(addr).expect("failed to start metrics server");
+    }
+
     // Setup signal handlers for shutdown
     let signals = Signals::new(&[SIGINT, SIGTERM, SIGQUIT]).expect("Failed to register signals");
     let local_ex = LocalExecutor::default();
@@ -48,9 +89,9 @@ fn main() {
 
 /// Loads configuration from ENV variables
 ///
-/// Panics if the BOT_ID or SERVER_ADDR variables are missing or
+/// Panics if the BOT_ID, SERVER_ADDR or BOT_SECRET variables are missing or
 /// BOT_ID can't be parsed as u16 number.
-fn load_configuration() -> (BotId, String) {
+fn load_configuration() -> (BotId, String, Box<str>) {
     let bot_id = var("BOT_ID")
         .expect("Please specify BOT_ID")
         .parse::<BotId>()
@@ -59,8 +100,43 @@ fn load_configuration() -> (BotId, String) {
     info!("bot_id = {}", bot_id.0);
 
     let server_addr = var("SERVER_ADDR").expect("Please specify SERVER_ADDR");
+    let secret = var("BOT_SECRET").expect("Please specify BOT_SECRET");
 
-    (bot_id, server_addr)
+    (bot_id, server_addr, secret.into_boxed_str())
+}
+
+/// Loads TLS configuration from ENV variables
+///
+/// TLS is enabled by SERVER_TLS_CA with CA certificate of the server.
+/// BOT_TLS_CERT and BOT_TLS_KEY give the client certificate when the server
+/// requires one. The server certificate is verified against SERVER_TLS_NAME,
+/// defaulting to host of the server address.
+fn load_tls_configuration(
+    server_addr: &str,
+) -> Option<(Arc<tls::rustls::ClientConfig>, tls::ServerName)> {
+    let ca = var("SERVER_TLS_CA").ok()?;
+    let cert = var("BOT_TLS_CERT").ok();
+    let key = var("BOT_TLS_KEY").ok();
+    let identity = match (&cert, &key) {
+        (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+        (None, None) => None,
+        _ => panic!("Both BOT_TLS_CERT and BOT_TLS_KEY must be specified"),
+    };
+    let config =
+        tls::client_config(Path::new(&ca), identity).expect("Failed to load TLS configuration");
+
+    let server_name = var("SERVER_TLS_NAME").unwrap_or_else(|_| {
+        server_addr
+            .rsplit_once(':')
+            .map_or(server_addr, |(host, _)| host)
+            .to_string()
+    });
+    let server_name = tls::ServerName::try_from(server_name.as_str())
+        .expect("SERVER_TLS_NAME must be valid DNS name or IP address");
+
+    info!("connecting over TLS, server name = {server_name:?}");
+
+    Some((config, server_name))
 }
 
 /// Handles shutdown signals from OS
@@ -1,7 +1,10 @@
 // Core market data modules
 pub mod adapter;
+pub mod arbiter;
+pub mod capture;
 pub mod engine;
 pub mod error;
+pub mod stats;
 
 // Exchange adapters
 pub mod binance;
@@ -22,5 +25,5 @@ mod prelude {
     pub use serde_json::json;
     pub use surf::Url;
 
-    pub use crate::market_data::{adapter::*, error::*};
+    pub use crate::market_data::{adapter::*, arbiter::*, error::*};
 }
@@ -2,12 +2,27 @@
 //!
 //! This module defines market data adapter traits that when implemented allow
 //! the market data engine to operate on any exchange.
+//!
+//! Markets can be split across several exchange connections to stay within
+//! exchange subscription limits. Each connection reconnects on its own and
+//! all of them push onto the same queues, so consumers see a single stream of
+//! market events.
+//!
+//! Each connection can also be opened on several redundant feeds with
+//! identical subscriptions, the [`FeedArbiter`] of the connection forwards
+//! whichever copy of a message arrives first. Adapters arbitrate messages
+//! while parsing them, see [`WsMarketDataAdapter::process_feed_msg`].
+
+use std::fmt;
 
 use async_tungstenite::{async_std::connect_async, tungstenite::Message};
 use glommio::timer::sleep;
 
-use crate::{market_data::prelude::*, prelude::*};
-use botvana::{exchange::ExchangeId, market::MarketVec};
+use crate::{
+    market_data::{capture::*, prelude::*, stats::MarketDataStats},
+    prelude::*,
+};
+use botvana::{cfg::ReplayConfig, clock::Clock, exchange::ExchangeId, market::MarketVec};
 
 /// Market data adapter trait
 #[async_trait(?Send)]
@@ -18,19 +33,97 @@ pub trait MarketDataAdapter<const TX_CAP: usize> {
     /// Fetches and returns markets information
     async fn fetch_markets(&self) -> Result<Box<MarketVec>, MarketDataError>;
 
+    /// Returns clock used to timestamp market events
+    fn clock(&self) -> &dyn Clock;
+
+    /// Splits markets into the markets subscribed on each exchange connection
+    fn connections<'a>(
+        &self,
+        markets: &'a [&'a str],
+        markets_per_connection: Option<usize>,
+    ) -> Vec<&'a [&'a str]> {
+        split_markets(markets, markets_per_connection)
+    }
+
+    /// Returns number of redundant feeds of each connection
+    fn feeds(&self, feeds: usize) -> usize {
+        feeds.max(1)
+    }
+
     /// Runs the adapter event loop
+    ///
+    /// Each connection subscribes at most `markets_per_connection` markets
+    /// and is opened on `feeds` redundant feeds. Connections reconnect on
+    /// network errors, the loop returns the first fatal error, e.g. when
+    /// market events can't be pushed to the consumers.
     async fn run_loop(
-        &mut self,
-        data_txs: crate::channels::ProducersArray<MarketEvent, TX_CAP>,
+        &self,
+        data_txs: &crate::channels::ProducersArray<MarketEvent, TX_CAP>,
         markets: &[&str],
+        markets_per_connection: Option<usize>,
+        feeds: usize,
+        stats: &RefCell<MarketDataStats>,
+        shutdown: Shutdown,
+    ) -> Result<(), MarketDataError> {
+        let connections = self.connections(markets, markets_per_connection);
+        let feeds = self.feeds(feeds);
+
+        if connections.len() > 1 {
+            info!(
+                "splitting {} markets across {} connections",
+                markets.len(),
+                connections.len()
+            );
+        }
+        if feeds > 1 {
+            info!("opening each connection on {feeds} redundant feeds");
+        }
+
+        let arbiters: Vec<_> = connections
+            .iter()
+            .map(|_| RefCell::new(FeedArbiter::default()))
+            .collect();
+        let shutdown = &shutdown;
+
+        futures::future::try_join_all(connections.iter().zip(&arbiters).enumerate().flat_map(
+            |(id, (markets, arbiter))| {
+                (0..feeds).map(move |feed| {
+                    let connection = Connection {
+                        id,
+                        feed,
+                        markets,
+                        arbiter: if feeds > 1 { Some(arbiter) } else { None },
+                    };
+
+                    self.run_connection(connection, data_txs, stats, shutdown.clone())
+                })
+            },
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Runs the exchange connection, reconnecting until shutdown
+    ///
+    /// Returns fatal errors, which stop all connections of the adapter.
+    async fn run_connection(
+        &self,
+        connection: Connection<'_>,
+        data_txs: &crate::channels::ProducersArray<MarketEvent, TX_CAP>,
+        stats: &RefCell<MarketDataStats>,
         shutdown: Shutdown,
     ) -> Result<(), MarketDataError> {
         loop {
-            if let Err(e) = self
-                .run_exchange_connection_loop(&data_txs, &markets, shutdown.clone())
-                .await
-            {
-                error!("Error running exchange connection loop: {e}");
+            let result = self
+                .run_exchange_connection_loop(&connection, data_txs, stats, shutdown.clone())
+                .await;
+            connection.disconnected();
+
+            match result {
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(e) => error!("Error running exchange connection {connection} loop: {e}"),
+                Ok(_) => {}
             }
 
             if shutdown.shutdown_started() {
@@ -38,26 +131,86 @@ pub trait MarketDataAdapter<const TX_CAP: usize> {
             }
 
             let wait = Duration::from_secs(5);
-            warn!("disconnected from the exchange; waiting for {wait:?}");
+            warn!("connection {connection} disconnected from the exchange; waiting for {wait:?}");
+            stats.borrow_mut().record_reconnect();
             sleep(wait).await;
         }
     }
 
     /// Runs the exchange connection event loop
     async fn run_exchange_connection_loop(
-        &mut self,
+        &self,
+        connection: &Connection<'_>,
         data_txs: &crate::channels::ProducersArray<MarketEvent, TX_CAP>,
-        markets: &[&str],
+        stats: &RefCell<MarketDataStats>,
         shutdown: Shutdown,
     ) -> Result<Option<MarketEvent>, MarketDataError>;
 }
 
+/// Exchange connection of the market data adapter
+#[derive(Clone, Copy, Debug)]
+pub struct Connection<'a> {
+    /// Index of the connection
+    pub id: usize,
+    /// Index of the redundant feed of the connection
+    pub feed: usize,
+    /// Markets subscribed on the connection
+    pub markets: &'a [&'a str],
+    /// Arbiter shared by the redundant feeds, `None` without redundancy
+    pub arbiter: Option<&'a RefCell<FeedArbiter>>,
+}
+
+impl<'a> Connection<'a> {
+    /// Returns the feed of the connection
+    pub fn feed(&self) -> Feed<'a> {
+        Feed::new(self.feed, self.arbiter)
+    }
+
+    /// Records that the connection subscribed its markets
+    pub fn connected(&self) {
+        if let Some(arbiter) = self.arbiter {
+            arbiter.borrow_mut().connect(self.feed);
+        }
+    }
+
+    /// Records that the connection disconnected
+    pub fn disconnected(&self) {
+        if let Some(arbiter) = self.arbiter {
+            arbiter.borrow_mut().disconnect(self.feed);
+        }
+    }
+}
+
+impl fmt::Display for Connection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.arbiter {
+            Some(_) => write!(f, "{}/{}", self.id, self.feed),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+/// Splits markets into chunks of at most `markets_per_connection` markets,
+/// one chunk with all markets when it's not set
+fn split_markets<'a>(
+    markets: &'a [&'a str],
+    markets_per_connection: Option<usize>,
+) -> Vec<&'a [&'a str]> {
+    match markets_per_connection {
+        Some(limit) if limit > 0 && !markets.is_empty() => markets.chunks(limit).collect(),
+        _ => vec![markets],
+    }
+}
+
 /// Websocket adapter for market data
 pub trait WsMarketDataAdapter {
     fn ws_url(&self) -> Box<str>;
 
+    /// Returns clock used to timestamp market events
+    fn clock(&self) -> &dyn Clock;
+
     /// Returns set of subscribe messages to send to subscribe to given markets
-    fn subscribe_msgs(&mut self, markets: &[&str]) -> Box<[String]>;
+    fn subscribe_msgs(&self, markets: &[&str]) -> Box<[String]>;
 
     /// Returns throughput metrics
     fn throughput_metrics(&self) -> &Throughput<StdInstant, RefCell<metered::common::TxPerSec>>;
@@ -67,7 +220,32 @@ pub trait WsMarketDataAdapter {
         &self,
         msg: &str,
         markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+    ) -> Result<Option<MarketEvent>, MarketDataError> {
+        self.process_feed_msg(msg, markets, &mut Feed::single())
+    }
+
+    /// Processes Websocket text message received on the feed
+    ///
+    /// The sequence number of the parsed message is arbitrated by the feed,
+    /// messages without sequence number are arbitrated with `None`. Messages
+    /// that aren't forwarded only update the orderbooks.
+    fn process_feed_msg(
+        &self,
+        msg: &str,
+        markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+        feed: &mut Feed<'_>,
     ) -> Result<Option<MarketEvent>, MarketDataError>;
+
+    /// Returns directory raw websocket messages are captured to
+    fn capture_dir(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns replay settings when the adapter should replay captured
+    /// messages instead of connecting to the exchange
+    fn replay(&self) -> Option<&ReplayConfig> {
+        None
+    }
 }
 
 /// REST-API market data adapter
@@ -100,38 +278,109 @@ where
         Ok(Box::new(boxed_markets.into()))
     }
 
+    fn clock(&self) -> &dyn Clock {
+        <T as WsMarketDataAdapter>::clock(self)
+    }
+
+    /// Splits markets across connections, captured messages of all markets
+    /// are replayed on one connection
+    fn connections<'a>(
+        &self,
+        markets: &'a [&'a str],
+        markets_per_connection: Option<usize>,
+    ) -> Vec<&'a [&'a str]> {
+        match self.replay() {
+            Some(_) => vec![markets],
+            None => split_markets(markets, markets_per_connection),
+        }
+    }
+
+    /// Captured messages are replayed on one feed
+    fn feeds(&self, feeds: usize) -> usize {
+        match self.replay() {
+            Some(_) => 1,
+            None => feeds.max(1),
+        }
+    }
+
     /// Runs the exchange connection event loop
     async fn run_exchange_connection_loop(
-        &mut self,
+        &self,
+        connection: &Connection<'_>,
         data_txs: &crate::channels::ProducersArray<MarketEvent, TX_CAP>,
-        markets: &[&str],
+        stats: &RefCell<MarketDataStats>,
         shutdown: Shutdown,
     ) -> Result<Option<MarketEvent>, MarketDataError> {
         let _token = shutdown
             .delay_shutdown_token()
             .map_err(MarketDataError::with_source)?;
+        let mut orderbooks: HashMap<Box<str>, PlainOrderbook<_>> = connection
+            .markets
+            .iter()
+            .map(|m| (Box::from(*m), PlainOrderbook::with_capacity(100)))
+            .collect();
+
+        if let Some(replay) = self.replay().cloned() {
+            info!("replaying websocket capture {}", replay.path);
+            replay_capture(self, &replay, data_txs, &mut orderbooks, stats, &shutdown).await?;
+            info!("websocket capture replay finished");
+
+            shutdown.wait_shutdown_triggered().await;
+            return Ok(None);
+        }
+
         let url = self.ws_url();
         info!("connecting to {}", url);
         let (mut ws_stream, _) = connect_async(url.to_string())
             .await
             .map_err(MarketDataError::with_source)?;
 
-        for msg in self.subscribe_msgs(&markets).iter() {
+        for msg in self.subscribe_msgs(connection.markets).iter() {
             info!("sending = {}", msg);
             ws_stream
                 .send(Message::text(msg))
                 .await
                 .map_err(MarketDataError::with_source)?;
         }
+        connection.connected();
 
-        let mut markets: HashMap<Box<str>, PlainOrderbook<_>> = markets
-            .iter()
-            .map(|m| (Box::from(*m), PlainOrderbook::with_capacity(100)))
-            .collect();
+        // Snapshots are taken after subscribing so no update is missed
+        // between them and the stream
+        for market in connection.markets {
+            let snapshot = self.fetch_orderbook_snapshot(market).await?;
+            orderbooks.insert(Box::from(*market), snapshot);
+        }
+
+        let mut capture = match self.capture_dir() {
+            Some(dir) => {
+                let mut exchange = <T as RestMarketDataAdapter>::EXCHANGE_REF
+                    .to_string()
+                    .to_lowercase();
+                if connection.id > 0 {
+                    exchange = format!("{exchange}-{}", connection.id);
+                }
+                if connection.feed > 0 {
+                    exchange = format!("{exchange}-feed{}", connection.feed);
+                }
+
+                match CaptureWriter::create(dir, &exchange) {
+                    Ok(writer) => {
+                        info!("capturing websocket messages to {:?}", writer.path());
+                        Some(writer)
+                    }
+                    Err(e) => {
+                        warn!("Failed to create websocket capture file: {e}");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
         let mut start = std::time::Instant::now();
         let throughput = self.throughput_metrics();
+        let mut feed = connection.feed();
 
-        info!("markets = {:?}", markets);
+        info!("markets = {:?}", orderbooks);
 
         loop {
             if shutdown.shutdown_started() {
@@ -140,17 +389,28 @@ where
             }
 
             let msg = ws_stream.next().await;
+            let clock = <T as WsMarketDataAdapter>::clock(self);
+            let received_at = clock.instant();
+            let received_time = clock.now();
             measure!(throughput, {
                 match msg {
-                    Some(Ok(Message::Text(msg))) => match self.process_ws_msg(&msg, &mut markets) {
-                        Ok(Some(event)) => {
-                            data_txs
-                                .push_value(event)
-                                .map_err(MarketDataError::with_source)?;
+                    Some(Ok(Message::Text(msg))) => {
+                        if let Some(writer) = capture.as_mut() {
+                            if let Err(e) = writer.write(received_time, &msg) {
+                                warn!("Failed to capture websocket message, capture stopped: {e}");
+                                capture = None;
+                            }
                         }
-                        Ok(None) => {}
-                        Err(e) => warn!("Failed to process websocket message: {e}"),
-                    },
+
+                        process_text_msg(
+                            self,
+                            &msg,
+                            received_at,
+                            (&mut orderbooks, &mut feed),
+                            data_txs,
+                            &mut stats.borrow_mut(),
+                        )?;
+                    }
                     Some(Ok(Message::Ping(_))) => {
                         debug!(message = "ping",);
                     }
@@ -171,17 +431,166 @@ where
                 }
             });
 
-            if start.elapsed().as_secs() >= 5 {
-                start = std::time::Instant::now();
+            // Connections share the statistics, whichever finds them due
+            // reports them
+            if stats.borrow().report_due() {
                 data_txs.0.iter().enumerate().for_each(|(idx, tx)| {
                     info!("{idx} {tx:?}");
                 });
-                info!(
-                    "max throughput over last 5s = {:?}",
-                    throughput.0.borrow().hdr_histogram.max()
-                );
+                let max_throughput = throughput.0.borrow().hdr_histogram.max();
+                info!("max throughput over last 5s = {max_throughput:?}");
+                stats.borrow_mut().report(max_throughput, data_txs);
                 throughput.clear();
             }
+
+            if start.elapsed().as_secs() >= 5 {
+                start = std::time::Instant::now();
+
+                if let Some(writer) = capture.as_mut() {
+                    let dropped = writer.take_dropped();
+                    if dropped > 0 {
+                        warn!("Websocket capture queue full, dropped {dropped} messages");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Processes websocket text message and pushes resulting event to consumers
+///
+/// The event is traced from `received_at`, when the frame was received.
+/// Copies of messages forwarded by another feed only keep the orderbooks of
+/// this feed up to date. Sequence gaps are returned, other errors of the
+/// message are counted and skipped.
+fn process_text_msg<T: WsMarketDataAdapter, const TX_CAP: usize>(
+    adapter: &T,
+    msg: &str,
+    received_at: std::time::Instant,
+    (orderbooks, feed): (&mut HashMap<Box<str>, PlainOrderbook<f64>>, &mut Feed<'_>),
+    data_txs: &crate::channels::ProducersArray<MarketEvent, TX_CAP>,
+    stats: &mut MarketDataStats,
+) -> Result<(), MarketDataError> {
+    let processed = adapter.process_feed_msg(msg, orderbooks, feed);
+
+    if feed.take_duplicate() {
+        stats.record_duplicate();
+    } else {
+        stats.record_message();
+    }
+
+    match processed {
+        Ok(Some(mut event)) => {
+            event.trace.mark(LatencyStage::BookUpdated, adapter.clock());
+            event.trace.mark_at(LatencyStage::Received, received_at);
+            stats.record_event(&event);
+            event.trace.mark(LatencyStage::Enqueued, adapter.clock());
+            data_txs.push_value(event).map_err(MarketDataError::fatal)
+        }
+        Ok(None) => Ok(()),
+        // Orderbooks are resynced from new snapshots by reconnecting
+        Err(e) if e.is_sequence_gap() => Err(e),
+        Err(e) => {
+            warn!("Failed to process websocket message: {e}");
+            stats.record_parse_error();
+            Ok(())
         }
     }
 }
+
+/// Feeds captured websocket messages to the adapter
+///
+/// Messages are delayed to follow the original receive times divided by the
+/// replay speed. They're processed as received on one feed, a sequence gap
+/// in the capture is logged and the feed starts over after it.
+async fn replay_capture<T: WsMarketDataAdapter, const TX_CAP: usize>(
+    adapter: &T,
+    replay: &ReplayConfig,
+    data_txs: &crate::channels::ProducersArray<MarketEvent, TX_CAP>,
+    orderbooks: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+    stats: &RefCell<MarketDataStats>,
+    shutdown: &Shutdown,
+) -> Result<(), MarketDataError> {
+    let reader = CaptureReader::open(replay.path.as_ref()).map_err(MarketDataError::with_source)?;
+    let start = std::time::Instant::now();
+    let mut first_received_at = None;
+    let mut feed = Feed::single();
+
+    for msg in reader {
+        if shutdown.shutdown_started() {
+            break;
+        }
+
+        let msg = msg.map_err(MarketDataError::with_source)?;
+        let first_received_at = *first_received_at.get_or_insert(msg.received_at);
+
+        if replay.speed > 0.0 {
+            let due = Duration::from_nanos(msg.received_at.saturating_sub(first_received_at))
+                .div_f64(replay.speed);
+            let elapsed = start.elapsed();
+
+            if due > elapsed {
+                sleep(due - elapsed).await;
+            }
+        }
+
+        let result = process_text_msg(
+            adapter,
+            &msg.text,
+            adapter.clock().instant(),
+            (orderbooks, &mut feed),
+            data_txs,
+            &mut stats.borrow_mut(),
+        );
+
+        match result {
+            Err(e) if e.is_sequence_gap() => {
+                warn!("Sequence gap in websocket capture: {e}");
+                feed = Feed::single();
+            }
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_markets() {
+        let markets = ["BTC/USD", "ETH/USD", "SOL/USD"];
+
+        assert_eq!(split_markets(&markets, None), vec![&markets[..]]);
+        assert_eq!(
+            split_markets(&markets, Some(2)),
+            vec![&markets[..2], &markets[2..]]
+        );
+        assert_eq!(split_markets(&markets, Some(0)), vec![&markets[..]]);
+        assert_eq!(split_markets(&[], Some(2)).len(), 1);
+    }
+
+    #[test]
+    fn test_disconnected_consumer_is_fatal() {
+        let (tx, rx) = spsc_queue::make(1);
+        drop(rx);
+        let mut data_txs = crate::channels::ProducersArray::<MarketEvent, 1>::default();
+        data_txs.0.push(tx);
+        let (metrics_tx, _metrics_rx) = spsc_queue::make(1);
+        let mut stats = MarketDataStats::new(ExchangeId::Ftx, metrics_tx);
+        let msg = r#"{"channel":"orderbook","market":"BTC/USD","type":"partial","data":{"time":1640995200.0,"checksum":0,"bids":[[41999.0,0.5]],"asks":[[42000.0,0.4]],"action":"partial"}}"#;
+
+        let result = process_text_msg(
+            &crate::market_data::ftx::Ftx::default(),
+            msg,
+            std::time::Instant::now(),
+            (&mut HashMap::new(), &mut Feed::single()),
+            &data_txs,
+            &mut stats,
+        );
+
+        assert!(result.unwrap_err().is_fatal());
+    }
+}
@@ -0,0 +1,317 @@
+//! Arbitration of redundant market data feeds
+//!
+//! Redundant feeds are identical subscriptions of the same markets on
+//! separate exchange connections. Each feed keeps its own orderbooks and the
+//! [`FeedArbiter`] shared by the feeds decides which of them forwards a
+//! message: the first copy of each sequence number is forwarded, copies
+//! arriving later from the other feeds are dropped. Messages without
+//! sequence number are forwarded from the first connected feed.
+//!
+//! Adapters arbitrate messages through the [`Feed`] they were received on
+//! while parsing them, so each copy is parsed once whether it's forwarded or
+//! not. Each feed also checks the sequence of the messages it receives, its
+//! orderbooks can't be trusted after a gap whichever feed forwards them.
+
+use std::collections::BTreeSet;
+
+use crate::market_data::prelude::*;
+
+/// Sequence number of a websocket message within its stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sequence<'a> {
+    /// Channel of the stream, e.g. trades
+    pub channel: &'static str,
+    /// Market of the stream
+    pub market: &'a str,
+    /// Number increasing with each message of the stream
+    pub number: u64,
+}
+
+impl<'a> Sequence<'a> {
+    pub fn new(channel: &'static str, market: &'a str, number: u64) -> Self {
+        Self {
+            channel,
+            market,
+            number,
+        }
+    }
+}
+
+/// Picks the feed forwarding each message of redundant feeds
+#[derive(Debug, Default)]
+pub struct FeedArbiter {
+    /// Last forwarded sequence number of each market in each channel
+    streams: HashMap<&'static str, HashMap<Box<str>, u64>>,
+    /// Feeds currently connected to the exchange
+    connected: BTreeSet<usize>,
+}
+
+impl FeedArbiter {
+    /// Records that given feed connected to the exchange
+    pub fn connect(&mut self, feed: usize) {
+        self.connected.insert(feed);
+    }
+
+    /// Records that given feed disconnected from the exchange
+    pub fn disconnect(&mut self, feed: usize) {
+        self.connected.remove(&feed);
+    }
+
+    /// Returns whether message received on given feed should be forwarded
+    ///
+    /// Message is forwarded when its sequence number is greater than the
+    /// last forwarded one of its stream. Messages without sequence number are
+    /// only forwarded from the first connected feed, the first feed before
+    /// any connects.
+    pub fn forward(&mut self, feed: usize, sequence: Option<Sequence<'_>>) -> bool {
+        let sequence = match sequence {
+            Some(sequence) => sequence,
+            None => return self.connected.iter().next().copied().unwrap_or(0) == feed,
+        };
+        let stream = self.streams.entry(sequence.channel).or_default();
+
+        match stream.get_mut(sequence.market) {
+            Some(last) if sequence.number <= *last => false,
+            Some(last) => {
+                *last = sequence.number;
+                true
+            }
+            None => {
+                stream.insert(Box::from(sequence.market), sequence.number);
+                true
+            }
+        }
+    }
+
+    /// Forwards a batch of messages numbered up to `sequence.number`
+    ///
+    /// Returns the last number forwarded before the batch, messages of the
+    /// batch numbered above it are forwarded, all of them when it's `None`.
+    /// Redundant feeds may batch the same messages differently, e.g. trades,
+    /// so batches are arbitrated per message.
+    pub fn forward_batch(&mut self, sequence: Sequence<'_>) -> Option<u64> {
+        let stream = self.streams.entry(sequence.channel).or_default();
+
+        match stream.get_mut(sequence.market) {
+            Some(last) => {
+                let forwarded = *last;
+                *last = forwarded.max(sequence.number);
+                Some(forwarded)
+            }
+            None => {
+                stream.insert(Box::from(sequence.market), sequence.number);
+                None
+            }
+        }
+    }
+}
+
+/// Feed a message was received on
+#[derive(Debug)]
+pub struct Feed<'a> {
+    /// Index of the feed within its connection
+    index: usize,
+    /// Arbiter shared by the redundant feeds, `None` without redundancy
+    arbiter: Option<&'a RefCell<FeedArbiter>>,
+    /// Whether a copy was dropped since last checked
+    duplicate: bool,
+    /// Last sequence number received on the feed of each market in each
+    /// channel
+    received: HashMap<&'static str, HashMap<Box<str>, u64>>,
+}
+
+impl<'a> Feed<'a> {
+    pub fn new(index: usize, arbiter: Option<&'a RefCell<FeedArbiter>>) -> Self {
+        Self {
+            index,
+            arbiter,
+            duplicate: false,
+            received: HashMap::new(),
+        }
+    }
+
+    /// Returns the only feed of a connection, forwarding all messages
+    pub fn single() -> Self {
+        Self::new(0, None)
+    }
+
+    /// Returns whether message with given sequence number should be forwarded
+    pub fn forward(&mut self, sequence: Option<Sequence<'_>>) -> bool {
+        let forward = match self.arbiter {
+            Some(arbiter) => arbiter.borrow_mut().forward(self.index, sequence),
+            None => true,
+        };
+        self.duplicate |= !forward;
+
+        forward
+    }
+
+    /// Returns the number above which messages of the batch should be
+    /// forwarded, see [`FeedArbiter::forward_batch`]
+    pub fn forward_batch(&mut self, sequence: Sequence<'_>) -> Option<u64> {
+        let forwarded = self
+            .arbiter
+            .and_then(|arbiter| arbiter.borrow_mut().forward_batch(sequence));
+        if let Some(forwarded) = forwarded {
+            self.duplicate |= sequence.number <= forwarded;
+        }
+
+        forwarded
+    }
+
+    /// Records message numbered from `first` to `sequence.number` received
+    /// on the feed
+    ///
+    /// Returns error when messages of the stream were skipped since the
+    /// previous one received on the feed.
+    pub fn receive(&mut self, first: u64, sequence: Sequence<'_>) -> Result<(), SequenceGap> {
+        let stream = self.received.entry(sequence.channel).or_default();
+
+        match stream.get_mut(sequence.market) {
+            Some(last) if first > *last + 1 => Err(SequenceGap {
+                channel: sequence.channel,
+                market: Box::from(sequence.market),
+                expected: *last + 1,
+                received: first,
+            }),
+            Some(last) => {
+                *last = sequence.number;
+                Ok(())
+            }
+            None => {
+                stream.insert(Box::from(sequence.market), sequence.number);
+                Ok(())
+            }
+        }
+    }
+
+    /// Records that messages of the stream up to `number` are contained in
+    /// a snapshot, the next message received has to continue it
+    ///
+    /// Does nothing once messages of the stream were received on the feed.
+    pub fn snapshot(&mut self, channel: &'static str, market: &str, number: u64) {
+        let stream = self.received.entry(channel).or_default();
+
+        if !stream.contains_key(market) {
+            stream.insert(Box::from(market), number);
+        }
+    }
+
+    /// Returns whether a copy was dropped since the last call
+    pub fn take_duplicate(&mut self) -> bool {
+        std::mem::take(&mut self.duplicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_snapshot() {
+        let mut feed = Feed::single();
+        let depth = |number| Sequence::new("depth", "BTCUSDT", number);
+
+        feed.snapshot("depth", "BTCUSDT", 100);
+        assert!(feed.receive(102, depth(103)).is_err());
+        assert!(feed.receive(99, depth(101)).is_ok());
+
+        // Snapshot taken after updates were received doesn't reset the feed
+        feed.snapshot("depth", "BTCUSDT", 90);
+        assert!(feed.receive(102, depth(102)).is_ok());
+    }
+
+    #[test]
+    fn test_feed_arbiter() {
+        let mut arbiter = FeedArbiter::default();
+        let trade = |number| Some(Sequence::new("trade", "BTCUSDT", number));
+
+        assert!(arbiter.forward(1, trade(1)));
+        assert!(!arbiter.forward(0, trade(1)));
+        assert!(arbiter.forward(0, trade(2)));
+        assert!(arbiter.forward(0, trade(3)));
+        assert!(!arbiter.forward(1, trade(2)));
+        assert!(!arbiter.forward(1, trade(3)));
+
+        // Streams are arbitrated independently
+        assert!(arbiter.forward(1, Some(Sequence::new("depth", "BTCUSDT", 2))));
+        assert!(arbiter.forward(1, Some(Sequence::new("trade", "ETHUSDT", 1))));
+
+        assert!(arbiter.forward(0, None));
+        assert!(!arbiter.forward(1, None));
+    }
+
+    #[test]
+    fn test_forward_batch() {
+        let arbiter = RefCell::new(FeedArbiter::default());
+        let mut first = Feed::new(0, Some(&arbiter));
+        let mut second = Feed::new(1, Some(&arbiter));
+        let trades = |number| Sequence::new("trades", "BTC/USD", number);
+
+        // Trades 1 to 3 batched as [1, 2], [3] and [1], [2, 3]
+        assert_eq!(first.forward_batch(trades(2)), None);
+        assert_eq!(second.forward_batch(trades(1)), Some(2));
+        assert!(second.take_duplicate());
+        assert_eq!(second.forward_batch(trades(3)), Some(2));
+        assert!(!second.take_duplicate());
+        assert_eq!(first.forward_batch(trades(3)), Some(3));
+        assert!(first.take_duplicate());
+    }
+
+    #[test]
+    fn test_single_feed_forwards_all() {
+        let mut feed = Feed::single();
+
+        assert!(feed.forward(None));
+        assert!(feed.forward(Some(Sequence::new("trade", "BTCUSDT", 1))));
+        assert!(feed.forward(Some(Sequence::new("trade", "BTCUSDT", 1))));
+        assert_eq!(
+            feed.forward_batch(Sequence::new("trades", "BTC/USD", 1)),
+            None
+        );
+        assert!(!feed.take_duplicate());
+    }
+
+    #[test]
+    fn test_feed_receive_gap() {
+        let mut feed = Feed::single();
+        let depth = |number| Sequence::new("depth", "BTCUSDT", number);
+
+        assert_eq!(feed.receive(101, depth(105)), Ok(()));
+        assert_eq!(feed.receive(106, depth(110)), Ok(()));
+        assert_eq!(
+            feed.receive(115, depth(120)),
+            Err(SequenceGap {
+                channel: "depth",
+                market: Box::from("BTCUSDT"),
+                expected: 111,
+                received: 115,
+            })
+        );
+
+        // Streams are checked independently
+        assert_eq!(
+            feed.receive(1, Sequence::new("depth", "ETHUSDT", 1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_unsequenced_messages_follow_connected_feed() {
+        let mut arbiter = FeedArbiter::default();
+        arbiter.connect(0);
+        arbiter.connect(1);
+
+        assert!(arbiter.forward(0, None));
+        assert!(!arbiter.forward(1, None));
+
+        arbiter.disconnect(0);
+
+        assert!(arbiter.forward(1, None));
+
+        arbiter.connect(0);
+
+        assert!(arbiter.forward(0, None));
+        assert!(!arbiter.forward(1, None));
+    }
+}
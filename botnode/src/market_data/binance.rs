@@ -3,27 +3,89 @@
 pub(crate) mod rest;
 pub(crate) mod ws;
 
+use std::cell::Cell;
+
 use chrono::TimeZone;
 
 use super::prelude::*;
 use crate::prelude::*;
-use botvana::exchange::ExchangeId;
+use botvana::{
+    cfg::{ApiKey, ExchangeConfig, ReplayConfig},
+    clock::{real_clock, Clock, SharedClock},
+    exchange::ExchangeId,
+};
+
+const REST_URL: &str = "https://api.binance.com";
+const WS_URL: &str = "wss://stream.binance.com:9443/ws";
+const TESTNET_REST_URL: &str = "https://testnet.binance.vision";
+const TESTNET_WS_URL: &str = "wss://testnet.binance.vision/ws";
 
 #[derive(Debug)]
 pub struct Binance {
     pub metrics: BinanceMetrics,
-    cur_idx: u64,
+    /// Id of the last subscribe request
+    cur_idx: Cell<u64>,
     api_url: Box<str>,
+    ws_url: Box<str>,
+    /// Sent with REST requests in `X-MBX-APIKEY` when configured
+    api_key: Option<ApiKey>,
+    capture_dir: Option<Box<str>>,
+    replay: Option<ReplayConfig>,
+    clock: SharedClock,
+    depth: Option<u32>,
+    /// Last update id of the latest orderbook snapshot of each symbol
+    snapshot_ids: RefCell<HashMap<Box<str>, u64>>,
 }
 
-impl Default for Binance {
-    fn default() -> Self {
+impl Binance {
+    /// Creates new Binance adapter with given exchange settings
+    pub fn new(config: &ExchangeConfig, clock: SharedClock) -> Self {
+        let (default_api_url, default_ws_url) = if config.testnet {
+            (TESTNET_REST_URL, TESTNET_WS_URL)
+        } else {
+            (REST_URL, WS_URL)
+        };
+
         Binance {
-            api_url: Box::from("https://api.binance.com"),
-            cur_idx: 0,
+            api_url: config
+                .rest_url
+                .clone()
+                .unwrap_or_else(|| Box::from(default_api_url)),
+            ws_url: config
+                .ws_url
+                .clone()
+                .unwrap_or_else(|| Box::from(default_ws_url)),
+            api_key: config.api_key(),
+            capture_dir: config.capture_dir.clone(),
+            replay: config.replay.clone(),
+            clock,
+            depth: config.depth,
+            snapshot_ids: RefCell::new(HashMap::new()),
+            cur_idx: Cell::new(0),
             metrics: BinanceMetrics::default(),
         }
     }
+
+    /// Returns REST API client with given request timeout
+    fn rest_client(&self, timeout: Duration) -> Result<surf::Client, MarketDataError> {
+        let mut config = surf::Config::new()
+            .set_base_url(Url::parse(&self.api_url).map_err(MarketDataError::with_source)?)
+            .set_timeout(Some(timeout));
+
+        if let Some(api_key) = &self.api_key {
+            config = config
+                .add_header("X-MBX-APIKEY", api_key.as_str())
+                .map_err(MarketDataError::surf_error)?;
+        }
+
+        config.try_into().map_err(MarketDataError::with_source)
+    }
+}
+
+impl Default for Binance {
+    fn default() -> Self {
+        Self::new(&ExchangeConfig::new("binance"), real_clock())
+    }
 }
 
 #[async_trait(?Send)]
@@ -33,11 +95,7 @@ impl RestMarketDataAdapter for Binance {
 
     /// Fetches availables markets on Binance
     async fn fetch_markets(&self) -> Result<Box<[Market]>, MarketDataError> {
-        let client: surf::Client = surf::Config::new()
-            .set_base_url(Url::parse(&self.api_url).map_err(MarketDataError::with_source)?)
-            .set_timeout(Some(Duration::from_secs(20)))
-            .try_into()
-            .map_err(MarketDataError::with_source)?;
+        let client = self.rest_client(Duration::from_secs(20))?;
 
         let mut res = client
             .get(format!("/api/v3/exchangeInfo"))
@@ -65,14 +123,15 @@ impl RestMarketDataAdapter for Binance {
         &self,
         symbol: &str,
     ) -> Result<PlainOrderbook<f64>, MarketDataError> {
-        let client: surf::Client = surf::Config::new()
-            .set_base_url(Url::parse(&self.api_url).map_err(MarketDataError::with_source)?)
-            .set_timeout(Some(Duration::from_secs(5)))
-            .try_into()
-            .map_err(MarketDataError::with_source)?;
+        let client = self.rest_client(Duration::from_secs(5))?;
+
+        let path = match self.depth {
+            Some(depth) => format!("/api/v3/depth?symbol={}&limit={}", symbol, depth),
+            None => format!("/api/v3/depth?symbol={}", symbol),
+        };
 
         let mut res = client
-            .get(format!("/api/v3/depth?symbol={}", symbol))
+            .get(path)
             .await
             .map_err(MarketDataError::surf_error)?;
         let body = res
@@ -83,6 +142,10 @@ impl RestMarketDataAdapter for Binance {
         let snapshot = serde_json::from_slice::<rest::OrderbookSnapshot>(body.as_bytes())
             .map_err(MarketDataError::with_source)?;
 
+        self.snapshot_ids
+            .borrow_mut()
+            .insert(Box::from(symbol), snapshot.last_update_id);
+
         let mut orderbook = PlainOrderbook::<f64>::with_capacity(1000);
         orderbook.update(&snapshot.bids, &snapshot.asks);
 
@@ -96,11 +159,23 @@ impl WsMarketDataAdapter for Binance {
     }
 
     fn ws_url(&self) -> Box<str> {
-        Box::from("wss://stream.binance.com:9443/ws")
+        self.ws_url.clone()
+    }
+
+    fn capture_dir(&self) -> Option<&str> {
+        self.capture_dir.as_deref()
     }
 
-    fn subscribe_msgs(&mut self, markets: &[&str]) -> Box<[String]> {
-        self.cur_idx += 1;
+    fn replay(&self) -> Option<&ReplayConfig> {
+        self.replay.as_ref()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    fn subscribe_msgs(&self, markets: &[&str]) -> Box<[String]> {
+        self.cur_idx.set(self.cur_idx.get() + 1);
 
         let params: Vec<_> = markets
             .iter()
@@ -116,17 +191,27 @@ impl WsMarketDataAdapter for Binance {
             .flatten()
             .collect();
 
-        Box::new([json!({"method": "SUBSCRIBE", "params": params, "id": self.cur_idx}).to_string()])
+        Box::new([
+            json!({"method": "SUBSCRIBE", "params": params, "id": self.cur_idx.get()}).to_string(),
+        ])
     }
 
-    fn process_ws_msg(
+    /// Processes Websocket text message received on the feed
+    ///
+    /// Trades are arbitrated by trade id, depth updates and book tickers by
+    /// orderbook update id. Depth updates already contained in the orderbook
+    /// snapshot are dropped and gaps between them are returned as errors.
+    fn process_feed_msg(
         &self,
         msg: &str,
         markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+        feed: &mut Feed<'_>,
     ) -> Result<Option<MarketEvent>, MarketDataError> {
         trace!("got ws_msg = {msg:?}");
 
         let ws_msg = serde_json::from_slice::<ws::WsMsg>(msg.as_bytes());
+        let clock = WsMarketDataAdapter::clock(self);
+        let parsed_at = clock.instant();
 
         match ws_msg {
             Err(e) => {
@@ -136,7 +221,14 @@ impl WsMarketDataAdapter for Binance {
                     source: Box::new(e),
                 })
             }
-            Ok(ws_msg) => Ok(process_data_ws_message(ws_msg, markets)?),
+            Ok(ws_msg) => {
+                let snapshot_ids = self.snapshot_ids.borrow();
+
+                Ok(
+                    process_data_ws_message(ws_msg, markets, &snapshot_ids, feed, clock)?
+                        .map(|event| event.with_stage_at(LatencyStage::Parsed, parsed_at)),
+                )
+            }
         }
     }
 }
@@ -145,15 +237,23 @@ impl WsMarketDataAdapter for Binance {
 fn process_data_ws_message(
     ws_msg: ws::WsMsg,
     markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+    snapshot_ids: &HashMap<Box<str>, u64>,
+    feed: &mut Feed<'_>,
+    clock: &dyn Clock,
 ) -> Result<Option<MarketEvent>, MarketDataError> {
     let data = ws_msg;
     match data {
         ws::WsMsg::Trade(trade) => {
+            if !feed.forward(Some(Sequence::new("trade", trade.symbol, trade.trade_id))) {
+                return Ok(None);
+            }
+
             let dt = Utc.timestamp(trade.trade_time as i64, 0);
             let symbol = trade.symbol;
-            let trade = botvana::market::trade::Trade::new(trade.price, trade.size, dt);
+            let trade = botvana::market::trade::Trade::new(clock, trade.price, trade.size, dt);
 
             Ok(Some(MarketEvent::trades(
+                clock,
                 Box::from(symbol),
                 Box::new([trade]),
             )))
@@ -167,8 +267,30 @@ fn process_data_ws_message(
             if let Some(symbol) = symbol {
                 let orderbook = markets.get_mut(&symbol);
                 if let Some(orderbook) = orderbook {
+                    // Updates received before the snapshot was taken
+                    let snapshot_id = snapshot_ids.get(&symbol).copied();
+                    if update.final_update_id <= snapshot_id.unwrap_or(0) {
+                        return Ok(None);
+                    }
+
+                    // The first update after the snapshot has to contain the
+                    // update following it, `U <= lastUpdateId + 1 <= u`
+                    if let Some(snapshot_id) = snapshot_id {
+                        feed.snapshot("depth", update.symbol, snapshot_id);
+                    }
+
+                    let sequence = Sequence::new("depth", update.symbol, update.final_update_id);
+                    feed.receive(update.first_update_id, sequence)
+                        .map_err(MarketDataError::with_source)?;
+
                     orderbook.update_with_timestamp(&update.bids, &update.asks, update.event_time);
+
+                    if !feed.forward(Some(sequence)) {
+                        return Ok(None);
+                    }
+
                     Ok(Some(MarketEvent::orderbook_update(
+                        clock,
                         Box::from(update.symbol),
                         Box::new(orderbook.clone()),
                     )))
@@ -182,11 +304,17 @@ fn process_data_ws_message(
             }
         }
         ws::WsMsg::OrderbookTicker(book_ticker) => {
+            let sequence = Sequence::new("bookTicker", book_ticker.symbol, book_ticker.update_id);
+            if !feed.forward(Some(sequence)) {
+                return Ok(None);
+            }
+
             return Ok(Some(MarketEvent::mid_price_change(
+                clock,
                 Box::from(book_ticker.symbol),
                 book_ticker.bid_price,
                 book_ticker.ask_price,
-            )))
+            )));
         }
         ws::WsMsg::Response(_response) => Ok(None),
     }
@@ -201,6 +329,36 @@ pub struct BinanceMetrics {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_new_with_testnet() {
+        let b = Binance::new(
+            &ExchangeConfig {
+                testnet: true,
+                ..ExchangeConfig::new("binance")
+            },
+            real_clock(),
+        );
+
+        assert_eq!(b.api_url.as_ref(), TESTNET_REST_URL);
+        assert_eq!(b.ws_url().as_ref(), TESTNET_WS_URL);
+    }
+
+    #[test]
+    fn test_new_with_custom_endpoints() {
+        let b = Binance::new(
+            &ExchangeConfig {
+                rest_url: Some(Box::from("http://127.0.0.1:9000")),
+                ws_url: Some(Box::from("ws://127.0.0.1:9001/ws")),
+                testnet: true,
+                ..ExchangeConfig::new("binance")
+            },
+            real_clock(),
+        );
+
+        assert_eq!(b.api_url.as_ref(), "http://127.0.0.1:9000");
+        assert_eq!(b.ws_url().as_ref(), "ws://127.0.0.1:9001/ws");
+    }
+
     #[test]
     fn test_process_ws_msg_err() {
         let b = Binance::default();
@@ -276,4 +434,60 @@ mod tests {
 
         //assert!(event.is_some());
     }
+
+    #[test]
+    fn test_process_feed_msg_redundant() {
+        let b = Binance::default();
+        let arbiter = RefCell::new(FeedArbiter::default());
+        let mut first = Feed::new(0, Some(&arbiter));
+        let mut second = Feed::new(1, Some(&arbiter));
+        let trade_msg = r#"{"e":"trade","E":1642011077609,"s":"BTCUSDT","t":1219924203,"p":"43000.01","q":"0.00100000","b":8962574684,"a":8962574679,"T":1642011077608,"m":false,"M":true}"#;
+        let book_ticker_msg = r#"{"u":13639707622,"s":"ETHUSDT","b":"3826.81000000","B":"0.26050000","a":"3826.82000000","A":"6.00000000"}"#;
+
+        for msg in [trade_msg, book_ticker_msg] {
+            let forwarded = b.process_feed_msg(msg, &mut HashMap::new(), &mut second);
+            let copy = b.process_feed_msg(msg, &mut HashMap::new(), &mut first);
+
+            assert!(forwarded.unwrap().is_some());
+            assert!(!second.take_duplicate());
+            assert!(copy.unwrap().is_none());
+            assert!(first.take_duplicate());
+        }
+    }
+
+    #[test]
+    fn test_process_feed_msg_depth_sequence() {
+        let b = Binance::default();
+        b.snapshot_ids
+            .borrow_mut()
+            .insert(Box::from("BTCUSDT"), 100);
+        let mut markets = HashMap::new();
+        markets.insert(Box::from("BTCUSDT"), PlainOrderbook::new());
+        let mut feed = Feed::single();
+        let depth = |first, last| {
+            format!(
+                r#"{{"e":"depthUpdate","E":1642011077609,"s":"BTCUSDT","U":{first},"u":{last},"b":[["41999.0","0.5"]],"a":[]}}"#
+            )
+        };
+
+        // Doesn't continue the snapshot
+        let err = b
+            .process_feed_msg(&depth(102, 103), &mut markets, &mut Feed::single())
+            .unwrap_err();
+        assert!(err.is_sequence_gap());
+
+        // Already contained in the snapshot
+        let event = b.process_feed_msg(&depth(95, 100), &mut markets, &mut feed);
+        assert!(event.unwrap().is_none());
+
+        let event = b.process_feed_msg(&depth(98, 102), &mut markets, &mut feed);
+        assert!(event.unwrap().is_some());
+        let event = b.process_feed_msg(&depth(103, 104), &mut markets, &mut feed);
+        assert!(event.unwrap().is_some());
+
+        let err = b
+            .process_feed_msg(&depth(110, 111), &mut markets, &mut feed)
+            .unwrap_err();
+        assert!(err.is_sequence_gap());
+    }
 }
@@ -0,0 +1,256 @@
+//! Raw websocket message capture
+//!
+//! Capture files start with a magic header followed by frames consisting of
+//! the receive time in nanoseconds since UNIX epoch (u64 LE), the message
+//! length (u32 LE) and the message text.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, TrySendError},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MAGIC: &[u8; 6] = b"BVCAP\x01";
+
+/// Maximum number of messages waiting for the capture thread
+const QUEUE_LEN: usize = 16_384;
+
+/// Interval of flushing captured messages to the file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Captured websocket text message
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedMessage {
+    /// Receive time in nanoseconds since UNIX epoch
+    pub received_at: u64,
+    pub text: String,
+}
+
+/// Writer of raw websocket messages
+///
+/// Messages are written to the file by a background thread, so capturing
+/// never blocks the websocket loop on file I/O. Messages are dropped and
+/// counted when the thread can't keep up. Dropping the writer waits for the
+/// queued messages to be written.
+pub struct CaptureWriter {
+    path: PathBuf,
+    tx: Option<mpsc::SyncSender<(u64, String)>>,
+    thread: Option<thread::JoinHandle<()>>,
+    /// Number of messages dropped on full queue
+    dropped: u64,
+}
+
+impl CaptureWriter {
+    /// Creates new capture file for given exchange in `dir`
+    pub fn create<P: AsRef<Path>>(dir: P, exchange: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        let path = dir.as_ref().join(format!(
+            "{exchange}-{}.wscap",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f")
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut writer = BufWriter::new(file);
+
+        writer.write_all(MAGIC)?;
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let thread = thread::Builder::new()
+            .name(format!("capture-{exchange}"))
+            .spawn(move || {
+                if let Err(e) = crate::topology::unpin_current_thread() {
+                    tracing::warn!("Failed to unpin capture thread: {e}");
+                }
+
+                if let Err(e) = run_capture_writer(writer, rx) {
+                    tracing::error!("Failed to write capture file: {e}");
+                }
+            })?;
+
+        Ok(Self {
+            path,
+            tx: Some(tx),
+            thread: Some(thread),
+            dropped: 0,
+        })
+    }
+
+    /// Returns path of the capture file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues message received at given time for writing
+    ///
+    /// Returns error once the capture thread stopped after failing to write.
+    pub fn write(&mut self, received_at: SystemTime, text: &str) -> io::Result<()> {
+        let received_at = received_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        let tx = self.tx.as_ref().expect("capture thread is running");
+
+        match tx.try_send((received_at, text.to_string())) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "capture thread stopped",
+            )),
+        }
+    }
+
+    /// Returns number of messages dropped since the last call because the
+    /// capture thread couldn't keep up
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        // The thread flushes remaining messages once the sender is dropped
+        self.tx.take();
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Capture thread panicked");
+            }
+        }
+    }
+}
+
+/// Writes queued messages until the capture writer is dropped
+fn run_capture_writer(
+    mut writer: BufWriter<File>,
+    rx: mpsc::Receiver<(u64, String)>,
+) -> io::Result<()> {
+    let mut last_flush = Instant::now();
+
+    loop {
+        let timeout = FLUSH_INTERVAL.saturating_sub(last_flush.elapsed());
+
+        match rx.recv_timeout(timeout) {
+            Ok((received_at, text)) => {
+                writer.write_all(&received_at.to_le_bytes())?;
+                writer.write_all(&(text.len() as u32).to_le_bytes())?;
+                writer.write_all(text.as_bytes())?;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return writer.flush(),
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            writer.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+}
+
+/// Reader of raw websocket message captures
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Opens capture file and validates its header
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Creates new reader and validates the header
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 6];
+
+        reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a websocket capture file",
+            ));
+        }
+
+        Ok(Self { reader })
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut received_at = [0u8; 8];
+
+        match self.reader.read_exact(&mut received_at) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let mut len = [0u8; 4];
+        if let Err(e) = self.reader.read_exact(&mut len) {
+            return Some(Err(e));
+        }
+
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+        if let Err(e) = self.reader.read_exact(&mut buf) {
+            return Some(Err(e));
+        }
+
+        Some(
+            String::from_utf8(buf)
+                .map(|text| CapturedMessage {
+                    received_at: u64::from_le_bytes(received_at),
+                    text,
+                })
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_capture() {
+        let dir = std::env::temp_dir().join(format!("botnode-capture-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let path = {
+            let mut writer = CaptureWriter::create(&dir, "ftx").unwrap();
+            writer.write(start, r#"{"type":"subscribed"}"#).unwrap();
+            writer
+                .write(start + Duration::from_millis(5), r#"{"type":"partial"}"#)
+                .unwrap();
+            writer.path().to_path_buf()
+        };
+
+        let messages: Vec<_> = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].received_at, 1_600_000_000_000_000_000);
+        assert_eq!(messages[1].received_at - messages[0].received_at, 5_000_000);
+        assert_eq!(messages[1].text, r#"{"type":"partial"}"#);
+    }
+
+    #[test]
+    fn reject_invalid_header() {
+        assert!(CaptureReader::new(&b"BVREC\x01"[..]).is_err());
+    }
+}
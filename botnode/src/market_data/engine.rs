@@ -1,8 +1,27 @@
 //! Market Data Engine
 
-use crate::{market_data::adapter::*, prelude::*};
+use crate::{
+    market_data::{adapter::*, stats::*},
+    prelude::*,
+};
+use std::cell::RefCell;
+
+use botvana::report::MarketDataMetrics;
 
 pub const MARKET_DATA_QUEUE_LEN: usize = 512;
+/// Interval of checking for configuration updates and restart requests in
+/// milliseconds
+const CONFIG_POLL_INTERVAL_MS: u64 = 100;
+
+/// Queues the market data engine pushes onto
+///
+/// Engine restarted in place of an exited one pushes onto the queues of the
+/// exited engine, so consumers of its market data keep working.
+#[derive(Clone)]
+pub struct MarketDataOutputs<const TX_CAP: usize> {
+    data_txs: crate::channels::ProducersArray<MarketEvent, TX_CAP>,
+    metrics_tx: spsc_queue::Producer<MarketDataMetrics>,
+}
 
 /// Market Data Engine
 ///
@@ -10,22 +29,57 @@ pub const MARKET_DATA_QUEUE_LEN: usize = 512;
 pub struct MarketDataEngine<A: MarketDataAdapter<TX_CAP>, const TX_CAP: usize> {
     adapter: A,
     config_rx: spsc_queue::Consumer<BotConfiguration>,
+    restart_rx: Option<spsc_queue::Consumer<()>>,
     data_txs: crate::channels::ProducersArray<MarketEvent, TX_CAP>,
     status_tx: spsc_queue::Producer<EngineStatus>,
     status_rx: spsc_queue::Consumer<EngineStatus>,
+    heartbeat: Heartbeat,
+    stats: RefCell<MarketDataStats>,
+    metrics_rx: spsc_queue::Consumer<MarketDataMetrics>,
 }
 
 impl<A: MarketDataAdapter<TX_CAP>, const TX_CAP: usize> MarketDataEngine<A, TX_CAP> {
     pub fn new(config_rx: spsc_queue::Consumer<BotConfiguration>, adapter: A) -> Self {
         let (status_tx, status_rx) = spsc_queue::make(1);
+        let (metrics_tx, metrics_rx) = spsc_queue::make(METRICS_QUEUE_LEN);
         Self {
             adapter,
             config_rx,
+            restart_rx: None,
             data_txs: crate::channels::ProducersArray::<MarketEvent, TX_CAP>::default(),
             status_tx,
             status_rx,
+            heartbeat: Heartbeat::new(),
+            stats: RefCell::new(MarketDataStats::new(A::EXCHANGE_REF, metrics_tx)),
+            metrics_rx,
+        }
+    }
+
+    /// Returns receiver of periodic market data metrics
+    pub fn metrics_rx(&self) -> spsc_queue::Consumer<MarketDataMetrics> {
+        self.metrics_rx.clone()
+    }
+
+    /// Returns queues the engine pushes market data and metrics onto
+    pub fn outputs(&self) -> MarketDataOutputs<TX_CAP> {
+        MarketDataOutputs {
+            data_txs: self.data_txs.clone(),
+            metrics_tx: self.stats.borrow().metrics_tx(),
         }
     }
+
+    /// Pushes market data and metrics onto given queues
+    pub fn with_outputs(mut self, outputs: MarketDataOutputs<TX_CAP>) -> Self {
+        self.data_txs = outputs.data_txs;
+        self.stats = RefCell::new(MarketDataStats::new(A::EXCHANGE_REF, outputs.metrics_tx));
+        self
+    }
+
+    /// Reconnects the adapter whenever a value is received on `restart_rx`
+    pub fn with_restart(mut self, restart_rx: spsc_queue::Consumer<()>) -> Self {
+        self.restart_rx = Some(restart_rx);
+        self
+    }
 }
 
 #[async_trait(?Send)]
@@ -38,8 +92,12 @@ impl<A: MarketDataAdapter<TX_CAP>, const TX_CAP: usize> Engine for MarketDataEng
         self.status_rx.clone()
     }
 
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        Some(self.heartbeat.rx())
+    }
+
     /// Start the market data engine
-    async fn start(mut self, shutdown: Shutdown) -> Result<(), EngineError> {
+    async fn start(self, shutdown: Shutdown) -> Result<(), EngineError> {
         info!("Starting market data engine for {}", A::NAME);
 
         self.status_tx.try_push(EngineStatus::Booting);
@@ -47,7 +105,7 @@ impl<A: MarketDataAdapter<TX_CAP>, const TX_CAP: usize> Engine for MarketDataEng
         // First, fetch available markets using the adapter
         match self.adapter.fetch_markets().await {
             Ok(markets) => {
-                let event = MarketEvent::markets(markets.into());
+                let event = MarketEvent::markets(self.adapter.clock(), markets.into());
                 self.push_value(event);
             }
             Err(e) => {
@@ -57,27 +115,117 @@ impl<A: MarketDataAdapter<TX_CAP>, const TX_CAP: usize> Engine for MarketDataEng
 
         // Await configuration from botvana-server
         debug!("Waiting for configuration");
-        let config = await_value(self.config_rx);
+        let config = await_value(&self.config_rx).await;
         debug!("Got config = {config:?}");
-        let markets: Vec<_> = config
-            .markets
-            .iter()
-            .map(|market| market.as_ref())
-            .collect();
+        let mut subscription = Subscription::from_config(config, A::EXCHANGE_REF);
 
         self.status_tx.try_push(EngineStatus::Running);
 
-        info!("Running loop w/ markets = {:?}", config.markets);
-        if let Err(e) = self
-            .adapter
-            .run_loop(self.data_txs, &markets[..], shutdown)
-            .await
-        {
-            error!("Error running loop: {e}");
-            self.status_tx.try_push(EngineStatus::Error);
+        // Run the adapter until the subscription changes or restart is
+        // requested, then resubscribe
+        loop {
+            let new_subscription = {
+                let market_refs: Vec<_> = subscription
+                    .markets
+                    .iter()
+                    .map(|market| market.as_ref())
+                    .collect();
+
+                info!("Running loop w/ {subscription:?}");
+                let run_loop = self
+                    .adapter
+                    .run_loop(
+                        &self.data_txs,
+                        &market_refs[..],
+                        subscription.markets_per_connection,
+                        subscription.feeds,
+                        &self.stats,
+                        shutdown.clone(),
+                    )
+                    .fuse();
+                let restart = await_restart(
+                    &self.config_rx,
+                    self.restart_rx.as_ref(),
+                    &self.heartbeat,
+                    &subscription,
+                )
+                .fuse();
+                futures::pin_mut!(run_loop, restart);
+
+                futures::select! {
+                    result = run_loop => {
+                        if let Err(e) = result {
+                            error!("Error running loop: {e}");
+                            self.status_tx.try_push(EngineStatus::Error);
+
+                            return Err(EngineError::with_source(e));
+                        }
+
+                        None
+                    }
+                    new_subscription = restart => Some(new_subscription),
+                }
+            };
+
+            match new_subscription {
+                Some(new_subscription) => {
+                    info!("Restarting w/ {new_subscription:?}");
+                    subscription = new_subscription;
+                }
+                None => break Ok(()),
+            }
+        }
+    }
+}
+
+/// Markets the engine subscribes to, how they are split across connections
+/// and number of redundant feeds of each connection
+#[derive(Clone, Debug, PartialEq)]
+struct Subscription {
+    exchange: ExchangeId,
+    markets: Box<[Box<str>]>,
+    markets_per_connection: Option<usize>,
+    feeds: usize,
+}
+
+impl Subscription {
+    fn from_config(config: BotConfiguration, exchange: ExchangeId) -> Self {
+        Self {
+            exchange,
+            markets_per_connection: config.markets_per_connection(exchange),
+            feeds: config.feeds(exchange),
+            markets: config.markets,
+        }
+    }
+}
+
+/// Waits for configuration update with different subscription or restart
+/// request and returns subscription to run with
+///
+/// The heartbeat is ticked on every poll, so it stops when the adapter loop
+/// doesn't yield to this future.
+async fn await_restart(
+    config_rx: &spsc_queue::Consumer<BotConfiguration>,
+    restart_rx: Option<&spsc_queue::Consumer<()>>,
+    heartbeat: &Heartbeat,
+    subscription: &Subscription,
+) -> Subscription {
+    loop {
+        heartbeat.tick();
+
+        if restart_rx.and_then(|rx| rx.try_pop()).is_some() {
+            info!("Restart requested");
+            break subscription.clone();
         }
 
-        Ok(())
+        match config_rx
+            .try_pop()
+            .map(|config| Subscription::from_config(config, subscription.exchange))
+        {
+            Some(updated) if updated != *subscription => break updated,
+            Some(_) => debug!("Configuration updated, subscription unchanged"),
+            None => glommio::timer::sleep(Duration::from_millis(CONFIG_POLL_INTERVAL_MS)).await,
+        }
     }
 }
 
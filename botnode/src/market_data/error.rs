@@ -18,6 +18,44 @@ impl MarketDataError {
     pub fn surf_error(e: surf::Error) -> Self {
         Self::with_source(SurfError { error: e })
     }
+
+    /// Returns error ending the market data engine instead of reconnecting
+    pub fn fatal(err: impl std::error::Error + 'static) -> Self {
+        Self::with_source(FatalError {
+            source: Box::new(err),
+        })
+    }
+
+    /// Returns whether the error ends the market data engine
+    pub fn is_fatal(&self) -> bool {
+        self.source.is::<FatalError>()
+    }
+
+    /// Returns whether the error is a gap in a sequenced stream
+    pub fn is_sequence_gap(&self) -> bool {
+        self.source.is::<SequenceGap>()
+    }
+}
+
+/// Error that can't be recovered from by reconnecting to the exchange, e.g.
+/// consumers of market events disconnected
+#[derive(Debug, thiserror::Error)]
+#[error("Fatal error: {source}")]
+pub struct FatalError {
+    source: Box<dyn std::error::Error>,
+}
+
+/// Messages of a sequenced stream were skipped, e.g. orderbook updates lost
+/// by the exchange
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("Sequence gap in {channel} of {market}: expected {expected}, received {received}")]
+pub struct SequenceGap {
+    pub channel: &'static str,
+    pub market: Box<str>,
+    /// First sequence number following the previous message
+    pub expected: u64,
+    /// First sequence number of the received message
+    pub received: u64,
 }
 
 #[derive(Debug, thiserror::Error)]
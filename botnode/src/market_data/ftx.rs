@@ -10,15 +10,54 @@ use serde_json::json;
 use surf::Url;
 
 use crate::{
-    market_data::{adapter::*, error::*},
+    market_data::{adapter::*, arbiter::*, error::*},
     prelude::*,
 };
-use botvana::exchange::ExchangeId;
+use botvana::{
+    cfg::{ExchangeConfig, ReplayConfig},
+    clock::{real_clock, Clock, SharedClock},
+    exchange::ExchangeId,
+};
+
+const REST_URL: &str = "https://ftx.com";
+const WS_URL: &str = "wss://ftx.com/ws";
 
 /// FTX market data
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Ftx {
     pub metrics: FtxMetrics,
+    rest_url: Box<str>,
+    ws_url: Box<str>,
+    capture_dir: Option<Box<str>>,
+    replay: Option<ReplayConfig>,
+    clock: SharedClock,
+}
+
+impl Ftx {
+    /// Creates new FTX adapter with given exchange settings
+    pub fn new(config: &ExchangeConfig, clock: SharedClock) -> Self {
+        if config.testnet {
+            warn!("FTX has no testnet, using configured endpoints");
+        }
+
+        Self {
+            metrics: FtxMetrics::default(),
+            rest_url: config
+                .rest_url
+                .clone()
+                .unwrap_or_else(|| Box::from(REST_URL)),
+            ws_url: config.ws_url.clone().unwrap_or_else(|| Box::from(WS_URL)),
+            capture_dir: config.capture_dir.clone(),
+            replay: config.replay.clone(),
+            clock,
+        }
+    }
+}
+
+impl Default for Ftx {
+    fn default() -> Self {
+        Self::new(&ExchangeConfig::new("ftx"), real_clock())
+    }
 }
 
 #[derive(Default, Debug)]
@@ -34,7 +73,7 @@ impl RestMarketDataAdapter for Ftx {
     /// Fetches available markets on FTX
     async fn fetch_markets(&self) -> Result<Box<[Market]>, MarketDataError> {
         let client: surf::Client = surf::Config::new()
-            .set_base_url(Url::parse("https://ftx.com").map_err(MarketDataError::with_source)?)
+            .set_base_url(Url::parse(&self.rest_url).map_err(MarketDataError::with_source)?)
             .set_timeout(Some(Duration::from_secs(5)))
             .try_into()
             .map_err(MarketDataError::with_source)?;
@@ -76,10 +115,22 @@ impl WsMarketDataAdapter for Ftx {
     }
 
     fn ws_url(&self) -> Box<str> {
-        Box::from("wss://ftx.com/ws")
+        self.ws_url.clone()
     }
 
-    fn subscribe_msgs(&mut self, markets: &[&str]) -> Box<[String]> {
+    fn capture_dir(&self) -> Option<&str> {
+        self.capture_dir.as_deref()
+    }
+
+    fn replay(&self) -> Option<&ReplayConfig> {
+        self.replay.as_ref()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    fn subscribe_msgs(&self, markets: &[&str]) -> Box<[String]> {
         markets
             .iter()
             .map(|market| {
@@ -95,16 +146,23 @@ impl WsMarketDataAdapter for Ftx {
             .collect()
     }
 
-    /// Processes Websocket text message
-    fn process_ws_msg(
+    /// Processes Websocket text message received on the feed
+    ///
+    /// Trades are arbitrated by their id, orderbook messages by their time in
+    /// microseconds.
+    fn process_feed_msg(
         &self,
         msg: &str,
         markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+        feed: &mut Feed<'_>,
     ) -> Result<Option<MarketEvent>, MarketDataError> {
         let ws_msg = serde_json::from_slice::<ws::WsMsg>(msg.as_bytes());
+        let clock = WsMarketDataAdapter::clock(self);
+        let parsed_at = clock.instant();
 
         match ws_msg {
-            Ok(ws_msg) => Ok(process_market_ws_message(ws_msg, markets)?),
+            Ok(ws_msg) => Ok(process_market_ws_message(ws_msg, markets, feed, clock)?
+                .map(|event| event.with_stage_at(LatencyStage::Parsed, parsed_at))),
             Err(e) => {
                 error!("Failed to parse {msg}");
 
@@ -118,6 +176,8 @@ impl WsMarketDataAdapter for Ftx {
 fn process_market_ws_message(
     mut ws_msg: ws::WsMsg,
     markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+    feed: &mut Feed<'_>,
+    clock: &dyn Clock,
 ) -> Result<Option<MarketEvent>, MarketDataError> {
     let data = ws_msg.data.to_mut();
     let market = match ws_msg.market {
@@ -129,12 +189,28 @@ fn process_market_ws_message(
         ws::Data::Trades(trades) => {
             trace!("got trades = {trades:?}");
 
+            // Redundant feeds batch trades differently, only trades newer than
+            // the last forwarded one are forwarded
+            let forwarded = trades
+                .iter()
+                .map(|trade| trade.id as u64)
+                .max()
+                .and_then(|id| feed.forward_batch(Sequence::new("trades", market, id)));
             let trades: Vec<_> = trades
                 .iter()
-                .filter_map(|trade| botvana::market::trade::Trade::try_from(trade).ok())
+                .filter(|trade| match forwarded {
+                    Some(forwarded) => trade.id as u64 > forwarded,
+                    None => true,
+                })
+                .filter_map(|trade| trade.to_trade(clock).ok())
                 .collect();
 
+            if trades.is_empty() && forwarded.is_some() {
+                return Ok(None);
+            }
+
             Ok(Some(MarketEvent::trades(
+                clock,
                 Box::from(market),
                 trades.into_boxed_slice(),
             )))
@@ -167,7 +243,13 @@ fn process_market_ws_message(
                 }
             };
 
+            let sequence = Sequence::new("orderbook", market, (orderbook_msg.time * 1e6) as u64);
+            if !feed.forward(Some(sequence)) {
+                return Ok(None);
+            }
+
             Ok(Some(MarketEvent::orderbook_update(
+                clock,
                 Box::from(market),
                 Box::new(orderbook),
             )))
@@ -179,3 +261,54 @@ fn process_market_ws_message(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trades_msg(ids: &[i64]) -> String {
+        let trades: Vec<_> = ids
+            .iter()
+            .map(|id| {
+                json!({
+                    "id": id,
+                    "price": 42000.0,
+                    "side": "buy",
+                    "size": 0.1,
+                    "liquidation": false,
+                    "time": "2022-01-01T00:00:00.000000+00:00",
+                })
+            })
+            .collect();
+
+        json!({"channel": "trades", "market": "BTC/USD", "type": "update", "data": trades})
+            .to_string()
+    }
+
+    /// Returns number of trades forwarded from the message
+    fn forwarded_trades(ftx: &Ftx, feed: &mut Feed<'_>, ids: &[i64]) -> usize {
+        let event = ftx
+            .process_feed_msg(&trades_msg(ids), &mut HashMap::new(), feed)
+            .unwrap();
+
+        match event.map(|event| event.r#type) {
+            Some(MarketEventType::Trades(_, trades)) => trades.len(),
+            Some(event) => panic!("unexpected event {event:?}"),
+            None => 0,
+        }
+    }
+
+    #[test]
+    fn test_trades_batched_differently_on_redundant_feeds() {
+        let ftx = Ftx::default();
+        let arbiter = RefCell::new(FeedArbiter::default());
+        let mut first = Feed::new(0, Some(&arbiter));
+        let mut second = Feed::new(1, Some(&arbiter));
+
+        // Trades 1 to 3 batched as [1, 2], [3] and [1], [2, 3]
+        assert_eq!(forwarded_trades(&ftx, &mut first, &[1, 2]), 2);
+        assert_eq!(forwarded_trades(&ftx, &mut second, &[1]), 0);
+        assert_eq!(forwarded_trades(&ftx, &mut second, &[2, 3]), 1);
+        assert_eq!(forwarded_trades(&ftx, &mut first, &[3]), 0);
+    }
+}
@@ -2,6 +2,8 @@ use std::borrow::Cow;
 
 use serde::Deserialize;
 
+use botvana::clock::Clock;
+
 /// FTX Websocket message
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -46,18 +48,18 @@ pub struct Trade<'a> {
     pub time: &'a str,
 }
 
-impl<'a> TryFrom<&Trade<'a>> for botvana::market::trade::Trade {
-    type Error = String;
-
-    fn try_from(trade: &Trade<'a>) -> Result<Self, Self::Error> {
-        Ok(Self {
-            price: trade.price,
-            size: trade.size,
-            received_at: std::time::Instant::now(),
-            time: trade
+impl<'a> Trade<'a> {
+    /// Converts the trade to Botvana trade received at current time of the
+    /// clock
+    pub fn to_trade(&self, clock: &dyn Clock) -> Result<botvana::market::trade::Trade, String> {
+        Ok(botvana::market::trade::Trade {
+            price: self.price,
+            size: self.size,
+            received_at: clock.now(),
+            time: self
                 .time
                 .parse()
-                .map_err(|_| format!("error parsing: {}", trade.time))?,
+                .map_err(|_| format!("error parsing: {}", self.time))?,
         })
     }
 }
@@ -10,9 +10,16 @@ use serde_json::json;
 use surf::Url;
 
 use crate::{
-    market_data::{adapter::*, error::*},
+    market_data::{adapter::*, arbiter::*, error::*},
     prelude::*,
 };
+use botvana::{
+    cfg::{ExchangeConfig, ReplayConfig},
+    clock::{real_clock, Clock, SharedClock},
+};
+
+const REST_URL: &str = "http://localhost:8000";
+const WS_URL: &str = "ws://localhost:8000/v1/ws";
 
 #[derive(Default, Debug)]
 pub struct SerumMetrics {
@@ -23,20 +30,40 @@ pub struct SerumMetrics {
 #[derive(Debug)]
 pub struct Serum {
     pub metrics: SerumMetrics,
-    pub rest_url: &'static str,
-    pub ws_url: &'static str,
+    pub rest_url: Box<str>,
+    pub ws_url: Box<str>,
+    pub capture_dir: Option<Box<str>>,
+    pub replay: Option<ReplayConfig>,
+    pub clock: SharedClock,
 }
 
-impl Default for Serum {
-    fn default() -> Self {
+impl Serum {
+    /// Creates new Serum adapter with given exchange settings
+    pub fn new(config: &ExchangeConfig, clock: SharedClock) -> Self {
+        if config.testnet {
+            warn!("Serum has no testnet, using configured endpoints");
+        }
+
         Self {
-            rest_url: "http://localhost:8000",
-            ws_url: "ws://localhost:8000/v1/ws",
+            rest_url: config
+                .rest_url
+                .clone()
+                .unwrap_or_else(|| Box::from(REST_URL)),
+            ws_url: config.ws_url.clone().unwrap_or_else(|| Box::from(WS_URL)),
+            capture_dir: config.capture_dir.clone(),
+            replay: config.replay.clone(),
+            clock,
             metrics: Default::default(),
         }
     }
 }
 
+impl Default for Serum {
+    fn default() -> Self {
+        Self::new(&ExchangeConfig::new("serum"), real_clock())
+    }
+}
+
 #[async_trait(?Send)]
 impl RestMarketDataAdapter for Serum {
     const NAME: &'static str = "serum";
@@ -45,7 +72,7 @@ impl RestMarketDataAdapter for Serum {
     /// Fetches available markets on Serum
     async fn fetch_markets(&self) -> Result<Box<[Market]>, MarketDataError> {
         let client: surf::Client = surf::Config::new()
-            .set_base_url(Url::parse(self.rest_url).map_err(MarketDataError::with_source)?)
+            .set_base_url(Url::parse(&self.rest_url).map_err(MarketDataError::with_source)?)
             .set_timeout(Some(Duration::from_secs(5)))
             .try_into()
             .map_err(MarketDataError::with_source)?;
@@ -82,10 +109,22 @@ impl WsMarketDataAdapter for Serum {
     }
 
     fn ws_url(&self) -> Box<str> {
-        Box::from(self.ws_url)
+        self.ws_url.clone()
+    }
+
+    fn capture_dir(&self) -> Option<&str> {
+        self.capture_dir.as_deref()
+    }
+
+    fn replay(&self) -> Option<&ReplayConfig> {
+        self.replay.as_ref()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
     }
 
-    fn subscribe_msgs(&mut self, markets: &[&str]) -> Box<[String]> {
+    fn subscribe_msgs(&self, markets: &[&str]) -> Box<[String]> {
         info!("Subscribing for {markets:?}");
 
         Box::new([
@@ -94,16 +133,26 @@ impl WsMarketDataAdapter for Serum {
         ])
     }
 
-    /// Processes Websocket text message
-    fn process_ws_msg(
+    /// Processes Websocket text message received on the feed
+    ///
+    /// Serum messages carry no sequence number.
+    fn process_feed_msg(
         &self,
         msg: &str,
         markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+        feed: &mut Feed<'_>,
     ) -> Result<Option<MarketEvent>, MarketDataError> {
         let ws_msg = serde_json::from_slice::<ws::WsMsg>(msg.as_bytes());
+        let clock = WsMarketDataAdapter::clock(self);
+        let parsed_at = clock.instant();
 
         match ws_msg {
-            Ok(ws_msg) => Ok(process_market_ws_message(ws_msg, markets)?),
+            Ok(ws_msg) => match process_market_ws_message(ws_msg, markets, clock)? {
+                Some(event) if feed.forward(None) => {
+                    Ok(Some(event.with_stage_at(LatencyStage::Parsed, parsed_at)))
+                }
+                _ => Ok(None),
+            },
             Err(e) => {
                 error!("Failed to parse {msg}");
 
@@ -117,6 +166,7 @@ impl WsMarketDataAdapter for Serum {
 fn process_market_ws_message(
     ws_msg: ws::WsMsg,
     markets: &mut HashMap<Box<str>, PlainOrderbook<f64>>,
+    clock: &dyn Clock,
 ) -> Result<Option<MarketEvent>, MarketDataError> {
     info!("ws_msg = {ws_msg:?}");
 
@@ -139,10 +189,12 @@ fn process_market_ws_message(
             let market = trade.market;
             info!("{market} trade: {trade:?}");
 
-            let trade = botvana::market::trade::Trade::try_from(&trade)
+            let trade = trade
+                .to_trade(clock)
                 .map_err(MarketDataError::convert_error)?;
 
             Ok(Some(MarketEvent::trades(
+                clock,
                 Box::from(market),
                 Box::new([trade]),
             )))
@@ -160,6 +212,7 @@ fn process_market_ws_message(
             markets.insert(Box::from(snapshot.market), orderbook.clone());
 
             Ok(Some(MarketEvent::orderbook_update(
+                clock,
                 Box::from(snapshot.market),
                 Box::new(orderbook),
             )))
@@ -177,6 +230,7 @@ fn process_market_ws_message(
             );
 
             Ok(Some(MarketEvent::orderbook_update(
+                clock,
                 Box::from(update.market),
                 Box::new(orderbook.clone()),
             )))
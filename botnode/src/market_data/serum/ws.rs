@@ -1,5 +1,7 @@
 use serde::Deserialize;
 
+use botvana::clock::Clock;
+
 /// Serum Websocket message
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -158,18 +160,18 @@ pub struct Trade<'a> {
     maker_account: &'a str,
 }
 
-impl<'a> TryFrom<&Trade<'a>> for botvana::market::trade::Trade {
-    type Error = String;
-
-    fn try_from(trade: &Trade<'a>) -> Result<Self, Self::Error> {
-        Ok(Self {
-            price: trade.price.parse::<f64>().map_err(|e| e.to_string())?,
-            size: trade.size.parse::<f64>().map_err(|e| e.to_string())?,
-            received_at: std::time::Instant::now(),
-            time: trade
+impl<'a> Trade<'a> {
+    /// Converts the trade to Botvana trade received at current time of the
+    /// clock
+    pub fn to_trade(&self, clock: &dyn Clock) -> Result<botvana::market::trade::Trade, String> {
+        Ok(botvana::market::trade::Trade {
+            price: self.price.parse::<f64>().map_err(|e| e.to_string())?,
+            size: self.size.parse::<f64>().map_err(|e| e.to_string())?,
+            received_at: clock.now(),
+            time: self
                 .timestamp
                 .parse()
-                .map_err(|_| format!("error parsing: {}", trade.timestamp))?,
+                .map_err(|_| format!("error parsing: {}", self.timestamp))?,
         })
     }
 }
@@ -444,7 +446,7 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_trade() {
+    fn test_to_trade() {
         let trade = Trade {
             id: "1234",
             market: "SOL/USDC",
@@ -458,6 +460,6 @@ mod tests {
             maker_account: "xyz",
         };
 
-        botvana::market::trade::Trade::try_from(&trade).unwrap();
+        trade.to_trade(&botvana::clock::RealClock).unwrap();
     }
 }
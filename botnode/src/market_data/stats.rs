@@ -0,0 +1,258 @@
+//! Market data statistics
+//!
+//! Collected by the adapter event loop and periodically sent to the control
+//! engine as [`MarketDataMetrics`]. Counters are also exported to the
+//! metrics registry.
+
+use std::{
+    sync::Arc,
+    time::{Instant, UNIX_EPOCH},
+};
+
+use hdrhistogram::Histogram;
+
+use crate::{prelude::*, util::*};
+use botvana::{
+    metrics::{self, Counter, Gauge, LATENCY_BUCKETS_US},
+    report::{MarketDataMetrics, QueueDepth},
+};
+
+/// Queue length of the metrics reports
+pub const METRICS_QUEUE_LEN: usize = 4;
+/// Interval of reporting the metrics
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Statistics of the market data of an exchange
+pub struct MarketDataStats {
+    exchange: ExchangeId,
+    messages: u64,
+    reconnects: u64,
+    latency: Histogram<u64>,
+    metrics_tx: spsc_queue::Producer<MarketDataMetrics>,
+    exported: ExportedMetrics,
+    last_report: Instant,
+}
+
+/// Handles of the metrics in the registry
+struct ExportedMetrics {
+    label: Box<str>,
+    messages: Arc<Counter>,
+    parse_errors: Arc<Counter>,
+    reconnects: Arc<Counter>,
+    duplicates: Arc<Counter>,
+    latency: Arc<metrics::Histogram>,
+    /// Bid and ask depth of each market
+    depths: HashMap<Box<str>, (Arc<Gauge>, Arc<Gauge>)>,
+}
+
+impl ExportedMetrics {
+    fn new(exchange: ExchangeId) -> Self {
+        let registry = metrics::registry();
+        let label = exchange.to_string().to_lowercase();
+        let labels = [("exchange", &*label)];
+
+        Self {
+            messages: registry.counter(
+                "botnode_market_data_messages_total",
+                "Messages received from the exchange",
+                &labels,
+            ),
+            parse_errors: registry.counter(
+                "botnode_market_data_parse_errors_total",
+                "Messages from the exchange that failed to parse",
+                &labels,
+            ),
+            reconnects: registry.counter(
+                "botnode_market_data_reconnects_total",
+                "Reconnects to the exchange",
+                &labels,
+            ),
+            duplicates: registry.counter(
+                "botnode_market_data_duplicates_total",
+                "Messages dropped as copies of messages received on another redundant feed",
+                &labels,
+            ),
+            latency: registry.histogram(
+                "botnode_market_data_latency_microseconds",
+                "Latency between exchange timestamp and receiving the event",
+                &labels,
+                LATENCY_BUCKETS_US,
+            ),
+            depths: HashMap::new(),
+            label: label.into_boxed_str(),
+        }
+    }
+
+    /// Returns bid and ask depth gauges of the market
+    fn depth(&mut self, market: &str) -> &(Arc<Gauge>, Arc<Gauge>) {
+        let exchange = &self.label;
+
+        if !self.depths.contains_key(market) {
+            let registry = metrics::registry();
+            let gauge = |side: &str| {
+                registry.gauge(
+                    "botnode_orderbook_depth",
+                    "Number of price levels in the orderbook",
+                    &[("exchange", exchange), ("market", market), ("side", side)],
+                )
+            };
+            let gauges = (gauge("bid"), gauge("ask"));
+
+            self.depths.insert(Box::from(market), gauges);
+        }
+
+        &self.depths[market]
+    }
+}
+
+impl MarketDataStats {
+    pub fn new(exchange: ExchangeId, metrics_tx: spsc_queue::Producer<MarketDataMetrics>) -> Self {
+        Self {
+            exchange,
+            messages: 0,
+            reconnects: 0,
+            latency: latency_histogram(),
+            metrics_tx,
+            exported: ExportedMetrics::new(exchange),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Returns producer of the metrics queue
+    pub fn metrics_tx(&self) -> spsc_queue::Producer<MarketDataMetrics> {
+        self.metrics_tx.clone()
+    }
+
+    /// Records message received from the exchange
+    pub fn record_message(&mut self) {
+        self.messages += 1;
+        self.exported.messages.inc();
+    }
+
+    /// Records message that failed to parse
+    pub fn record_parse_error(&mut self) {
+        self.exported.parse_errors.inc();
+    }
+
+    /// Records latency of the market event relative to the exchange
+    /// timestamp and depth of updated orderbook
+    pub fn record_event(&mut self, event: &MarketEvent) {
+        if let Some(latency) = exchange_latency(event) {
+            let latency = record_latency(&mut self.latency, latency);
+
+            self.exported.latency.observe(latency as f64);
+        }
+
+        if let MarketEventType::OrderbookUpdate(market, orderbook) = &event.r#type {
+            let (bids, asks) = self.exported.depth(market);
+
+            bids.set(orderbook.bids.price_vec.len() as i64);
+            asks.set(orderbook.asks.price_vec.len() as i64);
+        }
+    }
+
+    /// Records reconnect to the exchange
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+        self.exported.reconnects.inc();
+    }
+
+    /// Records message dropped as copy of one received on another feed
+    pub fn record_duplicate(&mut self) {
+        self.exported.duplicates.inc();
+    }
+
+    /// Returns whether the metrics are due to be reported
+    pub fn report_due(&self) -> bool {
+        self.last_report.elapsed() >= REPORT_INTERVAL
+    }
+
+    /// Sends metrics collected since the last report and starts new window
+    pub fn report<const N: usize>(
+        &mut self,
+        max_throughput: u64,
+        data_txs: &ProducersArray<MarketEvent, N>,
+    ) {
+        let name = format!("market-data-{}", self.exchange.to_string().to_lowercase());
+        let metrics = MarketDataMetrics {
+            exchange: self.exchange,
+            messages: self.messages,
+            max_throughput,
+            latency: percentiles(&self.latency),
+            reconnects: self.reconnects,
+            queues: data_txs
+                .0
+                .iter()
+                .enumerate()
+                .map(|(idx, tx)| QueueDepth {
+                    name: Box::from(format!("{name}-{idx}")),
+                    depth: tx.size(),
+                    capacity: tx.capacity(),
+                })
+                .collect(),
+        };
+
+        if self.metrics_tx.try_push(metrics).is_some() {
+            debug!("Metrics queue full, dropping report");
+        }
+
+        self.messages = 0;
+        self.latency.reset();
+        self.last_report = Instant::now();
+    }
+}
+
+/// Returns time between the exchange timestamp and receiving the event
+fn exchange_latency(event: &MarketEvent) -> Option<Duration> {
+    let exchange_time = match &event.r#type {
+        MarketEventType::OrderbookUpdate(_, orderbook)
+            if orderbook.time.is_finite() && orderbook.time > 0.0 =>
+        {
+            UNIX_EPOCH + Duration::from_secs_f64(orderbook.time)
+        }
+        MarketEventType::Trades(_, trades) => trades.first()?.time.into(),
+        _ => return None,
+    };
+
+    event.timestamp.duration_since(exchange_time).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botvana::market::trade::Trade;
+
+    #[test]
+    fn test_exchange_latency() {
+        let now = UNIX_EPOCH + Duration::from_secs(100);
+        let mut orderbook = PlainOrderbook::with_capacity(1);
+        orderbook.time = 99.5;
+
+        let event = MarketEvent {
+            r#type: MarketEventType::OrderbookUpdate(Box::from("BTC/USD"), Box::new(orderbook)),
+            timestamp: now,
+            trace: LatencyTrace::default(),
+        };
+        assert_eq!(exchange_latency(&event), Some(Duration::from_millis(500)));
+
+        let trade = Trade {
+            price: 1.0,
+            size: 1.0,
+            time: DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(99)),
+            received_at: now,
+        };
+        let event = MarketEvent {
+            r#type: MarketEventType::Trades(Box::from("BTC/USD"), Box::new([trade])),
+            timestamp: now,
+            trace: LatencyTrace::default(),
+        };
+        assert_eq!(exchange_latency(&event), Some(Duration::from_secs(1)));
+
+        let event = MarketEvent {
+            r#type: MarketEventType::MidPriceChange(Box::from("BTC/USD"), 1.0, 2.0),
+            timestamp: now,
+            trace: LatencyTrace::default(),
+        };
+        assert_eq!(exchange_latency(&event), None);
+    }
+}
@@ -0,0 +1,103 @@
+//! Prometheus metrics endpoint
+//!
+//! Serves the metrics registry over plain HTTP on `GET /metrics`. Requests
+//! are handled one at a time on a dedicated executor, away from the engines.
+
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use glommio::net::TcpListener;
+
+use crate::prelude::*;
+
+/// Maximum size of the request head
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Spawns executor serving the metrics on given address
+pub fn spawn_metrics_server(
+    addr: String,
+) -> Result<glommio::ExecutorJoinHandle<()>, StartEngineError> {
+    LocalExecutorBuilder::default()
+        .name("metrics")
+        .spawn(move || async move {
+            if let Err(e) = serve(&addr).await {
+                error!("Metrics server failed: {e}");
+            }
+        })
+        .map_err(StartEngineError::from)
+}
+
+/// Accepts connections and responds with the metrics
+async fn serve(addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    info!("serving metrics on {addr}");
+
+    loop {
+        let mut stream = listener.accept().await?;
+        let mut request = Vec::with_capacity(512);
+        let mut buf = [0; 512];
+
+        while !request.windows(4).any(|window| window == b"\r\n\r\n")
+            && request.len() < MAX_REQUEST_LEN
+        {
+            match stream.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(len) => request.extend_from_slice(&buf[..len]),
+                Err(e) => {
+                    debug!("Failed to read metrics request: {e}");
+                    break;
+                }
+            }
+        }
+
+        let response = response(&String::from_utf8_lossy(&request));
+
+        if let Err(e) = stream.write_all(response.as_bytes()).await {
+            debug!("Failed to write metrics response: {e}");
+        }
+        let _ = stream.close().await;
+    }
+}
+
+/// Returns HTTP response to the request
+fn response(request: &str) -> String {
+    let mut parts = request.split_whitespace();
+
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            botvana::metrics::CONTENT_TYPE,
+            botvana::metrics::registry().encode(),
+        ),
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", String::from("Not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("Method not allowed\n"),
+        ),
+    };
+
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response() {
+        botvana::metrics::registry()
+            .counter("botnode_test_total", "Test counter", &[])
+            .inc();
+
+        let response = response("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP "));
+        assert!(response.contains("\nbotnode_test_total 1\n"));
+
+        assert!(response("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found"));
+        assert!(response("POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+    }
+}
@@ -0,0 +1,55 @@
+//! Market data recorder
+//!
+//! Persists market data received from the market data engines to rotating,
+//! gzip-compressed, append-only files. The engine hands market events off to
+//! a writer thread over a bounded queue so compression and file I/O never
+//! block its loop, events are dropped and counted when the writer can't keep
+//! up.
+
+pub mod engine;
+pub mod file;
+
+use serde::{Deserialize, Serialize};
+
+use botvana::{exchange::ExchangeId, market::orderbook::PriceLevelsVec};
+
+/// Single recorded market data entry
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MarketRecord {
+    pub exchange: ExchangeId,
+    pub market: Box<str>,
+    /// Timestamp reported by the exchange, units are exchange specific
+    pub exchange_time: Option<f64>,
+    /// Local receive time in nanoseconds since UNIX epoch
+    pub received_at: u64,
+    pub data: RecordData,
+}
+
+/// Recorded market data
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum RecordData {
+    /// Full orderbook, written as the first orderbook record of each market
+    /// in a file
+    OrderbookSnapshot {
+        bids: PriceLevelsVec<f64>,
+        asks: PriceLevelsVec<f64>,
+    },
+    /// Changed price levels, removed levels have zero size
+    OrderbookDelta {
+        bids: PriceLevelsVec<f64>,
+        asks: PriceLevelsVec<f64>,
+    },
+    /// Trades
+    Trades(Box<[RecordedTrade]>),
+    /// Best bid and ask
+    Ticker { bid: f64, ask: f64 },
+}
+
+/// Recorded trade
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RecordedTrade {
+    pub price: f64,
+    pub size: f64,
+    /// Exchange time of the trade in nanoseconds since UNIX epoch
+    pub time: i64,
+}
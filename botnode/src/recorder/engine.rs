@@ -0,0 +1,395 @@
+use std::{
+    io,
+    sync::mpsc::{self, RecvTimeoutError, TrySendError},
+    thread,
+    time::{Instant, UNIX_EPOCH},
+};
+
+use super::{file::RecordFileWriter, MarketRecord, RecordData, RecordedTrade};
+use crate::{prelude::*, topology::unpin_current_thread};
+use botvana::cfg::RecorderConfig;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of market events waiting for the writer
+const QUEUE_LEN: usize = 16_384;
+
+/// Minimum interval between summaries of events dropped on full queue
+const DROPPED_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Last recorded orderbook per exchange market
+type RecordedBooks = HashMap<(ExchangeId, Box<str>), PlainOrderbook<f64>>;
+
+/// Market data recording engine
+pub struct RecorderEngine {
+    config: RecorderConfig,
+    market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    status_tx: spsc_queue::Producer<EngineStatus>,
+    status_rx: spsc_queue::Consumer<EngineStatus>,
+    heartbeat: Heartbeat,
+}
+
+impl RecorderEngine {
+    pub fn new(
+        config: RecorderConfig,
+        market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    ) -> Self {
+        let (status_tx, status_rx) = spsc_queue::make(1);
+
+        Self {
+            config,
+            market_data_rxs,
+            status_tx,
+            status_rx,
+            heartbeat: Heartbeat::new(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Engine for RecorderEngine {
+    fn name(&self) -> String {
+        "recorder-engine".to_string()
+    }
+
+    fn status_rx(&self) -> spsc_queue::Consumer<EngineStatus> {
+        self.status_rx.clone()
+    }
+
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        Some(self.heartbeat.rx())
+    }
+
+    async fn start(self, shutdown: Shutdown) -> Result<(), EngineError> {
+        info!("Starting recorder engine");
+
+        self.status_tx.try_push(EngineStatus::Booting);
+
+        let writer = match RecordFileWriter::new(&self.config) {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!(
+                    "Failed to open recorder directory {}: {e}",
+                    self.config.path
+                );
+                self.status_tx.try_push(EngineStatus::Error);
+
+                return Err(EngineError::with_source(e));
+            }
+        };
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let writer = thread::Builder::new()
+            .name("recorder-writer".to_string())
+            .spawn(move || {
+                if let Err(e) = unpin_current_thread() {
+                    warn!("Failed to unpin recorder writer thread: {e}");
+                }
+
+                run_writer(writer, rx)
+            })
+            .map_err(EngineError::with_source)?;
+
+        run_recorder_loop(
+            self.status_tx,
+            self.heartbeat,
+            self.market_data_rxs,
+            (tx, writer),
+            shutdown,
+        )
+        .await
+    }
+}
+
+/// Recorder engine loop
+///
+/// Ends when the writer fails, returning its error.
+async fn run_recorder_loop(
+    status_tx: spsc_queue::Producer<EngineStatus>,
+    heartbeat: Heartbeat,
+    market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
+    (tx, writer): (
+        mpsc::SyncSender<(ExchangeId, MarketEvent)>,
+        thread::JoinHandle<io::Result<()>>,
+    ),
+    shutdown: Shutdown,
+) -> Result<(), EngineError> {
+    let _token = shutdown
+        .delay_shutdown_token()
+        .map_err(EngineError::with_source)?;
+    let mut dropped = 0u64;
+    let mut last_dropped_report = Instant::now();
+
+    status_tx.try_push(EngineStatus::Running);
+
+    loop {
+        heartbeat.tick();
+        glommio::yield_if_needed().await;
+
+        if shutdown.shutdown_started() {
+            info!("shutting down recorder engine");
+
+            status_tx.try_push(EngineStatus::ShuttingDown);
+
+            // The writer finishes the current file once the queue is drained
+            drop(tx);
+            return join_writer(writer);
+        }
+
+        for (exchange, market_data_rx) in market_data_rxs.iter() {
+            if let Some(event) = market_data_rx.try_pop() {
+                heartbeat.event();
+
+                let exchange = match exchange.parse::<ExchangeId>() {
+                    Ok(exchange) => exchange,
+                    Err(e) => {
+                        warn!("Not recording event: {e}");
+                        continue;
+                    }
+                };
+
+                match tx.try_send((exchange, event)) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => dropped += 1,
+                    Err(TrySendError::Disconnected(_)) => {
+                        status_tx.try_push(EngineStatus::Error);
+
+                        return join_writer(writer);
+                    }
+                }
+            }
+        }
+
+        if last_dropped_report.elapsed() >= DROPPED_REPORT_INTERVAL {
+            if dropped > 0 {
+                warn!("Recorder queue full, dropped {dropped} market events");
+                dropped = 0;
+            }
+            last_dropped_report = Instant::now();
+        }
+    }
+}
+
+/// Waits for the writer thread to stop and returns its error
+fn join_writer(writer: thread::JoinHandle<io::Result<()>>) -> Result<(), EngineError> {
+    match writer.join() {
+        Ok(result) => result.map_err(EngineError::with_source),
+        Err(_) => Err(EngineError::with_source(io::Error::new(
+            io::ErrorKind::Other,
+            "recorder writer panicked",
+        ))),
+    }
+}
+
+/// Runs the writer loop until the engine drops the sender
+///
+/// Returns the first error writing the files, the engine stops recording.
+fn run_writer(
+    mut writer: RecordFileWriter,
+    rx: mpsc::Receiver<(ExchangeId, MarketEvent)>,
+) -> io::Result<()> {
+    let mut books = RecordedBooks::new();
+    let mut last_flush = Instant::now();
+
+    loop {
+        let timeout = FLUSH_INTERVAL.saturating_sub(last_flush.elapsed());
+
+        match rx.recv_timeout(timeout) {
+            Ok((exchange, event)) => {
+                // Each file starts with orderbook snapshots so it can be
+                // read on its own
+                if writer.rotate_if_needed()? {
+                    books.clear();
+                }
+
+                if let Some(record) = market_record(exchange, event, &mut books) {
+                    writer.write(&record)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return writer.finish(),
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            writer.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+}
+
+/// Converts market event to the recorded form
+///
+/// Orderbook updates are recorded as the difference to the last recorded
+/// orderbook of the market.
+fn market_record(
+    exchange: ExchangeId,
+    event: MarketEvent,
+    books: &mut RecordedBooks,
+) -> Option<MarketRecord> {
+    let received_at = event
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+
+    let (market, exchange_time, data) = match event.r#type {
+        MarketEventType::Markets(_) => return None,
+        MarketEventType::Trades(market, trades) => {
+            let trades = trades
+                .iter()
+                .map(|trade| RecordedTrade {
+                    price: trade.price,
+                    size: trade.size,
+                    time: trade.time.timestamp_nanos(),
+                })
+                .collect();
+
+            (market, None, RecordData::Trades(trades))
+        }
+        MarketEventType::OrderbookUpdate(market, orderbook) => {
+            let orderbook = *orderbook;
+            let data = match books.insert((exchange, market.clone()), orderbook.clone()) {
+                Some(prev) => {
+                    let bids = prev.bids.diff(&orderbook.bids);
+                    let asks = prev.asks.diff(&orderbook.asks);
+
+                    if bids.len() == 0 && asks.len() == 0 {
+                        return None;
+                    }
+
+                    RecordData::OrderbookDelta { bids, asks }
+                }
+                None => RecordData::OrderbookSnapshot {
+                    bids: orderbook.bids,
+                    asks: orderbook.asks,
+                },
+            };
+
+            (market, Some(orderbook.time), data)
+        }
+        MarketEventType::MidPriceChange(market, bid, ask) => {
+            (market, None, RecordData::Ticker { bid, ask })
+        }
+    };
+
+    Some(MarketRecord {
+        exchange,
+        market,
+        exchange_time,
+        received_at,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::file::{list_files, RecordFileReader};
+    use botvana::clock::RealClock;
+
+    fn orderbook_update(levels: &[(f64, f64)], time: f64) -> MarketEvent {
+        MarketEvent::orderbook_update(
+            &RealClock,
+            Box::from("BTC/USD"),
+            Box::new(PlainOrderbook {
+                bids: PriceLevelsVec::from_tuples_vec(levels),
+                asks: PriceLevelsVec::new(),
+                time,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_market_record_orderbook_delta() {
+        let mut books = RecordedBooks::new();
+
+        let snapshot = market_record(
+            ExchangeId::Ftx,
+            orderbook_update(&[(100.0, 1.0), (101.0, 2.0)], 1.0),
+            &mut books,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            snapshot.data,
+            RecordData::OrderbookSnapshot { .. }
+        ));
+        assert_eq!(snapshot.exchange_time, Some(1.0));
+
+        let delta = market_record(
+            ExchangeId::Ftx,
+            orderbook_update(&[(100.0, 1.0), (101.0, 3.0)], 2.0),
+            &mut books,
+        )
+        .unwrap();
+
+        match delta.data {
+            RecordData::OrderbookDelta { bids, asks } => {
+                assert_eq!(bids.price_vec, vec![101.0]);
+                assert_eq!(bids.size_vec, vec![3.0]);
+                assert_eq!(asks.len(), 0);
+            }
+            data => panic!("unexpected record data {data:?}"),
+        }
+
+        assert!(market_record(
+            ExchangeId::Ftx,
+            orderbook_update(&[(100.0, 1.0), (101.0, 3.0)], 3.0),
+            &mut books,
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_writer_records_queued_events() {
+        let dir =
+            std::env::temp_dir().join(format!("botnode-recorder-writer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = RecorderConfig {
+            path: Box::from(dir.to_str().unwrap()),
+            ..Default::default()
+        };
+        let writer = RecordFileWriter::new(&config).unwrap();
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+
+        for n in 0..3 {
+            let event = orderbook_update(&[(100.0, 1.0 + n as f64)], n as f64);
+            tx.send((ExchangeId::Ftx, event)).unwrap();
+        }
+        drop(tx);
+
+        run_writer(writer, rx).unwrap();
+
+        let files = list_files(&dir).unwrap();
+        let records: Vec<_> = RecordFileReader::open(&files[0])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert!(matches!(
+            records[0].data,
+            RecordData::OrderbookSnapshot { .. }
+        ));
+        assert!(matches!(records[2].data, RecordData::OrderbookDelta { .. }));
+    }
+
+    #[test]
+    fn test_market_record_ticker() {
+        let record = market_record(
+            ExchangeId::BinanceSpot,
+            MarketEvent::mid_price_change(&RealClock, Box::from("BTCUSDT"), 41999.0, 42000.0),
+            &mut RecordedBooks::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            record.data,
+            RecordData::Ticker {
+                bid: 41999.0,
+                ask: 42000.0
+            }
+        );
+        assert!(record.received_at > 0);
+    }
+}
@@ -0,0 +1,284 @@
+//! Recorded market data file format
+//!
+//! Each file is a gzip stream starting with a magic header followed by
+//! length-prefixed bincode encoded [`MarketRecord`]s. Files are named by the
+//! time they were opened, so sorting the names sorts them chronologically.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use super::MarketRecord;
+use botvana::cfg::RecorderConfig;
+
+const MAGIC: &[u8; 6] = b"BVREC\x01";
+const FILE_EXTENSION: &str = "bvrec.gz";
+
+/// Rotating writer of market data files
+pub struct RecordFileWriter {
+    dir: PathBuf,
+    max_file_size: u64,
+    rotate_interval: Duration,
+    encoder: Option<GzEncoder<BufWriter<File>>>,
+    opened_at: Instant,
+    written: u64,
+    files_opened: u64,
+}
+
+impl RecordFileWriter {
+    /// Creates new writer, the first file is opened with the first record
+    pub fn new(config: &RecorderConfig) -> io::Result<Self> {
+        let dir = PathBuf::from(config.path.as_ref());
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            max_file_size: config.max_file_size,
+            rotate_interval: Duration::from_secs(config.rotate_interval_secs),
+            encoder: None,
+            opened_at: Instant::now(),
+            written: 0,
+            files_opened: 0,
+        })
+    }
+
+    /// Finishes the current file and opens a new one when the current file
+    /// reached its size or age limit
+    ///
+    /// Returns `true` when a new file was opened.
+    pub fn rotate_if_needed(&mut self) -> io::Result<bool> {
+        let needs_rotation = match self.encoder {
+            Some(_) => {
+                self.written >= self.max_file_size
+                    || self.opened_at.elapsed() >= self.rotate_interval
+            }
+            None => true,
+        };
+
+        if needs_rotation {
+            self.finish()?;
+            self.open_file()?;
+        }
+
+        Ok(needs_rotation)
+    }
+
+    /// Appends the record to the current file
+    pub fn write(&mut self, record: &MarketRecord) -> io::Result<()> {
+        if self.encoder.is_none() {
+            self.open_file()?;
+        }
+
+        let buf = bincode::serialize(record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let encoder = self.encoder.as_mut().expect("file is open");
+
+        encoder.write_all(&(buf.len() as u32).to_le_bytes())?;
+        encoder.write_all(&buf)?;
+        self.written += buf.len() as u64 + 4;
+
+        Ok(())
+    }
+
+    /// Flushes buffered data to the current file
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.encoder.as_mut() {
+            Some(encoder) => encoder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Finishes the current file
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn open_file(&mut self) -> io::Result<()> {
+        let name = format!(
+            "market-data-{}-{:04}.{}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+            self.files_opened,
+            FILE_EXTENSION
+        );
+        let path = self.dir.join(name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+
+        encoder.write_all(MAGIC)?;
+
+        tracing::info!("recording market data to {path:?}");
+
+        self.encoder = Some(encoder);
+        self.opened_at = Instant::now();
+        self.written = 0;
+        self.files_opened += 1;
+
+        Ok(())
+    }
+}
+
+impl Drop for RecordFileWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            tracing::error!("Failed to finish recorded file: {e}");
+        }
+    }
+}
+
+/// Reader of a single market data file
+pub struct RecordFileReader {
+    decoder: GzDecoder<BufReader<File>>,
+}
+
+impl RecordFileReader {
+    /// Opens the file and validates its header
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut decoder = GzDecoder::new(BufReader::new(file));
+        let mut magic = [0u8; 6];
+
+        decoder.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a market data file",
+            ));
+        }
+
+        Ok(Self { decoder })
+    }
+}
+
+impl Iterator for RecordFileReader {
+    type Item = io::Result<MarketRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0u8; 4];
+
+        match self.decoder.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+
+        if let Err(e) = self.decoder.read_exact(&mut buf) {
+            return Some(Err(e));
+        }
+
+        Some(bincode::deserialize(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+/// Returns recorded files in given directory in chronological order
+pub fn list_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.ends_with(FILE_EXTENSION))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::RecordData;
+    use botvana::exchange::ExchangeId;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("botnode-recorder-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn ticker(received_at: u64) -> MarketRecord {
+        MarketRecord {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            exchange_time: None,
+            received_at,
+            data: RecordData::Ticker {
+                bid: 41999.0,
+                ask: 42000.0,
+            },
+        }
+    }
+
+    #[test]
+    fn write_and_read_records() {
+        let dir = test_dir("roundtrip");
+        let config = RecorderConfig {
+            path: Box::from(dir.to_str().unwrap()),
+            ..Default::default()
+        };
+        let mut writer = RecordFileWriter::new(&config).unwrap();
+
+        assert!(writer.rotate_if_needed().unwrap());
+        for n in 0..10 {
+            writer.write(&ticker(n)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let files = list_files(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let records: Vec<_> = RecordFileReader::open(&files[0])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 10);
+        assert_eq!(records[3], ticker(3));
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = test_dir("rotate");
+        let config = RecorderConfig {
+            path: Box::from(dir.to_str().unwrap()),
+            max_file_size: 1,
+            ..Default::default()
+        };
+        let mut writer = RecordFileWriter::new(&config).unwrap();
+
+        for n in 0..3 {
+            writer.rotate_if_needed().unwrap();
+            writer.write(&ticker(n)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let files = list_files(&dir).unwrap();
+        assert_eq!(files.len(), 3);
+
+        let received: Vec<_> = files
+            .iter()
+            .flat_map(|file| RecordFileReader::open(file).unwrap())
+            .map(|record| record.unwrap().received_at)
+            .collect();
+
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+}
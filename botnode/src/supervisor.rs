@@ -0,0 +1,490 @@
+//! Engine supervision
+//!
+//! Engines spawned with [`spawn_executor`] report their exit to the
+//! [`Supervisor`], which restarts them according to their [`RestartPolicy`]
+//! with exponential backoff. Restarted engines are built by the respawn
+//! function given when the engine started supervision, which reuses the
+//! channels of the exited engine so the engines on the other ends of the
+//! channels are unaffected. Restarted engines run in the executor they exited
+//! from, see [`CpuExecutor`]. Panics of supervised engines stop their executor
+//! and are reported like failures, see [`is_supervised_thread`].
+
+use std::{
+    cell::{Cell, RefCell},
+    pin::Pin,
+    rc::Rc,
+    sync::mpsc::{self, TryRecvError},
+    time::Instant,
+};
+
+use crate::prelude::*;
+use botvana::engine::EngineExit;
+
+/// Delay before the first restart of failed engine
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum delay before restarting failed engine
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Engine running at least this long is considered recovered and its
+/// backoff is reset
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// Preemption timer of executors shared by multiple engines, their engine
+/// loops yield to each other when it expires
+const SHARED_PREEMPT_TIMER: Duration = Duration::from_millis(1);
+
+/// Interval of checking for engines spawned into running executor
+const SPAWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+thread_local! {
+    /// Whether the thread runs executor of supervised engines
+    static SUPERVISED: Cell<bool> = Cell::new(false);
+}
+
+/// Returns whether the current thread runs supervised engines
+///
+/// Panics on such threads are detected and reported by the supervisor, they
+/// don't need to stop the whole bot.
+pub fn is_supervised_thread() -> bool {
+    SUPERVISED.with(Cell::get)
+}
+
+/// Result of the engine reported when it exits
+type ExitResult = Result<(), Box<str>>;
+
+/// Future of the started engine
+type EngineFuture = Pin<Box<dyn Future<Output = Result<(), EngineError>>>>;
+
+/// Function starting the engine
+type StartFn = Box<dyn FnOnce(Shutdown) -> EngineFuture + Send>;
+
+/// Engine to start in executor and the queue its exit is reported on
+type EngineStart = (StartFn, spsc_queue::Producer<ExitResult>);
+
+/// Function building new instance of an engine
+pub type Respawn = Box<dyn FnMut() -> BoxedEngine>;
+
+/// When to restart an exited engine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    /// The engine is never restarted
+    Never,
+    /// The engine is restarted when it fails, giving up after
+    /// `max_restarts` consecutive failures
+    OnFailure { max_restarts: u32 },
+    /// The engine is restarted whenever it exits
+    Always,
+}
+
+impl RestartPolicy {
+    /// Returns delay before restarting the engine, `None` when it shouldn't
+    /// be restarted
+    fn restart_delay(&self, failed: bool, consecutive: u32) -> Option<Duration> {
+        let restart = match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure { max_restarts } => failed && consecutive < *max_restarts,
+            RestartPolicy::Always => true,
+        };
+
+        if restart {
+            Some(
+                INITIAL_BACKOFF
+                    .saturating_mul(2u32.saturating_pow(consecutive))
+                    .min(MAX_BACKOFF),
+            )
+        } else {
+            None
+        }
+    }
+}
+
+/// Receivers of status and heartbeats of an engine
+pub struct EngineReceivers {
+    pub status_rx: spsc_queue::Consumer<EngineStatus>,
+    pub heartbeat_rx: Option<spsc_queue::Consumer<HeartbeatReport>>,
+}
+
+/// Engine that wasn't started yet
+pub struct BoxedEngine {
+    name: String,
+    receivers: EngineReceivers,
+    start: StartFn,
+}
+
+impl BoxedEngine {
+    pub fn new<E: Engine + Send + 'static>(engine: E) -> Self {
+        Self {
+            name: engine.name(),
+            receivers: EngineReceivers {
+                status_rx: engine.status_rx(),
+                heartbeat_rx: engine.heartbeat_rx(),
+            },
+            start: Box::new(move |shutdown| engine.start(shutdown)),
+        }
+    }
+}
+
+/// Engine running in an executor
+pub struct SpawnedEngine {
+    executor: CpuExecutor,
+    receivers: EngineReceivers,
+    exit_rx: spsc_queue::Consumer<ExitResult>,
+}
+
+/// Starts given engines in new executor pinned to given CPU and reports
+/// their exits to the supervisor
+pub fn spawn_executor(
+    cpu: usize,
+    engines: Vec<BoxedEngine>,
+    shutdown: Shutdown,
+) -> Result<Vec<SpawnedEngine>, StartEngineError> {
+    let name = engines
+        .iter()
+        .map(|engine| engine.name.as_str())
+        .collect::<Vec<_>>()
+        .join("+");
+
+    CpuExecutor::new(cpu, name, engines.len() > 1).spawn(engines, shutdown)
+}
+
+/// Executor pinned to a CPU running supervised engines
+///
+/// The executor keeps running while engines can be restarted into it, so
+/// restarted engines keep sharing the CPU with the engines they were placed
+/// with instead of oversubscribing it with another executor. Executor that
+/// stopped, e.g. because one of its engines panicked, is replaced by new one
+/// on the same CPU when an engine is restarted.
+///
+/// Executor shared by multiple engines preempts them every
+/// [`SHARED_PREEMPT_TIMER`].
+#[derive(Clone)]
+pub struct CpuExecutor(Rc<RefCell<ExecutorState>>);
+
+struct ExecutorState {
+    cpu: usize,
+    name: String,
+    shared: bool,
+    /// Queue of engines to start in the running executor and its handle
+    running: Option<(mpsc::Sender<EngineStart>, glommio::ExecutorJoinHandle<()>)>,
+}
+
+impl CpuExecutor {
+    fn new(cpu: usize, name: String, shared: bool) -> Self {
+        Self(Rc::new(RefCell::new(ExecutorState {
+            cpu,
+            name,
+            shared,
+            running: None,
+        })))
+    }
+
+    /// Starts given engines in the executor, starting new executor when it
+    /// doesn't run
+    pub fn spawn(
+        &self,
+        engines: Vec<BoxedEngine>,
+        shutdown: Shutdown,
+    ) -> Result<Vec<SpawnedEngine>, StartEngineError> {
+        let mut spawned = Vec::with_capacity(engines.len());
+        let mut starts = Vec::with_capacity(engines.len());
+        for engine in engines {
+            let (exit_tx, exit_rx) = spsc_queue::make(1);
+
+            starts.push((engine.start, exit_tx));
+            spawned.push(SpawnedEngine {
+                executor: self.clone(),
+                receivers: engine.receivers,
+                exit_rx,
+            });
+        }
+
+        let mut state = self.0.borrow_mut();
+
+        // Engines are handed back when the executor stopped
+        if let Some((tx, _)) = state.running.as_ref() {
+            starts = starts
+                .into_iter()
+                .filter_map(|start| tx.send(start).err().map(|mpsc::SendError(start)| start))
+                .collect();
+
+            if starts.is_empty() {
+                return Ok(spawned);
+            }
+        }
+
+        if let Some((_, handle)) = state.running.take() {
+            if let Err(e) = handle.join() {
+                warn!("{} executor stopped: {e:?}", state.name);
+            }
+        }
+
+        let mut builder = engine_executor(state.cpu, &state.name);
+        if state.shared {
+            builder = builder.preempt_timer(SHARED_PREEMPT_TIMER);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let handle = builder
+            .spawn(move || run_executor(starts, rx, shutdown))
+            .map_err(StartEngineError::from)?;
+
+        state.running = Some((tx, handle));
+
+        Ok(spawned)
+    }
+}
+
+/// Runs engines started in the executor
+///
+/// Stops once all of its engines exited and no more engines can be started
+/// in it or shutdown started.
+async fn run_executor(
+    mut starts: Vec<EngineStart>,
+    rx: mpsc::Receiver<EngineStart>,
+    shutdown: Shutdown,
+) {
+    SUPERVISED.with(|supervised| supervised.set(true));
+
+    let running = Rc::new(Cell::new(0usize));
+
+    loop {
+        let disconnected = loop {
+            match rx.try_recv() {
+                Ok(start) => starts.push(start),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+
+        for (start, exit_tx) in starts.drain(..) {
+            let running = running.clone();
+            let shutdown = shutdown.clone();
+
+            running.set(running.get() + 1);
+            glommio::Task::local(async move {
+                let result: ExitResult = start(shutdown).await.map_err(|e| {
+                    error!("Engine failed: {e}");
+                    Box::from(e.to_string())
+                });
+
+                exit_tx.try_push(result);
+                running.set(running.get() - 1);
+            })
+            .detach();
+        }
+
+        if running.get() == 0 && (disconnected || shutdown.shutdown_started()) {
+            break;
+        }
+
+        glommio::timer::sleep(SPAWN_POLL_INTERVAL).await;
+    }
+}
+
+/// Change of supervised engine
+pub enum SupervisorEvent {
+    /// Engine exited
+    Exited(EngineExit),
+    /// Engine was restarted and reports its status and heartbeats on the
+    /// receivers
+    Restarted(EngineType, EngineReceivers),
+}
+
+/// Supervised engine
+struct Supervised {
+    policy: RestartPolicy,
+    respawn: Option<Respawn>,
+    /// Executor the engine is restarted in
+    executor: CpuExecutor,
+    /// Whether the engine runs, `false` once it exited
+    running: bool,
+    exit_rx: spsc_queue::Consumer<ExitResult>,
+    started_at: Instant,
+    /// Engine reported error since it started
+    failed: bool,
+    /// Restarts since the engine last ran for `STABLE_RUN`
+    consecutive: u32,
+    restarts: u32,
+    restart_at: Option<Instant>,
+}
+
+/// Supervisor of engines spawned by the control engine
+#[derive(Default)]
+pub struct Supervisor {
+    engines: HashMap<EngineType, Supervised>,
+}
+
+impl Supervisor {
+    /// Supervises spawned engine and returns its receivers
+    ///
+    /// Engines without respawn function are never restarted.
+    pub fn supervise(
+        &mut self,
+        engine: EngineType,
+        spawned: SpawnedEngine,
+        policy: RestartPolicy,
+        respawn: Option<Respawn>,
+    ) -> EngineReceivers {
+        self.engines.insert(
+            engine,
+            Supervised {
+                policy,
+                respawn,
+                executor: spawned.executor,
+                running: true,
+                exit_rx: spawned.exit_rx,
+                started_at: Instant::now(),
+                failed: false,
+                consecutive: 0,
+                restarts: 0,
+                restart_at: None,
+            },
+        );
+
+        spawned.receivers
+    }
+
+    /// Records status reported by the engine
+    ///
+    /// Engines that reported error are treated as failed when they exit.
+    pub fn on_status(&mut self, engine: &EngineType, status: &EngineStatus) {
+        if let Some(supervised) = self.engines.get_mut(engine) {
+            if *status == EngineStatus::Error {
+                supervised.failed = true;
+            }
+        }
+    }
+
+    /// Reports exited engines and restarts those due for restart
+    ///
+    /// Nothing is restarted or reported once shutdown started.
+    pub fn poll(&mut self, shutdown: &Shutdown) -> Vec<SupervisorEvent> {
+        let mut events = Vec::new();
+
+        for (engine, supervised) in self.engines.iter_mut() {
+            if supervised.restart_at.is_some() {
+                if !shutdown.shutdown_started() {
+                    if let Some(event) = supervised.restart_if_due(engine, shutdown) {
+                        events.push(event);
+                    }
+                }
+                continue;
+            }
+
+            if !supervised.running {
+                continue;
+            }
+
+            let result = match supervised.exit_rx.try_pop() {
+                Some(result) => result,
+                None if supervised.exit_rx.producer_disconnected() => {
+                    Err(Box::from("engine executor panicked"))
+                }
+                None => continue,
+            };
+
+            supervised.running = false;
+
+            if shutdown.shutdown_started() {
+                continue;
+            }
+
+            events.push(SupervisorEvent::Exited(supervised.on_exit(engine, result)));
+        }
+
+        events
+    }
+}
+
+impl Supervised {
+    /// Schedules restart of the exited engine and returns the exit report
+    fn on_exit(&mut self, engine: &EngineType, result: ExitResult) -> EngineExit {
+        let failed = self.failed || result.is_err();
+        let reason = match result {
+            Err(e) => e,
+            Ok(()) if self.failed => Box::from("engine reported error"),
+            Ok(()) => Box::from("engine stopped"),
+        };
+
+        if self.started_at.elapsed() >= STABLE_RUN {
+            self.consecutive = 0;
+        }
+
+        let restart_in = match self.respawn {
+            Some(_) => self.policy.restart_delay(failed, self.consecutive),
+            None => None,
+        };
+
+        match restart_in {
+            Some(delay) => warn!("{engine:?} exited: {reason}, restarting in {delay:?}"),
+            None => error!("{engine:?} exited: {reason}"),
+        }
+
+        self.restart_at = restart_in.map(|delay| Instant::now() + delay);
+
+        EngineExit {
+            engine: engine.clone(),
+            reason,
+            restarts: self.restarts,
+            restart_in_ms: restart_in.map(|delay| delay.as_millis() as u64),
+        }
+    }
+
+    /// Restarts the engine when its backoff elapsed
+    fn restart_if_due(
+        &mut self,
+        engine: &EngineType,
+        shutdown: &Shutdown,
+    ) -> Option<SupervisorEvent> {
+        if self.restart_at? > Instant::now() {
+            return None;
+        }
+
+        let respawn = self.respawn.as_mut()?;
+
+        self.restart_at = None;
+        self.consecutive += 1;
+
+        match self.executor.spawn(vec![respawn()], shutdown.clone()) {
+            Ok(mut spawned) => {
+                let spawned = spawned.remove(0);
+
+                info!("{engine:?} restarted");
+
+                self.running = true;
+                self.exit_rx = spawned.exit_rx;
+                self.started_at = Instant::now();
+                self.failed = false;
+                self.restarts += 1;
+
+                Some(SupervisorEvent::Restarted(
+                    engine.clone(),
+                    spawned.receivers,
+                ))
+            }
+            Err(e) => Some(SupervisorEvent::Exited(
+                self.on_exit(engine, Err(format!("failed to restart: {e}").into())),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_delay() {
+        let policy = RestartPolicy::OnFailure { max_restarts: 3 };
+
+        assert_eq!(policy.restart_delay(false, 0), None);
+        assert_eq!(policy.restart_delay(true, 0), Some(INITIAL_BACKOFF));
+        assert_eq!(policy.restart_delay(true, 2), Some(INITIAL_BACKOFF * 4));
+        assert_eq!(policy.restart_delay(true, 3), None);
+
+        assert_eq!(RestartPolicy::Never.restart_delay(true, 0), None);
+        assert_eq!(
+            RestartPolicy::Always.restart_delay(false, 40),
+            Some(MAX_BACKOFF)
+        );
+    }
+}
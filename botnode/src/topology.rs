@@ -0,0 +1,342 @@
+//! Engine topology
+//!
+//! [`Topology`] places the engines on CPUs as given by the topology of the bot
+//! configuration, or each on its own CPU when it's not set. Engines placed on
+//! the same CPU share one executor and run as its tasks. The control engine
+//! always runs on [`CONTROL_CPU`] and no other engine can be placed there.
+
+use botvana::cfg::EnginePlacement;
+
+use crate::prelude::*;
+
+/// CPU the control engine runs on
+pub const CONTROL_CPU: usize = 0;
+
+/// Invalid topology
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum TopologyError {
+    #[error("Control engine can't be placed, it always runs on CPU {CONTROL_CPU}")]
+    ControlEngine,
+    #[error("{0:?} can't run on CPU {CONTROL_CPU} reserved for the control engine")]
+    ReservedCpu(EngineType),
+    #[error("{0:?} is placed more than once")]
+    Duplicate(EngineType),
+    #[error("{0:?} is placed but its exchange isn't configured")]
+    UnknownExchange(EngineType),
+    #[error("Market data engine of configured exchange {0} isn't placed")]
+    MissingMarketData(Box<str>),
+    #[error("Recorder is configured but its engine isn't placed")]
+    MissingRecorder,
+    #[error("Recorder engine is placed but recorder isn't configured")]
+    RecorderNotConfigured,
+    #[error("{0:?} requires {1:?}")]
+    Requires(EngineType, EngineType),
+    #[error("Unknown exchange {0}")]
+    InvalidExchange(Box<str>),
+    #[error("{engine:?} is placed on CPU {cpu} which isn't online")]
+    Offline { engine: EngineType, cpu: usize },
+    #[error("Failed to list online CPUs: {0}")]
+    OnlineCpus(String),
+}
+
+/// Engines to run and CPUs they run on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Topology {
+    placements: Vec<EnginePlacement>,
+}
+
+impl Topology {
+    /// Returns topology of given configuration
+    ///
+    /// Without explicit topology the market data engines run on CPUs
+    /// following the control engine in order of the configured exchanges,
+    /// followed by the other engines.
+    pub fn from_config(config: &BotConfiguration) -> Result<Self, TopologyError> {
+        let exchanges = config
+            .exchanges
+            .iter()
+            .map(|exchange| {
+                exchange
+                    .parse::<ExchangeId>()
+                    .map_err(|_| TopologyError::InvalidExchange(exchange.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let placements = match &config.topology {
+            Some(topology) => topology.engines.to_vec(),
+            None => default_placements(&exchanges, config.recorder.is_some()),
+        };
+
+        let topology = Self { placements };
+        topology.validate(config, &exchanges)?;
+
+        Ok(topology)
+    }
+
+    fn validate(
+        &self,
+        config: &BotConfiguration,
+        exchanges: &[ExchangeId],
+    ) -> Result<(), TopologyError> {
+        for (i, placement) in self.placements.iter().enumerate() {
+            let engine = &placement.engine;
+
+            match engine {
+                EngineType::ControlEngine => return Err(TopologyError::ControlEngine),
+                EngineType::MarketDataEngine(exchange) if !exchanges.contains(exchange) => {
+                    return Err(TopologyError::UnknownExchange(engine.clone()))
+                }
+                EngineType::RecorderEngine if config.recorder.is_none() => {
+                    return Err(TopologyError::RecorderNotConfigured)
+                }
+                _ => {}
+            }
+
+            if placement.cpu == CONTROL_CPU {
+                return Err(TopologyError::ReservedCpu(engine.clone()));
+            }
+
+            if self.placements[..i]
+                .iter()
+                .any(|placed| placed.engine == *engine)
+            {
+                return Err(TopologyError::Duplicate(engine.clone()));
+            }
+        }
+
+        for (exchange, exchange_id) in config.exchanges.iter().zip(exchanges) {
+            if !self.runs(&EngineType::MarketDataEngine(*exchange_id)) {
+                return Err(TopologyError::MissingMarketData(exchange.clone()));
+            }
+        }
+
+        if config.recorder.is_some() && !self.runs(&EngineType::RecorderEngine) {
+            return Err(TopologyError::MissingRecorder);
+        }
+
+        // Trading engine sends its orders to the exchange engine
+        if self.runs(&EngineType::TradingEngine) && !self.runs(&EngineType::ExchangeEngine) {
+            return Err(TopologyError::Requires(
+                EngineType::TradingEngine,
+                EngineType::ExchangeEngine,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that all engines are placed on online CPUs
+    pub fn validate_cpus(&self, online: &[usize]) -> Result<(), TopologyError> {
+        match self
+            .placements
+            .iter()
+            .find(|placement| !online.contains(&placement.cpu))
+        {
+            Some(placement) => Err(TopologyError::Offline {
+                engine: placement.engine.clone(),
+                cpu: placement.cpu,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns CPU of the engine, `None` when it doesn't run
+    pub fn cpu(&self, engine: &EngineType) -> Option<usize> {
+        self.placements
+            .iter()
+            .find(|placement| placement.engine == *engine)
+            .map(|placement| placement.cpu)
+    }
+
+    /// Returns whether the engine runs
+    pub fn runs(&self, engine: &EngineType) -> bool {
+        self.cpu(engine).is_some()
+    }
+}
+
+/// Places each engine on its own CPU
+fn default_placements(exchanges: &[ExchangeId], recorder: bool) -> Vec<EnginePlacement> {
+    let n_exchanges = exchanges.len();
+    let mut placements: Vec<_> = exchanges
+        .iter()
+        .enumerate()
+        .map(|(i, exchange)| EnginePlacement {
+            engine: EngineType::MarketDataEngine(*exchange),
+            cpu: CONTROL_CPU + i + 1,
+        })
+        .collect();
+
+    let mut engines = vec![
+        (EngineType::IndicatorEngine, n_exchanges + 3),
+        (EngineType::TradingEngine, n_exchanges + 4),
+        (EngineType::ExchangeEngine, n_exchanges + 5),
+        (EngineType::AuditEngine, n_exchanges + 6),
+    ];
+    if recorder {
+        engines.push((EngineType::RecorderEngine, n_exchanges + 7));
+    }
+
+    placements.extend(
+        engines
+            .into_iter()
+            .map(|(engine, cpu)| EnginePlacement { engine, cpu }),
+    );
+
+    placements
+}
+
+/// Returns CPUs that are online
+pub fn online_cpus() -> Result<Vec<usize>, TopologyError> {
+    let cpus = glommio::CpuSet::online().map_err(|e| TopologyError::OnlineCpus(e.to_string()))?;
+
+    Ok(cpus.into_iter().map(|location| location.cpu).collect())
+}
+
+/// Lets the calling thread run on any online CPU
+///
+/// Threads spawned from an engine inherit the CPU its executor is pinned
+/// to, helper threads doing blocking work call this so they don't compete
+/// with the engine for its CPU.
+pub fn unpin_current_thread() -> std::io::Result<()> {
+    let cpus = online_cpus().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // SAFETY: the set is initialized by zeroing and only CPUs of the online
+    // CPU set are added to it
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use botvana::cfg::{RecorderConfig, TopologyConfig};
+
+    use super::*;
+
+    fn config(topology: Option<&[(EngineType, usize)]>) -> BotConfiguration {
+        BotConfiguration {
+            bot_id: BotId(0),
+            peer_bots: Box::new([]),
+            exchanges: Box::new(["ftx".into(), "binance".into()]),
+            markets: Box::new([]),
+            indicators: Box::new([]),
+            exchange_configs: Box::new([]),
+            recorder: None,
+            strategy: None,
+            topology: topology.map(|engines| TopologyConfig {
+                engines: engines
+                    .iter()
+                    .map(|(engine, cpu)| EnginePlacement {
+                        engine: engine.clone(),
+                        cpu: *cpu,
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_default_topology() {
+        let topology = Topology::from_config(&config(None)).unwrap();
+
+        assert_eq!(
+            topology.cpu(&EngineType::MarketDataEngine(ExchangeId::Ftx)),
+            Some(1)
+        );
+        assert_eq!(
+            topology.cpu(&EngineType::MarketDataEngine(ExchangeId::BinanceSpot)),
+            Some(2)
+        );
+        assert_eq!(topology.cpu(&EngineType::IndicatorEngine), Some(5));
+        assert_eq!(topology.cpu(&EngineType::AuditEngine), Some(8));
+        assert!(!topology.runs(&EngineType::RecorderEngine));
+        assert!(!topology.runs(&EngineType::ControlEngine));
+    }
+
+    #[test]
+    fn test_shared_cpus() {
+        let topology = Topology::from_config(&config(Some(&[
+            (EngineType::MarketDataEngine(ExchangeId::Ftx), 1),
+            (EngineType::MarketDataEngine(ExchangeId::BinanceSpot), 1),
+            (EngineType::TradingEngine, 2),
+            (EngineType::ExchangeEngine, 2),
+        ])))
+        .unwrap();
+
+        assert_eq!(topology.cpu(&EngineType::ExchangeEngine), Some(2));
+        assert!(!topology.runs(&EngineType::IndicatorEngine));
+        assert_eq!(topology.validate_cpus(&[0, 1, 2]), Ok(()));
+        assert_eq!(
+            topology.validate_cpus(&[0, 1]),
+            Err(TopologyError::Offline {
+                engine: EngineType::TradingEngine,
+                cpu: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_topology() {
+        let ftx = || (EngineType::MarketDataEngine(ExchangeId::Ftx), 1);
+        let binance = || (EngineType::MarketDataEngine(ExchangeId::BinanceSpot), 1);
+        let invalid = |engines: &[(EngineType, usize)]| {
+            Topology::from_config(&config(Some(engines))).unwrap_err()
+        };
+
+        assert_eq!(
+            invalid(&[ftx(), binance(), (EngineType::ControlEngine, 0)]),
+            TopologyError::ControlEngine
+        );
+        assert_eq!(
+            invalid(&[ftx(), binance(), (EngineType::AuditEngine, 0)]),
+            TopologyError::ReservedCpu(EngineType::AuditEngine)
+        );
+        assert_eq!(
+            invalid(&[ftx(), binance(), ftx()]),
+            TopologyError::Duplicate(ftx().0)
+        );
+        assert_eq!(
+            invalid(&[
+                ftx(),
+                binance(),
+                (EngineType::MarketDataEngine(ExchangeId::Serum), 1)
+            ]),
+            TopologyError::UnknownExchange(EngineType::MarketDataEngine(ExchangeId::Serum))
+        );
+        assert_eq!(
+            invalid(&[ftx()]),
+            TopologyError::MissingMarketData("binance".into())
+        );
+        assert_eq!(
+            invalid(&[ftx(), binance(), (EngineType::RecorderEngine, 2)]),
+            TopologyError::RecorderNotConfigured
+        );
+        assert_eq!(
+            invalid(&[ftx(), binance(), (EngineType::TradingEngine, 2)]),
+            TopologyError::Requires(EngineType::TradingEngine, EngineType::ExchangeEngine)
+        );
+
+        let mut recorder = config(Some(&[ftx(), binance()]));
+        recorder.recorder = Some(RecorderConfig::default());
+        assert_eq!(
+            Topology::from_config(&recorder),
+            Err(TopologyError::MissingRecorder)
+        );
+
+        let mut unknown = config(None);
+        unknown.exchanges = Box::new(["nyse".into()]);
+        assert_eq!(
+            Topology::from_config(&unknown),
+            Err(TopologyError::InvalidExchange("nyse".into()))
+        );
+    }
+}
@@ -1,4 +1,19 @@
 //! Trading engine
 
+pub(crate) mod command;
 pub(crate) mod engine;
 pub(crate) mod event_loop;
+pub mod strategy;
+
+/// Command executed by the trading engine on behalf of botvana-server
+#[derive(Clone, Debug, PartialEq)]
+pub enum TradingCommand {
+    /// Stops passing events to the strategy
+    Pause,
+    /// Resumes passing events to the strategy
+    Resume,
+    /// Cancels all open orders
+    CancelAll,
+    /// Cancels all open orders, pauses and closes open positions
+    Flatten,
+}
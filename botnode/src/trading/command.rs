@@ -0,0 +1,181 @@
+//! Execution of trading commands
+
+use super::{strategy::StrategyContext, TradingCommand};
+use crate::prelude::*;
+use botvana::{
+    exchange::{Fill, Side},
+    net::msg::CommandResult,
+};
+
+/// Positions below this size are considered closed
+const MIN_POSITION: f64 = 1e-9;
+
+/// State of the trading engine controlled by commands
+#[derive(Debug, Default)]
+pub(crate) struct TradingControl {
+    paused: bool,
+    positions: HashMap<(ExchangeId, Box<str>), f64>,
+    tops: HashMap<(ExchangeId, Box<str>), (f64, f64)>,
+}
+
+impl TradingControl {
+    /// Returns whether the strategy is paused
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Records best bid and ask of the market
+    pub(crate) fn on_market_event(&mut self, exchange: ExchangeId, event: &MarketEvent) {
+        match &event.r#type {
+            MarketEventType::OrderbookUpdate(market, orderbook) => {
+                if let (Some(bid), Some(ask)) = (
+                    orderbook.bids.price_vec.last(),
+                    orderbook.asks.price_vec.first(),
+                ) {
+                    self.tops.insert((exchange, market.clone()), (*bid, *ask));
+                }
+            }
+            MarketEventType::MidPriceChange(market, bid, ask) => {
+                self.tops.insert((exchange, market.clone()), (*bid, *ask));
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies fill to the position
+    pub(crate) fn on_fill(&mut self, fill: &Fill) {
+        *self
+            .positions
+            .entry((fill.exchange, fill.market.clone()))
+            .or_default() += fill.side.sign() * fill.size;
+    }
+
+    /// Executes the command issuing exchange requests through the context
+    pub(crate) fn execute(
+        &mut self,
+        ctx: &mut StrategyContext,
+        command: TradingCommand,
+    ) -> CommandResult {
+        match command {
+            TradingCommand::Pause => {
+                self.paused = true;
+                Ok(Box::from("trading paused"))
+            }
+            TradingCommand::Resume => {
+                self.paused = false;
+                Ok(Box::from("trading resumed"))
+            }
+            TradingCommand::CancelAll => {
+                ctx.cancel_all();
+                Ok(Box::from("cancelling all orders"))
+            }
+            TradingCommand::Flatten => {
+                self.paused = true;
+                ctx.cancel_all();
+                self.flatten(ctx)
+            }
+        }
+    }
+
+    /// Places orders crossing the spread to close open positions
+    fn flatten(&mut self, ctx: &mut StrategyContext) -> CommandResult {
+        let mut closed = Vec::new();
+        let mut missing = Vec::new();
+
+        for ((exchange, market), position) in self.positions.iter() {
+            if position.abs() < MIN_POSITION {
+                continue;
+            }
+
+            match self.tops.get(&(*exchange, market.clone())) {
+                Some((bid, _)) if *position > 0.0 => {
+                    ctx.place_order(*exchange, market, Side::Sell, *bid, *position);
+                    closed.push(format!("{exchange} {market}"));
+                }
+                Some((_, ask)) => {
+                    ctx.place_order(*exchange, market, Side::Buy, *ask, -position);
+                    closed.push(format!("{exchange} {market}"));
+                }
+                None => missing.push(format!("{exchange} {market}")),
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(format!("trading paused, closing positions: {}", closed.join(", ")).into())
+        } else {
+            Err(format!("no price to close positions: {}", missing.join(", ")).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::ExchangeRequest;
+
+    fn fill(ctx: &StrategyContext, market: &str, side: Side, size: f64) -> Fill {
+        Fill {
+            exchange: ExchangeId::Ftx,
+            market: Box::from(market),
+            order_id: 1,
+            side,
+            price: 100.0,
+            size,
+            fee: 0.0,
+            time: ctx.now(),
+        }
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let mut control = TradingControl::default();
+        let mut ctx = StrategyContext::new(botvana::clock::real_clock());
+
+        assert!(!control.is_paused());
+        assert!(control.execute(&mut ctx, TradingCommand::Pause).is_ok());
+        assert!(control.is_paused());
+        assert!(control.execute(&mut ctx, TradingCommand::Resume).is_ok());
+        assert!(!control.is_paused());
+        assert!(ctx.take_requests().is_empty());
+    }
+
+    #[test]
+    fn test_flatten() {
+        let clock = botvana::clock::real_clock();
+        let mut control = TradingControl::default();
+        let mut ctx = StrategyContext::new(clock.clone());
+
+        control.on_fill(&fill(&ctx, "BTC/USD", Side::Buy, 2.0));
+        control.on_fill(&fill(&ctx, "BTC/USD", Side::Sell, 0.5));
+        control.on_market_event(
+            ExchangeId::Ftx,
+            &MarketEvent::mid_price_change(&*clock, Box::from("BTC/USD"), 99.0, 101.0),
+        );
+
+        assert!(control.execute(&mut ctx, TradingCommand::Flatten).is_ok());
+        assert!(control.is_paused());
+
+        let requests = ctx.take_requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], ExchangeRequest::CancelAll);
+        match &requests[1] {
+            ExchangeRequest::PlaceOrder(order) => {
+                assert_eq!(order.side, Side::Sell);
+                assert_eq!(order.price, 99.0);
+                assert_eq!(order.size, 1.5);
+            }
+            request => panic!("unexpected request {request:?}"),
+        }
+    }
+
+    #[test]
+    fn test_flatten_without_price() {
+        let mut control = TradingControl::default();
+        let mut ctx = StrategyContext::new(botvana::clock::real_clock());
+
+        control.on_fill(&fill(&ctx, "ETH/USD", Side::Sell, 1.0));
+
+        assert!(control.execute(&mut ctx, TradingCommand::Flatten).is_err());
+        assert_eq!(ctx.take_requests(), vec![ExchangeRequest::CancelAll]);
+    }
+}
@@ -1,35 +1,80 @@
+use super::{
+    strategy::{self, Strategy},
+    TradingCommand,
+};
+use botvana::{cfg::StrategyConfig, net::msg::CommandResult};
+
 use crate::{
+    audit::journal::JournalEvent,
     exchange::{ExchangeEvent, ExchangeRequest},
     prelude::*,
 };
 
+/// Length of the queue of latency traces sent to the audit engine
+pub const LATENCY_QUEUE_LEN: usize = 1024;
+/// Length of the queue of journaled events sent to the audit engine
+pub const JOURNAL_QUEUE_LEN: usize = 1024;
+
 /// Trading engine
 pub struct TradingEngine {
+    config_rx: spsc_queue::Consumer<BotConfiguration>,
     market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
     indicator_rx: spsc_queue::Consumer<IndicatorEvent>,
     exchange_tx: spsc_queue::Producer<ExchangeRequest>,
     exchange_rx: spsc_queue::Consumer<ExchangeEvent>,
+    command_rx: spsc_queue::Consumer<(u64, TradingCommand)>,
+    command_result_tx: spsc_queue::Producer<(u64, CommandResult)>,
+    latency_tx: Option<spsc_queue::Producer<(ExchangeId, LatencyTrace)>>,
+    journal_tx: Option<spsc_queue::Producer<(std::time::SystemTime, JournalEvent)>>,
+    clock: SharedClock,
     status_tx: spsc_queue::Producer<EngineStatus>,
     status_rx: spsc_queue::Consumer<EngineStatus>,
+    heartbeat: Heartbeat,
 }
 
 impl TradingEngine {
     pub fn new(
+        config_rx: spsc_queue::Consumer<BotConfiguration>,
         market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
         indicator_rx: spsc_queue::Consumer<IndicatorEvent>,
         exchange_tx: spsc_queue::Producer<ExchangeRequest>,
         exchange_rx: spsc_queue::Consumer<ExchangeEvent>,
+        command_rx: spsc_queue::Consumer<(u64, TradingCommand)>,
+        command_result_tx: spsc_queue::Producer<(u64, CommandResult)>,
+        clock: SharedClock,
     ) -> Self {
         let (status_tx, status_rx) = spsc_queue::make(1);
         Self {
+            config_rx,
             market_data_rxs,
             indicator_rx,
             exchange_tx,
             exchange_rx,
+            command_rx,
+            command_result_tx,
+            latency_tx: None,
+            journal_tx: None,
+            clock,
             status_tx,
             status_rx,
+            heartbeat: Heartbeat::new(),
         }
     }
+
+    /// Returns receiver of latency traces of processed market events
+    pub fn latency_rx(&mut self) -> spsc_queue::Consumer<(ExchangeId, LatencyTrace)> {
+        let (latency_tx, latency_rx) = spsc_queue::make(LATENCY_QUEUE_LEN);
+        self.latency_tx = Some(latency_tx);
+        latency_rx
+    }
+
+    /// Returns receiver of sent exchange requests and received exchange
+    /// events to be journaled
+    pub fn journal_rx(&mut self) -> spsc_queue::Consumer<(std::time::SystemTime, JournalEvent)> {
+        let (journal_tx, journal_rx) = spsc_queue::make(JOURNAL_QUEUE_LEN);
+        self.journal_tx = Some(journal_tx);
+        journal_rx
+    }
 }
 
 #[async_trait(?Send)]
@@ -42,19 +87,43 @@ impl Engine for TradingEngine {
         self.status_rx.clone()
     }
 
+    fn heartbeat_rx(&self) -> Option<spsc_queue::Consumer<HeartbeatReport>> {
+        Some(self.heartbeat.rx())
+    }
+
     /// Starts the trading engine
-    async fn start(mut self, shutdown: Shutdown) -> Result<(), EngineError> {
+    async fn start(self, shutdown: Shutdown) -> Result<(), EngineError> {
         info!("Starting trading engine");
 
         self.status_tx.try_push(EngineStatus::Booting);
 
+        let config = await_value(&self.config_rx).await;
+        debug!("config = {config:?}");
+
         super::event_loop::run_loop(
+            self.config_rx,
             self.market_data_rxs,
             self.indicator_rx,
             self.exchange_tx,
             self.exchange_rx,
-            self.status_tx,
+            (self.command_rx, self.command_result_tx),
+            (self.latency_tx, self.journal_tx),
+            config.strategy,
+            self.clock,
+            (self.status_tx, self.heartbeat),
             shutdown,
         )
+        .await
     }
 }
+
+/// Creates strategy from its configuration, `None` when it's invalid
+pub(crate) fn build_strategy(config: Option<&StrategyConfig>) -> Option<Box<dyn Strategy>> {
+    config.and_then(|config| match strategy::from_config(config) {
+        Ok(strategy) => Some(strategy),
+        Err(e) => {
+            error!("Invalid strategy configuration: {e}");
+            None
+        }
+    })
+}
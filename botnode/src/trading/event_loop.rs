@@ -1,35 +1,126 @@
+use super::{
+    command::TradingControl,
+    engine::build_strategy,
+    strategy::{Strategy, StrategyContext},
+    TradingCommand,
+};
+use std::{sync::Arc, time::SystemTime};
+
+use crate::audit::journal::JournalEvent;
 use crate::exchange::{ExchangeEvent, ExchangeRequest};
 use crate::prelude::*;
+use botvana::{
+    cfg::StrategyConfig,
+    metrics::{self, Counter, Histogram, LATENCY_BUCKETS_US},
+    net::msg::CommandResult,
+};
 
 const STALE_MARKET_EVENT_MS: u64 = 10;
 
 /// Runs trading event loop
-pub fn run_loop(
+///
+/// The strategy is replaced when updated configuration changes it. Commands
+/// are executed as they arrive and their results sent back. Latency traces of
+/// market events are sent to `latency_tx`, including the time the orders they
+/// triggered were sent. Sent requests and received exchange events are sent
+/// to `journal_tx`.
+pub async fn run_loop(
+    config_rx: spsc_queue::Consumer<BotConfiguration>,
     market_data_rxs: ConsumersMap<Box<str>, MarketEvent>,
     indicator_rx: spsc_queue::Consumer<IndicatorEvent>,
-    _exchange_tx: spsc_queue::Producer<ExchangeRequest>,
+    exchange_tx: spsc_queue::Producer<ExchangeRequest>,
     exchange_rx: spsc_queue::Consumer<ExchangeEvent>,
-    status_tx: spsc_queue::Producer<EngineStatus>,
+    (command_rx, command_result_tx): (
+        spsc_queue::Consumer<(u64, TradingCommand)>,
+        spsc_queue::Producer<(u64, CommandResult)>,
+    ),
+    (latency_tx, journal_tx): (
+        Option<spsc_queue::Producer<(ExchangeId, LatencyTrace)>>,
+        Option<spsc_queue::Producer<(SystemTime, JournalEvent)>>,
+    ),
+    mut strategy_config: Option<StrategyConfig>,
+    clock: SharedClock,
+    (status_tx, heartbeat): (spsc_queue::Producer<EngineStatus>, Heartbeat),
     shutdown: Shutdown,
 ) -> Result<(), EngineError> {
     let mut prices = HashMap::new();
+    let mut ctx = StrategyContext::new(clock.clone());
+    let mut strategy = build_strategy(strategy_config.as_ref());
+    let mut control = TradingControl::default();
+    let event_metrics: HashMap<_, _> = market_data_rxs
+        .iter()
+        .map(|(exchange, _)| (&**exchange, market_event_metrics(exchange)))
+        .collect();
+
+    if let Some(strategy) = &strategy {
+        info!("running strategy {}", strategy.name());
+    }
 
     status_tx.try_push(EngineStatus::Running);
 
     loop {
+        heartbeat.tick();
+        glommio::yield_if_needed().await;
+
         if shutdown.shutdown_started() {
             return Ok(());
         }
 
+        if let Some(config) = config_rx.try_pop() {
+            if config.strategy != strategy_config {
+                info!("strategy configuration changed to {:?}", config.strategy);
+
+                replace_strategy(
+                    &mut strategy,
+                    &mut ctx,
+                    &mut control,
+                    &exchange_tx,
+                    journal_tx.as_ref(),
+                );
+                strategy = build_strategy(config.strategy.as_ref());
+                strategy_config = config.strategy;
+            }
+        }
+
         for (exchange, market_data_rx) in market_data_rxs.iter() {
-            if let Some(event) = market_data_rx.try_pop() {
-                let elapsed = event.timestamp.elapsed().unwrap();
+            if let Some(mut event) = market_data_rx.try_pop() {
+                event.trace.mark(LatencyStage::Dequeued, clock.as_ref());
+                heartbeat.event();
+
+                let elapsed = clock.elapsed(event.timestamp);
+                let (stale_events, latency) = &event_metrics[&**exchange];
+
+                latency.observe(elapsed.as_micros() as f64);
 
                 if elapsed > Duration::from_millis(STALE_MARKET_EVENT_MS) {
                     warn!("Received stale market data: {elapsed:?}");
+                    stale_events.inc();
                     continue;
                 }
 
+                match exchange.parse::<ExchangeId>() {
+                    Ok(exchange) => {
+                        control.on_market_event(exchange, &event);
+
+                        if let Some(strategy) = strategy.as_mut() {
+                            if !control.is_paused() {
+                                strategy.on_market_event(&mut ctx, exchange, &event);
+                            }
+                        }
+
+                        if send_requests(&mut ctx, &exchange_tx, journal_tx.as_ref()) {
+                            event.trace.mark(LatencyStage::OrderSent, clock.as_ref());
+                        }
+
+                        if let Some(latency_tx) = &latency_tx {
+                            if latency_tx.try_push((exchange, event.trace)).is_some() {
+                                trace!("Latency queue full, dropping trace");
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Not passing event to strategy: {e}"),
+                }
+
                 process_market_event(exchange, event, elapsed, &mut prices)?
             }
         }
@@ -40,10 +131,117 @@ pub fn run_loop(
 
         if let Some(event) = exchange_rx.try_pop() {
             trace!("exchange = {event:?}");
+
+            if let Some(journal_tx) = &journal_tx {
+                journal(journal_tx, &ctx, JournalEvent::Exchange(event.clone()));
+            }
+
+            if let ExchangeEvent::OrderFill(fill) = &event {
+                control.on_fill(fill);
+
+                if let Some(strategy) = strategy.as_mut() {
+                    strategy.on_fill(&mut ctx, fill);
+                }
+            }
         }
+
+        if let Some((id, command)) = command_rx.try_pop() {
+            info!("executing command {id} {command:?}");
+
+            let result = control.execute(&mut ctx, command);
+
+            if let Some(result) = command_result_tx.try_push((id, result)) {
+                warn!("Command result queue full, dropping {result:?}");
+            }
+        }
+
+        send_requests(&mut ctx, &exchange_tx, journal_tx.as_ref());
     }
 }
 
+/// Stops the running strategy before it's replaced
+///
+/// Orders of the stopped strategy are cancelled like with
+/// `TradingCommand::CancelAll`, so they don't rest on the exchange unmanaged
+/// once the new strategy takes over.
+fn replace_strategy(
+    strategy: &mut Option<Box<dyn Strategy>>,
+    ctx: &mut StrategyContext,
+    control: &mut TradingControl,
+    exchange_tx: &spsc_queue::Producer<ExchangeRequest>,
+    journal_tx: Option<&spsc_queue::Producer<(SystemTime, JournalEvent)>>,
+) {
+    if let Some(strategy) = strategy.take() {
+        let result = control.execute(ctx, TradingCommand::CancelAll);
+        info!("stopped strategy {}: {result:?}", strategy.name());
+
+        send_requests(ctx, exchange_tx, journal_tx);
+    }
+}
+
+/// Sends requests issued by the strategy to the exchange engine
+///
+/// Requests that don't fit the queue are kept in order and retried on the
+/// next call. Returns whether any request was sent.
+fn send_requests(
+    ctx: &mut StrategyContext,
+    exchange_tx: &spsc_queue::Producer<ExchangeRequest>,
+    journal_tx: Option<&spsc_queue::Producer<(SystemTime, JournalEvent)>>,
+) -> bool {
+    let mut sent = false;
+    let mut requests = ctx.take_requests().into_iter();
+
+    while let Some(request) = requests.next() {
+        let journaled =
+            journal_tx.map(|journal_tx| (journal_tx, JournalEvent::Request(request.clone())));
+
+        if let Some(request) = exchange_tx.try_push(request) {
+            debug!("Exchange request queue full, retrying {request:?}");
+            ctx.retry_requests(std::iter::once(request).chain(requests).collect());
+            break;
+        }
+        sent = true;
+
+        if let Some((journal_tx, event)) = journaled {
+            journal(journal_tx, ctx, event);
+        }
+    }
+
+    sent
+}
+
+/// Sends the event to the audit journal
+fn journal(
+    journal_tx: &spsc_queue::Producer<(SystemTime, JournalEvent)>,
+    ctx: &StrategyContext,
+    event: JournalEvent,
+) {
+    if let Some((_, event)) = journal_tx.try_push((ctx.now(), event)) {
+        error!("Journal queue full, dropping {event:?}");
+    }
+}
+
+/// Returns stale events counter and latency histogram of market events from
+/// the exchange
+fn market_event_metrics(exchange: &str) -> (Arc<Counter>, Arc<Histogram>) {
+    let registry = metrics::registry();
+    let labels = [("exchange", exchange)];
+
+    (
+        registry.counter(
+            "botnode_trading_stale_events_total",
+            "Market events dropped by the trading engine as stale",
+            &labels,
+        ),
+        registry.histogram(
+            "botnode_trading_event_latency_microseconds",
+            "Latency between receiving market event and the trading engine processing it",
+            &labels,
+            LATENCY_BUCKETS_US,
+        ),
+    )
+}
+
 #[inline]
 fn process_market_event(
     exchange: &str,
@@ -81,3 +279,55 @@ fn process_market_event(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_strategy_cancels_orders() {
+        let (exchange_tx, exchange_rx) = spsc_queue::make(4);
+        let mut ctx = StrategyContext::new(botvana::clock::real_clock());
+        let mut control = TradingControl::default();
+        let mut strategy = build_strategy(Some(&StrategyConfig::QuoteMid {
+            exchange: Box::from("ftx"),
+            market: Box::from("BTC/USD"),
+            size: 1.0,
+            spread_bps: 10.0,
+        }));
+
+        replace_strategy(&mut strategy, &mut ctx, &mut control, &exchange_tx, None);
+
+        assert!(strategy.is_none());
+        assert_eq!(exchange_rx.try_pop(), Some(ExchangeRequest::CancelAll));
+        assert_eq!(exchange_rx.try_pop(), None);
+
+        // Nothing to cancel without a running strategy
+        replace_strategy(&mut strategy, &mut ctx, &mut control, &exchange_tx, None);
+
+        assert_eq!(exchange_rx.try_pop(), None);
+    }
+
+    #[test]
+    fn test_send_requests_retries_on_full_queue() {
+        let (exchange_tx, exchange_rx) = spsc_queue::make(1);
+        let mut ctx = StrategyContext::new(botvana::clock::real_clock());
+
+        ctx.cancel_order(1);
+        ctx.cancel_order(2);
+
+        assert!(send_requests(&mut ctx, &exchange_tx, None));
+        assert_eq!(exchange_rx.try_pop(), Some(ExchangeRequest::CancelOrder(1)));
+
+        ctx.cancel_order(3);
+
+        for order_id in [2, 3] {
+            assert!(send_requests(&mut ctx, &exchange_tx, None));
+            assert_eq!(
+                exchange_rx.try_pop(),
+                Some(ExchangeRequest::CancelOrder(order_id))
+            );
+        }
+        assert!(!send_requests(&mut ctx, &exchange_tx, None));
+    }
+}
@@ -0,0 +1,147 @@
+//! Trading strategies
+//!
+//! Strategies react to market events and fills by issuing exchange requests
+//! through the [`StrategyContext`]. The same strategy code is driven by the
+//! trading engine when running live and by the backtest runner offline.
+
+pub mod quote_mid;
+
+use std::time::SystemTime;
+
+use crate::{
+    exchange::{order_request::OrderRequest, ExchangeRequest},
+    prelude::*,
+};
+use botvana::{
+    cfg::StrategyConfig,
+    exchange::{Fill, Side},
+};
+
+/// Trading strategy
+pub trait Strategy {
+    /// Returns name of the strategy
+    fn name(&self) -> &str;
+
+    /// Called for every market event received from given exchange
+    fn on_market_event(
+        &mut self,
+        ctx: &mut StrategyContext,
+        exchange: ExchangeId,
+        event: &MarketEvent,
+    );
+
+    /// Called when an order placed by the strategy is filled
+    fn on_fill(&mut self, _ctx: &mut StrategyContext, _fill: &Fill) {}
+}
+
+/// Creates strategy from its configuration
+pub fn from_config(config: &StrategyConfig) -> Result<Box<dyn Strategy>, String> {
+    match config {
+        StrategyConfig::QuoteMid {
+            exchange,
+            market,
+            size,
+            spread_bps,
+        } => Ok(Box::new(quote_mid::QuoteMid::new(
+            exchange.parse()?,
+            market.clone(),
+            *size,
+            *spread_bps,
+        ))),
+    }
+}
+
+/// Context passed to strategy callbacks
+///
+/// Collects exchange requests issued by the strategy and provides the current
+/// time, which is the simulated time when backtesting.
+#[derive(Debug)]
+pub struct StrategyContext {
+    clock: SharedClock,
+    next_order_id: u64,
+    requests: Vec<ExchangeRequest>,
+}
+
+impl StrategyContext {
+    /// Creates new context using given clock
+    pub fn new(clock: SharedClock) -> Self {
+        Self {
+            clock,
+            next_order_id: 1,
+            requests: Vec::new(),
+        }
+    }
+
+    /// Returns current time
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// Places limit order and returns its client order id
+    pub fn place_order(
+        &mut self,
+        exchange: ExchangeId,
+        market: &str,
+        side: Side,
+        price: f64,
+        size: f64,
+    ) -> u64 {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+
+        self.requests
+            .push(ExchangeRequest::PlaceOrder(OrderRequest {
+                order_id,
+                exchange,
+                market: Box::from(market),
+                side,
+                price,
+                size,
+            }));
+
+        order_id
+    }
+
+    /// Cancels order with given client order id
+    pub fn cancel_order(&mut self, order_id: u64) {
+        self.requests.push(ExchangeRequest::CancelOrder(order_id));
+    }
+
+    /// Cancels all open orders
+    pub fn cancel_all(&mut self) {
+        self.requests.push(ExchangeRequest::CancelAll);
+    }
+
+    /// Takes requests issued since the last call
+    pub fn take_requests(&mut self) -> Vec<ExchangeRequest> {
+        std::mem::take(&mut self.requests)
+    }
+
+    /// Returns taken requests that couldn't be sent, they're taken again
+    /// ahead of requests issued since
+    pub(crate) fn retry_requests(&mut self, mut requests: Vec<ExchangeRequest>) {
+        requests.append(&mut self.requests);
+        self.requests = requests;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_requests() {
+        let mut ctx = StrategyContext::new(botvana::clock::real_clock());
+
+        let first = ctx.place_order(ExchangeId::Ftx, "BTC/USD", Side::Buy, 100.0, 1.0);
+        let second = ctx.place_order(ExchangeId::Ftx, "BTC/USD", Side::Sell, 101.0, 1.0);
+        ctx.cancel_order(first);
+
+        assert_ne!(first, second);
+
+        let requests = ctx.take_requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2], ExchangeRequest::CancelOrder(first));
+        assert!(ctx.take_requests().is_empty());
+    }
+}
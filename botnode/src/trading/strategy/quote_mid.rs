@@ -0,0 +1,131 @@
+//! Mid price quoting strategy
+
+use super::*;
+
+/// Quotes a bid and an ask at fixed distance from the mid price
+///
+/// Quotes are replaced whenever the mid price of the market changes.
+pub struct QuoteMid {
+    exchange: ExchangeId,
+    market: Box<str>,
+    size: f64,
+    spread_bps: f64,
+    mid: Option<f64>,
+    quotes: Vec<u64>,
+}
+
+impl QuoteMid {
+    pub fn new(exchange: ExchangeId, market: Box<str>, size: f64, spread_bps: f64) -> Self {
+        Self {
+            exchange,
+            market,
+            size,
+            spread_bps,
+            mid: None,
+            quotes: Vec::with_capacity(2),
+        }
+    }
+
+    fn requote(&mut self, ctx: &mut StrategyContext, bid: f64, ask: f64) {
+        let mid = (bid + ask) / 2.0;
+
+        if self.mid == Some(mid) {
+            return;
+        }
+
+        self.mid = Some(mid);
+
+        for order_id in self.quotes.drain(..) {
+            ctx.cancel_order(order_id);
+        }
+
+        let offset = mid * self.spread_bps / 10_000.0;
+
+        self.quotes.push(ctx.place_order(
+            self.exchange,
+            &self.market,
+            Side::Buy,
+            mid - offset,
+            self.size,
+        ));
+        self.quotes.push(ctx.place_order(
+            self.exchange,
+            &self.market,
+            Side::Sell,
+            mid + offset,
+            self.size,
+        ));
+    }
+}
+
+impl Strategy for QuoteMid {
+    fn name(&self) -> &str {
+        "quote-mid"
+    }
+
+    fn on_market_event(
+        &mut self,
+        ctx: &mut StrategyContext,
+        exchange: ExchangeId,
+        event: &MarketEvent,
+    ) {
+        if exchange != self.exchange {
+            return;
+        }
+
+        match &event.r#type {
+            MarketEventType::OrderbookUpdate(market, orderbook) if *market == self.market => {
+                if let (Some(bid), Some(ask)) = (
+                    orderbook.bids.price_vec.last(),
+                    orderbook.asks.price_vec.first(),
+                ) {
+                    self.requote(ctx, *bid, *ask);
+                }
+            }
+            MarketEventType::MidPriceChange(market, bid, ask) if *market == self.market => {
+                self.requote(ctx, *bid, *ask);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_fill(&mut self, _ctx: &mut StrategyContext, fill: &Fill) {
+        self.quotes.retain(|order_id| *order_id != fill.order_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_mid_requotes_on_mid_change() {
+        let mut strategy = QuoteMid::new(ExchangeId::Ftx, Box::from("BTC/USD"), 1.0, 10.0);
+        let clock = botvana::clock::real_clock();
+        let mut ctx = StrategyContext::new(clock.clone());
+        let event = MarketEvent::mid_price_change(&*clock, Box::from("BTC/USD"), 99.0, 101.0);
+
+        strategy.on_market_event(&mut ctx, ExchangeId::Ftx, &event);
+
+        let requests = ctx.take_requests();
+        assert_eq!(requests.len(), 2);
+        match &requests[0] {
+            ExchangeRequest::PlaceOrder(order) => {
+                assert_eq!(order.side, Side::Buy);
+                assert!((order.price - 99.9).abs() < 1e-9);
+            }
+            request => panic!("unexpected request {request:?}"),
+        }
+
+        // Same mid price does not requote
+        strategy.on_market_event(&mut ctx, ExchangeId::Ftx, &event);
+        assert!(ctx.take_requests().is_empty());
+
+        let event = MarketEvent::mid_price_change(&*clock, Box::from("BTC/USD"), 100.0, 102.0);
+        strategy.on_market_event(&mut ctx, ExchangeId::Ftx, &event);
+
+        let requests = ctx.take_requests();
+        assert_eq!(requests.len(), 4);
+        assert!(matches!(requests[0], ExchangeRequest::CancelOrder(_)));
+    }
+}
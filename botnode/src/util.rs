@@ -1 +1,64 @@
+//! Helpers shared by engines
 
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+use botvana::report::Percentiles;
+
+/// Highest tracked latency in microseconds, higher values are saturated
+pub const MAX_LATENCY_US: u64 = 60_000_000;
+
+/// Returns HDR histogram for latencies in microseconds
+pub fn latency_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).expect("valid histogram bounds")
+}
+
+/// Records latency into the histogram and returns it in microseconds
+pub fn record_latency(histogram: &mut Histogram<u64>, latency: Duration) -> u64 {
+    let latency = latency.as_micros().min(MAX_LATENCY_US as u128) as u64;
+
+    histogram.saturating_record(latency);
+
+    latency
+}
+
+/// Returns percentiles of recorded values
+pub fn percentiles(histogram: &Histogram<u64>) -> Percentiles {
+    if histogram.is_empty() {
+        return Percentiles::default();
+    }
+
+    Percentiles {
+        count: histogram.len(),
+        p50: histogram.value_at_quantile(0.5),
+        p90: histogram.value_at_quantile(0.9),
+        p99: histogram.value_at_quantile(0.99),
+        max: histogram.max(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = latency_histogram();
+        assert_eq!(percentiles(&histogram), Percentiles::default());
+
+        for value in 1..=100 {
+            record_latency(&mut histogram, Duration::from_micros(value));
+        }
+        assert_eq!(
+            record_latency(&mut histogram, Duration::from_secs(3600)),
+            MAX_LATENCY_US
+        );
+
+        let percentiles = percentiles(&histogram);
+        assert_eq!(percentiles.count, 101);
+        assert_eq!(percentiles.p50, 51);
+        assert_eq!(percentiles.p90, 91);
+        assert!(percentiles.max >= MAX_LATENCY_US);
+    }
+}
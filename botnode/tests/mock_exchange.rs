@@ -0,0 +1,201 @@
+//! End-to-end market data tests against the mock exchange
+
+use std::time::Instant;
+
+use botnode::{
+    market_data::{binance::Binance, ftx::Ftx, MarketDataEngine},
+    prelude::*,
+};
+use botvana::{cfg::ExchangeConfig, clock::real_clock};
+use mock_exchange::{MockExchange, Script, Step, Venue};
+
+const FTX_PARTIAL: &str = r#"{"channel":"orderbook","market":"BTC/USD","type":"partial","data":{"time":{time},"checksum":0,"bids":[[41999.0,0.5],[41998.0,1.2]],"asks":[[42000.0,0.4],[42001.0,1.1]],"action":"partial"}}"#;
+const FTX_UPDATE: &str = r#"{"channel":"orderbook","market":"BTC/USD","type":"update","data":{"time":{time},"checksum":0,"bids":[[41999.0,0.0]],"asks":[],"action":"update"}}"#;
+const BINANCE_DEPTH_UPDATE: &str = r#"{"e":"depthUpdate","E":{time_ms},"s":"BTCUSDT","U":{seq},"u":{seq},"b":[["41999.00000000","0.00000000"]],"a":[]}"#;
+const BINANCE_EMPTY_DEPTH_UPDATE: &str =
+    r#"{"e":"depthUpdate","E":{time_ms},"s":"BTCUSDT","U":{seq},"u":{seq},"b":[],"a":[]}"#;
+
+fn bot_configuration(exchange_config: ExchangeConfig, market: &str) -> BotConfiguration {
+    BotConfiguration {
+        bot_id: BotId(0),
+        peer_bots: Box::new([]),
+        exchanges: Box::from([exchange_config.exchange.clone()]),
+        markets: Box::from([Box::from(market)]),
+        indicators: Box::new([]),
+        exchange_configs: Box::new([exchange_config]),
+        recorder: None,
+        strategy: None,
+        topology: None,
+    }
+}
+
+/// Pops market events until the predicate matches or the timeout elapses
+///
+/// Sleeps briefly while the queue is empty instead of spinning.
+fn wait_for_event<F>(rx: &spsc_queue::Consumer<MarketEvent>, timeout: Duration, f: F) -> MarketEvent
+where
+    F: Fn(&MarketEvent) -> bool,
+{
+    let start = Instant::now();
+
+    loop {
+        match rx.try_pop() {
+            Some(event) if f(&event) => return event,
+            Some(_) => {}
+            None => std::thread::sleep(Duration::from_millis(1)),
+        }
+
+        assert!(start.elapsed() < timeout, "Timed out waiting for event");
+    }
+}
+
+fn best_bid(event: &MarketEvent) -> Option<f64> {
+    match &event.r#type {
+        MarketEventType::OrderbookUpdate(_, orderbook) => orderbook.bids.price_vec.last().cloned(),
+        _ => None,
+    }
+}
+
+#[test]
+fn ftx_reconnects_and_resyncs_orderbook() {
+    let mock = MockExchange::new(Venue::Ftx)
+        .with_ws_script(Script::new(vec![
+            Step::Expect,
+            Step::Send(FTX_PARTIAL.to_string()),
+            Step::Send(FTX_UPDATE.to_string()),
+            Step::Disconnect,
+        ]))
+        .with_ws_script(Script::new(vec![
+            Step::Expect,
+            Step::Send(FTX_PARTIAL.to_string()),
+        ]))
+        .start()
+        .unwrap();
+
+    let exchange_config = ExchangeConfig {
+        rest_url: Some(Box::from(mock.rest_url())),
+        ws_url: Some(Box::from(mock.ws_url())),
+        ..ExchangeConfig::new("ftx")
+    };
+    let (config_tx, config_rx) = spsc_queue::make(1);
+    let mut engine =
+        MarketDataEngine::<_, 1>::new(config_rx, Ftx::new(&exchange_config, real_clock()));
+    let data_rx = engine.data_rx();
+    let shutdown = Shutdown::new();
+
+    config_tx.try_push(bot_configuration(exchange_config, "BTC/USD"));
+    spawn_engine(0, engine, shutdown.clone()).unwrap();
+
+    let timeout = Duration::from_secs(10);
+
+    wait_for_event(&data_rx, timeout, |event| {
+        matches!(event.r#type, MarketEventType::Markets(_))
+    });
+
+    // Partial snapshot followed by an update removing the best bid
+    let event = wait_for_event(&data_rx, timeout, |event| best_bid(event).is_some());
+    assert_eq!(best_bid(&event), Some(41999.0));
+    let event = wait_for_event(&data_rx, timeout, |event| best_bid(event).is_some());
+    assert_eq!(best_bid(&event), Some(41998.0));
+
+    // After the scripted disconnect the adapter reconnects and receives
+    // a new snapshot
+    let event = wait_for_event(&data_rx, timeout, |event| best_bid(event).is_some());
+    assert_eq!(best_bid(&event), Some(41999.0));
+    assert_eq!(mock.connections(), 2);
+
+    shutdown.shutdown();
+}
+
+#[test]
+fn binance_resyncs_orderbook_after_sequence_gap() {
+    let mock = MockExchange::new(Venue::Binance)
+        .with_ws_script(Script::new(vec![
+            Step::Expect,
+            Step::Send(BINANCE_DEPTH_UPDATE.to_string()),
+            Step::Gap(5),
+            Step::Send(BINANCE_EMPTY_DEPTH_UPDATE.to_string()),
+        ]))
+        .with_ws_script(Script::new(vec![
+            Step::Expect,
+            Step::Send(BINANCE_EMPTY_DEPTH_UPDATE.to_string()),
+        ]))
+        .start()
+        .unwrap();
+
+    let exchange_config = ExchangeConfig {
+        rest_url: Some(Box::from(mock.rest_url())),
+        ws_url: Some(Box::from(mock.ws_url())),
+        ..ExchangeConfig::new("binance")
+    };
+    let (config_tx, config_rx) = spsc_queue::make(1);
+    let mut engine =
+        MarketDataEngine::<_, 1>::new(config_rx, Binance::new(&exchange_config, real_clock()));
+    let data_rx = engine.data_rx();
+    let shutdown = Shutdown::new();
+
+    config_tx.try_push(bot_configuration(exchange_config, "BTCUSDT"));
+    spawn_engine(0, engine, shutdown.clone()).unwrap();
+
+    let timeout = Duration::from_secs(10);
+
+    wait_for_event(&data_rx, timeout, |event| {
+        matches!(event.r#type, MarketEventType::Markets(_))
+    });
+
+    // Update removing the best bid of the snapshot
+    let event = wait_for_event(&data_rx, timeout, |event| best_bid(event).is_some());
+    assert_eq!(best_bid(&event), Some(41998.0));
+
+    // The update after the gap isn't applied, the adapter reconnects and
+    // starts over from a new snapshot
+    let event = wait_for_event(&data_rx, timeout, |event| best_bid(event).is_some());
+    assert_eq!(best_bid(&event), Some(41999.0));
+    assert_eq!(mock.connections(), 2);
+
+    shutdown.shutdown();
+}
+
+#[test]
+fn binance_resyncs_orderbook_when_first_update_skips_snapshot() {
+    let mock = MockExchange::new(Venue::Binance)
+        .with_ws_script(Script::new(vec![
+            Step::Expect,
+            Step::Gap(5),
+            Step::Send(BINANCE_DEPTH_UPDATE.to_string()),
+        ]))
+        .with_ws_script(Script::new(vec![
+            Step::Expect,
+            Step::Send(BINANCE_EMPTY_DEPTH_UPDATE.to_string()),
+        ]))
+        .start()
+        .unwrap();
+
+    let exchange_config = ExchangeConfig {
+        rest_url: Some(Box::from(mock.rest_url())),
+        ws_url: Some(Box::from(mock.ws_url())),
+        ..ExchangeConfig::new("binance")
+    };
+    let (config_tx, config_rx) = spsc_queue::make(1);
+    let mut engine =
+        MarketDataEngine::<_, 1>::new(config_rx, Binance::new(&exchange_config, real_clock()));
+    let data_rx = engine.data_rx();
+    let shutdown = Shutdown::new();
+
+    config_tx.try_push(bot_configuration(exchange_config, "BTCUSDT"));
+    spawn_engine(0, engine, shutdown.clone()).unwrap();
+
+    let timeout = Duration::from_secs(10);
+
+    wait_for_event(&data_rx, timeout, |event| {
+        matches!(event.r#type, MarketEventType::Markets(_))
+    });
+
+    // The first update starts after the update following the snapshot, so
+    // it isn't applied and the adapter starts over from a new snapshot
+    let event = wait_for_event(&data_rx, timeout, |event| best_bid(event).is_some());
+    assert_eq!(best_bid(&event), Some(41999.0));
+    assert_eq!(mock.connections(), 2);
+
+    shutdown.shutdown();
+}
@@ -0,0 +1,50 @@
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+CREATE TABLE top_of_book (
+    time        TIMESTAMPTZ      NOT NULL,
+    received_at TIMESTAMPTZ      NOT NULL,
+    bot_id      INTEGER          NOT NULL,
+    exchange    TEXT             NOT NULL,
+    market      TEXT             NOT NULL,
+    bid         DOUBLE PRECISION,
+    bid_size    DOUBLE PRECISION,
+    ask         DOUBLE PRECISION,
+    ask_size    DOUBLE PRECISION
+);
+SELECT create_hypertable('top_of_book', 'time');
+CREATE INDEX ON top_of_book (exchange, market, time DESC);
+
+CREATE TABLE trades (
+    time        TIMESTAMPTZ      NOT NULL,
+    received_at TIMESTAMPTZ      NOT NULL,
+    bot_id      INTEGER          NOT NULL,
+    exchange    TEXT             NOT NULL,
+    market      TEXT             NOT NULL,
+    price       DOUBLE PRECISION NOT NULL,
+    size        DOUBLE PRECISION NOT NULL
+);
+SELECT create_hypertable('trades', 'time');
+CREATE INDEX ON trades (exchange, market, time DESC);
+
+CREATE TABLE fills (
+    time        TIMESTAMPTZ      NOT NULL,
+    bot_id      INTEGER          NOT NULL,
+    exchange    TEXT             NOT NULL,
+    market      TEXT             NOT NULL,
+    order_id    BIGINT           NOT NULL,
+    side        TEXT             NOT NULL,
+    price       DOUBLE PRECISION NOT NULL,
+    size        DOUBLE PRECISION NOT NULL,
+    fee         DOUBLE PRECISION NOT NULL
+);
+SELECT create_hypertable('fills', 'time');
+CREATE INDEX ON fills (bot_id, time DESC);
+
+CREATE TABLE bot_metrics (
+    time        TIMESTAMPTZ      NOT NULL,
+    bot_id      INTEGER          NOT NULL,
+    name        TEXT             NOT NULL,
+    value       DOUBLE PRECISION NOT NULL
+);
+SELECT create_hypertable('bot_metrics', 'time');
+CREATE INDEX ON bot_metrics (bot_id, name, time DESC);
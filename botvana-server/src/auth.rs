@@ -0,0 +1,58 @@
+//! API token authentication of the websocket gateway and HTTP API
+//!
+//! Clients pass the token in `Authorization: Bearer <token>` header or, when
+//! headers can't be set such as in browser websockets, in `token` query
+//! parameter.
+
+use tide::{Next, Request, Response, StatusCode};
+
+use crate::http::HttpState;
+
+/// Returns token of `Authorization` header value
+pub fn bearer_token(header: &str) -> Option<&str> {
+    header.strip_prefix("Bearer ").map(str::trim)
+}
+
+/// Returns value of `token` parameter of the query string
+pub fn query_token(query: &str) -> Option<&str> {
+    query
+        .split('&')
+        .find_map(|param| param.strip_prefix("token="))
+}
+
+/// HTTP middleware rejecting requests without accepted token
+pub struct TokenAuth;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<HttpState> for TokenAuth {
+    async fn handle(&self, req: Request<HttpState>, next: Next<'_, HttpState>) -> tide::Result {
+        let token = req
+            .header("Authorization")
+            .and_then(|values| bearer_token(values.last().as_str()))
+            .or_else(|| req.url().query().and_then(query_token));
+
+        if !req.state().auth.is_authorized(token) {
+            return Ok(Response::new(StatusCode::Unauthorized));
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+    }
+
+    #[test]
+    fn test_query_token() {
+        assert_eq!(query_token("token=abc"), Some("abc"));
+        assert_eq!(query_token("exchange=ftx&token=abc"), Some("abc"));
+        assert_eq!(query_token("exchange=ftx"), None);
+    }
+}
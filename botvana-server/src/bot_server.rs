@@ -1,20 +1,34 @@
-use std::{net::ToSocketAddrs, rc::Rc, time::Duration};
+use std::{
+    net::ToSocketAddrs,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use futures::{prelude::*, stream::StreamExt};
 use glommio::{enclose, net::TcpListener, net::TcpStream, sync::Semaphore, timer::sleep, Task};
 use tracing::{debug, error, info, warn};
 
-use crate::config::BotnodeConfig;
+use crate::{config::BotnodeConfig, persistence::PersistenceHandle};
 use botvana::{
     cfg::{BotConfiguration, PeerBot},
+    clock::{Clock, RealClock},
+    metrics::{self, Counter},
     net::{
-        codec,
-        msg::{BotId, Message},
+        auth, codec,
+        msg::{negotiate_version, BotId, BotMetadata, Capabilities, Message},
+        tls::{MaybeTlsStream, TlsAcceptor},
     },
     state,
 };
 
 const ACTIVITY_TIMEOUT_SECS: u64 = 15;
+/// Interval of checking for configuration updates and commands to push to
+/// the bot
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// Framed connection to the bot
+pub type BotStream = codec::Framed<MaybeTlsStream<TcpStream>, codec::BotvanaCodec>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum BotServerError {
@@ -28,14 +42,90 @@ pub enum BotServerError {
     DuplicateHello,
     #[error("unknown bot id supplied")]
     UnknownBotID,
+    #[error("authentication failed")]
+    AuthenticationFailed,
+    #[error("message received before authentication")]
+    Unauthenticated,
+    #[error("unsupported protocol version {0}")]
+    UnsupportedProtocolVersion(u32),
+    #[error("TLS handshake failed: {0}")]
+    TlsHandshake(std::io::Error),
+}
+
+/// Metrics of bot connections
+struct ConnectionMetrics {
+    connections: Arc<Counter>,
+    messages: Arc<Counter>,
+    read_errors: Arc<Counter>,
+    timeouts: Arc<Counter>,
+}
+
+impl ConnectionMetrics {
+    fn new() -> Self {
+        let registry = metrics::registry();
+
+        Self {
+            connections: registry.counter(
+                "botvana_server_bot_connections_total",
+                "Connections accepted from bots",
+                &[],
+            ),
+            messages: registry.counter(
+                "botvana_server_bot_messages_total",
+                "Messages received from bots",
+                &[],
+            ),
+            read_errors: registry.counter(
+                "botvana_server_bot_read_errors_total",
+                "Bot connections closed because of unreadable frame",
+                &[],
+            ),
+            timeouts: registry.counter(
+                "botvana_server_bot_timeouts_total",
+                "Bot connections closed because of inactivity",
+                &[],
+            ),
+        }
+    }
+}
+
+/// State of the bot connection
+#[derive(Debug)]
+pub enum ConnectionState {
+    /// Waiting for `Hello`
+    Connected,
+    /// Challenge was sent, waiting for the response
+    Challenged {
+        bot_id: BotId,
+        metadata: BotMetadata,
+        nonce: auth::Nonce,
+    },
+    /// Bot is authenticated
+    Authenticated(BotId),
+}
+
+impl ConnectionState {
+    /// Returns id of authenticated bot
+    pub fn bot_id(&self) -> Option<&BotId> {
+        match self {
+            ConnectionState::Authenticated(bot_id) => Some(bot_id),
+            _ => None,
+        }
+    }
 }
 
 /// Bot server loop
+///
+/// Connections are accepted over TLS when `tls` acceptor is given. Frames
+/// larger than `max_frame_size` close the connection.
 pub async fn serve<A>(
     addr: A,
     max_connections: usize,
+    max_frame_size: usize,
     global_state: state::GlobalState,
     botnode_configs: Box<[BotnodeConfig]>,
+    persistence: Option<PersistenceHandle>,
+    tls: Option<TlsAcceptor>,
 ) -> std::io::Result<()>
 where
     A: ToSocketAddrs + std::net::ToSocketAddrs,
@@ -56,14 +146,24 @@ where
 
         {
             let botnode_configs = botnode_configs.clone();
+            let persistence = persistence.clone();
+            let tls = tls.clone();
             Task::local(enclose! { (conn_control) async move {
                 let _permit = conn_control
                     .acquire_permit(1)
                     .await
                     .expect("failed to acquire permit");
-                let mut stream = codec::Framed::new(stream, codec::BotvanaCodec);
+                let stream = match accept_tls(tls.as_ref(), stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Error while accepting the connection: {}", e);
+                        return;
+                    }
+                };
+                let codec = codec::BotvanaCodec::new().with_max_frame_size(max_frame_size);
+                let mut stream = codec::Framed::new(stream, codec);
 
-                if let Err(e) = handle_connection(&mut stream, global_state, botnode_configs).await {
+                if let Err(e) = handle_connection(&mut stream, global_state, botnode_configs, persistence).await {
                     error!("Error while handling the connection: {}", e);
                 }
             }})
@@ -72,112 +172,220 @@ where
     }
 }
 
+/// Performs TLS handshake when enabled
+async fn accept_tls(
+    tls: Option<&TlsAcceptor>,
+    stream: TcpStream,
+) -> Result<MaybeTlsStream<TcpStream>, BotServerError> {
+    match tls {
+        Some(acceptor) => acceptor
+            .accept(stream)
+            .await
+            .map(|stream| MaybeTlsStream::Server(Box::new(stream)))
+            .map_err(BotServerError::TlsHandshake),
+        None => Ok(MaybeTlsStream::Plain(stream)),
+    }
+}
+
 /// Handle an incoming connection from the bot
 pub async fn handle_connection(
-    stream: &mut codec::Framed<TcpStream, codec::BotvanaCodec>,
+    stream: &mut BotStream,
     global_state: state::GlobalState,
     botnode_configs: Box<[BotnodeConfig]>,
+    persistence: Option<PersistenceHandle>,
 ) -> Result<(), BotServerError> {
-    let mut conn_bot_id = None;
+    let mut conn_state = ConnectionState::Connected;
+    let mut last_activity = Instant::now();
+    let mut sent_config_version = 0;
+    let metrics = ConnectionMetrics::new();
 
-    loop {
+    metrics.connections.inc();
+
+    let result = loop {
         futures::select! {
             frame = stream.next().fuse() => {
+                last_activity = Instant::now();
+
                 let frame = match frame {
                     Some(Ok(frame)) => frame,
-                    _ => {
-                        if let Some(conn_bot_id) = conn_bot_id {
-                            global_state.remove_bot(conn_bot_id);
-                        }
-
-                        if frame.is_none() {
-                            return Ok(())
-                        } else {
-                            return Err(BotServerError::ReadError)
-                        }
+                    Some(Err(e)) => {
+                        warn!("Failed to read frame: {:?}", e);
+                        metrics.read_errors.inc();
+                        break Err(BotServerError::ReadError)
                     }
+                    None => break Ok(()),
                 };
 
-                debug!("received frame={:?} botid={:?}", frame, conn_bot_id);
+                debug!("received frame={:?} state={:?}", frame, conn_state);
+                metrics.messages.inc();
 
-                process_bot_message(stream, &mut conn_bot_id, global_state.clone(), &botnode_configs, frame)
-                    .await
-                    .expect("Failed to process");
+                if let Err(e) = process_bot_message(
+                    stream,
+                    &mut conn_state,
+                    global_state.clone(),
+                    &botnode_configs,
+                    persistence.as_ref(),
+                    frame,
+                )
+                .await
+                {
+                    break Err(e);
+                }
             }
-            _ = sleep(Duration::from_secs(ACTIVITY_TIMEOUT_SECS)).fuse() => {
-                warn!("Timeout while waiting for activity");
+            _ = sleep(Duration::from_millis(POLL_INTERVAL_MS)).fuse() => {
+                if last_activity.elapsed() > Duration::from_secs(ACTIVITY_TIMEOUT_SECS) {
+                    warn!("Timeout while waiting for activity");
+                    metrics.timeouts.inc();
+
+                    break Err(BotServerError::Timeout)
+                }
 
-                break Err(BotServerError::Timeout)
+                if let Some(bot_id) = conn_state.bot_id() {
+                    if let Err(e) =
+                        push_config_update(stream, &global_state, bot_id, &mut sent_config_version)
+                            .await
+                    {
+                        break Err(e);
+                    }
+
+                    if let Err(e) = push_commands(stream, &global_state, bot_id).await {
+                        break Err(e);
+                    }
+                }
             }
         }
+    };
+
+    if let ConnectionState::Authenticated(bot_id) = conn_state {
+        global_state.fail_pending_commands(&bot_id, "Bot disconnected");
+        global_state.remove_bot(bot_id);
     }
+
+    result
 }
 
 /// Process one message coming from the bot over the network
+///
+/// Only `Hello` and `AuthResponse` are accepted until the bot is
+/// authenticated. Bots speaking protocol version 1 without a configured
+/// secret are accepted right after `Hello`.
 pub async fn process_bot_message(
-    stream: &mut codec::Framed<TcpStream, codec::BotvanaCodec>,
-    conn_bot_id: &mut Option<BotId>,
+    stream: &mut BotStream,
+    conn_state: &mut ConnectionState,
     global_state: state::GlobalState,
     botnode_configs: &[BotnodeConfig],
+    persistence: Option<&PersistenceHandle>,
     msg: Message,
 ) -> Result<(), BotServerError> {
-    match msg {
-        Message::Hello(bot_id, bot_metadata) => {
-            // If the bot has sent Hello message before, we don't accept it and
-            // break the current connection
-            if let Some(conn_bot_id) = conn_bot_id {
-                warn!("Bot {:?} sending duplicate Hello message", conn_bot_id);
-                global_state.remove_bot(conn_bot_id.clone());
-                return Err(BotServerError::DuplicateHello);
-            }
+    let (conn_bot_id, msg) = match (&*conn_state, msg) {
+        (ConnectionState::Connected, Message::Hello(bot_id, metadata)) => {
+            let version = negotiate_version(metadata.bot_version).ok_or_else(|| {
+                warn!(
+                    "Bot {:?} speaks unsupported protocol version {}",
+                    bot_id, metadata.bot_version
+                );
+                BotServerError::UnsupportedProtocolVersion(metadata.bot_version)
+            })?;
 
-            if bot_id.0 >= botnode_configs.len() as u16 {
+            debug!(
+                "Negotiated protocol version {} with bot {:?}",
+                version, bot_id
+            );
+
+            let config = botnode_configs.get(bot_id.0 as usize).ok_or_else(|| {
                 warn!("Invalid bot id supplied: {:?}", bot_id);
-                return Err(BotServerError::UnknownBotID);
-            }
+                BotServerError::UnknownBotID
+            })?;
 
-            // Save the bot_id in local variable that's scoped for this
-            // connection only
-            *conn_bot_id = Some(bot_id.clone());
+            // Bots speaking protocol version 1 predate authentication, they
+            // are accepted until a secret is configured for them
+            if version == 1 {
+                if config.secret.is_some() {
+                    warn!(
+                        "Bot {:?} can't authenticate with protocol version 1, rejecting",
+                        bot_id
+                    );
+                    return Err(BotServerError::AuthenticationFailed);
+                }
 
-            let bots = global_state.connected_bots();
+                warn!("Accepting bot {:?} without authentication", bot_id);
 
-            global_state.add_bot(bot_id.clone());
+                accept_bot(stream, &global_state, config, &bot_id, metadata).await?;
 
-            let peer_bots = bots
-                .iter()
-                .map(|id| PeerBot { bot_id: id.clone() })
-                .collect();
+                *conn_state = ConnectionState::Authenticated(bot_id);
 
-            info!(
-                "Hello from bot id = {:?}, metadata = {:?}; total = {}",
-                bot_id,
-                bot_metadata,
-                bots.len()
-            );
+                return Ok(());
+            }
 
-            let config = botnode_configs.get(bot_id.0 as usize).unwrap();
-            let exchanges = config.exchanges.clone();
-            let markets = config.markets.clone();
-            let out_msg = Message::BotConfiguration(BotConfiguration {
-                bot_id: bot_id.clone(),
-                peer_bots,
-                exchanges,
-                markets,
-                indicators: Box::new([]),
-            });
-            info!("Sending bot configuration {:?}", out_msg);
+            if config.secret.is_none() {
+                warn!("No secret configured for bot {:?}, rejecting", bot_id);
+                return Err(BotServerError::AuthenticationFailed);
+            }
+
+            let nonce = auth::nonce().map_err(|e| {
+                error!("Failed to generate nonce: {}", e);
+                BotServerError::AuthenticationFailed
+            })?;
 
             stream
-                .send(out_msg)
+                .send(Message::auth_challenge(nonce))
                 .await
                 .map_err(|_| BotServerError::WriteError)?;
+
+            *conn_state = ConnectionState::Challenged {
+                bot_id,
+                metadata,
+                nonce,
+            };
+
+            return Ok(());
         }
+        (
+            ConnectionState::Challenged {
+                bot_id,
+                metadata,
+                nonce,
+            },
+            Message::AuthResponse(signature),
+        ) => {
+            let config = &botnode_configs[bot_id.0 as usize];
+            let secret = config.secret.as_deref().unwrap_or_default();
+
+            if !auth::verify(secret.as_bytes(), bot_id, nonce, &signature) {
+                warn!("Bot {:?} failed to authenticate", bot_id);
+                return Err(BotServerError::AuthenticationFailed);
+            }
+
+            let bot_id = bot_id.clone();
+            let metadata = metadata.clone();
+
+            accept_bot(stream, &global_state, config, &bot_id, metadata).await?;
+
+            *conn_state = ConnectionState::Authenticated(bot_id);
+
+            return Ok(());
+        }
+        (ConnectionState::Authenticated(bot_id), Message::Hello(..)) => {
+            warn!("Bot {:?} sending duplicate Hello message", bot_id);
+            return Err(BotServerError::DuplicateHello);
+        }
+        (ConnectionState::Authenticated(bot_id), msg) => (bot_id.clone(), msg),
+        (state, msg) => {
+            warn!("Unexpected message = {:?} in state {:?}", msg, state);
+            return Err(BotServerError::Unauthenticated);
+        }
+    };
+
+    if let Some(persistence) = persistence {
+        persistence.persist_message(&conn_bot_id, &msg, RealClock.now());
+    }
+
+    match msg {
         Message::Ping(timestamp) => {
             debug!("received ping {}", timestamp);
 
             stream
-                .send(Message::pong())
+                .send(Message::pong(&RealClock))
                 .await
                 .map_err(|_| BotServerError::WriteError)?;
         }
@@ -191,6 +399,53 @@ pub async fn process_bot_message(
                 orderbook.into(),
             );
         }
+        Message::EngineStatus(engine, status) => {
+            global_state.update_engine_status(&conn_bot_id, engine, status);
+        }
+        Message::EngineExit(exit) => {
+            warn!(
+                "Bot {:?} engine {:?} exited: {} (restarts = {}, restart in {:?}ms)",
+                conn_bot_id, exit.engine, exit.reason, exit.restarts, exit.restart_in_ms
+            );
+            global_state.record_engine_exit(&conn_bot_id, exit);
+        }
+        Message::EngineHealth(health) => {
+            if health.stalled {
+                warn!("Bot {:?} engine stalled: {:?}", conn_bot_id, health);
+            } else {
+                info!("Bot {:?} engine recovered: {:?}", conn_bot_id, health);
+            }
+            global_state.record_engine_health(&conn_bot_id, health);
+        }
+        Message::Trades(exchange, market, trades) => {
+            global_state.add_trades(exchange, &market, &trades);
+        }
+        Message::Fill(fill) => {
+            global_state.add_fill(&conn_bot_id, &fill);
+        }
+        Message::Capabilities(capabilities) => {
+            global_state.update_capabilities(&conn_bot_id, capabilities);
+        }
+        Message::ConfigAck(version) => {
+            info!(
+                "Bot {:?} applied configuration version {}",
+                conn_bot_id, version
+            );
+            global_state.update_config_version(&conn_bot_id, version);
+        }
+        Message::CommandResult(id, result) => {
+            info!(
+                "Bot {:?} executed command {}: {:?}",
+                conn_bot_id, id, result
+            );
+            global_state.complete_command(&conn_bot_id, id, result);
+        }
+        Message::StatusReport(report) => {
+            global_state.update_status_report(&conn_bot_id, report);
+        }
+        Message::Metrics(metrics) => {
+            global_state.update_metrics(&conn_bot_id, metrics);
+        }
         msg => {
             warn!("Unhandled message = {:?} from bot {:?}", msg, conn_bot_id);
         }
@@ -198,3 +453,130 @@ pub async fn process_bot_message(
 
     Ok(())
 }
+
+/// Registers authenticated bot and sends its configuration
+async fn accept_bot(
+    stream: &mut BotStream,
+    global_state: &state::GlobalState,
+    config: &BotnodeConfig,
+    bot_id: &BotId,
+    bot_metadata: BotMetadata,
+) -> Result<(), BotServerError> {
+    let bots = global_state.connected_bots();
+
+    global_state.add_bot(bot_id.clone(), bot_metadata.clone());
+
+    let peer_bots = bots
+        .iter()
+        .map(|id| PeerBot { bot_id: id.clone() })
+        .collect();
+
+    info!(
+        "Hello from bot id = {:?}, metadata = {:?}; total = {}",
+        bot_id,
+        bot_metadata,
+        bots.len()
+    );
+
+    let current = global_state.bot_config(bot_id).unwrap_or_else(|| {
+        let config = config.bot_configuration(bot_id.clone());
+        let version = global_state.set_bot_config(bot_id.clone(), config.clone());
+
+        state::VersionedConfig { version, config }
+    });
+    let out_msg = Message::BotConfiguration(BotConfiguration {
+        peer_bots,
+        ..current.config
+    });
+    info!("Sending bot configuration {:?}", out_msg);
+
+    stream
+        .send(out_msg)
+        .await
+        .map_err(|_| BotServerError::WriteError)?;
+
+    global_state.update_config_version(bot_id, current.version);
+
+    // Bots speaking protocol version 2 and newer negotiate capabilities
+    if negotiate_version(bot_metadata.bot_version) >= Some(2) {
+        stream
+            .send(Message::capabilities(Capabilities::SUPPORTED))
+            .await
+            .map_err(|_| BotServerError::WriteError)?;
+    }
+
+    Ok(())
+}
+
+/// Sends newer configuration to the bot when it supports configuration updates
+///
+/// Each version is sent at most once per connection.
+async fn push_config_update(
+    stream: &mut BotStream,
+    global_state: &state::GlobalState,
+    bot_id: &BotId,
+    sent_version: &mut u64,
+) -> Result<(), BotServerError> {
+    let bot = match global_state.bot(bot_id) {
+        Some(bot) if bot.capabilities.contains(Capabilities::CONFIG_UPDATES) => bot,
+        _ => return Ok(()),
+    };
+    let current = match global_state.bot_config(bot_id) {
+        Some(current) if current.version > bot.config_version.max(*sent_version) => current,
+        _ => return Ok(()),
+    };
+    let peer_bots = peer_bots(global_state, bot_id);
+
+    info!(
+        "Sending configuration version {} to bot {:?}",
+        current.version, bot_id
+    );
+
+    stream
+        .send(Message::config_update(
+            current.version,
+            BotConfiguration {
+                peer_bots,
+                ..current.config
+            },
+        ))
+        .await
+        .map_err(|_| BotServerError::WriteError)?;
+
+    *sent_version = current.version;
+
+    Ok(())
+}
+
+/// Sends queued commands to the bot when it supports commands
+async fn push_commands(
+    stream: &mut BotStream,
+    global_state: &state::GlobalState,
+    bot_id: &BotId,
+) -> Result<(), BotServerError> {
+    match global_state.bot(bot_id) {
+        Some(bot) if bot.capabilities.contains(Capabilities::COMMANDS) => {}
+        _ => return Ok(()),
+    }
+
+    for (id, command) in global_state.take_unsent_commands(bot_id) {
+        info!("Sending command {} {:?} to bot {:?}", id, command, bot_id);
+
+        stream
+            .send(Message::command(id, command))
+            .await
+            .map_err(|_| BotServerError::WriteError)?;
+    }
+
+    Ok(())
+}
+
+/// Returns other connected bots
+fn peer_bots(global_state: &state::GlobalState, bot_id: &BotId) -> Box<[PeerBot]> {
+    global_state
+        .connected_bots()
+        .into_iter()
+        .filter(|id| id != bot_id)
+        .map(|bot_id| PeerBot { bot_id })
+        .collect()
+}
@@ -1,9 +1,62 @@
+use std::{path::PathBuf, time::Duration};
+
 use serde::Deserialize;
 
+use botvana::{
+    cfg::{BotConfiguration, ExchangeConfig, RecorderConfig, StrategyConfig, TopologyConfig},
+    net::{auth, msg::BotId, tls},
+};
+
 /// Configuration for the bot server
 #[derive(Deserialize)]
 pub struct BotServerConfig {
     pub listen_address: String,
+    /// Maximum size of frame payload received from bots in bytes
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    /// Bots connect over TLS when configured
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+/// TLS configuration of the bot server
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain of the server
+    pub cert_path: PathBuf,
+    /// PEM encoded private key of the server
+    pub key_path: PathBuf,
+    /// PEM encoded CA certificates, bots must present client certificate
+    /// signed by one of them when set
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Loads certificates and returns TLS acceptor
+    pub fn acceptor(&self) -> Result<tls::TlsAcceptor, tls::TlsError> {
+        let config = tls::server_config(
+            &self.cert_path,
+            &self.key_path,
+            self.client_ca_path.as_deref(),
+        )?;
+
+        Ok(tls::TlsAcceptor::from(config))
+    }
+}
+
+/// Configuration for HTTP API
+#[derive(Deserialize)]
+pub struct HttpServerConfig {
+    pub listen_address: String,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: "127.0.0.1:8080".to_string(),
+        }
+    }
 }
 
 /// Configuration for Websocket gateway
@@ -17,6 +70,94 @@ pub struct WebsocketServerConfig {
 pub struct BotnodeConfig {
     pub markets: Box<[Box<str>]>,
     pub exchanges: Box<[Box<str>]>,
+    #[serde(default)]
+    pub exchange_config: Box<[ExchangeConfig]>,
+    #[serde(default)]
+    pub recorder: Option<RecorderConfig>,
+    #[serde(default)]
+    pub strategy: Option<StrategyConfig>,
+    /// Engines the bot runs and their CPUs
+    #[serde(default)]
+    pub topology: Option<TopologyConfig>,
+    /// Shared secret the bot authenticates with, bots without a secret are
+    /// rejected unless they speak protocol version 1
+    #[serde(default)]
+    pub secret: Option<Box<str>>,
+}
+
+impl BotnodeConfig {
+    /// Returns initial configuration of the bot, without peer bots
+    pub fn bot_configuration(&self, bot_id: BotId) -> BotConfiguration {
+        BotConfiguration {
+            bot_id,
+            peer_bots: Box::new([]),
+            exchanges: self.exchanges.clone(),
+            markets: self.markets.clone(),
+            indicators: Box::new([]),
+            exchange_configs: self.exchange_config.clone(),
+            recorder: self.recorder.clone(),
+            strategy: self.strategy.clone(),
+            topology: self.topology.clone(),
+        }
+    }
+}
+
+/// Authentication of the websocket gateway and HTTP API clients
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AuthConfig {
+    /// Accepted API tokens, authentication is disabled when empty
+    #[serde(default)]
+    pub api_tokens: Box<[Box<str>]>,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.api_tokens.is_empty()
+    }
+
+    /// Returns whether the token is accepted
+    pub fn is_authorized(&self, token: Option<&str>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        token.map_or(false, |token| {
+            self.api_tokens
+                .iter()
+                .any(|accepted| auth::tokens_eq(accepted.as_bytes(), token.as_bytes()))
+        })
+    }
+}
+
+/// Configuration for persisting data into TimescaleDB
+#[derive(Clone, Debug, Deserialize)]
+pub struct PersistenceConfig {
+    /// Postgres connection string
+    pub url: String,
+    /// Interval between flushes of buffered records in milliseconds
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// Number of buffered records that triggers a flush before the interval
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+impl PersistenceConfig {
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.flush_interval_ms)
+    }
+}
+
+fn default_max_frame_size() -> usize {
+    botvana::net::codec::DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn default_batch_size() -> usize {
+    10_000
 }
 
 /// botvana-server configuration
@@ -24,5 +165,31 @@ pub struct BotnodeConfig {
 pub struct ServerConfig {
     pub bot_server: BotServerConfig,
     pub ws_server: WebsocketServerConfig,
+    #[serde(default)]
+    pub http_server: HttpServerConfig,
     pub botnode: Box<[BotnodeConfig]>,
+    #[serde(default)]
+    pub persistence: Option<PersistenceConfig>,
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_config_is_authorized() {
+        let auth = AuthConfig::default();
+
+        assert!(auth.is_authorized(None));
+
+        let auth = AuthConfig {
+            api_tokens: Box::new([Box::from("token")]),
+        };
+
+        assert!(auth.is_authorized(Some("token")));
+        assert!(!auth.is_authorized(Some("other")));
+        assert!(!auth.is_authorized(None));
+    }
 }
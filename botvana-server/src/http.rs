@@ -0,0 +1,287 @@
+//! HTTP API of botvana-server
+
+use tide::{prelude::*, Body, Request, Response, StatusCode};
+
+use crate::{
+    auth::TokenAuth,
+    config::AuthConfig,
+    persistence::history::{HistoryError, HistoryHandle, HistoryQuery},
+};
+use botvana::{
+    cfg::BotConfiguration,
+    exchange::ExchangeId,
+    market::{orderbook::Orderbook, Market},
+    metrics,
+    net::msg::{BotId, Capabilities, Command},
+    state,
+};
+
+/// State shared by HTTP handlers
+#[derive(Clone)]
+pub struct HttpState {
+    pub global_state: state::GlobalState,
+    /// Historical queries, available only with persistence enabled
+    pub history: Option<HistoryHandle>,
+    pub auth: AuthConfig,
+}
+
+/// Query parameters of the markets endpoint
+#[derive(Debug, Deserialize)]
+struct MarketsQuery {
+    exchange: Option<String>,
+}
+
+/// Builds the HTTP application
+///
+/// State endpoints return bots, markets and orderbooks known to the server,
+/// unknown bots, exchanges and markets result in 404. Bot configuration is
+/// replaced with `PUT /bots/:bot_id/config` and pushed to the connected bot.
+/// Commands posted to `/bots/:bot_id/commands` are queued for the bot and
+/// their results are polled by id. `GET /metrics` returns server metrics in
+/// Prometheus text format.
+/// Historical endpoints accept `exchange`, `market`, `from` and `to` (RFC 3339)
+/// query parameters and optional `interval_secs` for downsampling, `limit`
+/// and `bot_id`. All endpoints require API token when authentication is
+/// enabled.
+pub fn app(state: HttpState) -> tide::Server<HttpState> {
+    let mut app = tide::with_state(state);
+
+    app.with(TokenAuth);
+
+    app.at("/").get(|req: Request<HttpState>| async move {
+        let state = req.state();
+        let connected_bots: Vec<_> = state
+            .global_state
+            .connected_bots()
+            .iter()
+            .map(|bot_id| bot_id.0)
+            .collect();
+
+        Ok(json!({
+            "connected_bots": connected_bots,
+        }))
+    });
+
+    app.at("/metrics")
+        .get(|req: Request<HttpState>| async move {
+            let registry = metrics::registry();
+
+            registry
+                .gauge(
+                    "botvana_server_connected_bots",
+                    "Number of connected bots",
+                    &[],
+                )
+                .set(req.state().global_state.connected_bots().len() as i64);
+
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(registry.encode());
+            res.insert_header("Content-Type", metrics::CONTENT_TYPE);
+
+            Ok(res)
+        });
+
+    app.at("/bots").get(|req: Request<HttpState>| async move {
+        Body::from_json(&req.state().global_state.bots())
+    });
+
+    app.at("/bots/:bot_id")
+        .get(|req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+
+            match req.state().global_state.bot(&bot_id) {
+                Some(bot) => Body::from_json(&bot),
+                None => Err(not_found(format!("Unknown bot {}", bot_id.0))),
+            }
+        });
+
+    app.at("/bots/:bot_id/config")
+        .get(|req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+
+            match req.state().global_state.bot_config(&bot_id) {
+                Some(config) => Body::from_json(&config),
+                None => Err(not_found(format!("Unknown bot {}", bot_id.0))),
+            }
+        })
+        .put(|mut req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+            let config: BotConfiguration = req.body_json().await?;
+            let global_state = &req.state().global_state;
+
+            if global_state.bot_config(&bot_id).is_none() {
+                return Err(not_found(format!("Unknown bot {}", bot_id.0)));
+            }
+
+            let version =
+                global_state.set_bot_config(bot_id.clone(), BotConfiguration { bot_id, ..config });
+
+            Body::from_json(&json!({ "version": version }))
+        });
+
+    app.at("/bots/:bot_id/status")
+        .get(|req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+
+            match req.state().global_state.status_report(&bot_id) {
+                Some(report) => Body::from_json(&report),
+                None => Err(not_found(format!("No status report from bot {}", bot_id.0))),
+            }
+        });
+
+    app.at("/bots/:bot_id/metrics")
+        .get(|req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+
+            match req.state().global_state.metrics(&bot_id) {
+                Some(metrics) => Body::from_json(&metrics),
+                None => Err(not_found(format!("No metrics from bot {}", bot_id.0))),
+            }
+        });
+
+    app.at("/bots/:bot_id/commands")
+        .get(|req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+
+            Body::from_json(&req.state().global_state.commands(&bot_id))
+        })
+        .post(|mut req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+            let command: Command = req.body_json().await?;
+            let global_state = &req.state().global_state;
+            let bot = global_state
+                .bot(&bot_id)
+                .ok_or_else(|| not_found(format!("Bot {} is not connected", bot_id.0)))?;
+
+            if !bot.capabilities.contains(Capabilities::COMMANDS) {
+                return Err(tide::Error::from_str(
+                    StatusCode::Conflict,
+                    format!("Bot {} does not support commands", bot_id.0),
+                ));
+            }
+
+            let id = global_state.add_command(bot_id, command);
+            let mut res = Response::new(StatusCode::Accepted);
+            res.set_body(Body::from_json(&json!({ "id": id }))?);
+
+            Ok(res)
+        });
+
+    app.at("/bots/:bot_id/commands/:id")
+        .get(|req: Request<HttpState>| async move {
+            let bot_id = bot_id_param(&req)?;
+            let id = req
+                .param("id")?
+                .parse::<u64>()
+                .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+
+            match req.state().global_state.command(&bot_id, id) {
+                Some(command) => Body::from_json(&command),
+                None => Err(not_found(format!("Unknown command {id}"))),
+            }
+        });
+
+    app.at("/markets")
+        .get(|req: Request<HttpState>| async move {
+            let query: MarketsQuery = req.query()?;
+            let exchange = query.exchange.as_deref().map(parse_exchange).transpose()?;
+            let markets: Vec<_> = req
+                .state()
+                .global_state
+                .markets()
+                .iter()
+                .map(Market::from)
+                .filter(|market| exchange.map_or(true, |exchange| market.exchange == exchange))
+                .collect();
+
+            Body::from_json(&markets)
+        });
+
+    app.at("/orderbooks")
+        .get(|req: Request<HttpState>| async move {
+            Body::from_json(&req.state().global_state.orderbooks())
+        });
+
+    app.at("/orderbooks/:exchange/*market")
+        .get(|req: Request<HttpState>| async move {
+            let exchange = parse_exchange(req.param("exchange")?)?;
+            let market = req.param("market")?;
+
+            match req.state().global_state.get_orderbook(exchange, market) {
+                Some(orderbook) => Body::from_json(&Orderbook {
+                    exchange,
+                    market: Box::from(market),
+                    bids: orderbook.bids,
+                    asks: orderbook.asks,
+                    time: orderbook.time,
+                }),
+                None => Err(not_found(format!("Unknown market {market} on {exchange}"))),
+            }
+        });
+
+    app.at("/history/top-of-book")
+        .get(|req: Request<HttpState>| async move {
+            let (history, query) = history_request(&req)?;
+
+            body(history.top_of_book(query).await)
+        });
+
+    app.at("/history/trades")
+        .get(|req: Request<HttpState>| async move {
+            let (history, query) = history_request(&req)?;
+
+            body(history.trades(query).await)
+        });
+
+    app.at("/history/pnl")
+        .get(|req: Request<HttpState>| async move {
+            let (history, query) = history_request(&req)?;
+
+            body(history.pnl(query).await)
+        });
+
+    app
+}
+
+fn not_found(msg: String) -> tide::Error {
+    tide::Error::from_str(StatusCode::NotFound, msg)
+}
+
+fn bot_id_param(req: &Request<HttpState>) -> tide::Result<BotId> {
+    req.param("bot_id")?
+        .parse::<BotId>()
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))
+}
+
+fn parse_exchange(exchange: &str) -> tide::Result<ExchangeId> {
+    exchange
+        .parse::<ExchangeId>()
+        .map_err(|e| tide::Error::from_str(StatusCode::NotFound, e))
+}
+
+/// Returns history handle and parsed query of the request
+fn history_request(req: &Request<HttpState>) -> tide::Result<(HistoryHandle, HistoryQuery)> {
+    let history = req.state().history.clone().ok_or_else(|| {
+        tide::Error::from_str(StatusCode::ServiceUnavailable, "Persistence is disabled")
+    })?;
+    let query = req.query::<HistoryQuery>()?;
+
+    Ok((history, query))
+}
+
+/// Converts query result into JSON body or error with matching status
+fn body<T: serde::Serialize>(result: Result<T, HistoryError>) -> tide::Result<Body> {
+    match result {
+        Ok(value) => Body::from_json(&value),
+        Err(e @ HistoryError::InvalidQuery(_)) => {
+            Err(tide::Error::from_str(StatusCode::BadRequest, e.to_string()))
+        }
+        Err(e) => {
+            tracing::error!("History query failed: {e:?}");
+            Err(tide::Error::from_str(
+                StatusCode::InternalServerError,
+                e.to_string(),
+            ))
+        }
+    }
+}
@@ -1,3 +1,6 @@
+pub mod auth;
 pub mod bot_server;
 pub mod config;
+pub mod http;
+pub mod persistence;
 pub mod ws;
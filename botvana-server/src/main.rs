@@ -7,11 +7,10 @@ use futures::prelude::*;
 use glommio::{prelude::*, CpuSet};
 use signal_hook::consts::signal::*;
 use signal_hook_async_std::Signals;
-use tide::{prelude::*, Request};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-use botvana::state;
+use botvana::{net::msg::BotId, state};
 use botvana_server::*;
 
 fn main() {
@@ -28,32 +27,60 @@ fn main() {
         .extract()
         .expect("Failed to load configuration");
 
+    if !config.auth.is_enabled() {
+        warn!("No API tokens configured, websocket gateway and HTTP API are open");
+    }
+
+    // The server keeps serving bots when the database is unavailable
+    let persistence = match config.persistence.clone().map(persistence::start) {
+        Some(Ok(persistence)) => Some(persistence),
+        Some(Err(e)) => {
+            error!("Failed to start persistence: {e}");
+            None
+        }
+        None => None,
+    };
+
+    let history = match config
+        .persistence
+        .as_ref()
+        .map(|persistence_config| persistence::history::start(&persistence_config.url))
+    {
+        Some(Ok(history)) => Some(history),
+        Some(Err(e)) => {
+            error!("Failed to start history queries: {e}");
+            None
+        }
+        None => None,
+    };
+
+    let tls = config.bot_server.tls.as_ref().map(|tls_config| {
+        tls_config
+            .acceptor()
+            .expect("Failed to load bot server TLS configuration")
+    });
+
     let state = state::GlobalState::new();
     let state_ref = state.clone();
 
+    for (idx, botnode_config) in config.botnode.iter().enumerate() {
+        let bot_id = BotId(idx as u16);
+        state.set_bot_config(bot_id.clone(), botnode_config.bot_configuration(bot_id));
+    }
+
     LocalExecutorBuilder::new()
         .spawn(|| async move {
             {
                 let state_ref = state_ref.clone();
                 glommio::Task::local(async move {
                     tide::log::start();
-                    let mut app = tide::with_state(state_ref);
-
-                    app.at("/")
-                        .get(|req: Request<state::GlobalState>| async move {
-                            let state = req.state();
-                            let connected_bots: Vec<_> = state
-                                .connected_bots()
-                                .iter()
-                                .map(|bot_id| bot_id.0)
-                                .collect();
-
-                            Ok(json!({
-                                "connected_bots": connected_bots,
-                            }))
-                        });
-
-                    app.listen("127.0.0.1:8080")
+                    let app = http::app(http::HttpState {
+                        global_state: state_ref,
+                        history,
+                        auth: config.auth.clone(),
+                    });
+
+                    app.listen(config.http_server.listen_address)
                         .await
                         .expect("Tide listener failed");
                 })
@@ -61,7 +88,7 @@ fn main() {
             }
 
             let state_ref = state_ref.clone();
-            ws::run_listener(config.ws_server, state_ref).await;
+            ws::run_listener(config.ws_server, config.auth, state_ref).await;
         })
         .unwrap();
 
@@ -73,8 +100,11 @@ fn main() {
             if let Err(e) = bot_server::serve::<_>(
                 config.bot_server.listen_address,
                 4096,
+                config.bot_server.max_frame_size,
                 state,
                 config.botnode,
+                persistence,
+                tls,
             )
             .await
             {
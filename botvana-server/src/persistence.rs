@@ -0,0 +1,776 @@
+//! Persistence of market data, fills and bot metrics into TimescaleDB
+//!
+//! Records are queued on a bounded channel and written by a dedicated writer
+//! thread in batches using binary `COPY`. A batch is flushed when the flush
+//! interval elapses or when it reaches the configured size. The writer
+//! connects to the database and applies schema migrations itself, when the
+//! database is unavailable it reconnects with backoff and keeps buffering
+//! records up to a bound.
+
+pub mod history;
+pub mod schema;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use postgres::{binary_copy::BinaryCopyInWriter, types::Type, Client, NoTls};
+use tracing::{debug, error, info, warn};
+
+use crate::config::PersistenceConfig;
+use botvana::{
+    exchange::{ExchangeId, Fill},
+    market::{orderbook::Orderbook, trade::Trade},
+    net::msg::{BotId, Message},
+    report::Metrics,
+};
+
+/// Maximum number of records waiting for the writer
+const QUEUE_LEN: usize = 65_536;
+
+/// Minimum interval between summaries of records dropped on full queue
+const DROPPED_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Delay before the first reconnect to the database
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between reconnects to the database
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of full batches buffered while the database is unavailable
+const MAX_BUFFERED_BATCHES: usize = 10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PersistenceError {
+    #[error("failed to connect to the database")]
+    Connect(#[source] postgres::Error),
+    #[error("failed to migrate the database schema")]
+    Migrate(#[source] postgres::Error),
+    #[error("failed to spawn the writer thread")]
+    Spawn(#[source] std::io::Error),
+}
+
+/// Record persisted into the database
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    TopOfBook(TopOfBookRecord),
+    Trade(TradeRecord),
+    Fill(BotId, Fill),
+    BotMetric(BotMetricRecord),
+}
+
+/// Best bid and ask of a market
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopOfBookRecord {
+    /// Orderbook time reported by the exchange
+    pub time: SystemTime,
+    pub received_at: SystemTime,
+    pub bot_id: BotId,
+    pub exchange: ExchangeId,
+    pub market: Box<str>,
+    pub bid: Option<f64>,
+    pub bid_size: Option<f64>,
+    pub ask: Option<f64>,
+    pub ask_size: Option<f64>,
+}
+
+impl TopOfBookRecord {
+    /// Creates record from orderbook received at given time
+    ///
+    /// Orderbooks without exchange time are stored with the receive time.
+    pub fn from_orderbook(
+        bot_id: BotId,
+        orderbook: &Orderbook<f64>,
+        received_at: SystemTime,
+    ) -> Self {
+        let time = if orderbook.time > 0.0 {
+            UNIX_EPOCH + Duration::from_secs_f64(orderbook.time)
+        } else {
+            received_at
+        };
+
+        Self {
+            time,
+            received_at,
+            bot_id,
+            exchange: orderbook.exchange,
+            market: orderbook.market.clone(),
+            bid: orderbook.bids.price_vec.last().cloned(),
+            bid_size: orderbook.bids.size_vec.last().cloned(),
+            ask: orderbook.asks.price_vec.first().cloned(),
+            ask_size: orderbook.asks.size_vec.first().cloned(),
+        }
+    }
+}
+
+/// Trade on an exchange market
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeRecord {
+    /// Trade time reported by the exchange
+    pub time: SystemTime,
+    pub received_at: SystemTime,
+    pub bot_id: BotId,
+    pub exchange: ExchangeId,
+    pub market: Box<str>,
+    pub price: f64,
+    pub size: f64,
+}
+
+impl TradeRecord {
+    pub fn new(bot_id: BotId, exchange: ExchangeId, market: Box<str>, trade: &Trade) -> Self {
+        Self {
+            time: trade.time.into(),
+            received_at: trade.received_at,
+            bot_id,
+            exchange,
+            market,
+            price: trade.price,
+            size: trade.size,
+        }
+    }
+}
+
+/// Named metric value reported for a bot
+#[derive(Clone, Debug, PartialEq)]
+pub struct BotMetricRecord {
+    pub time: SystemTime,
+    pub bot_id: BotId,
+    pub name: Box<str>,
+    pub value: f64,
+}
+
+/// Converts bot message into records to persist
+///
+/// Pings are recorded as `ping_delay_us` metric measuring the delay between
+/// the bot sending the ping and the server receiving it. Reported metrics are
+/// stored as bot metrics, see [`metric_records`].
+pub fn message_records(bot_id: &BotId, msg: &Message, received_at: SystemTime) -> Vec<Record> {
+    match msg {
+        Message::Orderbook(orderbook) => vec![Record::TopOfBook(TopOfBookRecord::from_orderbook(
+            bot_id.clone(),
+            orderbook,
+            received_at,
+        ))],
+        Message::Trades(exchange, market, trades) => trades
+            .iter()
+            .map(|trade| {
+                Record::Trade(TradeRecord::new(
+                    bot_id.clone(),
+                    *exchange,
+                    market.clone(),
+                    trade,
+                ))
+            })
+            .collect(),
+        Message::Fill(fill) => vec![Record::Fill(bot_id.clone(), fill.clone())],
+        Message::Ping(sent_at) => {
+            let sent_at = UNIX_EPOCH + Duration::from_nanos(*sent_at as u64);
+            let delay = received_at.duration_since(sent_at).unwrap_or_default();
+
+            vec![Record::BotMetric(BotMetricRecord {
+                time: received_at,
+                bot_id: bot_id.clone(),
+                name: Box::from("ping_delay_us"),
+                value: delay.as_micros() as f64,
+            })]
+        }
+        Message::Metrics(metrics) => metric_records(bot_id, metrics, received_at),
+        _ => vec![],
+    }
+}
+
+/// Converts metrics reported by bot into bot metric records
+///
+/// Metrics of market data engines are named after the exchange, e.g.
+/// `market_data.Ftx.latency_p99_us` or `market_data.Ftx.queue.<name>.depth`.
+/// Metrics without collection time are stored with the receive time.
+pub fn metric_records(bot_id: &BotId, metrics: &Metrics, received_at: SystemTime) -> Vec<Record> {
+    let time = metrics.time.map(SystemTime::from).unwrap_or(received_at);
+    let mut records = vec![];
+    let mut push = |name: String, value: f64| {
+        records.push(Record::BotMetric(BotMetricRecord {
+            time,
+            bot_id: bot_id.clone(),
+            name: name.into_boxed_str(),
+            value,
+        }))
+    };
+
+    for market_data in metrics.market_data.iter() {
+        let prefix = format!("market_data.{}", market_data.exchange);
+        let latency = &market_data.latency;
+
+        push(format!("{prefix}.messages"), market_data.messages as f64);
+        push(
+            format!("{prefix}.max_throughput"),
+            market_data.max_throughput as f64,
+        );
+        push(
+            format!("{prefix}.reconnects"),
+            market_data.reconnects as f64,
+        );
+        push(format!("{prefix}.latency_count"), latency.count as f64);
+        push(format!("{prefix}.latency_p50_us"), latency.p50 as f64);
+        push(format!("{prefix}.latency_p90_us"), latency.p90 as f64);
+        push(format!("{prefix}.latency_p99_us"), latency.p99 as f64);
+        push(format!("{prefix}.latency_max_us"), latency.max as f64);
+
+        for queue in market_data.queues.iter() {
+            push(
+                format!("{prefix}.queue.{}.depth", queue.name),
+                queue.depth as f64,
+            );
+        }
+    }
+
+    records
+}
+
+/// Handle used to queue records for persistence
+#[derive(Clone, Debug)]
+pub struct PersistenceHandle {
+    tx: mpsc::SyncSender<Record>,
+    /// Number of records dropped since the writer last reported them
+    dropped: Arc<AtomicU64>,
+}
+
+impl PersistenceHandle {
+    /// Queues record for writing
+    ///
+    /// The record is dropped when the writer can't keep up and the queue is
+    /// full, persistence never blocks the caller. Dropped records are counted
+    /// and periodically reported by the writer.
+    pub fn persist(&self, record: Record) {
+        match self.tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("Persistence writer stopped");
+            }
+        }
+    }
+
+    /// Queues all records produced by given bot message
+    pub fn persist_message(&self, bot_id: &BotId, msg: &Message, received_at: SystemTime) {
+        for record in message_records(bot_id, msg, received_at) {
+            self.persist(record);
+        }
+    }
+}
+
+/// Starts the writer thread
+///
+/// The writer connects to the database in the background, so persistence
+/// starts even when the database is down. It flushes remaining records and
+/// stops once all handles are dropped.
+pub fn start(config: PersistenceConfig) -> Result<PersistenceHandle, PersistenceError> {
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let writer_dropped = dropped.clone();
+
+    thread::Builder::new()
+        .name("persistence".to_string())
+        .spawn(move || run_writer(rx, writer_dropped, config))
+        .map_err(PersistenceError::Spawn)?;
+
+    Ok(PersistenceHandle { tx, dropped })
+}
+
+/// Records buffered until the next flush
+#[derive(Debug, Default)]
+pub struct Batch {
+    top_of_book: Vec<TopOfBookRecord>,
+    trades: Vec<TradeRecord>,
+    fills: Vec<(BotId, Fill)>,
+    metrics: Vec<BotMetricRecord>,
+}
+
+impl Batch {
+    pub fn push(&mut self, record: Record) {
+        match record {
+            Record::TopOfBook(record) => self.top_of_book.push(record),
+            Record::Trade(record) => self.trades.push(record),
+            Record::Fill(bot_id, fill) => self.fills.push((bot_id, fill)),
+            Record::BotMetric(record) => self.metrics.push(record),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.top_of_book.len() + self.trades.len() + self.fills.len() + self.metrics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.top_of_book.clear();
+        self.trades.clear();
+        self.fills.clear();
+        self.metrics.clear();
+    }
+
+    /// Writes all records in single transaction
+    pub fn write(&self, client: &mut Client) -> Result<(), postgres::Error> {
+        let mut tx = client.transaction()?;
+
+        if !self.top_of_book.is_empty() {
+            let sink = tx.copy_in(
+                "COPY top_of_book (time, received_at, bot_id, exchange, market, \
+                 bid, bid_size, ask, ask_size) FROM STDIN BINARY",
+            )?;
+            let mut writer = BinaryCopyInWriter::new(
+                sink,
+                &[
+                    Type::TIMESTAMPTZ,
+                    Type::TIMESTAMPTZ,
+                    Type::INT4,
+                    Type::TEXT,
+                    Type::TEXT,
+                    Type::FLOAT8,
+                    Type::FLOAT8,
+                    Type::FLOAT8,
+                    Type::FLOAT8,
+                ],
+            );
+
+            for record in self.top_of_book.iter() {
+                writer.write(&[
+                    &record.time,
+                    &record.received_at,
+                    &(record.bot_id.0 as i32),
+                    &record.exchange.to_string(),
+                    &record.market.as_ref(),
+                    &record.bid,
+                    &record.bid_size,
+                    &record.ask,
+                    &record.ask_size,
+                ])?;
+            }
+
+            writer.finish()?;
+        }
+
+        if !self.trades.is_empty() {
+            let sink = tx.copy_in(
+                "COPY trades (time, received_at, bot_id, exchange, market, price, size) \
+                 FROM STDIN BINARY",
+            )?;
+            let mut writer = BinaryCopyInWriter::new(
+                sink,
+                &[
+                    Type::TIMESTAMPTZ,
+                    Type::TIMESTAMPTZ,
+                    Type::INT4,
+                    Type::TEXT,
+                    Type::TEXT,
+                    Type::FLOAT8,
+                    Type::FLOAT8,
+                ],
+            );
+
+            for record in self.trades.iter() {
+                writer.write(&[
+                    &record.time,
+                    &record.received_at,
+                    &(record.bot_id.0 as i32),
+                    &record.exchange.to_string(),
+                    &record.market.as_ref(),
+                    &record.price,
+                    &record.size,
+                ])?;
+            }
+
+            writer.finish()?;
+        }
+
+        if !self.fills.is_empty() {
+            let sink = tx.copy_in(
+                "COPY fills (time, bot_id, exchange, market, order_id, side, price, size, fee) \
+                 FROM STDIN BINARY",
+            )?;
+            let mut writer = BinaryCopyInWriter::new(
+                sink,
+                &[
+                    Type::TIMESTAMPTZ,
+                    Type::INT4,
+                    Type::TEXT,
+                    Type::TEXT,
+                    Type::INT8,
+                    Type::TEXT,
+                    Type::FLOAT8,
+                    Type::FLOAT8,
+                    Type::FLOAT8,
+                ],
+            );
+
+            for (bot_id, fill) in self.fills.iter() {
+                writer.write(&[
+                    &fill.time,
+                    &(bot_id.0 as i32),
+                    &fill.exchange.to_string(),
+                    &fill.market.as_ref(),
+                    &(fill.order_id as i64),
+                    &format!("{:?}", fill.side),
+                    &fill.price,
+                    &fill.size,
+                    &fill.fee,
+                ])?;
+            }
+
+            writer.finish()?;
+        }
+
+        if !self.metrics.is_empty() {
+            let sink =
+                tx.copy_in("COPY bot_metrics (time, bot_id, name, value) FROM STDIN BINARY")?;
+            let mut writer = BinaryCopyInWriter::new(
+                sink,
+                &[Type::TIMESTAMPTZ, Type::INT4, Type::TEXT, Type::FLOAT8],
+            );
+
+            for record in self.metrics.iter() {
+                writer.write(&[
+                    &record.time,
+                    &(record.bot_id.0 as i32),
+                    &record.name.as_ref(),
+                    &record.value,
+                ])?;
+            }
+
+            writer.finish()?;
+        }
+
+        tx.commit()
+    }
+}
+
+/// Connects to the database and applies migrations
+fn connect(config: &PersistenceConfig) -> Result<Client, PersistenceError> {
+    let mut client = Client::connect(&config.url, NoTls).map_err(PersistenceError::Connect)?;
+
+    let applied = schema::migrate(&mut client).map_err(PersistenceError::Migrate)?;
+    info!("Database schema up to date, applied {applied} migrations");
+
+    Ok(client)
+}
+
+/// Database connection of the writer, reconnected with backoff when lost
+struct Connection {
+    client: Option<Client>,
+    /// Time of the next connection attempt
+    retry_at: Instant,
+    /// Delay before the next connection attempt after a failure
+    delay: Duration,
+}
+
+impl Connection {
+    fn new() -> Self {
+        Self {
+            client: None,
+            retry_at: Instant::now(),
+            delay: RECONNECT_DELAY,
+        }
+    }
+
+    /// Returns connected client, connecting when the backoff has elapsed
+    fn client(&mut self, config: &PersistenceConfig) -> Option<&mut Client> {
+        if self.client.is_none() && Instant::now() >= self.retry_at {
+            match connect(config) {
+                Ok(client) => {
+                    info!("Connected to the database");
+                    self.client = Some(client);
+                    self.delay = RECONNECT_DELAY;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to connect to the database, retrying in {:?}: {e}",
+                        self.delay
+                    );
+                    self.retry_at = Instant::now() + self.delay;
+                    self.delay = next_reconnect_delay(self.delay);
+                }
+            }
+        }
+
+        self.client.as_mut()
+    }
+
+    /// Writes batch and clears it, keeps the batch when the connection is lost
+    ///
+    /// Batches failing for other reasons are dropped, retrying them would
+    /// fail again.
+    fn write(&mut self, batch: &mut Batch, config: &PersistenceConfig) {
+        let client = match self.client(config) {
+            Some(client) => client,
+            None => return,
+        };
+
+        match batch.write(client) {
+            Ok(()) => debug!("Persisted {} records", batch.len()),
+            Err(e) if client.is_closed() => {
+                warn!(
+                    "Database connection lost, keeping {} records: {e}",
+                    batch.len()
+                );
+                self.client = None;
+                return;
+            }
+            Err(e) => error!("Failed to persist {} records: {e}", batch.len()),
+        }
+
+        batch.clear();
+    }
+}
+
+/// Returns delay before the reconnect following a failed one
+fn next_reconnect_delay(delay: Duration) -> Duration {
+    (delay * 2).min(MAX_RECONNECT_DELAY)
+}
+
+/// Runs the writer loop until all handles are dropped
+fn run_writer(rx: mpsc::Receiver<Record>, dropped: Arc<AtomicU64>, config: PersistenceConfig) {
+    let flush_interval = config.flush_interval();
+    let max_buffered = config.batch_size.saturating_mul(MAX_BUFFERED_BATCHES);
+    let mut connection = Connection::new();
+    let mut batch = Batch::default();
+    let mut last_flush = Instant::now();
+    let mut last_dropped_report = Instant::now();
+
+    loop {
+        let timeout = flush_interval.saturating_sub(last_flush.elapsed());
+        let disconnected = match rx.recv_timeout(timeout) {
+            // Records over the bound are dropped so the buffer can't grow
+            // unbounded while the database is unavailable
+            Ok(_) if batch.len() >= max_buffered => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Ok(record) => {
+                batch.push(record);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if disconnected
+            || batch.len() >= config.batch_size
+            || last_flush.elapsed() >= flush_interval
+        {
+            if !batch.is_empty() {
+                connection.write(&mut batch, &config);
+            }
+
+            last_flush = Instant::now();
+        }
+
+        if disconnected || last_dropped_report.elapsed() >= DROPPED_REPORT_INTERVAL {
+            report_dropped(&dropped);
+            last_dropped_report = Instant::now();
+        }
+
+        if disconnected {
+            if !batch.is_empty() {
+                error!("Persistence writer stopped, {} records lost", batch.len());
+            } else {
+                info!("Persistence writer stopped");
+            }
+            return;
+        }
+    }
+}
+
+/// Logs number of records dropped since the last report
+fn report_dropped(dropped: &AtomicU64) {
+    let count = dropped.swap(0, Ordering::Relaxed);
+
+    if count > 0 {
+        warn!("Persistence queue full, dropped {count} records");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botvana::{
+        market::orderbook::PriceLevelsVec,
+        report::{MarketDataMetrics, Percentiles, QueueDepth},
+    };
+
+    fn orderbook(time: f64) -> Orderbook<f64> {
+        Orderbook {
+            bids: PriceLevelsVec::from_tuples_vec(&[(99.0, 2.0), (100.0, 1.0)]),
+            asks: PriceLevelsVec::from_tuples_vec(&[(101.0, 3.0), (102.0, 4.0)]),
+            time,
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+        }
+    }
+
+    #[test]
+    fn test_top_of_book_from_orderbook() {
+        let received_at = UNIX_EPOCH + Duration::from_secs(20);
+        let record = TopOfBookRecord::from_orderbook(BotId(1), &orderbook(10.5), received_at);
+
+        assert_eq!(record.time, UNIX_EPOCH + Duration::from_millis(10_500));
+        assert_eq!(record.bid, Some(100.0));
+        assert_eq!(record.bid_size, Some(1.0));
+        assert_eq!(record.ask, Some(101.0));
+        assert_eq!(record.ask_size, Some(3.0));
+
+        let record = TopOfBookRecord::from_orderbook(BotId(1), &orderbook(0.0), received_at);
+        assert_eq!(record.time, received_at);
+    }
+
+    #[test]
+    fn test_message_records_batch() {
+        let received_at = UNIX_EPOCH + Duration::from_secs(20);
+        let mut batch = Batch::default();
+
+        for msg in [
+            Message::Orderbook(orderbook(10.0)),
+            Message::Ping(19_999_000_000),
+            Message::metrics(Default::default()),
+        ] {
+            for record in message_records(&BotId(0), &msg, received_at) {
+                batch.push(record);
+            }
+        }
+
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.metrics[0].value, 1_000.0);
+
+        let metrics = Metrics {
+            time: None,
+            market_data: vec![MarketDataMetrics {
+                exchange: ExchangeId::Ftx,
+                messages: 100,
+                max_throughput: 10,
+                latency: Percentiles {
+                    count: 100,
+                    p50: 200,
+                    p90: 300,
+                    p99: 400,
+                    max: 500,
+                },
+                reconnects: 1,
+                queues: vec![QueueDepth {
+                    name: Box::from("market-data-ftx-0"),
+                    depth: 3,
+                    capacity: 1024,
+                }],
+            }],
+        };
+        for record in message_records(&BotId(0), &Message::metrics(metrics), received_at) {
+            batch.push(record);
+        }
+
+        assert_eq!(batch.len(), 11);
+        assert_eq!(batch.metrics[1].time, received_at);
+        assert_eq!(&*batch.metrics[1].name, "market_data.Ftx.messages");
+        assert_eq!(&*batch.metrics[7].name, "market_data.Ftx.latency_p99_us");
+        assert_eq!(batch.metrics[7].value, 400.0);
+        assert_eq!(
+            &*batch.metrics[9].name,
+            "market_data.Ftx.queue.market-data-ftx-0.depth"
+        );
+
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn test_next_reconnect_delay() {
+        assert_eq!(
+            next_reconnect_delay(RECONNECT_DELAY),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            next_reconnect_delay(Duration::from_secs(40)),
+            MAX_RECONNECT_DELAY
+        );
+    }
+
+    #[test]
+    fn test_persist_counts_dropped_records() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let handle = PersistenceHandle {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        let record = |time| {
+            let received_at = UNIX_EPOCH + Duration::from_secs(20);
+            Record::TopOfBook(TopOfBookRecord::from_orderbook(
+                BotId(1),
+                &orderbook(time),
+                received_at,
+            ))
+        };
+
+        for time in [1.0, 2.0, 3.0] {
+            handle.persist(record(time));
+        }
+
+        assert_eq!(rx.try_recv().unwrap(), record(1.0));
+        assert_eq!(handle.dropped.load(Ordering::Relaxed), 2);
+
+        report_dropped(&handle.dropped);
+        assert_eq!(handle.dropped.load(Ordering::Relaxed), 0);
+    }
+
+    /// Writes records into Postgres given by `BOTVANA_TEST_DATABASE_URL`
+    #[test]
+    fn test_write_batch_postgres() {
+        let url = match std::env::var("BOTVANA_TEST_DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let mut client = Client::connect(&url, NoTls).unwrap();
+
+        schema::migrate(&mut client).unwrap();
+        assert_eq!(schema::migrate(&mut client).unwrap(), 0);
+
+        let market = format!("TEST-{}", std::process::id());
+        let now = SystemTime::now();
+        let mut batch = Batch::default();
+
+        batch.push(Record::TopOfBook(TopOfBookRecord {
+            market: Box::from(market.as_str()),
+            ..TopOfBookRecord::from_orderbook(BotId(0), &orderbook(0.0), now)
+        }));
+        batch.push(Record::Fill(
+            BotId(0),
+            Fill {
+                exchange: ExchangeId::Ftx,
+                market: Box::from(market.as_str()),
+                order_id: 1,
+                side: botvana::exchange::Side::Buy,
+                price: 100.0,
+                size: 1.0,
+                fee: 0.01,
+                time: now,
+            },
+        ));
+        batch.write(&mut client).unwrap();
+
+        for table in ["top_of_book", "fills"] {
+            let count: i64 = client
+                .query_one(
+                    format!("SELECT count(*) FROM {table} WHERE market = $1").as_str(),
+                    &[&market],
+                )
+                .unwrap()
+                .get(0);
+            assert_eq!(count, 1);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Queries over persisted market data and fills
+//!
+//! Queries run on a dedicated thread with its own database connection so the
+//! HTTP executor is never blocked, results are sent back over a oneshot
+//! channel.
+
+use std::{thread, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on_stream,
+};
+use postgres::{Client, NoTls};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use botvana::exchange::ExchangeId;
+
+/// Default maximum number of returned rows
+const DEFAULT_LIMIT: i64 = 10_000;
+/// Upper bound of the limit accepted in queries
+const MAX_LIMIT: i64 = 100_000;
+
+type Job = Box<dyn FnOnce(&mut Client) + Send>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum HistoryError {
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("database error")]
+    Database(#[from] postgres::Error),
+    #[error("history worker stopped")]
+    WorkerStopped,
+}
+
+/// Parameters of historical queries
+#[derive(Clone, Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub exchange: Box<str>,
+    pub market: Box<str>,
+    /// Start of the time range, inclusive
+    pub from: DateTime<Utc>,
+    /// End of the time range, exclusive
+    pub to: DateTime<Utc>,
+    /// Downsampling interval in seconds, raw rows are returned when missing
+    pub interval_secs: Option<f64>,
+    /// Maximum number of returned rows
+    pub limit: Option<i64>,
+    /// Restricts fills to given bot
+    pub bot_id: Option<u16>,
+}
+
+impl HistoryQuery {
+    /// Returns exchange name as stored in the database
+    fn exchange(&self) -> Result<String, HistoryError> {
+        self.exchange
+            .parse::<ExchangeId>()
+            .map(|exchange| exchange.to_string())
+            .map_err(HistoryError::InvalidQuery)
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    fn interval(&self) -> Result<Option<f64>, HistoryError> {
+        match self.interval_secs {
+            Some(interval) if interval <= 0.0 || !interval.is_finite() => Err(
+                HistoryError::InvalidQuery(format!("invalid interval {interval}")),
+            ),
+            interval => Ok(interval),
+        }
+    }
+
+    fn validate(&self) -> Result<(), HistoryError> {
+        if self.from >= self.to {
+            return Err(HistoryError::InvalidQuery(
+                "`from` must be before `to`".to_string(),
+            ));
+        }
+
+        self.interval().map(|_| ())
+    }
+}
+
+/// Best bid and ask at given time, last values of the bucket when downsampled
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TopOfBookRow {
+    pub time: DateTime<Utc>,
+    pub bid: Option<f64>,
+    pub bid_size: Option<f64>,
+    pub ask: Option<f64>,
+    pub ask_size: Option<f64>,
+}
+
+/// Single trade
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TradeRow {
+    pub time: DateTime<Utc>,
+    pub price: f64,
+    pub size: f64,
+}
+
+/// Trades aggregated over an interval
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CandleRow {
+    pub time: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub trades: i64,
+}
+
+/// Trades result, candles are returned when downsampling
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradesResult {
+    Trades(Vec<TradeRow>),
+    Candles(Vec<CandleRow>),
+}
+
+/// Fill as stored in the database
+#[derive(Clone, Debug, PartialEq)]
+pub struct FillRow {
+    pub time: DateTime<Utc>,
+    pub side: String,
+    pub price: f64,
+    pub size: f64,
+    pub fee: f64,
+}
+
+/// Profit and loss after the fills up to the time
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PnlPoint {
+    pub time: DateTime<Utc>,
+    pub position: f64,
+    pub fees: f64,
+    /// PnL net of fees with the position marked at the last fill price
+    pub pnl: f64,
+}
+
+/// Computes PnL series from fills ordered by time
+///
+/// Position and PnL accumulate from zero at the first fill. With interval
+/// given only the last point of each interval is kept, timestamped with the
+/// start of the interval.
+pub fn pnl_series(fills: &[FillRow], interval: Option<Duration>) -> Vec<PnlPoint> {
+    let mut points: Vec<PnlPoint> = Vec::with_capacity(fills.len());
+    let mut position = 0.0;
+    let mut cash = 0.0;
+    let mut fees = 0.0;
+
+    for fill in fills {
+        let sign = if fill.side == "Buy" { 1.0 } else { -1.0 };
+
+        position += sign * fill.size;
+        cash -= sign * fill.price * fill.size;
+        fees += fill.fee;
+
+        let time = match interval {
+            Some(interval) => bucket_start(fill.time, interval),
+            None => fill.time,
+        };
+        let point = PnlPoint {
+            time,
+            position,
+            fees,
+            pnl: cash + position * fill.price - fees,
+        };
+
+        match points.last_mut() {
+            Some(last) if interval.is_some() && last.time == time => *last = point,
+            _ => points.push(point),
+        }
+    }
+
+    points
+}
+
+fn bucket_start(time: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+    let interval = interval.as_nanos().max(1) as i64;
+    let nanos = time.timestamp_nanos();
+
+    chrono::TimeZone::timestamp_nanos(&Utc, nanos - nanos.rem_euclid(interval))
+}
+
+/// Handle used to run historical queries
+#[derive(Clone)]
+pub struct HistoryHandle {
+    tx: mpsc::UnboundedSender<Job>,
+}
+
+impl HistoryHandle {
+    /// Returns top of book of the market in the time range
+    pub async fn top_of_book(
+        &self,
+        query: HistoryQuery,
+    ) -> Result<Vec<TopOfBookRow>, HistoryError> {
+        query.validate()?;
+        let exchange = query.exchange()?;
+
+        self.run(move |client| {
+            let rows = match query.interval()? {
+                Some(interval) => client.query(
+                    "SELECT time_bucket(make_interval(secs => $5), time) AS bucket,
+                            last(bid, time), last(bid_size, time),
+                            last(ask, time), last(ask_size, time)
+                     FROM top_of_book
+                     WHERE exchange = $1 AND market = $2 AND time >= $3 AND time < $4
+                     GROUP BY bucket ORDER BY bucket LIMIT $6",
+                    &[
+                        &exchange,
+                        &query.market.as_ref(),
+                        &query.from,
+                        &query.to,
+                        &interval,
+                        &query.limit(),
+                    ],
+                )?,
+                None => client.query(
+                    "SELECT time, bid, bid_size, ask, ask_size
+                     FROM top_of_book
+                     WHERE exchange = $1 AND market = $2 AND time >= $3 AND time < $4
+                     ORDER BY time LIMIT $5",
+                    &[
+                        &exchange,
+                        &query.market.as_ref(),
+                        &query.from,
+                        &query.to,
+                        &query.limit(),
+                    ],
+                )?,
+            };
+
+            Ok(rows
+                .iter()
+                .map(|row| TopOfBookRow {
+                    time: row.get(0),
+                    bid: row.get(1),
+                    bid_size: row.get(2),
+                    ask: row.get(3),
+                    ask_size: row.get(4),
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// Returns trades of the market in the time range, candles when downsampled
+    pub async fn trades(&self, query: HistoryQuery) -> Result<TradesResult, HistoryError> {
+        query.validate()?;
+        let exchange = query.exchange()?;
+
+        self.run(move |client| match query.interval()? {
+            Some(interval) => {
+                let rows = client.query(
+                    "SELECT time_bucket(make_interval(secs => $5), time) AS bucket,
+                            first(price, time), max(price), min(price), last(price, time),
+                            sum(size), count(*)
+                     FROM trades
+                     WHERE exchange = $1 AND market = $2 AND time >= $3 AND time < $4
+                     GROUP BY bucket ORDER BY bucket LIMIT $6",
+                    &[
+                        &exchange,
+                        &query.market.as_ref(),
+                        &query.from,
+                        &query.to,
+                        &interval,
+                        &query.limit(),
+                    ],
+                )?;
+
+                Ok(TradesResult::Candles(
+                    rows.iter()
+                        .map(|row| CandleRow {
+                            time: row.get(0),
+                            open: row.get(1),
+                            high: row.get(2),
+                            low: row.get(3),
+                            close: row.get(4),
+                            volume: row.get(5),
+                            trades: row.get(6),
+                        })
+                        .collect(),
+                ))
+            }
+            None => {
+                let rows = client.query(
+                    "SELECT time, price, size
+                     FROM trades
+                     WHERE exchange = $1 AND market = $2 AND time >= $3 AND time < $4
+                     ORDER BY time LIMIT $5",
+                    &[
+                        &exchange,
+                        &query.market.as_ref(),
+                        &query.from,
+                        &query.to,
+                        &query.limit(),
+                    ],
+                )?;
+
+                Ok(TradesResult::Trades(
+                    rows.iter()
+                        .map(|row| TradeRow {
+                            time: row.get(0),
+                            price: row.get(1),
+                            size: row.get(2),
+                        })
+                        .collect(),
+                ))
+            }
+        })
+        .await
+    }
+
+    /// Returns PnL of fills of the market in the time range
+    pub async fn pnl(&self, query: HistoryQuery) -> Result<Vec<PnlPoint>, HistoryError> {
+        query.validate()?;
+        let exchange = query.exchange()?;
+
+        self.run(move |client| {
+            let bot_id = query.bot_id.map(i32::from);
+            let rows = client.query(
+                "SELECT time, side, price, size, fee
+                 FROM fills
+                 WHERE exchange = $1 AND market = $2 AND time >= $3 AND time < $4
+                   AND ($5::INTEGER IS NULL OR bot_id = $5)
+                 ORDER BY time",
+                &[
+                    &exchange,
+                    &query.market.as_ref(),
+                    &query.from,
+                    &query.to,
+                    &bot_id,
+                ],
+            )?;
+            let fills: Vec<_> = rows
+                .iter()
+                .map(|row| FillRow {
+                    time: row.get(0),
+                    side: row.get(1),
+                    price: row.get(2),
+                    size: row.get(3),
+                    fee: row.get(4),
+                })
+                .collect();
+            let interval = query.interval()?.map(Duration::from_secs_f64);
+            let mut points = pnl_series(&fills, interval);
+
+            points.truncate(query.limit() as usize);
+
+            Ok(points)
+        })
+        .await
+    }
+
+    /// Runs the closure on the query thread and awaits its result
+    async fn run<T, F>(&self, f: F) -> Result<T, HistoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Client) -> Result<T, HistoryError> + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let job: Job = Box::new(move |client| {
+            let _ = result_tx.send(f(client));
+        });
+
+        self.tx
+            .unbounded_send(job)
+            .map_err(|_| HistoryError::WorkerStopped)?;
+
+        result_rx.await.map_err(|_| HistoryError::WorkerStopped)?
+    }
+}
+
+/// Connects to the database and starts the query thread
+pub fn start(url: &str) -> Result<HistoryHandle, super::PersistenceError> {
+    let mut client = Client::connect(url, NoTls).map_err(super::PersistenceError::Connect)?;
+    let (tx, rx) = mpsc::unbounded::<Job>();
+
+    thread::Builder::new()
+        .name("history".to_string())
+        .spawn(move || {
+            for job in block_on_stream(rx) {
+                if client.is_closed() {
+                    error!("History database connection closed");
+                    return;
+                }
+
+                job(&mut client);
+            }
+
+            info!("History worker stopped");
+        })
+        .map_err(super::PersistenceError::Spawn)?;
+
+    Ok(HistoryHandle { tx })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn fill(secs: i64, side: &str, price: f64) -> FillRow {
+        FillRow {
+            time: Utc.timestamp(secs, 0),
+            side: side.to_string(),
+            price,
+            size: 1.0,
+            fee: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_pnl_series() {
+        let fills = [
+            fill(0, "Buy", 100.0),
+            fill(30, "Sell", 101.0),
+            fill(70, "Buy", 99.0),
+        ];
+
+        let points = pnl_series(&fills, None);
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].pnl, -0.1);
+        assert!((points[1].pnl - 0.8).abs() < 1e-9);
+        assert_eq!(points[1].position, 0.0);
+        assert_eq!(points[2].position, 1.0);
+
+        let points = pnl_series(&fills, Some(Duration::from_secs(60)));
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].time, Utc.timestamp(0, 0));
+        assert_eq!(points[0].position, 0.0);
+        assert_eq!(points[1].time, Utc.timestamp(60, 0));
+    }
+
+    #[test]
+    fn test_query_validation() {
+        let query = HistoryQuery {
+            exchange: Box::from("ftx"),
+            market: Box::from("BTC/USD"),
+            from: Utc.timestamp(10, 0),
+            to: Utc.timestamp(20, 0),
+            interval_secs: Some(0.0),
+            limit: Some(1_000_000),
+            bot_id: None,
+        };
+
+        assert!(query.validate().is_err());
+        assert_eq!(query.exchange().unwrap(), "Ftx");
+        assert_eq!(query.limit(), MAX_LIMIT);
+
+        let query = HistoryQuery {
+            interval_secs: None,
+            to: Utc.timestamp(10, 0),
+            ..query
+        };
+        assert!(query.validate().is_err());
+    }
+}
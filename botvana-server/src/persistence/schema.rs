@@ -0,0 +1,60 @@
+//! Database schema migrations
+
+use postgres::Client;
+use tracing::info;
+
+/// Schema migrations in the order they are applied
+///
+/// Applied migrations are recorded in the `schema_migrations` table, new
+/// migrations must be appended with higher version.
+const MIGRATIONS: &[(i32, &str)] = &[(
+    1,
+    include_str!("../../migrations/0001_create_hypertables.sql"),
+)];
+
+/// Applies migrations that were not applied yet
+///
+/// Returns number of applied migrations.
+pub fn migrate(client: &mut Client) -> Result<usize, postgres::Error> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )?;
+
+    let current: Option<i32> = client
+        .query_one("SELECT max(version) FROM schema_migrations", &[])?
+        .get(0);
+    let mut applied = 0;
+
+    for (version, sql) in MIGRATIONS
+        .iter()
+        .filter(|(version, _)| Some(*version) > current)
+    {
+        info!("Applying schema migration {version}");
+
+        let mut tx = client.transaction()?;
+        tx.batch_execute(sql)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            &[version],
+        )?;
+        tx.commit()?;
+
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_versions_increase() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(MIGRATIONS.iter().all(|(version, _)| *version > 0));
+    }
+}
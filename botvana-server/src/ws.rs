@@ -1,3 +1,14 @@
+//! Websocket gateway
+//!
+//! Clients subscribe to channels and receive a snapshot followed by
+//! incremental updates, see [`botvana::net::gateway`] for the protocol.
+//! Updates are computed against the state last sent to the client, so a slow
+//! client receives coalesced updates instead of a growing queue. Clients that
+//! can't receive a message within [`SEND_TIMEOUT_MS`] are disconnected.
+//!
+//! With authentication enabled the handshake is rejected unless it carries
+//! an accepted API token, see [`crate::auth`].
+
 use std::{
     net::SocketAddr,
     rc::Rc,
@@ -5,29 +16,51 @@ use std::{
 };
 
 use async_tungstenite::{
-    accept_async,
-    tungstenite::{Message, Result},
+    accept_hdr_async,
+    tungstenite::{
+        self,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+        Message,
+    },
+    WebSocketStream,
 };
-use futures::prelude::*;
+use futures::{prelude::*, select, stream::SplitSink};
 use glommio::{
     enclose,
     net::{TcpListener, TcpStream},
     sync::Semaphore,
     Task,
 };
-use serde_json::json;
 use tracing::*;
 
-use crate::config;
-use botvana::state;
+use crate::{auth, config};
+use botvana::{
+    market::{orderbook::PlainOrderbook, Market},
+    net::gateway::*,
+    state::{self, BotInfo, PositionPnl},
+};
 
 const MAX_CONNECTIONS: u64 = 1024;
-const SNAPSHOT_TICK_INTERVAL_MS: u64 = 500;
-const READ_TIMEOUT_US: u64 = 500;
+/// Interval of checking subscribed channels for changes
+const UPDATE_TICK_INTERVAL_MS: u64 = 100;
+/// Time a client has to receive a message before it's disconnected
+const SEND_TIMEOUT_MS: u64 = 5000;
+/// Maximum number of subscriptions of one client
+const MAX_SUBSCRIPTIONS: usize = 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum GatewayError {
+    #[error("websocket error: {0}")]
+    Websocket(#[from] tungstenite::Error),
+    #[error("timeout: client is not receiving messages")]
+    SendTimeout,
+}
 
 /// Runs TCP listener loop and spawn new task for each incoming connection.
 pub async fn run_listener(
     ws_config: config::WebsocketServerConfig,
+    auth_config: config::AuthConfig,
     global_state: state::GlobalState,
 ) {
     let listener = TcpListener::bind(&ws_config.listen_address).expect("Can't listen");
@@ -42,6 +75,7 @@ pub async fn run_listener(
 
         {
             let global_state = global_state.clone();
+            let auth_config = auth_config.clone();
 
             Task::local(enclose! { (conn_control) async move {
                 let _permit = conn_control
@@ -49,7 +83,7 @@ pub async fn run_listener(
                     .await
                     .expect("failed to acquire permit");
 
-                if let Err(e) = handle_connection(peer, stream, global_state).await {
+                if let Err(e) = handle_connection(peer, stream, &auth_config, global_state).await {
                     error!("Error while handling the connection: {}", e);
                 }
             }})
@@ -62,45 +96,425 @@ pub async fn run_listener(
 async fn handle_connection(
     peer: SocketAddr,
     stream: TcpStream,
+    auth_config: &config::AuthConfig,
     global_state: state::GlobalState,
-) -> Result<()> {
-    let mut ws_stream = accept_async(stream).await.expect("Failed to accept");
+) -> Result<(), GatewayError> {
+    let callback = |req: &Request, res: Response| authorize(auth_config, req, res);
+    let (mut sink, mut stream) = accept_hdr_async(stream, callback).await?.split();
+    let mut client = Client::default();
+    let mut last_tick = Instant::now();
+    let tick_interval = Duration::from_millis(UPDATE_TICK_INTERVAL_MS);
 
     info!("New WebSocket connection from: {}", peer);
 
-    send_state(&mut ws_stream, &global_state).await?;
-    let mut last_state = Instant::now();
-
     loop {
-        let _ = glommio::timer::timeout(Duration::from_micros(READ_TIMEOUT_US), async {
-            ws_stream.next().await;
-            Ok(())
-        })
-        .await;
-
-        if last_state.elapsed() >= Duration::from_millis(SNAPSHOT_TICK_INTERVAL_MS) {
-            send_state(&mut ws_stream, &global_state).await?;
-            last_state = Instant::now();
+        let tick = glommio::timer::sleep(tick_interval.saturating_sub(last_tick.elapsed()));
+
+        select! {
+            msg = stream.next().fuse() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    send(&mut sink, &client.handle_text(&text)).await?;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+            _ = tick.fuse() => {
+                last_tick = Instant::now();
+
+                for msg in client.poll(&global_state) {
+                    send(&mut sink, &msg).await?;
+                }
+            }
+        }
+    }
+
+    info!("WebSocket connection from {} closed", peer);
+
+    Ok(())
+}
+
+/// Accepts the handshake only with an accepted API token
+fn authorize(
+    auth_config: &config::AuthConfig,
+    req: &Request,
+    res: Response,
+) -> Result<Response, ErrorResponse> {
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(auth::bearer_token)
+        .or_else(|| req.uri().query().and_then(auth::query_token));
+
+    if auth_config.is_authorized(token) {
+        Ok(res)
+    } else {
+        let mut res = ErrorResponse::new(Some("Unauthorized".to_string()));
+        *res.status_mut() = StatusCode::UNAUTHORIZED;
+
+        Err(res)
+    }
+}
+
+/// Sends message to the client, failing when it isn't received in time
+async fn send(
+    sink: &mut SplitSink<WebSocketStream<TcpStream>, Message>,
+    msg: &ServerMessage,
+) -> Result<(), GatewayError> {
+    let text = serde_json::to_string(msg).expect("gateway messages are serializable");
+    let timeout = glommio::timer::sleep(Duration::from_millis(SEND_TIMEOUT_MS));
+
+    select! {
+        res = sink.send(Message::Text(text)).fuse() => Ok(res?),
+        _ = timeout.fuse() => Err(GatewayError::SendTimeout),
+    }
+}
+
+/// Subscriptions of one gateway client
+#[derive(Debug, Default)]
+struct Client {
+    subscriptions: Vec<Subscription>,
+}
+
+impl Client {
+    /// Handles text message from the client and returns the response
+    fn handle_text(&mut self, text: &str) -> ServerMessage {
+        match serde_json::from_str(text) {
+            Ok(msg) => self.handle(msg),
+            Err(e) => ServerMessage::Error {
+                message: format!("Invalid message: {e}"),
+            },
+        }
+    }
+
+    fn handle(&mut self, msg: ClientMessage) -> ServerMessage {
+        match msg {
+            ClientMessage::Subscribe { channel } => {
+                if self.subscriptions.iter().any(|sub| sub.channel == channel) {
+                    return ServerMessage::Error {
+                        message: format!("Already subscribed to {channel:?}"),
+                    };
+                }
+
+                if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                    return ServerMessage::Error {
+                        message: format!("Too many subscriptions, maximum is {MAX_SUBSCRIPTIONS}"),
+                    };
+                }
+
+                self.subscriptions.push(Subscription::new(channel.clone()));
+
+                ServerMessage::Subscribed { channel }
+            }
+            ClientMessage::Unsubscribe { channel } => {
+                let len = self.subscriptions.len();
+                self.subscriptions.retain(|sub| sub.channel != channel);
+
+                if self.subscriptions.len() == len {
+                    ServerMessage::Error {
+                        message: format!("Not subscribed to {channel:?}"),
+                    }
+                } else {
+                    ServerMessage::Unsubscribed { channel }
+                }
+            }
+        }
+    }
+
+    /// Returns snapshots and updates of all subscribed channels
+    fn poll(&mut self, state: &state::GlobalState) -> Vec<ServerMessage> {
+        self.subscriptions
+            .iter_mut()
+            .filter_map(|sub| sub.poll(state))
+            .collect()
+    }
+}
+
+/// Subscribed channel with the state last sent to the client
+#[derive(Debug)]
+struct Subscription {
+    channel: Channel,
+    /// `None` until the snapshot is sent
+    sent: Option<SentState>,
+}
+
+/// Channel state last sent to the client
+#[derive(Debug)]
+enum SentState {
+    Bots(Vec<BotInfo>),
+    Markets(Vec<Market>),
+    Orderbook(PlainOrderbook<f64>),
+    /// Sequence number of the last sent trade
+    Trades(u64),
+    Pnl(Vec<PositionPnl>),
+}
+
+impl Subscription {
+    fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            sent: None,
+        }
+    }
+
+    /// Returns snapshot or update when the channel state changed
+    fn poll(&mut self, state: &state::GlobalState) -> Option<ServerMessage> {
+        match &self.channel {
+            Channel::Bots => {
+                let bots = state.bots();
+
+                if let Some(SentState::Bots(sent)) = &self.sent {
+                    if *sent == bots {
+                        return None;
+                    }
+                }
+
+                self.record(SentState::Bots(bots.clone()), ChannelData::Bots(bots))
+            }
+            Channel::Markets => {
+                let markets: Vec<_> = state.markets().iter().map(Market::from).collect();
+
+                if let Some(SentState::Markets(sent)) = &self.sent {
+                    if *sent == markets {
+                        return None;
+                    }
+                }
+
+                self.record(
+                    SentState::Markets(markets.clone()),
+                    ChannelData::Markets(markets),
+                )
+            }
+            Channel::Orderbook { exchange, market } => {
+                let orderbook = state.get_orderbook(*exchange, market)?;
+                let data = match &self.sent {
+                    Some(SentState::Orderbook(sent)) => {
+                        let bids = sent.bids.diff(&orderbook.bids);
+                        let asks = sent.asks.diff(&orderbook.asks);
+
+                        if bids.is_empty() && asks.is_empty() {
+                            return None;
+                        }
+
+                        ChannelData::OrderbookDelta {
+                            bids,
+                            asks,
+                            time: orderbook.time,
+                        }
+                    }
+                    _ => ChannelData::Orderbook(orderbook.clone()),
+                };
+
+                self.record(SentState::Orderbook(orderbook), data)
+            }
+            Channel::Trades { exchange, market } => {
+                let after = match self.sent {
+                    Some(SentState::Trades(seq)) => Some(seq),
+                    _ => None,
+                };
+                let recent = state.trades_since(*exchange, market, after.unwrap_or(0))?;
+
+                if recent.trades.is_empty() {
+                    return None;
+                }
+
+                // Trades missed by the client are no longer kept, it gets a
+                // snapshot of the retained ones instead
+                if recent.gap {
+                    self.sent = None;
+                }
+
+                self.record(
+                    SentState::Trades(recent.seq),
+                    ChannelData::Trades(recent.trades),
+                )
+            }
+            Channel::Pnl { bot_id } => {
+                let pnl = state.bot_pnl(bot_id);
+
+                if let Some(SentState::Pnl(sent)) = &self.sent {
+                    if *sent == pnl {
+                        return None;
+                    }
+                }
+
+                self.record(SentState::Pnl(pnl.clone()), ChannelData::Pnl(pnl))
+            }
         }
     }
+
+    /// Records the sent state and returns the message carrying `data`
+    fn record(&mut self, sent: SentState, data: ChannelData) -> Option<ServerMessage> {
+        let channel = self.channel.clone();
+        let msg = match self.sent {
+            Some(_) => ServerMessage::Update { channel, data },
+            None => ServerMessage::Snapshot { channel, data },
+        };
+
+        self.sent = Some(sent);
+
+        Some(msg)
+    }
 }
 
-async fn send_state(
-    ws_stream: &mut async_tungstenite::WebSocketStream<glommio::net::TcpStream>,
-    state: &state::GlobalState,
-) -> Result<()> {
-    let connected_bots = state.connected_bots();
-    let markets = state.markets();
-    let orderbooks = state.orderbooks();
-
-    Ok(ws_stream
-        .send(Message::Text(
-            json!({
-                "connected_bots": connected_bots,
-                "markets": markets,
-                "orderbooks": orderbooks,
-            })
-            .to_string(),
-        ))
-        .await?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botvana::{
+        exchange::ExchangeId,
+        market::{orderbook::PriceLevelsVec, trade::Trade},
+        net::msg::{BotId, BotMetadata},
+    };
+
+    fn orderbook_channel() -> Channel {
+        Channel::Orderbook {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+        }
+    }
+
+    fn orderbook(bids: &mut [(f64, f64)], asks: &mut [(f64, f64)]) -> PlainOrderbook<f64> {
+        PlainOrderbook {
+            bids: PriceLevelsVec::from_tuples_vec_unsorted(bids),
+            asks: PriceLevelsVec::from_tuples_vec_unsorted(asks),
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_subscribe_unsubscribe() {
+        let mut client = Client::default();
+
+        assert!(matches!(
+            client.handle(ClientMessage::subscribe(Channel::Bots)),
+            ServerMessage::Subscribed { .. }
+        ));
+        assert!(matches!(
+            client.handle(ClientMessage::subscribe(Channel::Bots)),
+            ServerMessage::Error { .. }
+        ));
+        assert!(matches!(
+            client.handle(ClientMessage::unsubscribe(Channel::Bots)),
+            ServerMessage::Unsubscribed { .. }
+        ));
+        assert!(matches!(
+            client.handle(ClientMessage::unsubscribe(Channel::Bots)),
+            ServerMessage::Error { .. }
+        ));
+        assert!(matches!(
+            client.handle_text("{\"op\":\"subscribe\"}"),
+            ServerMessage::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn test_bots_snapshot_and_update() {
+        let state = state::GlobalState::new();
+        let mut client = Client::default();
+
+        client.handle(ClientMessage::subscribe(Channel::Bots));
+
+        let msgs = client.poll(&state);
+        assert!(matches!(
+            msgs.as_slice(),
+            [ServerMessage::Snapshot {
+                data: ChannelData::Bots(bots),
+                ..
+            }] if bots.is_empty()
+        ));
+        assert!(client.poll(&state).is_empty());
+
+        state.add_bot(BotId(0), BotMetadata::new(1));
+
+        let msgs = client.poll(&state);
+        assert!(matches!(
+            msgs.as_slice(),
+            [ServerMessage::Update {
+                data: ChannelData::Bots(bots),
+                ..
+            }] if bots.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_orderbook_delta() {
+        let state = state::GlobalState::new();
+        let mut client = Client::default();
+
+        client.handle(ClientMessage::subscribe(orderbook_channel()));
+
+        assert!(client.poll(&state).is_empty());
+
+        state.update_orderbook(
+            ExchangeId::Ftx,
+            "BTC/USD",
+            orderbook(&mut [(99.0, 1.0), (100.0, 2.0)], &mut [(101.0, 1.0)]),
+        );
+
+        let msgs = client.poll(&state);
+        assert!(matches!(
+            msgs.as_slice(),
+            [ServerMessage::Snapshot {
+                data: ChannelData::Orderbook(_),
+                ..
+            }]
+        ));
+        assert!(client.poll(&state).is_empty());
+
+        state.update_orderbook(
+            ExchangeId::Ftx,
+            "BTC/USD",
+            orderbook(&mut [(100.0, 3.0)], &mut [(101.0, 1.0)]),
+        );
+
+        match client.poll(&state).as_slice() {
+            [ServerMessage::Update {
+                data: ChannelData::OrderbookDelta { bids, asks, .. },
+                ..
+            }] => {
+                assert_eq!(bids.price_vec, vec![99.0, 100.0]);
+                assert_eq!(bids.size_vec, vec![0.0, 3.0]);
+                assert!(asks.is_empty());
+            }
+            msgs => panic!("unexpected messages {msgs:?}"),
+        }
+    }
+
+    #[test]
+    fn test_trades_updates() {
+        let state = state::GlobalState::new();
+        let mut client = Client::default();
+        let trade = |price| Trade {
+            price,
+            size: 1.0,
+            time: chrono::Utc::now(),
+            received_at: std::time::UNIX_EPOCH,
+        };
+
+        client.handle(ClientMessage::subscribe(Channel::Trades {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+        }));
+        state.add_trades(ExchangeId::Ftx, "BTC/USD", &[trade(1.0), trade(2.0)]);
+
+        assert!(matches!(
+            client.poll(&state).as_slice(),
+            [ServerMessage::Snapshot {
+                data: ChannelData::Trades(trades),
+                ..
+            }] if trades.len() == 2
+        ));
+        assert!(client.poll(&state).is_empty());
+
+        state.add_trades(ExchangeId::Ftx, "BTC/USD", &[trade(3.0)]);
+
+        assert!(matches!(
+            client.poll(&state).as_slice(),
+            [ServerMessage::Update {
+                data: ChannelData::Trades(trades),
+                ..
+            }] if trades.len() == 1 && trades[0].price == 3.0
+        ));
+    }
 }
@@ -1,12 +1,13 @@
 //! Module holding all configuration types
 use serde::{Deserialize, Serialize};
 
-use crate::net::msg::BotId;
+use crate::{engine::EngineType, exchange::ExchangeId, net::msg::BotId};
 
 /// Configuration for botnode
 ///
 /// This configuration is sent to the bot after
-/// receiving correct `Hello` message.
+/// receiving correct `Hello` message and again as
+/// `ConfigUpdate` whenever it changes.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct BotConfiguration {
     pub bot_id: BotId,
@@ -14,6 +15,61 @@ pub struct BotConfiguration {
     pub exchanges: Box<[Box<str>]>,
     pub markets: Box<[Box<str>]>,
     pub indicators: Box<[IndicatorConfig]>,
+    /// Since protocol version 2, as are the fields below.
+    pub exchange_configs: Box<[ExchangeConfig]>,
+    pub recorder: Option<RecorderConfig>,
+    pub strategy: Option<StrategyConfig>,
+    /// Engines to run and their CPUs, each engine runs on its own CPU when
+    /// not set
+    pub topology: Option<TopologyConfig>,
+}
+
+impl BotConfiguration {
+    /// Returns settings for given exchange
+    ///
+    /// Falls back to default settings when the exchange has no explicit
+    /// configuration.
+    pub fn exchange_config(&self, exchange: &str) -> ExchangeConfig {
+        self.exchange_configs
+            .iter()
+            .find(|config| config.exchange.as_ref() == exchange)
+            .cloned()
+            .unwrap_or_else(|| ExchangeConfig::new(exchange))
+    }
+
+    /// Returns maximum number of markets subscribed on one market data
+    /// connection to given exchange, `None` when unlimited
+    pub fn markets_per_connection(&self, exchange: ExchangeId) -> Option<usize> {
+        self.exchange_configs
+            .iter()
+            .find(|config| config.exchange.parse::<ExchangeId>() == Ok(exchange))
+            .and_then(|config| config.markets_per_connection)
+    }
+
+    /// Returns number of redundant market data feeds of given exchange
+    pub fn feeds(&self, exchange: ExchangeId) -> usize {
+        self.exchange_configs
+            .iter()
+            .find(|config| config.exchange.parse::<ExchangeId>() == Ok(exchange))
+            .and_then(|config| config.feeds)
+            .map_or(1, |feeds| feeds.max(1))
+    }
+}
+
+/// Engines the bot runs and CPUs they are pinned to
+///
+/// Engines placed on the same CPU share one executor. Engines that aren't
+/// placed don't run, except the control engine which always runs on CPU 0.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TopologyConfig {
+    pub engines: Box<[EnginePlacement]>,
+}
+
+/// CPU the engine runs on
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct EnginePlacement {
+    pub engine: EngineType,
+    pub cpu: usize,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -25,3 +81,241 @@ pub struct PeerBot {
 pub enum IndicatorConfig {
     Midprice,
 }
+
+/// Trading strategy configuration
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum StrategyConfig {
+    /// Quotes both sides of the market around the mid price
+    QuoteMid {
+        exchange: Box<str>,
+        market: Box<str>,
+        /// Size of each quote
+        size: f64,
+        /// Distance of quotes from the mid price in basis points
+        spread_bps: f64,
+    },
+}
+
+/// Per-exchange connection settings read by adapters at construction
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ExchangeConfig {
+    /// Exchange name as listed in `BotConfiguration::exchanges`
+    pub exchange: Box<str>,
+    /// Base URL of the REST API, adapter default is used when not set
+    pub rest_url: Option<Box<str>>,
+    /// Websocket URL, adapter default is used when not set
+    pub ws_url: Option<Box<str>>,
+    /// Connect to the exchange testnet instead of production
+    pub testnet: bool,
+    /// Name of the environment variable holding the API key
+    pub api_key_ref: Option<Box<str>>,
+    /// Number of orderbook levels to request
+    pub depth: Option<u32>,
+    /// Maximum number of markets subscribed on one market data connection,
+    /// all markets are subscribed on one connection when not set
+    ///
+    /// Markets are split across as many websocket connections as needed to
+    /// keep within exchange subscription limits. The connections reconnect
+    /// independently and their market data is merged into one stream.
+    pub markets_per_connection: Option<usize>,
+    /// Number of redundant market data feeds, one feed when not set
+    ///
+    /// Each market data connection is opened `feeds` times with identical
+    /// subscriptions. The first copy of every message is forwarded by its
+    /// sequence number and the copies arriving later are dropped.
+    pub feeds: Option<usize>,
+    /// Directory raw websocket messages are captured to
+    pub capture_dir: Option<Box<str>>,
+    /// Replay captured websocket messages instead of connecting
+    pub replay: Option<ReplayConfig>,
+}
+
+impl ExchangeConfig {
+    /// Returns default settings for given exchange
+    pub fn new(exchange: &str) -> Self {
+        Self {
+            exchange: Box::from(exchange),
+            ..Default::default()
+        }
+    }
+
+    /// Resolves the API key from the environment variable referenced by
+    /// `api_key_ref`
+    pub fn api_key(&self) -> Option<ApiKey> {
+        self.api_key_with(|var| std::env::var(var).ok())
+    }
+
+    /// Resolves the API key referenced by `api_key_ref` using `lookup`
+    pub fn api_key_with<F>(&self, lookup: F) -> Option<ApiKey>
+    where
+        F: FnOnce(&str) -> Option<String>,
+    {
+        self.api_key_ref
+            .as_deref()
+            .and_then(lookup)
+            .map(|key| ApiKey(key.into_boxed_str()))
+    }
+}
+
+/// Exchange API key, not shown in debug output
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKey(Box<str>);
+
+impl ApiKey {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ApiKey(..)")
+    }
+}
+
+/// Websocket capture replay settings
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ReplayConfig {
+    /// Path to the capture file
+    pub path: Box<str>,
+    /// Replay speed relative to the original timing, `0` replays messages
+    /// without any delay
+    pub speed: f64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            path: Box::from(""),
+            speed: 1.0,
+        }
+    }
+}
+
+/// Market data recorder configuration
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RecorderConfig {
+    /// Directory the recorded files are written to
+    pub path: Box<str>,
+    /// Size of uncompressed data after which the file is rotated
+    pub max_file_size: u64,
+    /// Number of seconds after which the file is rotated
+    pub rotate_interval_secs: u64,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            path: Box::from("data"),
+            max_file_size: 512 * 1024 * 1024,
+            rotate_interval_secs: 3600,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bot_configuration(exchange_configs: Box<[ExchangeConfig]>) -> BotConfiguration {
+        BotConfiguration {
+            bot_id: BotId(0),
+            peer_bots: Box::new([]),
+            exchanges: Box::from([Box::from("ftx"), Box::from("binance")]),
+            markets: Box::from([Box::from("BTC/USD")]),
+            indicators: Box::new([]),
+            exchange_configs,
+            recorder: None,
+            strategy: None,
+            topology: None,
+        }
+    }
+
+    #[test]
+    fn exchange_config_default() {
+        let config = bot_configuration(Box::new([]));
+
+        let ftx = config.exchange_config("ftx");
+
+        assert_eq!(ftx.exchange.as_ref(), "ftx");
+        assert!(ftx.rest_url.is_none());
+        assert!(ftx.ws_url.is_none());
+        assert!(!ftx.testnet);
+    }
+
+    #[test]
+    fn exchange_config_explicit() {
+        let config = bot_configuration(Box::new([ExchangeConfig {
+            ws_url: Some(Box::from("ws://127.0.0.1:9000/ws")),
+            testnet: true,
+            ..ExchangeConfig::new("binance")
+        }]));
+
+        let binance = config.exchange_config("binance");
+
+        assert_eq!(binance.ws_url.as_deref(), Some("ws://127.0.0.1:9000/ws"));
+        assert!(binance.testnet);
+        assert!(config.exchange_config("ftx").ws_url.is_none());
+    }
+
+    #[test]
+    fn exchange_config_api_key() {
+        let config = ExchangeConfig {
+            api_key_ref: Some(Box::from("FTX_API_KEY")),
+            ..ExchangeConfig::new("ftx")
+        };
+        let lookup = |var: &str| (var == "FTX_API_KEY").then(|| String::from("secret"));
+
+        let api_key = config.api_key_with(lookup).unwrap();
+
+        assert_eq!(api_key.as_str(), "secret");
+        assert_eq!(format!("{:?}", api_key), "ApiKey(..)");
+        assert!(ExchangeConfig::new("ftx").api_key_with(lookup).is_none());
+        assert!(config.api_key_with(|_| None).is_none());
+    }
+
+    #[test]
+    fn markets_per_connection() {
+        let config = bot_configuration(Box::new([]));
+
+        assert_eq!(config.markets_per_connection(ExchangeId::Ftx), None);
+
+        let config = bot_configuration(Box::new([
+            ExchangeConfig::new("ftx"),
+            ExchangeConfig {
+                markets_per_connection: Some(4),
+                ..ExchangeConfig::new("binance")
+            },
+        ]));
+
+        assert_eq!(
+            config.markets_per_connection(ExchangeId::BinanceSpot),
+            Some(4)
+        );
+        assert_eq!(config.markets_per_connection(ExchangeId::Ftx), None);
+    }
+
+    #[test]
+    fn feeds() {
+        let config = bot_configuration(Box::new([]));
+
+        assert_eq!(config.feeds(ExchangeId::Ftx), 1);
+
+        let config = bot_configuration(Box::new([
+            ExchangeConfig {
+                feeds: Some(2),
+                ..ExchangeConfig::new("ftx")
+            },
+            ExchangeConfig {
+                feeds: Some(0),
+                ..ExchangeConfig::new("binance")
+            },
+        ]));
+
+        assert_eq!(config.feeds(ExchangeId::Ftx), 2);
+        assert_eq!(config.feeds(ExchangeId::BinanceSpot), 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! Time sources
+//!
+//! All timestamps are taken from a [`Clock`] so that replays and backtests
+//! can run on simulated time and latency measurements use one consistent
+//! time source.
+
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Clock shared between engines
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Source of current time
+pub trait Clock: Debug + Send + Sync {
+    /// Returns current time
+    fn now(&self) -> SystemTime;
+
+    /// Returns current time in nanoseconds since UNIX epoch
+    fn unix_nanos(&self) -> u128 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default()
+    }
+
+    /// Returns time elapsed since given time, zero when it is in the future
+    fn elapsed(&self, since: SystemTime) -> Duration {
+        self.now().duration_since(since).unwrap_or_default()
+    }
+
+    /// Returns current monotonic instant, used to trace latency
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Returns offset added to the clock in nanoseconds
+    fn offset_nanos(&self) -> i64 {
+        0
+    }
+
+    /// Sets offset added to the clock in nanoseconds
+    ///
+    /// Clocks that can't be adjusted ignore it.
+    fn set_offset_nanos(&self, _offset: i64) {}
+}
+
+/// Returns offset of a reference clock from the local clock in nanoseconds
+///
+/// `sent_at` and `received_at` are local times of a request and its
+/// response, `reference` is the time of the reference clock when it
+/// responded. The response is assumed to be sent halfway through the round
+/// trip.
+pub fn estimate_offset_nanos(sent_at: u128, reference: u128, received_at: u128) -> i64 {
+    let midpoint = sent_at + received_at.saturating_sub(sent_at) / 2;
+
+    (reference as i128 - midpoint as i128) as i64
+}
+
+/// Returns shared real time clock
+pub fn real_clock() -> SharedClock {
+    Arc::new(RealClock)
+}
+
+/// System wall clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Monotonic clock with adjustable offset
+///
+/// Wall time is read once at creation and then advanced using a monotonic
+/// clock, so system clock adjustments don't distort latency measurements.
+/// The offset aligns the clock with a reference, botnode sets it to the
+/// offset of botvana-server measured by pings.
+#[derive(Debug)]
+pub struct MonotonicClock {
+    base: SystemTime,
+    start: Instant,
+    offset_nanos: AtomicI64,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self {
+            base: SystemTime::now(),
+            start: Instant::now(),
+            offset_nanos: AtomicI64::new(0),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> SystemTime {
+        let now = self.base + self.start.elapsed();
+        let offset = self.offset_nanos();
+
+        if offset >= 0 {
+            now + Duration::from_nanos(offset as u64)
+        } else {
+            now - Duration::from_nanos(offset.unsigned_abs())
+        }
+    }
+
+    fn offset_nanos(&self) -> i64 {
+        self.offset_nanos.load(Ordering::Relaxed)
+    }
+
+    fn set_offset_nanos(&self, offset: i64) {
+        self.offset_nanos.store(offset, Ordering::Relaxed);
+    }
+}
+
+/// Simulated clock that only moves when told to
+///
+/// Its instants move with the simulated time, so latency of simulated
+/// processing is measured in simulated time.
+#[derive(Debug)]
+pub struct SimulatedClock {
+    nanos: AtomicU64,
+    /// Instant corresponding to the time the clock was created with
+    start: (Instant, u64),
+}
+
+impl SimulatedClock {
+    /// Creates new clock set to given time
+    pub fn new(now: SystemTime) -> Self {
+        let nanos = to_nanos(now);
+
+        Self {
+            nanos: AtomicU64::new(nanos),
+            start: (Instant::now(), nanos),
+        }
+    }
+
+    /// Sets the clock to given time, the clock never moves backwards
+    pub fn set(&self, now: SystemTime) {
+        self.nanos.fetch_max(to_nanos(now), Ordering::Relaxed);
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    fn instant(&self) -> Instant {
+        let (start, start_nanos) = self.start;
+        let nanos = self.nanos.load(Ordering::Relaxed);
+
+        start + Duration::from_nanos(nanos.saturating_sub(start_nanos))
+    }
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_clock() {
+        let clock = SimulatedClock::new(UNIX_EPOCH + Duration::from_secs(10));
+
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_millis(10_005));
+        assert_eq!(clock.unix_nanos(), 10_005_000_000);
+
+        // Never moves backwards
+        clock.set(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_millis(10_005));
+
+        assert_eq!(
+            clock.elapsed(UNIX_EPOCH + Duration::from_secs(10)),
+            Duration::from_millis(5)
+        );
+        assert_eq!(
+            clock.elapsed(UNIX_EPOCH + Duration::from_secs(20)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn simulated_clock_instant() {
+        let clock = SimulatedClock::new(UNIX_EPOCH + Duration::from_secs(10));
+        let start = clock.instant();
+
+        assert_eq!(clock.instant(), start);
+
+        clock.advance(Duration::from_micros(30));
+        assert_eq!(clock.instant() - start, Duration::from_micros(30));
+    }
+
+    #[test]
+    fn monotonic_clock_offset() {
+        let clock = MonotonicClock::new();
+        let before = clock.now();
+
+        clock.set_offset_nanos(-1_000_000_000);
+        assert!(clock.now() < before);
+
+        clock.set_offset_nanos(1_000_000_000);
+        assert!(clock.now() >= before + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn estimate_offset() {
+        // Reference is 300ns ahead, the round trip took 100ns
+        assert_eq!(estimate_offset_nanos(1_000, 1_350, 1_100), 300);
+        assert_eq!(estimate_offset_nanos(1_000, 800, 1_100), -250);
+        assert_eq!(estimate_offset_nanos(1_000, 1_000, 900), 0);
+    }
+}
@@ -0,0 +1,62 @@
+//! Engine types shared by botnode and botvana-server
+
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::ExchangeId;
+
+/// Botnode engines type
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Serialize)]
+pub enum EngineType {
+    AuditEngine,
+    ControlEngine,
+    ExchangeEngine,
+    IndicatorEngine,
+    MarketDataEngine(ExchangeId),
+    RecorderEngine,
+    TradingEngine,
+}
+
+/// Engine status
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum EngineStatus {
+    /// The engine is booting
+    Booting,
+    /// The engine is running and operating as expected
+    Running,
+    /// The engine is shutting down
+    ShuttingDown,
+    /// Engine has encountered error and shut down
+    Error,
+}
+
+impl Default for EngineStatus {
+    fn default() -> Self {
+        Self::Booting
+    }
+}
+
+/// Engine exit detected by the botnode supervisor
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EngineExit {
+    pub engine: EngineType,
+    /// Why the engine exited
+    pub reason: Box<str>,
+    /// Number of times the engine was restarted since botnode started
+    pub restarts: u32,
+    /// Delay before the engine is restarted, `None` when it won't be
+    pub restart_in_ms: Option<u64>,
+}
+
+/// Engine health monitored by botnode from engine heartbeats
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EngineHealth {
+    pub engine: EngineType,
+    /// The engine missed its heartbeat deadline
+    pub stalled: bool,
+    /// Loop iterations reported in the last heartbeat
+    pub iterations: u64,
+    /// Milliseconds since the last heartbeat
+    pub heartbeat_age_ms: u64,
+    /// Milliseconds since the engine last processed an event
+    pub last_event_age_ms: Option<u64>,
+}
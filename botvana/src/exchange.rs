@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::SystemTime};
 
 use serde::{Deserialize, Serialize};
 
@@ -29,6 +29,38 @@ impl FromStr for ExchangeId {
     }
 }
 
+/// Order side
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    /// Returns `1.0` for buy and `-1.0` for sell side
+    pub fn sign(&self) -> f64 {
+        match self {
+            Side::Buy => 1.0,
+            Side::Sell => -1.0,
+        }
+    }
+}
+
+/// Order fill reported by an exchange
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Fill {
+    pub exchange: ExchangeId,
+    pub market: Box<str>,
+    pub order_id: u64,
+    pub side: Side,
+    pub price: f64,
+    pub size: f64,
+    /// Fee paid in quote currency
+    pub fee: f64,
+    /// Time of the fill
+    pub time: SystemTime,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
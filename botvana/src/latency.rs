@@ -0,0 +1,140 @@
+//! Tick-to-trade latency tracing
+//!
+//! Market events carry a [`LatencyTrace`] with the time each processing stage
+//! was reached, from receiving the websocket frame to sending the order the
+//! event triggered. Stages are taken from [`Clock::instant`] of the engine
+//! clocks, monotonic instants comparable across threads, so traces are not
+//! affected by offsets of the wall time and follow simulated time in
+//! backtests.
+
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// Stage of market event processing
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LatencyStage {
+    /// Websocket frame was received from the socket
+    Received,
+    /// Exchange message was parsed
+    Parsed,
+    /// Orderbook was updated and the event built
+    BookUpdated,
+    /// Event was pushed onto engine queues
+    Enqueued,
+    /// Event was taken from the queue by the trading engine
+    Dequeued,
+    /// Order triggered by the event was sent to the exchange engine
+    OrderSent,
+}
+
+impl LatencyStage {
+    /// All stages in processing order
+    pub const ALL: [LatencyStage; 6] = [
+        LatencyStage::Received,
+        LatencyStage::Parsed,
+        LatencyStage::BookUpdated,
+        LatencyStage::Enqueued,
+        LatencyStage::Dequeued,
+        LatencyStage::OrderSent,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LatencyStage::Received => "received",
+            LatencyStage::Parsed => "parsed",
+            LatencyStage::BookUpdated => "book_updated",
+            LatencyStage::Enqueued => "enqueued",
+            LatencyStage::Dequeued => "dequeued",
+            LatencyStage::OrderSent => "order_sent",
+        }
+    }
+}
+
+/// Times at which an event reached each processing stage
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencyTrace {
+    stages: [Option<Instant>; 6],
+}
+
+impl LatencyTrace {
+    /// Creates trace of a frame received at given time
+    pub fn received_at(at: Instant) -> Self {
+        let mut trace = Self::default();
+        trace.mark_at(LatencyStage::Received, at);
+        trace
+    }
+
+    /// Records that the stage was reached at current instant of the clock
+    pub fn mark(&mut self, stage: LatencyStage, clock: &dyn Clock) {
+        self.mark_at(stage, clock.instant());
+    }
+
+    /// Records that the stage was reached at given time
+    pub fn mark_at(&mut self, stage: LatencyStage, at: Instant) {
+        self.stages[stage as usize] = Some(at);
+    }
+
+    /// Returns time the stage was reached at
+    pub fn get(&self, stage: LatencyStage) -> Option<Instant> {
+        self.stages[stage as usize]
+    }
+
+    /// Returns time spent reaching each recorded stage since the previous
+    /// recorded one
+    pub fn spans(&self) -> Vec<(LatencyStage, Duration)> {
+        let mut previous: Option<Instant> = None;
+        let mut spans = Vec::with_capacity(self.stages.len());
+
+        for stage in LatencyStage::ALL {
+            if let Some(at) = self.get(stage) {
+                if let Some(previous) = previous {
+                    spans.push((stage, at.saturating_duration_since(previous)));
+                }
+                previous = Some(at);
+            }
+        }
+
+        spans
+    }
+
+    /// Returns time from receiving the frame to sending the order
+    pub fn tick_to_trade(&self) -> Option<Duration> {
+        let received = self.get(LatencyStage::Received)?;
+        let sent = self.get(LatencyStage::OrderSent)?;
+
+        Some(sent.saturating_duration_since(received))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    #[test]
+    fn test_spans() {
+        let clock = SimulatedClock::new(std::time::UNIX_EPOCH);
+        let start = clock.instant();
+        let mut trace = LatencyTrace::received_at(start);
+
+        trace.mark_at(LatencyStage::Parsed, start + Duration::from_micros(20));
+        trace.mark_at(LatencyStage::Enqueued, start + Duration::from_micros(30));
+        clock.advance(Duration::from_micros(35));
+        trace.mark(LatencyStage::Dequeued, &clock);
+
+        assert_eq!(
+            trace.spans(),
+            vec![
+                (LatencyStage::Parsed, Duration::from_micros(20)),
+                (LatencyStage::Enqueued, Duration::from_micros(10)),
+                (LatencyStage::Dequeued, Duration::from_micros(5)),
+            ]
+        );
+        assert_eq!(trace.tick_to_trade(), None);
+
+        trace.mark_at(LatencyStage::OrderSent, start + Duration::from_micros(50));
+        assert_eq!(trace.tick_to_trade(), Some(Duration::from_micros(50)));
+        assert_eq!(LatencyTrace::default().spans(), vec![]);
+    }
+}
@@ -3,7 +3,12 @@
 //! This crate is a foundation of Botvana.
 
 pub mod cfg;
+pub mod clock;
+pub mod engine;
 pub mod exchange;
+pub mod latency;
 pub mod market;
+pub mod metrics;
 pub mod net;
+pub mod report;
 pub mod state;
@@ -11,7 +11,7 @@ use soa_derive::StructOfArray;
 use crate::exchange::ExchangeId;
 
 /// Single market information
-#[derive(Clone, Debug, StructOfArray)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, StructOfArray)]
 #[soa_derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Market {
     pub exchange: ExchangeId,
@@ -36,21 +36,21 @@ impl From<MarketRef<'_>> for Market {
 }
 
 /// Market types enum
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum MarketType {
     Spot(SpotMarket),
     Futures,
 }
 
 /// Spot market information
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SpotMarket {
     pub base: String,
     pub quote: String,
 }
 
 /// Futures market
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct FuturesMarket {
     pub expires_at: Option<DateTime<Utc>>,
 }
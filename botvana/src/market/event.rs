@@ -1,10 +1,16 @@
 use super::{orderbook::*, trade::*, MarketVec};
+use crate::{
+    clock::Clock,
+    latency::{LatencyStage, LatencyTrace},
+};
 
 /// Market event enum produced by market data engine
 #[derive(Clone, Debug)]
 pub struct MarketEvent {
     pub r#type: MarketEventType,
     pub timestamp: std::time::SystemTime,
+    /// Processing stages reached by the event
+    pub trace: LatencyTrace,
 }
 
 #[derive(Clone, Debug)]
@@ -20,31 +26,42 @@ pub enum MarketEventType {
 }
 
 impl MarketEvent {
-    /// Creates new `MarketEvent` with current timestamp
-    pub fn new(r#type: MarketEventType) -> Self {
+    /// Creates new `MarketEvent` with current time of the clock
+    pub fn new(clock: &dyn Clock, r#type: MarketEventType) -> Self {
         Self {
             r#type,
-            timestamp: std::time::SystemTime::now(),
+            timestamp: clock.now(),
+            trace: LatencyTrace::default(),
         }
     }
 
+    /// Returns the event with the stage marked as reached at given time
+    pub fn with_stage_at(mut self, stage: LatencyStage, at: std::time::Instant) -> Self {
+        self.trace.mark_at(stage, at);
+        self
+    }
+
     /// Creates new `MarketEvent::Trades` variant
-    pub fn trades(market: Box<str>, trades: Box<[Trade]>) -> Self {
-        Self::new(MarketEventType::Trades(market, trades))
+    pub fn trades(clock: &dyn Clock, market: Box<str>, trades: Box<[Trade]>) -> Self {
+        Self::new(clock, MarketEventType::Trades(market, trades))
     }
 
     /// Creates new `MarketEvent::MidPriceChange` variant
-    pub fn mid_price_change(market: Box<str>, bid: f64, ask: f64) -> Self {
-        Self::new(MarketEventType::MidPriceChange(market, bid, ask))
+    pub fn mid_price_change(clock: &dyn Clock, market: Box<str>, bid: f64, ask: f64) -> Self {
+        Self::new(clock, MarketEventType::MidPriceChange(market, bid, ask))
     }
 
     /// Creates new `MarketEvent::OrderbookUpdate` variant
-    pub fn orderbook_update(market: Box<str>, orderbook: Box<PlainOrderbook<f64>>) -> Self {
-        Self::new(MarketEventType::OrderbookUpdate(market, orderbook))
+    pub fn orderbook_update(
+        clock: &dyn Clock,
+        market: Box<str>,
+        orderbook: Box<PlainOrderbook<f64>>,
+    ) -> Self {
+        Self::new(clock, MarketEventType::OrderbookUpdate(market, orderbook))
     }
 
     /// Creates new `MarketEvent::Markets` variant
-    pub fn markets(market_vec: Box<MarketVec>) -> Self {
-        Self::new(MarketEventType::Markets(market_vec))
+    pub fn markets(clock: &dyn Clock, market_vec: Box<MarketVec>) -> Self {
+        Self::new(clock, MarketEventType::Markets(market_vec))
     }
 }
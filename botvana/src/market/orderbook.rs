@@ -112,7 +112,7 @@ impl<T> From<Orderbook<T>> for PlainOrderbook<T> {
 }
 
 /// Columnar struct of price levels
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct PriceLevelsVec<T> {
     pub price_vec: Vec<T>,
     pub size_vec: Vec<T>,
@@ -165,6 +165,10 @@ impl<T> PriceLevelsVec<T> {
     pub fn len(&self) -> usize {
         self.price_vec.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.price_vec.is_empty()
+    }
 }
 
 impl PriceLevelsVec<Decimal> {
@@ -197,6 +201,52 @@ impl PriceLevelsVec<Decimal> {
 }
 
 impl PriceLevelsVec<f64> {
+    /// Returns price levels that need to be applied using `update` to turn
+    /// `self` into `new`
+    ///
+    /// Removed levels are returned with zero size. Both vectors are expected
+    /// to be sorted by price.
+    pub fn diff(&self, new: &PriceLevelsVec<f64>) -> PriceLevelsVec<f64> {
+        let mut diff = PriceLevelsVec::new();
+        let (mut i, mut j) = (0, 0);
+
+        while i < self.len() || j < new.len() {
+            let old_level = self.price_vec.get(i).zip(self.size_vec.get(i));
+            let new_level = new.price_vec.get(j).zip(new.size_vec.get(j));
+
+            match (old_level, new_level) {
+                (Some((old_price, old_size)), Some((new_price, new_size)))
+                    if old_price == new_price =>
+                {
+                    if old_size != new_size {
+                        diff.price_vec.push(*new_price);
+                        diff.size_vec.push(*new_size);
+                    }
+                    i += 1;
+                    j += 1;
+                }
+                (Some((old_price, _)), Some((new_price, _))) if old_price < new_price => {
+                    diff.price_vec.push(*old_price);
+                    diff.size_vec.push(0.0);
+                    i += 1;
+                }
+                (Some((old_price, _)), None) => {
+                    diff.price_vec.push(*old_price);
+                    diff.size_vec.push(0.0);
+                    i += 1;
+                }
+                (_, Some((new_price, new_size))) => {
+                    diff.price_vec.push(*new_price);
+                    diff.size_vec.push(*new_size);
+                    j += 1;
+                }
+                (None, None) => break,
+            }
+        }
+
+        diff
+    }
+
     pub fn update(&mut self, update: &PriceLevelsVec<f64>) {
         update
             .price_vec
@@ -298,6 +348,32 @@ mod tests {
         assert_eq!(price_levels.size_vec.len(), 0);
     }
 
+    #[test]
+    fn test_diff_price_levels_vec() {
+        let old = PriceLevelsVec::from_tuples_vec(&[(13.0, 120.0), (13.05, 90.0), (13.1, 20.0)]);
+        let new = PriceLevelsVec::from_tuples_vec(&[(13.01, 270.0), (13.05, 90.0), (13.1, 25.0)]);
+
+        let diff = old.diff(&new);
+
+        assert_eq!(diff.price_vec, vec![13.0, 13.01, 13.1]);
+        assert_eq!(diff.size_vec, vec![0.0, 270.0, 25.0]);
+
+        let mut updated = old.clone();
+        updated.update(&diff);
+
+        assert_eq!(updated.price_vec, new.price_vec);
+        assert_eq!(updated.size_vec, new.size_vec);
+    }
+
+    #[test]
+    fn test_diff_price_levels_vec_empty() {
+        let old = PriceLevelsVec::from_tuples_vec(&[(13.0, 120.0), (13.05, 90.0)]);
+
+        assert_eq!(old.diff(&old).len(), 0);
+        assert_eq!(old.diff(&PriceLevelsVec::new()).size_vec, vec![0.0, 0.0]);
+        assert_eq!(PriceLevelsVec::new().diff(&old).size_vec, vec![120.0, 90.0]);
+    }
+
     #[test]
     fn test_update_price_levels_vec() {
         let mut price_levels = PriceLevelsVec {
@@ -1,24 +1,29 @@
 //! Trade
+use std::time::SystemTime;
+
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Trade {
     pub price: f64,
     pub size: f64,
     /// Time of the trade specified by the exchange
     pub time: DateTime<Utc>,
     /// Time the trade was received
-    pub received_at: std::time::Instant,
+    pub received_at: SystemTime,
 }
 
 impl Trade {
-    /// Creates new trade
-    pub fn new(price: f64, size: f64, time: DateTime<Utc>) -> Self {
+    /// Creates new trade received at the current time of the clock
+    pub fn new(clock: &dyn Clock, price: f64, size: f64, time: DateTime<Utc>) -> Self {
         Self {
             price,
             size,
             time,
-            received_at: std::time::Instant::now(),
+            received_at: clock.now(),
         }
     }
 }
@@ -28,7 +33,7 @@ pub struct TradesVec {
     pub prices: Vec<f64>,
     pub sizes: Vec<f64>,
     pub times: Vec<DateTime<Utc>>,
-    pub received_times: Vec<std::time::Instant>,
+    pub received_times: Vec<SystemTime>,
 }
 
 impl TradesVec {
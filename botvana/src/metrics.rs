@@ -0,0 +1,464 @@
+//! Metrics registry exported in Prometheus text format
+//!
+//! Counters and histograms are sharded per thread, engines pinned to
+//! different cores update their own cache lines and shards are only summed
+//! when the registry is encoded. Metrics are registered once, when the engine
+//! starts, and the returned handles are updated on the hot path.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+/// Content type of the Prometheus text format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Histogram buckets for latencies in microseconds
+pub const LATENCY_BUCKETS_US: &[f64] = &[
+    10.0,
+    25.0,
+    50.0,
+    100.0,
+    250.0,
+    500.0,
+    1_000.0,
+    2_500.0,
+    5_000.0,
+    10_000.0,
+    25_000.0,
+    50_000.0,
+    100_000.0,
+    250_000.0,
+    1_000_000.0,
+];
+
+/// Number of per-thread shards of counters and histograms
+const SHARDS: usize = 32;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+/// Returns registry shared by the whole process
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Returns shard of the current thread
+fn shard() -> usize {
+    SHARD.with(|shard| *shard)
+}
+
+/// Value aligned to its own cache line
+#[derive(Default)]
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+/// Monotonically increasing counter
+pub struct Counter {
+    shards: Box<[CachePadded<AtomicU64>]>,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| CachePadded::default()).collect(),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.shards[shard()].0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Returns sum of all shards
+    pub fn get(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.0.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+/// Value that can go up and down
+///
+/// Gauges are not sharded, they are expected to be set by one engine.
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Histogram with fixed bucket upper bounds
+pub struct Histogram {
+    bounds: Box<[f64]>,
+    shards: Box<[CachePadded<HistogramShard>]>,
+}
+
+struct HistogramShard {
+    /// Observations per bucket, the last bucket is `+Inf`
+    buckets: Box<[AtomicU64]>,
+    /// Sum of observations as `f64` bits
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.into(),
+            shards: (0..SHARDS)
+                .map(|_| {
+                    CachePadded(HistogramShard {
+                        buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+                        sum: AtomicU64::new(0f64.to_bits()),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let shard = &self.shards[shard()].0;
+        let idx = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+
+        shard.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        let _ = shard
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    /// Returns number of observations
+    pub fn count(&self) -> u64 {
+        self.cumulative_counts().last().copied().unwrap_or_default()
+    }
+
+    /// Returns sum of observations
+    pub fn sum(&self) -> f64 {
+        self.shards
+            .iter()
+            .map(|shard| f64::from_bits(shard.0.sum.load(Ordering::Relaxed)))
+            .sum()
+    }
+
+    /// Returns number of observations less or equal to each bound, the last
+    /// count includes all observations
+    fn cumulative_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0; self.bounds.len() + 1];
+
+        for shard in self.shards.iter() {
+            for (count, bucket) in counts.iter_mut().zip(shard.0.buckets.iter()) {
+                *count += bucket.load(Ordering::Relaxed);
+            }
+        }
+
+        let mut total = 0;
+        for count in counts.iter_mut() {
+            total += *count;
+            *count = total;
+        }
+
+        counts
+    }
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+type Labels = Box<[(Box<str>, Box<str>)]>;
+
+/// Metrics sharing the same name
+struct Family {
+    help: Box<str>,
+    kind: &'static str,
+    series: Vec<(Labels, Metric)>,
+}
+
+/// Registry of metrics
+#[derive(Default)]
+pub struct Registry {
+    families: RwLock<BTreeMap<Box<str>, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns counter with given name and labels, registering it first
+    ///
+    /// Panics when the name is registered with different type.
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        let metric = self.register(name, help, "counter", labels, || {
+            Metric::Counter(Arc::new(Counter::new()))
+        });
+
+        match metric {
+            Metric::Counter(counter) => counter,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns gauge with given name and labels, registering it first
+    ///
+    /// Panics when the name is registered with different type.
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        let metric = self.register(name, help, "gauge", labels, || {
+            Metric::Gauge(Arc::new(Gauge::default()))
+        });
+
+        match metric {
+            Metric::Gauge(gauge) => gauge,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns histogram with given name and labels, registering it first
+    /// with given bucket upper bounds
+    ///
+    /// Panics when the name is registered with different type.
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        buckets: &[f64],
+    ) -> Arc<Histogram> {
+        let metric = self.register(name, help, "histogram", labels, || {
+            Metric::Histogram(Arc::new(Histogram::new(buckets)))
+        });
+
+        match metric {
+            Metric::Histogram(histogram) => histogram,
+            _ => unreachable!(),
+        }
+    }
+
+    fn register(
+        &self,
+        name: &str,
+        help: &str,
+        kind: &'static str,
+        labels: &[(&str, &str)],
+        make: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let mut families = self.families.write();
+        let family = families.entry(Box::from(name)).or_insert_with(|| Family {
+            help: Box::from(help),
+            kind,
+            series: Vec::new(),
+        });
+
+        assert_eq!(
+            family.kind, kind,
+            "metric {name} is already registered as {}",
+            family.kind
+        );
+
+        let labels: Labels = labels
+            .iter()
+            .map(|(name, value)| (Box::from(*name), Box::from(*value)))
+            .collect();
+
+        match family.series.iter().find(|(other, _)| *other == labels) {
+            Some((_, metric)) => metric.clone(),
+            None => {
+                let metric = make();
+                family.series.push((labels, metric.clone()));
+                metric
+            }
+        }
+    }
+
+    /// Returns all metrics in Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+
+        for (name, family) in self.families.read().iter() {
+            let _ = writeln!(out, "# HELP {name} {}", escape(&family.help, false));
+            let _ = writeln!(out, "# TYPE {name} {}", family.kind);
+
+            for (labels, metric) in family.series.iter() {
+                match metric {
+                    Metric::Counter(counter) => {
+                        let _ =
+                            writeln!(out, "{name}{} {}", fmt_labels(labels, None), counter.get());
+                    }
+                    Metric::Gauge(gauge) => {
+                        let _ = writeln!(out, "{name}{} {}", fmt_labels(labels, None), gauge.get());
+                    }
+                    Metric::Histogram(histogram) => {
+                        let counts = histogram.cumulative_counts();
+                        let bounds = histogram.bounds.iter().map(|bound| bound.to_string());
+
+                        for (bound, count) in bounds.chain(Some("+Inf".to_string())).zip(&counts) {
+                            let labels = fmt_labels(labels, Some(("le", &bound)));
+                            let _ = writeln!(out, "{name}_bucket{labels} {count}");
+                        }
+
+                        let labels = fmt_labels(labels, None);
+                        let _ = writeln!(out, "{name}_sum{labels} {}", histogram.sum());
+                        let _ = writeln!(
+                            out,
+                            "{name}_count{labels} {}",
+                            counts.last().copied().unwrap_or_default()
+                        );
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Formats labels as `{name="value",...}`, empty when there are none
+fn fmt_labels(labels: &Labels, extra: Option<(&str, &str)>) -> String {
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| (&**name, &**value))
+        .chain(extra)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value, true)))
+        .collect();
+
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// Escapes backslashes, newlines and, in label values, double quotes
+fn escape(value: &str, quotes: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if quotes => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_shards() {
+        let registry = Registry::new();
+        let counter = registry.counter("events_total", "Events", &[]);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || (0..1000).for_each(|_| counter.inc()))
+            })
+            .collect();
+        threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+
+        assert_eq!(counter.get(), 4000);
+        assert_eq!(registry.counter("events_total", "Events", &[]).get(), 4000);
+        assert_eq!(
+            registry
+                .counter("events_total", "Events", &[("exchange", "ftx")])
+                .get(),
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_register_different_type() {
+        let registry = Registry::new();
+
+        registry.counter("events", "Events", &[]);
+        registry.gauge("events", "Events", &[]);
+    }
+
+    #[test]
+    fn test_encode() {
+        let registry = Registry::new();
+
+        registry
+            .counter(
+                "messages_total",
+                "Messages received",
+                &[("exchange", "ftx")],
+            )
+            .add(3);
+        registry
+            .gauge(
+                "depth",
+                "Orderbook depth",
+                &[("market", "BTC/USD \"spot\"")],
+            )
+            .set(-2);
+
+        let histogram = registry.histogram("latency_us", "Latency", &[], &[10.0, 100.0]);
+        histogram.observe(5.0);
+        histogram.observe(50.0);
+        histogram.observe(500.0);
+
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(
+            registry.encode(),
+            "# HELP depth Orderbook depth\n\
+             # TYPE depth gauge\n\
+             depth{market=\"BTC/USD \\\"spot\\\"\"} -2\n\
+             # HELP latency_us Latency\n\
+             # TYPE latency_us histogram\n\
+             latency_us_bucket{le=\"10\"} 1\n\
+             latency_us_bucket{le=\"100\"} 2\n\
+             latency_us_bucket{le=\"+Inf\"} 3\n\
+             latency_us_sum 555\n\
+             latency_us_count 3\n\
+             # HELP messages_total Messages received\n\
+             # TYPE messages_total counter\n\
+             messages_total{exchange=\"ftx\"} 3\n"
+        );
+    }
+}
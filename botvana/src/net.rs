@@ -1,5 +1,9 @@
 //! Botvana network module
 
+pub mod auth;
 pub mod codec;
 pub mod frame;
+pub mod gateway;
 pub mod msg;
+pub mod tls;
+pub mod v1;
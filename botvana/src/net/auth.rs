@@ -0,0 +1,75 @@
+//! Authentication of botnode connections
+//!
+//! After receiving `Hello` the server sends a random [`Nonce`] as a
+//! challenge. The bot answers with HMAC-SHA256 of the nonce and its bot id
+//! keyed by the shared secret, so the secret never goes over the wire.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::msg::BotId;
+
+/// Length of the challenge nonce and the response
+pub const AUTH_LEN: usize = 32;
+
+/// Random challenge sent by the server
+pub type Nonce = [u8; AUTH_LEN];
+
+/// Challenge response sent by the bot
+pub type Signature = [u8; AUTH_LEN];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Returns new random nonce
+pub fn nonce() -> Result<Nonce, getrandom::Error> {
+    let mut nonce = [0; AUTH_LEN];
+    getrandom::getrandom(&mut nonce)?;
+
+    Ok(nonce)
+}
+
+/// Signs the challenge with the shared secret of the bot
+pub fn sign(secret: &[u8], bot_id: &BotId, nonce: &Nonce) -> Signature {
+    mac(secret, bot_id, nonce).finalize().into_bytes().into()
+}
+
+/// Verifies the challenge response in constant time
+pub fn verify(secret: &[u8], bot_id: &BotId, nonce: &Nonce, signature: &Signature) -> bool {
+    mac(secret, bot_id, nonce).verify_slice(signature).is_ok()
+}
+
+/// Compares two tokens in constant time
+pub fn tokens_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn mac(secret: &[u8], bot_id: &BotId, nonce: &Nonce) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(nonce);
+    mac.update(&bot_id.0.to_le_bytes());
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let nonce = nonce().unwrap();
+        let signature = sign(b"secret", &BotId(1), &nonce);
+
+        assert!(verify(b"secret", &BotId(1), &nonce, &signature));
+        assert!(!verify(b"other", &BotId(1), &nonce, &signature));
+        assert!(!verify(b"secret", &BotId(2), &nonce, &signature));
+        assert!(!verify(b"secret", &BotId(1), &[0; AUTH_LEN], &signature));
+    }
+
+    #[test]
+    fn test_tokens_eq() {
+        assert!(tokens_eq(b"token", b"token"));
+        assert!(!tokens_eq(b"token", b"tokem"));
+        assert!(!tokens_eq(b"token", b"token2"));
+    }
+}
@@ -1,12 +1,96 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
 use async_codec::*;
+use bincode::Options;
 use tracing::{error, trace};
 
-use super::msg::*;
+use super::{msg::*, v1};
 
 pub use async_codec::Framed;
 
+/// Length of frame header with version and payload size
+const HEADER_LEN: usize = 5;
+
+/// Default maximum size of frame payload
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Codec of Botvana protocol frames
+///
+/// Each frame starts with the protocol version its message is encoded with.
+/// The codec encodes with the current protocol version until it decodes
+/// a frame of an older supported version, from then on it uses the older
+/// version so that both sides settle on the lower one. Version 1 frames use
+/// the layout of the original protocol, see [`v1`].
+///
+/// Frames with payload larger than the maximum frame size are rejected
+/// without buffering them.
 #[derive(Debug)]
-pub struct BotvanaCodec;
+pub struct BotvanaCodec {
+    version: NegotiatedVersion,
+    max_frame_size: usize,
+}
+
+impl BotvanaCodec {
+    pub fn new() -> Self {
+        Self::with_version(PROTOCOL_VERSION as u8)
+    }
+
+    /// Creates codec encoding with given protocol version
+    pub fn with_version(version: u8) -> Self {
+        Self {
+            version: NegotiatedVersion(Arc::new(AtomicU8::new(version))),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Sets maximum size of frame payload
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Returns protocol version used for encoding
+    pub fn version(&self) -> u8 {
+        self.version.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns handle of the protocol version, which follows the version
+    /// negotiated by the codec after it's moved into the framed stream
+    pub fn negotiated_version(&self) -> NegotiatedVersion {
+        self.version.clone()
+    }
+}
+
+/// Protocol version used by a codec
+#[derive(Clone, Debug)]
+pub struct NegotiatedVersion(Arc<AtomicU8>);
+
+impl NegotiatedVersion {
+    /// Returns the protocol version
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed) as u32
+    }
+
+    /// Returns whether the message can be sent with the protocol version
+    pub fn supports(&self, msg: &Message) -> bool {
+        msg.min_version() <= self.get()
+    }
+}
+
+impl Default for NegotiatedVersion {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU8::new(PROTOCOL_VERSION as u8)))
+    }
+}
+
+impl Default for BotvanaCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Encode for BotvanaCodec {
     type Item = Message;
@@ -15,7 +99,18 @@ impl Encode for BotvanaCodec {
     fn encode(&mut self, item: &Self::Item, buf: &mut [u8]) -> EncodeResult<()> {
         trace!("serializing {:?}", item);
 
-        let msg = match bincode::serialize(item) {
+        if !self.version.supports(item) {
+            error!(
+                "Message {:?} requires protocol version {}, negotiated {}",
+                item,
+                item.min_version(),
+                self.version.get()
+            );
+
+            return EncodeResult::Err(());
+        }
+
+        let msg = match serialize(item, self.version()) {
             Ok(msg) => msg,
             Err(e) => {
                 error!("Failed to serialize: {}", e);
@@ -25,57 +120,118 @@ impl Encode for BotvanaCodec {
         };
         let msg_size = msg.len();
 
-        if buf.len() < msg_size + 5 {
-            return EncodeResult::Overflow(msg_size + 5);
+        if msg_size > self.max_frame_size {
+            error!(
+                "Message of {} bytes exceeds maximum frame size {}",
+                msg_size, self.max_frame_size
+            );
+
+            return EncodeResult::Err(());
+        }
+
+        if buf.len() < msg_size + HEADER_LEN {
+            return EncodeResult::Overflow(msg_size + HEADER_LEN);
         }
 
         // Write frame version
-        buf[0] = 1;
+        buf[0] = self.version();
 
         // Encode frame size & write to stream
-        let encoded: [u8; 4] = unsafe { std::mem::transmute((msg_size as u32).to_le_bytes()) };
-        buf[1..5].copy_from_slice(&encoded);
-        buf[5..msg_size as usize + 5].copy_from_slice(&msg);
+        buf[1..HEADER_LEN].copy_from_slice(&(msg_size as u32).to_le_bytes());
+        buf[HEADER_LEN..msg_size + HEADER_LEN].copy_from_slice(&msg);
 
-        Ok(msg_size + 5).into()
+        Ok(msg_size + HEADER_LEN).into()
     }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum DecodeError {
-    #[error("Invalid frame version")]
-    InvalidVersion,
+    #[error("Invalid frame version {0}")]
+    InvalidVersion(u8),
+    #[error("Message not supported by protocol version {0}")]
+    UnsupportedMessage(u8),
+    #[error("Frame of {size} bytes exceeds maximum frame size {max}")]
+    FrameTooLarge { size: usize, max: usize },
+    #[error("Failed to deserialize message: {0}")]
+    Deserialize(#[from] bincode::Error),
 }
 
 impl Decode for BotvanaCodec {
     type Item = Message;
     type Error = DecodeError;
 
+    /// Decodes one frame
+    ///
+    /// Frames that fail to deserialize are consumed. Oversized frames and
+    /// frames with invalid version consume the whole buffer since the stream
+    /// can't be resynchronized.
     fn decode(&mut self, buf: &mut [u8]) -> (usize, DecodeResult<Self::Item, Self::Error>) {
-        if buf.len() < 6 {
+        if buf.len() < HEADER_LEN {
             return (0, DecodeResult::UnexpectedEnd);
         }
 
         // Read the frame version
-        if buf[0] == 1 {
-            let size = unsafe {
-                std::mem::transmute::<[u8; 4], u32>(buf[1..5].try_into().expect("Failed try_into"))
+        let version = buf[0];
+
+        if !(1..=PROTOCOL_VERSION).contains(&(version as u32)) {
+            error!("Invalid frame version = {}", version);
+
+            return (buf.len(), Err(DecodeError::InvalidVersion(version)).into());
+        }
+
+        let size = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+
+        if size > self.max_frame_size {
+            let err = DecodeError::FrameTooLarge {
+                size,
+                max: self.max_frame_size,
             };
 
-            let end_pos = size as usize + 5;
-            if buf.len() < end_pos {
-                (0, DecodeResult::UnexpectedEnd)
-            } else {
-                let msg_buf = &buf[5..end_pos];
-                let message = bincode::deserialize(msg_buf).expect("Failed to deserialize msg_buf");
+            return (buf.len(), Err(err).into());
+        }
 
-                (end_pos, Ok(message).into())
-            }
+        let end_pos = size + HEADER_LEN;
+        if buf.len() < end_pos {
+            return (0, DecodeResult::UnexpectedEnd);
+        }
+
+        let payload = &buf[HEADER_LEN..end_pos];
+
+        self.version.0.fetch_min(version, Ordering::Relaxed);
+
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .with_limit(self.max_frame_size as u64);
+        let message = if version == 1 {
+            options
+                .deserialize::<v1::Message>(payload)
+                .map_err(DecodeError::from)
+                .and_then(|msg| {
+                    msg.into_current()
+                        .ok_or(DecodeError::UnsupportedMessage(version))
+                })
         } else {
-            error!("Invalid frame version = {}", buf[0]);
+            options.deserialize(payload).map_err(DecodeError::from)
+        };
+
+        (end_pos, message.into())
+    }
+}
 
-            (1, Err(DecodeError::InvalidVersion).into())
+/// Serializes the message in the layout of given protocol version
+fn serialize(item: &Message, version: u8) -> bincode::Result<Vec<u8>> {
+    let options = bincode::DefaultOptions::new().with_fixint_encoding();
+
+    if version == 1 {
+        match v1::Message::from_current(item) {
+            Some(msg) => options.serialize(&msg),
+            None => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "{:?} not supported by protocol version 1",
+                item
+            )))),
         }
+    } else {
+        options.serialize(item)
     }
 }
 
@@ -84,6 +240,7 @@ mod tests {
     use futures::io::Cursor;
 
     use super::*;
+    use crate::cfg::{BotConfiguration, ExchangeConfig};
     use futures::{SinkExt, StreamExt};
 
     #[async_std::test]
@@ -91,7 +248,7 @@ mod tests {
         let mut bytes = Vec::with_capacity(1024);
         let writer = Cursor::new(&mut bytes);
 
-        let mut framed = Framed::new(writer, BotvanaCodec);
+        let mut framed = Framed::new(writer, BotvanaCodec::with_version(1));
         let hello = Message::Hello(BotId(333), BotMetadata::new(1));
         framed.send(hello).await.unwrap();
 
@@ -102,7 +259,243 @@ mod tests {
     async fn framed_reader() {
         let bytes: Vec<_> = [1, 10, 0, 0, 0, 0, 0, 0, 0, 77, 1, 1, 0, 0, 0].into();
         let reader = Cursor::new(&bytes);
-        let mut framed = Framed::new(reader, BotvanaCodec);
+        let mut framed = Framed::new(reader, BotvanaCodec::new());
         while let Some(_frame) = framed.next().await.transpose().expect("Failed to read") {}
     }
+
+    #[async_std::test]
+    async fn framed_writer_current_version() {
+        let mut bytes = Vec::with_capacity(1024);
+        let writer = Cursor::new(&mut bytes);
+
+        let mut framed = Framed::new(writer, BotvanaCodec::new());
+        framed.send(Message::hello(BotId(333))).await.unwrap();
+
+        assert_eq!(bytes[0], PROTOCOL_VERSION as u8);
+    }
+
+    #[test]
+    fn test_decode_downgrades_version() {
+        let mut codec = BotvanaCodec::new();
+        let mut buf = [1, 10, 0, 0, 0, 0, 0, 0, 0, 77, 1, 1, 0, 0, 0];
+
+        let (consumed, _) = codec.decode(&mut buf);
+
+        assert_eq!(consumed, buf.len());
+        assert_eq!(codec.version(), 1);
+    }
+
+    /// Frames as written by the original unversioned codec
+    #[test]
+    fn test_decode_baseline_frames() {
+        // Configuration of bot 1 without peers, exchanges, markets and
+        // indicators
+        let mut config = vec![1, 38, 0, 0, 0, 1, 0, 0, 0, 1, 0];
+        config.extend_from_slice(&[0; 32]);
+        // Ping
+        let mut ping = vec![1, 20, 0, 0, 0, 3, 0, 0, 0];
+        ping.extend_from_slice(&[7; 16]);
+        // Status report placeholder
+        let mut status_report = vec![1, 4, 0, 0, 0, 8, 0, 0, 0];
+
+        let mut codec = BotvanaCodec::new();
+
+        let (consumed, result) = codec.decode(&mut config);
+        assert_eq!(consumed, config.len());
+        assert!(matches!(
+            result,
+            DecodeResult::Ok(Message::BotConfiguration(BotConfiguration {
+                bot_id: BotId(1),
+                recorder: None,
+                ..
+            }))
+        ));
+
+        let (consumed, result) = codec.decode(&mut ping);
+        assert_eq!(consumed, ping.len());
+        assert!(matches!(result, DecodeResult::Ok(Message::Ping(_))));
+
+        let (consumed, result) = codec.decode(&mut status_report);
+        assert_eq!(consumed, status_report.len());
+        assert!(matches!(
+            result,
+            DecodeResult::Err(DecodeError::UnsupportedMessage(1))
+        ));
+    }
+
+    /// Version 1 bot says hello and receives its configuration in the
+    /// original layout
+    #[test]
+    fn test_version_1_hello_configuration_exchange() {
+        let mut bot = BotvanaCodec::with_version(1);
+        let mut server = BotvanaCodec::new();
+        let mut buf = [0; 128];
+
+        let size = match bot.encode(&Message::Hello(BotId(1), BotMetadata::new(1)), &mut buf) {
+            EncodeResult::Ok(size) => size,
+            _ => panic!("failed to encode hello"),
+        };
+        assert_eq!(&buf[..size], [1, 10, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0]);
+
+        let (consumed, result) = server.decode(&mut buf[..size]);
+        assert_eq!(consumed, size);
+        let version = match result {
+            DecodeResult::Ok(Message::Hello(BotId(1), metadata)) => {
+                negotiate_version(metadata.bot_version)
+            }
+            _ => panic!("failed to decode hello"),
+        };
+        assert_eq!(version, Some(1));
+        assert_eq!(server.version(), 1);
+
+        let config = BotConfiguration {
+            bot_id: BotId(1),
+            peer_bots: Box::new([]),
+            exchanges: Box::new([]),
+            markets: Box::new([]),
+            indicators: Box::new([]),
+            exchange_configs: Box::new([ExchangeConfig::new("ftx")]),
+            recorder: None,
+            strategy: None,
+            topology: None,
+        };
+        let size = match server.encode(&Message::BotConfiguration(config), &mut buf) {
+            EncodeResult::Ok(size) => size,
+            _ => panic!("failed to encode configuration"),
+        };
+
+        // Only the fields of the original configuration are sent
+        let mut expected = vec![1, 38, 0, 0, 0, 1, 0, 0, 0, 1, 0];
+        expected.extend_from_slice(&[0; 32]);
+        assert_eq!(&buf[..size], expected);
+
+        let (consumed, result) = bot.decode(&mut buf[..size]);
+        assert_eq!(consumed, size);
+        assert!(matches!(
+            result,
+            DecodeResult::Ok(Message::BotConfiguration(BotConfiguration {
+                bot_id: BotId(1),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_encode_rejects_newer_message() {
+        let mut codec = BotvanaCodec::with_version(1);
+        let mut buf = [0; 64];
+
+        assert!(matches!(
+            codec.encode(&Message::capabilities(Capabilities::NONE), &mut buf),
+            EncodeResult::Err(())
+        ));
+    }
+
+    #[test]
+    fn test_decode_invalid_version() {
+        let mut codec = BotvanaCodec::new();
+        let mut buf = [9, 10, 0, 0, 0, 0, 0, 0, 0, 77, 1, 1, 0, 0, 0];
+
+        let (consumed, result) = codec.decode(&mut buf);
+
+        assert_eq!(consumed, buf.len());
+        assert!(matches!(
+            result,
+            DecodeResult::Err(DecodeError::InvalidVersion(9))
+        ));
+    }
+
+    #[test]
+    fn test_decode_frame_too_large() {
+        let mut codec = BotvanaCodec::new().with_max_frame_size(8);
+        let mut buf = [1, 10, 0, 0, 0, 0, 0];
+
+        let (consumed, result) = codec.decode(&mut buf);
+
+        assert_eq!(consumed, buf.len());
+        assert!(matches!(
+            result,
+            DecodeResult::Err(DecodeError::FrameTooLarge { size: 10, max: 8 })
+        ));
+
+        // Bogus 4GB length prefix is rejected before buffering the payload
+        let mut codec = BotvanaCodec::new();
+        let mut buf = [1, 255, 255, 255, 255, 0];
+
+        assert!(matches!(
+            codec.decode(&mut buf).1,
+            DecodeResult::Err(DecodeError::FrameTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_malformed_payload() {
+        let mut codec = BotvanaCodec::new();
+        // Unknown message variant
+        let mut buf = [2, 4, 0, 0, 0, 255, 0, 0, 0, 1, 2, 3];
+
+        let (consumed, result) = codec.decode(&mut buf);
+
+        assert_eq!(consumed, 9);
+        assert!(matches!(
+            result,
+            DecodeResult::Err(DecodeError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_empty_payload() {
+        let mut codec = BotvanaCodec::new();
+        let mut buf = [2, 0, 0, 0, 0];
+
+        let (consumed, result) = codec.decode(&mut buf);
+
+        // The whole frame is there, it's the payload that's invalid
+        assert_eq!(consumed, HEADER_LEN);
+        assert!(matches!(
+            result,
+            DecodeResult::Err(DecodeError::Deserialize(_))
+        ));
+    }
+
+    /// Decodes pseudo-random and mutated frames, the codec must never panic
+    /// and never consume more than the buffer
+    #[test]
+    fn test_decode_random_frames() {
+        let mut rng = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            rng
+        };
+        let mut valid = vec![0; 64];
+        let valid_len = match BotvanaCodec::new().encode(&Message::hello(BotId(1)), &mut valid) {
+            EncodeResult::Ok(len) => len,
+            _ => panic!("failed to encode"),
+        };
+        valid.truncate(valid_len);
+
+        for i in 0..100_000 {
+            let mut buf = if i % 2 == 0 {
+                let len = (next() % 64) as usize;
+                let mut buf: Vec<_> = (0..len).map(|_| next() as u8).collect();
+                // Keep the version valid to exercise the payload decoding
+                if let Some(version) = buf.first_mut() {
+                    *version = 1 + (*version % PROTOCOL_VERSION as u8);
+                }
+                buf
+            } else {
+                let mut buf = valid.clone();
+                let pos = (next() as usize) % buf.len();
+                buf[pos] = next() as u8;
+                buf
+            };
+            let mut codec = BotvanaCodec::new().with_max_frame_size(1024);
+
+            let (consumed, _) = codec.decode(&mut buf);
+
+            assert!(consumed <= buf.len());
+        }
+    }
 }
@@ -0,0 +1,121 @@
+//! Websocket gateway protocol
+//!
+//! Clients send [`ClientMessage`] to subscribe to channels. For each
+//! subscription the server first sends [`ServerMessage::Snapshot`] with the
+//! full state of the channel, followed by [`ServerMessage::Update`] whenever
+//! the state changes. All messages are JSON encoded.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    exchange::ExchangeId,
+    market::{orderbook::*, trade::Trade, Market},
+    net::msg::BotId,
+    state::{BotInfo, PositionPnl},
+};
+
+/// Channel a client can subscribe to
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Channel {
+    /// Connected bots and their engines
+    Bots,
+    /// Known markets
+    Markets,
+    /// Orderbook of an exchange market
+    Orderbook {
+        exchange: ExchangeId,
+        market: Box<str>,
+    },
+    /// Trades of an exchange market
+    Trades {
+        exchange: ExchangeId,
+        market: Box<str>,
+    },
+    /// Positions and PnL of a bot
+    Pnl { bot_id: BotId },
+}
+
+/// Message sent by gateway client
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe { channel: Channel },
+    Unsubscribe { channel: Channel },
+}
+
+/// Message sent by the gateway
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Subscription was accepted, snapshot follows
+    Subscribed { channel: Channel },
+    /// Subscription was removed
+    Unsubscribed { channel: Channel },
+    /// Full state of the channel
+    ///
+    /// Sent after subscribing and whenever the client fell too far behind to
+    /// receive updates.
+    Snapshot { channel: Channel, data: ChannelData },
+    /// Change of the channel state since the last message
+    Update { channel: Channel, data: ChannelData },
+    /// Client message could not be processed
+    Error { message: String },
+}
+
+/// Channel payload
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum ChannelData {
+    Bots(Vec<BotInfo>),
+    Markets(Vec<Market>),
+    Orderbook(PlainOrderbook<f64>),
+    /// Changed price levels, removed levels have zero size
+    OrderbookDelta {
+        bids: PriceLevelsVec<f64>,
+        asks: PriceLevelsVec<f64>,
+        time: f64,
+    },
+    Trades(Vec<Trade>),
+    Pnl(Vec<PositionPnl>),
+}
+
+impl ClientMessage {
+    pub fn subscribe(channel: Channel) -> Self {
+        ClientMessage::Subscribe { channel }
+    }
+
+    pub fn unsubscribe(channel: Channel) -> Self {
+        ClientMessage::Unsubscribe { channel }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_message_format() {
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"op":"subscribe","channel":{"type":"orderbook","exchange":"Ftx","market":"BTC/USD"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            msg,
+            ClientMessage::subscribe(Channel::Orderbook {
+                exchange: ExchangeId::Ftx,
+                market: Box::from("BTC/USD"),
+            })
+        );
+
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"op":"unsubscribe","channel":{"type":"pnl","bot_id":1}}"#)
+                .unwrap();
+
+        assert_eq!(
+            msg,
+            ClientMessage::unsubscribe(Channel::Pnl { bot_id: BotId(1) })
+        );
+    }
+}
@@ -1,14 +1,45 @@
-use std::{num::ParseIntError, str::FromStr, time::SystemTime};
+//! Botvana protocol messages
+//!
+//! # Schema evolution
+//!
+//! Messages are bincode encoded, so variants are identified by their
+//! position. Variants and their payloads are never reordered, removed or
+//! changed within a protocol version. New variants are appended and
+//! [`Message::min_version`] records the protocol version that introduced
+//! them, the codec refuses to send them to peers negotiated to an older
+//! version. Optional features within a version are announced with
+//! [`Capabilities`].
+//!
+//! The protocol version is negotiated during `Hello`: the bot sends the
+//! highest version it supports in [`BotMetadata::bot_version`] and the lower
+//! of that and [`PROTOCOL_VERSION`] is used for the connection.
+//!
+//! Version 1 is the original unversioned protocol, its bots can't
+//! authenticate. The codec encodes and decodes version 1 frames with the
+//! layout in [`super::v1`], so only the messages it had reach such bots.
+
+use std::{num::ParseIntError, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
+use super::auth::{Nonce, Signature};
 use crate::{
     cfg::BotConfiguration,
-    market::{orderbook::*, MarketVec},
+    clock::Clock,
+    engine::{EngineExit, EngineHealth, EngineStatus, EngineType},
+    exchange::{ExchangeId, Fill},
+    market::{orderbook::*, trade::Trade, MarketVec},
+    report::{Metrics, StatusReport},
 };
 
+/// Current protocol version
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version still supported
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
 /// Botvana protocol message
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Message {
     /// Hello message
     ///
@@ -35,32 +66,159 @@ pub enum Message {
     MarketList(MarketVec),
     /// List of markets that the bot has access to
     Orderbook(Orderbook<f64>),
-    /// A set of metrics
-    Metrics,
-    /// Status report
-    StatusReport,
+    /// Metrics collected by the bot
+    ///
+    /// Sent periodically by bots when both peers support
+    /// [`Capabilities::REPORTS`]. Since protocol version 2.
+    Metrics(Metrics),
+    /// Status report of the bot
+    ///
+    /// Sent periodically by bots when both peers support
+    /// [`Capabilities::REPORTS`]. Since protocol version 2.
+    StatusReport(StatusReport),
+    /// Trades that happened on an exchange market
+    ///
+    /// Since protocol version 2.
+    Trades(ExchangeId, Box<str>, Box<[Trade]>),
+    /// Fill of an order placed by the bot
+    ///
+    /// Since protocol version 2.
+    Fill(Fill),
+    /// Status of a bot engine changed
+    ///
+    /// Since protocol version 2.
+    EngineStatus(EngineType, EngineStatus),
+    /// Authentication challenge
+    ///
+    /// Server responds to `Hello` with a random nonce the bot has to sign
+    /// using its shared secret. Since protocol version 2.
+    AuthChallenge(Nonce),
+    /// Authentication response
+    ///
+    /// Signature of the challenge, see [`super::auth`]. Since protocol
+    /// version 2.
+    AuthResponse(Signature),
+    /// Capabilities of the sender
+    ///
+    /// Sent by the server after the configuration, the bot responds with its
+    /// own capabilities. Since protocol version 2.
+    Capabilities(Capabilities),
+    /// Updated bot configuration with its version
+    ///
+    /// Sent by the server to bots supporting
+    /// [`Capabilities::CONFIG_UPDATES`], the bot applies it without
+    /// reconnecting. Since protocol version 2.
+    ConfigUpdate(u64, BotConfiguration),
+    /// Bot applied configuration with given version
+    ///
+    /// Since protocol version 2.
+    ConfigAck(u64),
+    /// Command for the bot to execute
+    ///
+    /// Sent by the server to bots supporting [`Capabilities::COMMANDS`], the
+    /// bot replies with `CommandResult` of the same id. Since protocol
+    /// version 2.
+    Command(u64, Command),
+    /// Result of the command with given id
+    ///
+    /// Since protocol version 2.
+    CommandResult(u64, CommandResult),
+    /// Engine of the bot exited
+    ///
+    /// Since protocol version 2.
+    EngineExit(EngineExit),
+    /// Engine of the bot stalled or recovered
+    ///
+    /// Since protocol version 2.
+    EngineHealth(EngineHealth),
 }
 
 impl Message {
-    /// Creates new Hello message
+    /// Creates new Hello message announcing the current protocol version
     pub fn hello(bot_id: BotId) -> Self {
-        Message::Hello(bot_id, BotMetadata::new(1))
+        Message::Hello(bot_id, BotMetadata::new(PROTOCOL_VERSION))
     }
 
-    /// Returns new ping message with current time
-    pub fn ping() -> Self {
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(n) => Message::Ping(n.as_nanos()),
-            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+    /// Returns protocol version that introduced the message
+    pub fn min_version(&self) -> u32 {
+        match self {
+            Message::Hello(..)
+            | Message::BotConfiguration(_)
+            | Message::BotError(_)
+            | Message::Ping(_)
+            | Message::Pong(_)
+            | Message::MarketList(_)
+            | Message::Orderbook(_) => 1,
+            Message::Metrics(_)
+            | Message::StatusReport(_)
+            | Message::Trades(..)
+            | Message::Fill(_)
+            | Message::EngineStatus(..)
+            | Message::AuthChallenge(_)
+            | Message::AuthResponse(_)
+            | Message::Capabilities(_)
+            | Message::ConfigUpdate(..)
+            | Message::ConfigAck(_)
+            | Message::Command(..)
+            | Message::CommandResult(..)
+            | Message::EngineExit(_)
+            | Message::EngineHealth(_) => 2,
         }
     }
 
-    /// Returns new ping message with current time
-    pub fn pong() -> Self {
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(n) => Message::Pong(n.as_nanos()),
-            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-        }
+    /// Returns new capabilities message
+    pub fn capabilities(capabilities: Capabilities) -> Self {
+        Message::Capabilities(capabilities)
+    }
+
+    /// Returns new configuration update message
+    pub fn config_update(version: u64, config: BotConfiguration) -> Self {
+        Message::ConfigUpdate(version, config)
+    }
+
+    /// Returns acknowledgement of applied configuration version
+    pub fn config_ack(version: u64) -> Self {
+        Message::ConfigAck(version)
+    }
+
+    /// Returns new command message
+    pub fn command(id: u64, command: Command) -> Self {
+        Message::Command(id, command)
+    }
+
+    /// Returns result of the command with given id
+    pub fn command_result(id: u64, result: CommandResult) -> Self {
+        Message::CommandResult(id, result)
+    }
+
+    /// Returns new metrics message
+    pub fn metrics(metrics: Metrics) -> Self {
+        Message::Metrics(metrics)
+    }
+
+    /// Returns new status report message
+    pub fn status_report(report: StatusReport) -> Self {
+        Message::StatusReport(report)
+    }
+
+    /// Returns new authentication challenge message
+    pub fn auth_challenge(nonce: Nonce) -> Self {
+        Message::AuthChallenge(nonce)
+    }
+
+    /// Returns response to the challenge signed by the secret of the bot
+    pub fn auth_response(secret: &[u8], bot_id: &BotId, nonce: &Nonce) -> Self {
+        Message::AuthResponse(super::auth::sign(secret, bot_id, nonce))
+    }
+
+    /// Returns new ping message with current time of the clock
+    pub fn ping(clock: &dyn Clock) -> Self {
+        Message::Ping(clock.unix_nanos())
+    }
+
+    /// Returns new pong message with current time of the clock
+    pub fn pong(clock: &dyn Clock) -> Self {
+        Message::Pong(clock.unix_nanos())
     }
 
     /// Returns new markets list message with given markets
@@ -72,6 +230,31 @@ impl Message {
     pub fn orderbook(orderbook: Orderbook<f64>) -> Self {
         Self::Orderbook(orderbook)
     }
+
+    /// Returns new trades message
+    pub fn trades(exchange: ExchangeId, market: Box<str>, trades: Box<[Trade]>) -> Self {
+        Self::Trades(exchange, market, trades)
+    }
+
+    /// Returns new fill message
+    pub fn fill(fill: Fill) -> Self {
+        Self::Fill(fill)
+    }
+
+    /// Returns new engine status message
+    pub fn engine_status(engine: EngineType, status: EngineStatus) -> Self {
+        Self::EngineStatus(engine, status)
+    }
+
+    /// Returns new engine exit message
+    pub fn engine_exit(exit: EngineExit) -> Self {
+        Self::EngineExit(exit)
+    }
+
+    /// Returns new engine health message
+    pub fn engine_health(health: EngineHealth) -> Self {
+        Self::EngineHealth(health)
+    }
 }
 
 /// Unique ID representing bot
@@ -87,8 +270,11 @@ impl FromStr for BotId {
 }
 
 /// Metadata associated to connected bot
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// The layout is part of `Hello` and must stay the same in all versions.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct BotMetadata {
+    /// Highest protocol version supported by the bot
     pub bot_version: u32,
 }
 
@@ -98,8 +284,74 @@ impl BotMetadata {
     }
 }
 
+/// Returns protocol version used with a peer supporting `peer_version`
+///
+/// Returns `None` when the peer is too old.
+pub fn negotiate_version(peer_version: u32) -> Option<u32> {
+    let version = peer_version.min(PROTOCOL_VERSION);
+
+    if version >= MIN_PROTOCOL_VERSION {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+/// Set of optional protocol features supported by a peer
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Capabilities(pub u64);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+
+    /// Configuration can be updated without reconnecting
+    pub const CONFIG_UPDATES: Capabilities = Capabilities(1 << 0);
+
+    /// Bot executes commands sent by the server
+    pub const COMMANDS: Capabilities = Capabilities(1 << 1);
+
+    /// Bot periodically sends status reports and metrics
+    pub const REPORTS: Capabilities = Capabilities(1 << 2);
+
+    /// Capabilities supported by this build
+    pub const SUPPORTED: Capabilities = Capabilities(
+        Capabilities::CONFIG_UPDATES.0 | Capabilities::COMMANDS.0 | Capabilities::REPORTS.0,
+    );
+
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns capabilities supported by both peers
+    pub fn intersection(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+/// Command sent by the server for the bot to execute
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Command {
+    /// Stops the strategy from reacting to market data
+    PauseTrading,
+    /// Resumes paused strategy
+    ResumeTrading,
+    /// Cancels all open orders
+    CancelAll,
+    /// Cancels all open orders, pauses trading and closes open positions
+    FlattenPositions,
+    /// Replaces the log filter, e.g. `info,botnode=debug`
+    SetLogLevel(Box<str>),
+    /// Restarts given engine
+    RestartEngine(EngineType),
+    /// Returns status of the bot and its engines
+    StatusDump,
+}
+
+/// Output of successfully executed command or the error
+pub type CommandResult = Result<Box<str>, Box<str>>;
+
 /// Enum of possible errors reported by botnode
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum BotError {
     ConfigurationError(String),
 }
@@ -107,6 +359,7 @@ pub enum BotError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::report::{EngineReport, MarketDataMetrics, Percentiles, QueueDepth};
 
     #[test]
     fn ser_deser_hello() {
@@ -133,6 +386,10 @@ mod tests {
             exchanges: Box::from([Box::from("ftx")]),
             markets: Box::from([Box::from("BTC/USD")]),
             indicators: Box::new([]),
+            exchange_configs: Box::new([]),
+            recorder: None,
+            strategy: None,
+            topology: None,
         });
         let encoded = bincode::serialize(&hello).unwrap();
         let decoded: Message = bincode::deserialize(&encoded).unwrap();
@@ -146,4 +403,181 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn ser_deser_config_update() {
+        let update = Message::config_update(
+            3,
+            BotConfiguration {
+                bot_id: BotId(1),
+                peer_bots: Box::new([]),
+                exchanges: Box::from([Box::from("ftx")]),
+                markets: Box::from([Box::from("ETH/USD")]),
+                indicators: Box::new([]),
+                exchange_configs: Box::new([]),
+                recorder: None,
+                strategy: None,
+                topology: None,
+            },
+        );
+        let encoded = bincode::serialize(&update).unwrap();
+        let decoded: Message = bincode::deserialize(&encoded).unwrap();
+
+        match decoded {
+            Message::ConfigUpdate(version, BotConfiguration { markets, .. }) => {
+                assert_eq!(version, 3);
+                assert_eq!(&*markets, &[Box::from("ETH/USD")]);
+            }
+            _ => {
+                panic!("unexpected message deserialized");
+            }
+        }
+        assert_eq!(Message::config_ack(3).min_version(), 2);
+    }
+
+    #[test]
+    fn ser_deser_command() {
+        let commands = [
+            Message::command(1, Command::CancelAll),
+            Message::command(
+                2,
+                Command::RestartEngine(EngineType::MarketDataEngine(ExchangeId::Ftx)),
+            ),
+            Message::command_result(2, Err(Box::from("failed"))),
+        ];
+
+        for msg in commands {
+            let encoded = bincode::serialize(&msg).unwrap();
+            let decoded: Message = bincode::deserialize(&encoded).unwrap();
+
+            assert_eq!(format!("{decoded:?}"), format!("{msg:?}"));
+            assert_eq!(decoded.min_version(), 2);
+        }
+    }
+
+    #[test]
+    fn ser_deser_reports() {
+        let reports = [
+            Message::status_report(StatusReport {
+                time: None,
+                engines: vec![EngineReport {
+                    engine: EngineType::TradingEngine,
+                    status: EngineStatus::Running,
+                }],
+                server_reconnects: 2,
+            }),
+            Message::metrics(Metrics {
+                time: None,
+                market_data: vec![MarketDataMetrics {
+                    exchange: ExchangeId::Ftx,
+                    messages: 100,
+                    max_throughput: 20,
+                    latency: Percentiles {
+                        count: 100,
+                        p50: 800,
+                        p90: 1200,
+                        p99: 3000,
+                        max: 5000,
+                    },
+                    reconnects: 1,
+                    queues: vec![QueueDepth {
+                        name: Box::from("market-data-ftx-0"),
+                        depth: 3,
+                        capacity: 512,
+                    }],
+                }],
+            }),
+        ];
+
+        for msg in reports {
+            let encoded = bincode::serialize(&msg).unwrap();
+            let decoded: Message = bincode::deserialize(&encoded).unwrap();
+
+            assert_eq!(format!("{decoded:?}"), format!("{msg:?}"));
+            assert_eq!(decoded.min_version(), 2);
+        }
+    }
+
+    #[test]
+    fn ser_deser_engine_exit() {
+        let msg = Message::engine_exit(EngineExit {
+            engine: EngineType::MarketDataEngine(ExchangeId::Ftx),
+            reason: Box::from("engine reported error"),
+            restarts: 2,
+            restart_in_ms: Some(4000),
+        });
+        let encoded = bincode::serialize(&msg).unwrap();
+        let decoded: Message = bincode::deserialize(&encoded).unwrap();
+
+        assert_eq!(format!("{decoded:?}"), format!("{msg:?}"));
+        assert_eq!(decoded.min_version(), 2);
+    }
+
+    #[test]
+    fn ser_deser_engine_health() {
+        let msg = Message::engine_health(EngineHealth {
+            engine: EngineType::TradingEngine,
+            stalled: true,
+            iterations: 1_000_000,
+            heartbeat_age_ms: 5000,
+            last_event_age_ms: Some(5200),
+        });
+        let encoded = bincode::serialize(&msg).unwrap();
+        let decoded: Message = bincode::deserialize(&encoded).unwrap();
+
+        assert_eq!(format!("{decoded:?}"), format!("{msg:?}"));
+        assert_eq!(decoded.min_version(), 2);
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 1),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate_version(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(
+            negotiate_version(MIN_PROTOCOL_VERSION),
+            Some(MIN_PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION - 1), None);
+    }
+
+    #[test]
+    fn test_hello_layout_is_stable() {
+        let encoded = bincode::serialize(&Message::Hello(BotId(1), BotMetadata::new(7))).unwrap();
+
+        assert_eq!(encoded, [0, 0, 0, 0, 1, 0, 7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_baseline_variant_tags_are_stable() {
+        let tag = |msg: &Message| bincode::serialize(msg).unwrap()[..4].to_vec();
+
+        assert_eq!(tag(&Message::hello(BotId(1))), [0, 0, 0, 0]);
+        assert_eq!(
+            tag(&Message::BotError(BotError::ConfigurationError(
+                String::new()
+            ))),
+            [2, 0, 0, 0]
+        );
+        assert_eq!(tag(&Message::Ping(1)), [3, 0, 0, 0]);
+        assert_eq!(tag(&Message::Pong(1)), [4, 0, 0, 0]);
+        assert_eq!(tag(&Message::metrics(Metrics::default())), [7, 0, 0, 0]);
+        assert_eq!(
+            tag(&Message::status_report(StatusReport::default())),
+            [8, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let a = Capabilities(0b011);
+        let b = Capabilities(0b110);
+
+        assert_eq!(a.intersection(b), Capabilities(0b010));
+        assert!(a.contains(Capabilities(0b001)));
+        assert!(!a.contains(b));
+        assert!(a.contains(Capabilities::NONE));
+    }
 }
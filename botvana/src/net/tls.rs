@@ -0,0 +1,321 @@
+//! TLS for the botnode to server connection
+//!
+//! The server always presents its certificate and optionally requires client
+//! certificates signed by a given CA (mutual TLS). Certificates and keys are
+//! loaded from PEM files.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_io::{AsyncRead, AsyncWrite};
+pub use futures_rustls::{client, rustls, rustls::ServerName, server, TlsAcceptor, TlsConnector};
+use rustls::{server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore};
+use rustls_pemfile::Item;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Failed to read {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("No private key found in {0}")]
+    NoPrivateKey(PathBuf),
+    #[error("Invalid TLS configuration: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("Invalid certificate: {0}")]
+    Certificate(#[from] webpki::Error),
+}
+
+/// Returns config of TLS server presenting given certificate
+///
+/// Clients are required to present certificate signed by `client_ca` when
+/// given.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<rustls::ServerConfig>, TlsError> {
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(client_ca) => builder
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(root_store(client_ca)?)),
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Arc::new(
+        builder.with_single_cert(load_certs(cert)?, load_key(key)?)?,
+    ))
+}
+
+/// Returns config of TLS client trusting servers signed by `ca`
+///
+/// The client presents given certificate and key when the server requires
+/// client authentication.
+pub fn client_config(
+    ca: &Path,
+    identity: Option<(&Path, &Path)>,
+) -> Result<Arc<rustls::ClientConfig>, TlsError> {
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store(ca)?);
+    let config = match identity {
+        Some((cert, key)) => builder.with_single_cert(load_certs(cert)?, load_key(key)?)?,
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Arc::new(config))
+}
+
+fn root_store(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(&cert)?;
+    }
+
+    Ok(roots)
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).map_err(|source| TlsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let items = rustls_pemfile::read_all(&mut open(path)?).map_err(|source| TlsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| TlsError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Stream that is either plain or TLS encrypted
+pub enum MaybeTlsStream<S> {
+    Plain(S),
+    Server(Box<server::TlsStream<S>>),
+    Client(Box<client::TlsStream<S>>),
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for MaybeTlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeTlsStream::Server(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeTlsStream::Client(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MaybeTlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeTlsStream::Server(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeTlsStream::Client(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(s) => Pin::new(s).poll_flush(cx),
+            MaybeTlsStream::Server(s) => Pin::new(s).poll_flush(cx),
+            MaybeTlsStream::Client(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(s) => Pin::new(s).poll_close(cx),
+            MaybeTlsStream::Server(s) => Pin::new(s).poll_close(cx),
+            MaybeTlsStream::Client(s) => Pin::new(s).poll_close(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::{SocketAddr, TcpListener, TcpStream};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa};
+
+    /// CA with server and client certificates it signed, written as PEM files
+    struct TestPki {
+        dir: PathBuf,
+    }
+
+    impl TestPki {
+        fn generate(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("botvana-tls-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = Certificate::from_params(params).unwrap();
+            std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+            for (name, usage) in [
+                ("localhost", ExtendedKeyUsagePurpose::ServerAuth),
+                ("botnode", ExtendedKeyUsagePurpose::ClientAuth),
+            ] {
+                let mut params = CertificateParams::new(vec![name.to_string()]);
+                params.extended_key_usages = vec![usage];
+                let cert = Certificate::from_params(params).unwrap();
+
+                std::fs::write(
+                    dir.join(format!("{name}.pem")),
+                    cert.serialize_pem_with_signer(&ca).unwrap(),
+                )
+                .unwrap();
+                std::fs::write(
+                    dir.join(format!("{name}.key")),
+                    cert.serialize_private_key_pem(),
+                )
+                .unwrap();
+            }
+
+            Self { dir }
+        }
+
+        fn path(&self, file: &str) -> PathBuf {
+            self.dir.join(file)
+        }
+
+        fn server(&self, mutual: bool) -> Arc<rustls::ServerConfig> {
+            let client_ca = self.path("ca.pem");
+
+            server_config(
+                &self.path("localhost.pem"),
+                &self.path("localhost.key"),
+                mutual.then(|| client_ca.as_path()),
+            )
+            .unwrap()
+        }
+
+        fn client(&self, identity: bool) -> Arc<rustls::ClientConfig> {
+            let (cert, key) = (self.path("botnode.pem"), self.path("botnode.key"));
+
+            client_config(
+                &self.path("ca.pem"),
+                identity.then(|| (cert.as_path(), key.as_path())),
+            )
+            .unwrap()
+        }
+    }
+
+    impl Drop for TestPki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Accepts single connection, reads ping and replies with pong
+    async fn serve(listener: TcpListener, config: Arc<rustls::ServerConfig>) -> io::Result<()> {
+        let (stream, _) = listener.accept().await?;
+        let stream = TlsAcceptor::from(config).accept(stream).await?;
+        let mut stream = MaybeTlsStream::Server(Box::new(stream));
+        let mut buf = [0; 4];
+
+        stream.read_exact(&mut buf).await?;
+        stream.write_all(b"pong").await?;
+        stream.flush().await
+    }
+
+    /// Connects to the server, sends ping and returns the reply
+    async fn ping(addr: SocketAddr, config: Arc<rustls::ClientConfig>) -> io::Result<[u8; 4]> {
+        let stream = TcpStream::connect(addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let stream = TlsConnector::from(config)
+            .connect(server_name, stream)
+            .await?;
+        let mut stream = MaybeTlsStream::Client(Box::new(stream));
+        let mut buf = [0; 4];
+
+        stream.write_all(b"ping").await?;
+        stream.flush().await?;
+        stream.read_exact(&mut buf).await?;
+
+        Ok(buf)
+    }
+
+    async fn handshake(
+        server: Arc<rustls::ServerConfig>,
+        client: Arc<rustls::ClientConfig>,
+    ) -> (io::Result<()>, io::Result<[u8; 4]>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        futures::join!(serve(listener, server), ping(addr, client))
+    }
+
+    #[async_std::test]
+    async fn test_handshake() {
+        let pki = TestPki::generate("handshake");
+        let (served, reply) = handshake(pki.server(false), pki.client(false)).await;
+
+        served.unwrap();
+        assert_eq!(&reply.unwrap(), b"pong");
+    }
+
+    #[async_std::test]
+    async fn test_mutual_tls() {
+        let pki = TestPki::generate("mutual");
+        let (served, reply) = handshake(pki.server(true), pki.client(true)).await;
+
+        served.unwrap();
+        assert_eq!(&reply.unwrap(), b"pong");
+
+        // Client without certificate is rejected
+        let (served, reply) = handshake(pki.server(true), pki.client(false)).await;
+
+        assert!(served.is_err());
+        assert!(reply.is_err());
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let missing = Path::new("does/not/exist.pem");
+
+        assert!(matches!(
+            server_config(missing, missing, None),
+            Err(TlsError::Io { .. })
+        ));
+        assert!(matches!(
+            client_config(missing, None),
+            Err(TlsError::Io { .. })
+        ));
+    }
+}
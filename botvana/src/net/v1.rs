@@ -0,0 +1,144 @@
+//! Messages of protocol version 1
+//!
+//! Version 1 is the original unversioned protocol and bots speaking it are
+//! still deployed. The codec decodes their frames with this layout and
+//! encodes the messages they understand with it.
+
+use serde::{Deserialize, Serialize};
+
+use super::msg::{self, BotError, BotId, BotMetadata};
+use crate::{
+    cfg::{self, IndicatorConfig, PeerBot},
+    market::{orderbook::Orderbook, MarketVec},
+};
+
+/// Botvana protocol message of version 1
+///
+/// The variants and their payloads must stay exactly as they were.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Message {
+    Hello(BotId, BotMetadata),
+    BotConfiguration(BotConfiguration),
+    BotError(BotError),
+    Ping(u128),
+    Pong(u128),
+    MarketList(MarketVec),
+    Orderbook(Orderbook<f64>),
+    /// Placeholder never sent
+    Metrics,
+    /// Placeholder never sent
+    StatusReport,
+}
+
+impl Message {
+    /// Returns the message in version 1 layout, `None` when version 1 has no
+    /// such message
+    pub fn from_current(msg: &msg::Message) -> Option<Self> {
+        let msg = match msg {
+            msg::Message::Hello(bot_id, metadata) => {
+                Message::Hello(bot_id.clone(), metadata.clone())
+            }
+            msg::Message::BotConfiguration(config) => {
+                Message::BotConfiguration(BotConfiguration::from(config))
+            }
+            msg::Message::BotError(error) => Message::BotError(error.clone()),
+            msg::Message::Ping(timestamp) => Message::Ping(*timestamp),
+            msg::Message::Pong(timestamp) => Message::Pong(*timestamp),
+            msg::Message::MarketList(markets) => Message::MarketList(markets.clone()),
+            msg::Message::Orderbook(orderbook) => Message::Orderbook(orderbook.clone()),
+            _ => return None,
+        };
+
+        Some(msg)
+    }
+
+    /// Returns the message in the current layout, `None` for the placeholders
+    pub fn into_current(self) -> Option<msg::Message> {
+        let msg = match self {
+            Message::Hello(bot_id, metadata) => msg::Message::Hello(bot_id, metadata),
+            Message::BotConfiguration(config) => msg::Message::BotConfiguration(config.into()),
+            Message::BotError(error) => msg::Message::BotError(error),
+            Message::Ping(timestamp) => msg::Message::Ping(timestamp),
+            Message::Pong(timestamp) => msg::Message::Pong(timestamp),
+            Message::MarketList(markets) => msg::Message::MarketList(markets),
+            Message::Orderbook(orderbook) => msg::Message::Orderbook(orderbook),
+            Message::Metrics | Message::StatusReport => return None,
+        };
+
+        Some(msg)
+    }
+}
+
+/// Bot configuration of version 1, without the settings added since
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BotConfiguration {
+    pub bot_id: BotId,
+    pub peer_bots: Box<[PeerBot]>,
+    pub exchanges: Box<[Box<str>]>,
+    pub markets: Box<[Box<str>]>,
+    pub indicators: Box<[IndicatorConfig]>,
+}
+
+impl From<&cfg::BotConfiguration> for BotConfiguration {
+    fn from(config: &cfg::BotConfiguration) -> Self {
+        Self {
+            bot_id: config.bot_id.clone(),
+            peer_bots: config.peer_bots.clone(),
+            exchanges: config.exchanges.clone(),
+            markets: config.markets.clone(),
+            indicators: config.indicators.clone(),
+        }
+    }
+}
+
+impl From<BotConfiguration> for cfg::BotConfiguration {
+    fn from(config: BotConfiguration) -> Self {
+        Self {
+            bot_id: config.bot_id,
+            peer_bots: config.peer_bots,
+            exchanges: config.exchanges,
+            markets: config.markets,
+            indicators: config.indicators,
+            exchange_configs: Box::new([]),
+            recorder: None,
+            strategy: None,
+            topology: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baseline_layout() {
+        let hello = Message::Hello(BotId(333), BotMetadata::new(1));
+        let config = Message::BotConfiguration(BotConfiguration {
+            bot_id: BotId(1),
+            peer_bots: Box::new([]),
+            exchanges: Box::new([]),
+            markets: Box::new([]),
+            indicators: Box::new([]),
+        });
+
+        assert_eq!(
+            bincode::serialize(&hello).unwrap(),
+            [0, 0, 0, 0, 77, 1, 1, 0, 0, 0]
+        );
+
+        let mut expected = vec![1, 0, 0, 0, 1, 0];
+        expected.extend_from_slice(&[0; 32]);
+        assert_eq!(bincode::serialize(&config).unwrap(), expected);
+        assert_eq!(
+            bincode::serialize(&Message::StatusReport).unwrap(),
+            [8, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_newer_messages_have_no_version_1_layout() {
+        assert!(Message::from_current(&msg::Message::config_ack(1)).is_none());
+        assert!(Message::Metrics.into_current().is_none());
+    }
+}
@@ -0,0 +1,73 @@
+//! Status reports and metrics periodically sent by bots
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{EngineStatus, EngineType},
+    exchange::ExchangeId,
+};
+
+/// Status of the bot and its engines
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StatusReport {
+    /// Time the report was created at
+    pub time: Option<DateTime<Utc>>,
+    /// Status of each engine of the bot
+    pub engines: Vec<EngineReport>,
+    /// Number of times the bot reconnected to the server
+    pub server_reconnects: u64,
+}
+
+/// Last known status of an engine
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EngineReport {
+    pub engine: EngineType,
+    pub status: EngineStatus,
+}
+
+/// Metrics collected by the bot
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Metrics {
+    /// Time the metrics were collected at
+    pub time: Option<DateTime<Utc>>,
+    /// Metrics of each market data engine
+    pub market_data: Vec<MarketDataMetrics>,
+}
+
+/// Metrics of a market data engine over the last reporting window
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MarketDataMetrics {
+    pub exchange: ExchangeId,
+    /// Number of messages received from the exchange
+    pub messages: u64,
+    /// Maximum throughput in messages per second
+    pub max_throughput: u64,
+    /// Latency between exchange timestamp and receiving the event
+    pub latency: Percentiles,
+    /// Number of reconnects to the exchange since the engine started
+    pub reconnects: u64,
+    /// Depths of queues to consumers of the market data
+    pub queues: Vec<QueueDepth>,
+}
+
+/// Percentiles of a distribution in microseconds
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// Depth of a queue between engines
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueueDepth {
+    /// Name of the queue, e.g. `market-data-ftx-0`
+    pub name: Box<str>,
+    /// Number of items waiting in the queue
+    pub depth: usize,
+    /// Capacity of the queue
+    pub capacity: usize,
+}
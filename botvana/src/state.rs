@@ -1,22 +1,144 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
+use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    cfg::BotConfiguration,
+    engine::{EngineExit, EngineHealth, EngineStatus, EngineType},
     exchange::*,
-    market::{orderbook::*, MarketVec},
-    net::msg::BotId,
+    market::{orderbook::*, trade::Trade, MarketVec},
+    net::msg::{BotId, BotMetadata, Capabilities, Command, CommandResult},
+    report::{Metrics, StatusReport},
 };
 
 const SYMBOL_TABLE_CAP: u32 = 1024;
+/// Number of recent trades kept per market
+const TRADE_LOG_CAP: usize = 1024;
+/// Number of recent commands kept per bot
+const COMMAND_LOG_CAP: usize = 256;
+
+/// Details of a connected bot
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BotInfo {
+    pub bot_id: BotId,
+    pub metadata: BotMetadata,
+    pub connected_at: DateTime<Utc>,
+    /// Last reported status of each engine
+    pub engines: Vec<EngineInfo>,
+    /// Capabilities announced by the bot
+    pub capabilities: Capabilities,
+    /// Version of the configuration the bot runs with
+    pub config_version: u64,
+}
+
+impl BotInfo {
+    pub fn new(bot_id: BotId, metadata: BotMetadata) -> Self {
+        Self {
+            bot_id,
+            metadata,
+            connected_at: Utc::now(),
+            engines: Vec::new(),
+            capabilities: Capabilities::NONE,
+            config_version: 0,
+        }
+    }
+}
+
+/// Command issued to a bot
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CommandInfo {
+    pub id: u64,
+    pub command: Command,
+    pub issued_at: DateTime<Utc>,
+    /// Command was sent to the bot
+    pub sent: bool,
+    /// Result reported by the bot, `None` while pending
+    pub result: Option<CommandResult>,
+}
+
+/// Bot configuration with its version
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VersionedConfig {
+    /// Version starting from 1, incremented with each update
+    pub version: u64,
+    pub config: BotConfiguration,
+}
+
+/// Last reported status of bot engine
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EngineInfo {
+    pub engine: EngineType,
+    pub status: EngineStatus,
+    pub updated_at: DateTime<Utc>,
+    /// Number of times the engine was restarted by the bot
+    pub restarts: u32,
+    /// Reason of the last exit of the engine
+    pub last_exit: Option<Box<str>>,
+    /// The engine stopped sending heartbeats
+    pub stalled: bool,
+}
+
+/// Recent trades of a market
+#[derive(Debug, Default)]
+struct TradeLog {
+    /// Sequence number of the last trade, starting from 1
+    seq: u64,
+    trades: VecDeque<Trade>,
+}
+
+/// Trades returned by [`GlobalState::trades_since`]
+#[derive(Clone, Debug)]
+pub struct RecentTrades {
+    /// Sequence number of the last trade
+    pub seq: u64,
+    pub trades: Vec<Trade>,
+    /// Some trades after the requested sequence number are no longer kept
+    pub gap: bool,
+}
+
+/// Position of a bot accumulated from fills
+#[derive(Clone, Debug, Default)]
+struct Position {
+    position: f64,
+    cash: f64,
+    fees: f64,
+    last_price: f64,
+}
+
+/// Position and profit and loss of a bot in a market
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PositionPnl {
+    pub exchange: ExchangeId,
+    pub market: Box<str>,
+    pub position: f64,
+    pub fees: f64,
+    /// PnL net of fees, position is marked at the orderbook mid price when
+    /// known or at the last fill price
+    pub pnl: f64,
+}
 
 /// Global state held by botvana-server
 #[derive(Clone, Debug)]
 pub struct GlobalState {
-    connected_bots: Arc<RwLock<Vec<BotId>>>,
+    connected_bots: Arc<RwLock<Vec<BotInfo>>>,
     markets: Arc<RwLock<MarketVec>>,
     symbol_table: Arc<RwLock<MarketSymbolTable>>,
     orderbooks: Arc<RwLock<HashMap<(ExchangeId, u32), PlainOrderbook<f64>>>>,
+    trades: Arc<RwLock<HashMap<(ExchangeId, u32), TradeLog>>>,
+    positions: Arc<RwLock<HashMap<(BotId, ExchangeId, u32), Position>>>,
+    configs: Arc<RwLock<HashMap<BotId, VersionedConfig>>>,
+    commands: Arc<RwLock<HashMap<BotId, VecDeque<CommandInfo>>>>,
+    next_command_id: Arc<AtomicU64>,
+    status_reports: Arc<RwLock<HashMap<BotId, StatusReport>>>,
+    metrics: Arc<RwLock<HashMap<BotId, Metrics>>>,
 }
 
 impl GlobalState {
@@ -29,28 +151,265 @@ impl GlobalState {
                 SYMBOL_TABLE_CAP,
             ))),
             orderbooks: Arc::new(RwLock::new(HashMap::new())),
+            trades: Arc::new(RwLock::new(HashMap::new())),
+            positions: Arc::new(RwLock::new(HashMap::new())),
+            configs: Arc::new(RwLock::new(HashMap::new())),
+            commands: Arc::new(RwLock::new(HashMap::new())),
+            next_command_id: Arc::new(AtomicU64::new(1)),
+            status_reports: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
     /// Adds online bot
-    pub fn add_bot(&self, bot_id: BotId) {
+    pub fn add_bot(&self, bot_id: BotId, metadata: BotMetadata) {
         let mut bots = self.connected_bots.write();
 
-        bots.push(bot_id);
+        bots.push(BotInfo::new(bot_id, metadata));
     }
 
     /// Removes a bot (bot is offline)
     pub fn remove_bot(&self, bot_id: BotId) {
         let mut bots = self.connected_bots.write();
 
-        bots.retain(|id| *id != bot_id);
+        bots.retain(|bot| bot.bot_id != bot_id);
     }
 
     /// Returns set of connected bots
     pub fn connected_bots(&self) -> Vec<BotId> {
+        self.connected_bots
+            .read()
+            .iter()
+            .map(|bot| bot.bot_id.clone())
+            .collect()
+    }
+
+    /// Returns details of connected bots
+    pub fn bots(&self) -> Vec<BotInfo> {
         self.connected_bots.read().to_vec()
     }
 
+    /// Returns details of connected bot
+    pub fn bot(&self, bot_id: &BotId) -> Option<BotInfo> {
+        self.connected_bots
+            .read()
+            .iter()
+            .find(|bot| bot.bot_id == *bot_id)
+            .cloned()
+    }
+
+    /// Updates status of an engine of connected bot
+    pub fn update_engine_status(&self, bot_id: &BotId, engine: EngineType, status: EngineStatus) {
+        let mut bots = self.connected_bots.write();
+        let bot = match bots.iter_mut().find(|bot| bot.bot_id == *bot_id) {
+            Some(bot) => bot,
+            None => return,
+        };
+        let updated_at = Utc::now();
+
+        match bot.engines.iter_mut().find(|info| info.engine == engine) {
+            Some(info) => {
+                info.status = status;
+                info.updated_at = updated_at;
+            }
+            None => bot.engines.push(EngineInfo {
+                engine,
+                status,
+                updated_at,
+                restarts: 0,
+                last_exit: None,
+                stalled: false,
+            }),
+        }
+    }
+
+    /// Records exit of an engine of connected bot
+    pub fn record_engine_exit(&self, bot_id: &BotId, exit: EngineExit) {
+        let mut bots = self.connected_bots.write();
+        let bot = match bots.iter_mut().find(|bot| bot.bot_id == *bot_id) {
+            Some(bot) => bot,
+            None => return,
+        };
+        let updated_at = Utc::now();
+
+        match bot
+            .engines
+            .iter_mut()
+            .find(|info| info.engine == exit.engine)
+        {
+            Some(info) => {
+                info.restarts = exit.restarts;
+                info.last_exit = Some(exit.reason);
+                info.updated_at = updated_at;
+            }
+            None => bot.engines.push(EngineInfo {
+                engine: exit.engine,
+                status: EngineStatus::Error,
+                updated_at,
+                restarts: exit.restarts,
+                last_exit: Some(exit.reason),
+                stalled: false,
+            }),
+        }
+    }
+
+    /// Records health of an engine of connected bot
+    ///
+    /// Health of engines that haven't reported their status is ignored.
+    pub fn record_engine_health(&self, bot_id: &BotId, health: EngineHealth) {
+        let mut bots = self.connected_bots.write();
+
+        if let Some(info) = bots
+            .iter_mut()
+            .find(|bot| bot.bot_id == *bot_id)
+            .and_then(|bot| {
+                bot.engines
+                    .iter_mut()
+                    .find(|info| info.engine == health.engine)
+            })
+        {
+            info.stalled = health.stalled;
+            info.updated_at = Utc::now();
+        }
+    }
+
+    /// Updates capabilities announced by connected bot
+    pub fn update_capabilities(&self, bot_id: &BotId, capabilities: Capabilities) {
+        if let Some(bot) = self
+            .connected_bots
+            .write()
+            .iter_mut()
+            .find(|bot| bot.bot_id == *bot_id)
+        {
+            bot.capabilities = capabilities;
+        }
+    }
+
+    /// Records configuration version applied by connected bot
+    pub fn update_config_version(&self, bot_id: &BotId, version: u64) {
+        if let Some(bot) = self
+            .connected_bots
+            .write()
+            .iter_mut()
+            .find(|bot| bot.bot_id == *bot_id)
+        {
+            bot.config_version = version;
+        }
+    }
+
+    /// Returns current configuration of the bot
+    pub fn bot_config(&self, bot_id: &BotId) -> Option<VersionedConfig> {
+        self.configs.read().get(bot_id).cloned()
+    }
+
+    /// Replaces configuration of the bot and returns its new version
+    pub fn set_bot_config(&self, bot_id: BotId, config: BotConfiguration) -> u64 {
+        let mut configs = self.configs.write();
+        let version = configs.get(&bot_id).map_or(0, |config| config.version) + 1;
+
+        configs.insert(bot_id, VersionedConfig { version, config });
+
+        version
+    }
+
+    /// Stores the latest status report of the bot
+    pub fn update_status_report(&self, bot_id: &BotId, report: StatusReport) {
+        self.status_reports.write().insert(bot_id.clone(), report);
+    }
+
+    /// Returns the latest status report of the bot
+    pub fn status_report(&self, bot_id: &BotId) -> Option<StatusReport> {
+        self.status_reports.read().get(bot_id).cloned()
+    }
+
+    /// Stores the latest metrics of the bot
+    pub fn update_metrics(&self, bot_id: &BotId, metrics: Metrics) {
+        self.metrics.write().insert(bot_id.clone(), metrics);
+    }
+
+    /// Returns the latest metrics of the bot
+    pub fn metrics(&self, bot_id: &BotId) -> Option<Metrics> {
+        self.metrics.read().get(bot_id).cloned()
+    }
+
+    /// Queues command for the bot and returns its id
+    ///
+    /// Only the last [`COMMAND_LOG_CAP`] commands of each bot are kept.
+    pub fn add_command(&self, bot_id: BotId, command: Command) -> u64 {
+        let id = self.next_command_id.fetch_add(1, Ordering::Relaxed);
+        let mut commands = self.commands.write();
+        let log = commands.entry(bot_id).or_default();
+
+        if log.len() == COMMAND_LOG_CAP {
+            log.pop_front();
+        }
+
+        log.push_back(CommandInfo {
+            id,
+            command,
+            issued_at: Utc::now(),
+            sent: false,
+            result: None,
+        });
+
+        id
+    }
+
+    /// Returns commands of the bot not sent yet and marks them as sent
+    pub fn take_unsent_commands(&self, bot_id: &BotId) -> Vec<(u64, Command)> {
+        match self.commands.write().get_mut(bot_id) {
+            Some(log) => log
+                .iter_mut()
+                .filter(|info| !info.sent)
+                .map(|info| {
+                    info.sent = true;
+                    (info.id, info.command.clone())
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Records result of the command reported by the bot
+    pub fn complete_command(&self, bot_id: &BotId, id: u64, result: CommandResult) {
+        if let Some(info) = self
+            .commands
+            .write()
+            .get_mut(bot_id)
+            .and_then(|log| log.iter_mut().find(|info| info.id == id))
+        {
+            info.result = Some(result);
+        }
+    }
+
+    /// Fails all commands of the bot without result
+    pub fn fail_pending_commands(&self, bot_id: &BotId, reason: &str) {
+        if let Some(log) = self.commands.write().get_mut(bot_id) {
+            log.iter_mut()
+                .filter(|info| info.result.is_none())
+                .for_each(|info| info.result = Some(Err(Box::from(reason))));
+        }
+    }
+
+    /// Returns recent commands of the bot
+    pub fn commands(&self, bot_id: &BotId) -> Vec<CommandInfo> {
+        self.commands
+            .read()
+            .get(bot_id)
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns command of the bot with given id
+    pub fn command(&self, bot_id: &BotId, id: u64) -> Option<CommandInfo> {
+        self.commands
+            .read()
+            .get(bot_id)?
+            .iter()
+            .find(|info| info.id == id)
+            .cloned()
+    }
+
     /// Returns current known markets
     pub fn markets(&self) -> MarketVec {
         self.markets.read().clone()
@@ -91,15 +450,110 @@ impl GlobalState {
         orderbooks.insert((exchange, symbol), orderbook);
     }
 
+    /// Returns orderbook of a given exchange market, `None` when unknown
     pub fn get_orderbook(&self, exchange: ExchangeId, market: &str) -> Option<PlainOrderbook<f64>> {
-        let mut symbol_table = self.symbol_table.write();
-        let symbol = symbol_table.get(market);
-        drop(symbol_table);
+        let symbol = self.symbol_table.read().find(market)?;
 
         let orderbooks = self.orderbooks.read();
         orderbooks.get(&(exchange, symbol)).cloned()
     }
 
+    /// Appends trades of a given exchange market
+    ///
+    /// Only the last [`TRADE_LOG_CAP`] trades of each market are kept.
+    pub fn add_trades(&self, exchange: ExchangeId, market: &str, trades: &[Trade]) {
+        let symbol = self.symbol_table.write().get(market);
+        let mut logs = self.trades.write();
+        let log = logs.entry((exchange, symbol)).or_default();
+
+        for trade in trades {
+            if log.trades.len() == TRADE_LOG_CAP {
+                log.trades.pop_front();
+            }
+
+            log.trades.push_back(trade.clone());
+            log.seq += 1;
+        }
+    }
+
+    /// Returns trades with sequence number greater than `after`
+    ///
+    /// Returns `None` when no trades of the market were received.
+    pub fn trades_since(
+        &self,
+        exchange: ExchangeId,
+        market: &str,
+        after: u64,
+    ) -> Option<RecentTrades> {
+        let symbol = self.symbol_table.read().find(market)?;
+        let logs = self.trades.read();
+        let log = logs.get(&(exchange, symbol))?;
+        let first_seq = log.seq + 1 - log.trades.len() as u64;
+        let gap = after + 1 < first_seq;
+        let skip = if gap {
+            0
+        } else {
+            (after + 1 - first_seq) as usize
+        };
+
+        Some(RecentTrades {
+            seq: log.seq,
+            trades: log.trades.iter().skip(skip).cloned().collect(),
+            gap,
+        })
+    }
+
+    /// Applies fill to the position of the bot
+    pub fn add_fill(&self, bot_id: &BotId, fill: &Fill) {
+        let symbol = self.symbol_table.write().get(&fill.market);
+        let mut positions = self.positions.write();
+        let position = positions
+            .entry((bot_id.clone(), fill.exchange, symbol))
+            .or_default();
+        let sign = fill.side.sign();
+
+        position.position += sign * fill.size;
+        position.cash -= sign * fill.price * fill.size;
+        position.fees += fill.fee;
+        position.last_price = fill.price;
+    }
+
+    /// Returns positions and PnL of the bot ordered by exchange and market
+    pub fn bot_pnl(&self, bot_id: &BotId) -> Vec<PositionPnl> {
+        let table = &self.symbol_table.read().table;
+        let orderbooks = self.orderbooks.read();
+        let mut pnl: Vec<_> = self
+            .positions
+            .read()
+            .iter()
+            .filter(|((id, _, _), _)| id == bot_id)
+            .map(|((_, exchange, symbol), position)| {
+                let mark = orderbooks
+                    .get(&(*exchange, *symbol))
+                    .and_then(|orderbook| {
+                        let bid = orderbook.bids.price_vec.last()?;
+                        let ask = orderbook.asks.price_vec.first()?;
+                        Some((bid + ask) / 2.0)
+                    })
+                    .unwrap_or(position.last_price);
+
+                PositionPnl {
+                    exchange: *exchange,
+                    market: table[*symbol as usize].clone(),
+                    position: position.position,
+                    fees: position.fees,
+                    pnl: position.cash + position.position * mark - position.fees,
+                }
+            })
+            .collect();
+
+        pnl.sort_by(|a, b| {
+            (a.exchange.to_string(), &a.market).cmp(&(b.exchange.to_string(), &b.market))
+        });
+
+        pnl
+    }
+
     pub fn orderbooks(&self) -> Box<[Orderbook<f64>]> {
         let table = &self.symbol_table.read().table;
 
@@ -164,20 +618,21 @@ impl MarketSymbolTable {
     /// assert_ne!(s3, s4);
     /// ```
     pub fn get(&mut self, symbol: &str) -> u32 {
-        let symbol = symbol.to_ascii_lowercase();
-        match self
-            .table
-            .iter()
-            .enumerate()
-            .find(|(_idx, existing)| symbol == existing.as_ref())
-        {
-            Some((idx, _)) => idx as u32,
-            None => {
-                let len = self.table.len();
-                self.table.push(Box::from(symbol));
-                len as u32
-            }
+        if let Some(idx) = self.find(symbol) {
+            return idx;
         }
+
+        let len = self.table.len();
+        self.table.push(Box::from(symbol.to_ascii_lowercase()));
+        len as u32
+    }
+
+    /// Returns id of already interned symbol
+    pub fn find(&self, symbol: &str) -> Option<u32> {
+        self.table
+            .iter()
+            .position(|existing| existing.eq_ignore_ascii_case(symbol))
+            .map(|idx| idx as u32)
     }
 }
 
@@ -202,11 +657,11 @@ mod tests {
     fn test_add_bot() {
         let state = GlobalState::new();
 
-        state.add_bot(BotId(0));
+        state.add_bot(BotId(0), BotMetadata::new(1));
 
         assert_eq!(state.connected_bots.read().len(), 1);
 
-        state.add_bot(BotId(1));
+        state.add_bot(BotId(1), BotMetadata::new(1));
 
         assert_eq!(state.connected_bots.read().len(), 2);
     }
@@ -216,9 +671,9 @@ mod tests {
         let state = GlobalState::new();
 
         let mut bots = state.connected_bots.write();
-        bots.push(BotId(0));
-        bots.push(BotId(1));
-        bots.push(BotId(2));
+        bots.push(BotInfo::new(BotId(0), BotMetadata::new(1)));
+        bots.push(BotInfo::new(BotId(1), BotMetadata::new(1)));
+        bots.push(BotInfo::new(BotId(2), BotMetadata::new(1)));
         drop(bots);
 
         assert_eq!(state.connected_bots.read().len(), 3);
@@ -241,9 +696,9 @@ mod tests {
         let state = GlobalState::new();
 
         let mut bots = state.connected_bots.write();
-        bots.push(BotId(0));
-        bots.push(BotId(1));
-        bots.push(BotId(2));
+        bots.push(BotInfo::new(BotId(0), BotMetadata::new(1)));
+        bots.push(BotInfo::new(BotId(1), BotMetadata::new(1)));
+        bots.push(BotInfo::new(BotId(2), BotMetadata::new(1)));
         drop(bots);
 
         assert_eq!(state.connected_bots().len(), 3);
@@ -325,8 +780,11 @@ mod tests {
         let mut orderbooks = HashMap::new();
         let orderbook = PlainOrderbook::<f64>::new();
         orderbooks.insert((ExchangeId::Ftx, 0 as u32), orderbook.clone());
+        let mut symbol_table = MarketSymbolTable::default();
+        symbol_table.get(market);
         let state = GlobalState {
             orderbooks: Arc::new(RwLock::new(orderbooks)),
+            symbol_table: Arc::new(RwLock::new(symbol_table)),
             ..GlobalState::new()
         };
 
@@ -345,5 +803,222 @@ mod tests {
         let state = GlobalState::new();
 
         assert!(state.get_orderbook(ExchangeId::Ftx, market).is_none());
+        assert!(state.symbol_table.read().find(market).is_none());
+    }
+
+    #[test]
+    fn test_update_engine_status() {
+        let state = GlobalState::new();
+
+        state.add_bot(BotId(0), BotMetadata::new(1));
+        state.update_engine_status(&BotId(0), EngineType::TradingEngine, EngineStatus::Booting);
+        state.update_engine_status(&BotId(0), EngineType::TradingEngine, EngineStatus::Running);
+        state.update_engine_status(&BotId(1), EngineType::AuditEngine, EngineStatus::Running);
+
+        let bot = state.bot(&BotId(0)).unwrap();
+        assert_eq!(bot.metadata, BotMetadata::new(1));
+        assert_eq!(bot.engines.len(), 1);
+        assert_eq!(bot.engines[0].status, EngineStatus::Running);
+        assert!(state.bot(&BotId(1)).is_none());
+
+        state.record_engine_exit(
+            &BotId(0),
+            EngineExit {
+                engine: EngineType::TradingEngine,
+                reason: Box::from("engine reported error"),
+                restarts: 1,
+                restart_in_ms: Some(1000),
+            },
+        );
+
+        let bot = state.bot(&BotId(0)).unwrap();
+        assert_eq!(bot.engines[0].restarts, 1);
+        assert_eq!(
+            bot.engines[0].last_exit.as_deref(),
+            Some("engine reported error")
+        );
+        assert!(!bot.engines[0].stalled);
+
+        state.record_engine_health(
+            &BotId(0),
+            EngineHealth {
+                engine: EngineType::TradingEngine,
+                stalled: true,
+                iterations: 10,
+                heartbeat_age_ms: 5000,
+                last_event_age_ms: None,
+            },
+        );
+
+        assert!(state.bot(&BotId(0)).unwrap().engines[0].stalled);
+    }
+
+    #[test]
+    fn test_commands() {
+        let state = GlobalState::new();
+
+        let first = state.add_command(BotId(0), Command::PauseTrading);
+        let second = state.add_command(BotId(0), Command::CancelAll);
+        assert_ne!(first, second);
+
+        assert_eq!(
+            state.take_unsent_commands(&BotId(0)),
+            vec![(first, Command::PauseTrading), (second, Command::CancelAll)]
+        );
+        assert!(state.take_unsent_commands(&BotId(0)).is_empty());
+        assert!(state.take_unsent_commands(&BotId(1)).is_empty());
+
+        state.complete_command(&BotId(0), first, Ok(Box::from("paused")));
+        state.fail_pending_commands(&BotId(0), "disconnected");
+
+        let commands = state.commands(&BotId(0));
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].result, Some(Ok(Box::from("paused"))));
+        assert_eq!(
+            state.command(&BotId(0), second).unwrap().result,
+            Some(Err(Box::from("disconnected")))
+        );
+        assert!(state.command(&BotId(1), first).is_none());
+    }
+
+    #[test]
+    fn test_set_bot_config() {
+        let state = GlobalState::new();
+        let config = BotConfiguration {
+            bot_id: BotId(0),
+            peer_bots: Box::new([]),
+            exchanges: Box::from([Box::from("ftx")]),
+            markets: Box::from([Box::from("BTC/USD")]),
+            indicators: Box::new([]),
+            exchange_configs: Box::new([]),
+            recorder: None,
+            strategy: None,
+            topology: None,
+        };
+
+        assert!(state.bot_config(&BotId(0)).is_none());
+        assert_eq!(state.set_bot_config(BotId(0), config.clone()), 1);
+
+        let config = BotConfiguration {
+            markets: Box::from([Box::from("ETH/USD")]),
+            ..config
+        };
+        assert_eq!(state.set_bot_config(BotId(0), config), 2);
+
+        let current = state.bot_config(&BotId(0)).unwrap();
+        assert_eq!(current.version, 2);
+        assert_eq!(&*current.config.markets, &[Box::from("ETH/USD")]);
+
+        state.add_bot(BotId(0), BotMetadata::new(2));
+        state.update_config_version(&BotId(0), 2);
+        assert_eq!(state.bot(&BotId(0)).unwrap().config_version, 2);
+    }
+
+    #[test]
+    fn test_reports() {
+        let state = GlobalState::new();
+
+        assert!(state.status_report(&BotId(0)).is_none());
+        assert!(state.metrics(&BotId(0)).is_none());
+
+        state.update_status_report(&BotId(0), StatusReport::default());
+        state.update_status_report(
+            &BotId(0),
+            StatusReport {
+                server_reconnects: 1,
+                ..StatusReport::default()
+            },
+        );
+        state.update_metrics(&BotId(0), Metrics::default());
+
+        assert_eq!(state.status_report(&BotId(0)).unwrap().server_reconnects, 1);
+        assert_eq!(state.metrics(&BotId(0)), Some(Metrics::default()));
+        assert!(state.metrics(&BotId(1)).is_none());
+    }
+
+    fn trade(price: f64) -> Trade {
+        Trade {
+            price,
+            size: 1.0,
+            time: Utc::now(),
+            received_at: std::time::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_trades_since() {
+        let state = GlobalState::new();
+
+        assert!(state.trades_since(ExchangeId::Ftx, "BTC/USD", 0).is_none());
+
+        state.add_trades(ExchangeId::Ftx, "BTC/USD", &[trade(1.0), trade(2.0)]);
+        state.add_trades(ExchangeId::Ftx, "BTC/USD", &[trade(3.0)]);
+
+        let recent = state.trades_since(ExchangeId::Ftx, "btc/usd", 1).unwrap();
+        assert_eq!(recent.seq, 3);
+        assert!(!recent.gap);
+        assert_eq!(recent.trades.len(), 2);
+        assert_eq!(recent.trades[0].price, 2.0);
+
+        let recent = state.trades_since(ExchangeId::Ftx, "BTC/USD", 3).unwrap();
+        assert!(recent.trades.is_empty());
+    }
+
+    #[test]
+    fn test_trades_since_gap() {
+        let state = GlobalState::new();
+        let trades: Vec<_> = (0..TRADE_LOG_CAP + 10).map(|n| trade(n as f64)).collect();
+
+        state.add_trades(ExchangeId::Ftx, "BTC/USD", &trades);
+
+        let recent = state.trades_since(ExchangeId::Ftx, "BTC/USD", 5).unwrap();
+        assert!(recent.gap);
+        assert_eq!(recent.trades.len(), TRADE_LOG_CAP);
+        assert_eq!(recent.trades[0].price, 10.0);
+
+        let recent = state.trades_since(ExchangeId::Ftx, "BTC/USD", 10).unwrap();
+        assert!(!recent.gap);
+        assert_eq!(recent.trades.len(), TRADE_LOG_CAP);
+    }
+
+    #[test]
+    fn test_bot_pnl() {
+        let state = GlobalState::new();
+        let fill = |side, price| Fill {
+            exchange: ExchangeId::Ftx,
+            market: Box::from("BTC/USD"),
+            order_id: 1,
+            side,
+            price,
+            size: 2.0,
+            fee: 0.5,
+            time: std::time::UNIX_EPOCH,
+        };
+
+        state.add_fill(&BotId(0), &fill(Side::Buy, 100.0));
+        state.add_fill(&BotId(0), &fill(Side::Sell, 101.0));
+        state.add_fill(&BotId(0), &fill(Side::Buy, 102.0));
+
+        let pnl = state.bot_pnl(&BotId(0));
+        assert_eq!(pnl.len(), 1);
+        assert_eq!(pnl[0].position, 2.0);
+        assert_eq!(pnl[0].fees, 1.5);
+        assert_eq!(pnl[0].pnl, 0.5);
+
+        let mut orderbook = PlainOrderbook::<f64>::new();
+        orderbook
+            .bids
+            .update(&PriceLevelsVec::from_tuples_vec_unsorted(&mut [(
+                103.0, 1.0,
+            )]));
+        orderbook
+            .asks
+            .update(&PriceLevelsVec::from_tuples_vec_unsorted(&mut [(
+                105.0, 1.0,
+            )]));
+        state.update_orderbook(ExchangeId::Ftx, "BTC/USD", orderbook);
+
+        assert_eq!(state.bot_pnl(&BotId(0))[0].pnl, 4.5);
+        assert!(state.bot_pnl(&BotId(1)).is_empty());
     }
 }
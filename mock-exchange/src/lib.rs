@@ -0,0 +1,11 @@
+//! Mock exchange server
+//!
+//! Serves recorded REST responses and scripted websocket streams in FTX,
+//! Binance and Serum formats on localhost, so botnode adapters can be tested
+//! end-to-end without connecting to the real exchanges.
+
+pub mod script;
+pub mod server;
+
+pub use script::{Script, Step};
+pub use server::{MockExchange, MockExchangeHandle, Venue};
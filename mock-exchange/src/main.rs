@@ -0,0 +1,48 @@
+use std::{env::args, net::SocketAddr};
+
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+use mock_exchange::*;
+
+/// Runs the mock exchange
+///
+/// Usage: `mock-exchange <ftx|binance|serum> <rest-addr> <ws-addr> [script.json...]`
+#[async_std::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let mut args = args().skip(1);
+    let venue = args
+        .next()
+        .expect("Please specify venue")
+        .parse::<Venue>()
+        .expect("Invalid venue");
+    let rest_addr = parse_addr(args.next(), "127.0.0.1:8001");
+    let ws_addr = parse_addr(args.next(), "127.0.0.1:8002");
+
+    let mut mock = MockExchange::new(venue)
+        .with_rest_addr(rest_addr)
+        .with_ws_addr(ws_addr);
+
+    for path in args {
+        let script = Script::load(&path).expect("Failed to load script");
+        mock = mock.with_ws_script(script);
+    }
+
+    let handle = mock.start().expect("Failed to start mock exchange");
+
+    info!("REST API: {}", handle.rest_url());
+    info!("Websocket: {}", handle.ws_url());
+
+    futures::future::pending::<()>().await;
+}
+
+fn parse_addr(arg: Option<String>, default: &str) -> SocketAddr {
+    arg.as_deref()
+        .unwrap_or(default)
+        .parse()
+        .expect("Invalid listen address")
+}
@@ -0,0 +1,156 @@
+//! Websocket stream scripts
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Single step of the websocket script
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Sends text frame after substituting the placeholders
+    ///
+    /// Supported placeholders are `{seq}`, `{prev_seq}`, `{time}` (seconds
+    /// since epoch), `{time_ms}` and `{timestamp}` (RFC 3339).
+    Send(String),
+    /// Waits for one frame sent by the client, e.g. subscription request
+    Expect,
+    /// Sleeps for given number of milliseconds
+    Sleep(u64),
+    /// Skips given number of sequence numbers, creating a sequence gap
+    Gap(u64),
+    /// Drops the connection without closing handshake
+    Disconnect,
+}
+
+/// Script played on a websocket connection
+///
+/// When all steps are played the connection stays open until the client
+/// closes it.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Script {
+    pub steps: Vec<Step>,
+}
+
+impl Script {
+    /// Creates new script from given steps
+    pub fn new(steps: Vec<Step>) -> Self {
+        Self { steps }
+    }
+
+    /// Parses script from JSON
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mock_exchange::{Script, Step};
+    /// let script = Script::from_json(r#"{"steps": [{"sleep": 10}, "disconnect"]}"#).unwrap();
+    ///
+    /// assert_eq!(script.steps, vec![Step::Sleep(10), Step::Disconnect]);
+    /// ```
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Loads script from JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+
+        Self::from_json(&json).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Sequence counter shared by the steps of one connection
+#[derive(Debug, Default)]
+pub(crate) struct Sequence {
+    prev: u64,
+    next: u64,
+}
+
+impl Sequence {
+    pub(crate) fn new(start: u64) -> Self {
+        Self {
+            prev: start,
+            next: start + 1,
+        }
+    }
+
+    /// Skips `n` sequence numbers
+    pub(crate) fn gap(&mut self, n: u64) {
+        self.next += n;
+    }
+
+    /// Renders the message template consuming one sequence number
+    pub(crate) fn render(&mut self, template: &str) -> String {
+        let now = chrono::Utc::now();
+        let seq = self.next;
+
+        let msg = template
+            .replace("{seq}", &seq.to_string())
+            .replace("{prev_seq}", &self.prev.to_string())
+            .replace("{time_ms}", &now.timestamp_millis().to_string())
+            .replace(
+                "{time}",
+                &format!("{:.6}", now.timestamp_nanos() as f64 / 1_000_000_000.0),
+            )
+            .replace("{timestamp}", &now.to_rfc3339());
+
+        self.prev = seq;
+        self.next = seq + 1;
+
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_script() {
+        let script = Script::from_json(
+            r#"{"steps": ["expect", {"send": "{\"u\":{seq}}"}, {"gap": 5}, "disconnect"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.steps,
+            vec![
+                Step::Expect,
+                Step::Send(r#"{"u":{seq}}"#.to_string()),
+                Step::Gap(5),
+                Step::Disconnect
+            ]
+        );
+    }
+
+    #[test]
+    fn render_sequence() {
+        let mut seq = Sequence::new(100);
+
+        assert_eq!(
+            seq.render(r#"{"U":{seq},"p":{prev_seq}}"#),
+            r#"{"U":101,"p":100}"#
+        );
+        assert_eq!(
+            seq.render(r#"{"U":{seq},"p":{prev_seq}}"#),
+            r#"{"U":102,"p":101}"#
+        );
+
+        seq.gap(3);
+
+        assert_eq!(
+            seq.render(r#"{"U":{seq},"p":{prev_seq}}"#),
+            r#"{"U":106,"p":102}"#
+        );
+    }
+
+    #[test]
+    fn render_time() {
+        let mut seq = Sequence::default();
+
+        let msg = seq.render("{time_ms}");
+
+        assert!(msg.parse::<i64>().is_ok());
+    }
+}
@@ -0,0 +1,271 @@
+//! Mock exchange REST and websocket servers
+
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_std::{net::TcpStream, task};
+use async_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+use futures::prelude::*;
+use tide::{http::mime, Response};
+use tracing::{debug, error, info, warn};
+
+use crate::script::{Script, Sequence, Step};
+
+const FTX_MARKETS: &str = include_str!("../fixtures/ftx_markets.json");
+const BINANCE_EXCHANGE_INFO: &str = include_str!("../fixtures/binance_exchange_info.json");
+const BINANCE_DEPTH: &str = include_str!("../fixtures/binance_depth.json");
+const SERUM_MARKETS: &str = include_str!("../fixtures/serum_markets.json");
+
+/// Exchange whose API format is mocked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Venue {
+    Ftx,
+    Binance,
+    Serum,
+}
+
+impl Venue {
+    /// Returns recorded REST responses keyed by path
+    pub fn rest_responses(&self) -> HashMap<String, String> {
+        let responses: &[(&str, &str)] = match self {
+            Venue::Ftx => &[("/api/markets", FTX_MARKETS)],
+            Venue::Binance => &[
+                ("/api/v3/exchangeInfo", BINANCE_EXCHANGE_INFO),
+                ("/api/v3/depth", BINANCE_DEPTH),
+            ],
+            Venue::Serum => &[("/api/markets", SERUM_MARKETS)],
+        };
+
+        responses
+            .iter()
+            .map(|(path, body)| (path.to_string(), body.to_string()))
+            .collect()
+    }
+
+    /// Returns path of the websocket endpoint
+    pub fn ws_path(&self) -> &'static str {
+        match self {
+            Venue::Ftx | Venue::Binance => "/ws",
+            Venue::Serum => "/v1/ws",
+        }
+    }
+}
+
+impl std::str::FromStr for Venue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ftx" => Ok(Venue::Ftx),
+            "binance" => Ok(Venue::Binance),
+            "serum" => Ok(Venue::Serum),
+            _ => Err(format!("Unknown venue: {}", s)),
+        }
+    }
+}
+
+/// Mock exchange server configuration
+///
+/// Websocket connection `n` plays the `n`-th script, the last script is
+/// replayed for any further connections.
+#[derive(Debug)]
+pub struct MockExchange {
+    venue: Venue,
+    rest_addr: SocketAddr,
+    ws_addr: SocketAddr,
+    rest_responses: HashMap<String, String>,
+    scripts: Vec<Script>,
+    start_seq: u64,
+}
+
+impl MockExchange {
+    /// Creates new mock exchange serving recorded responses of given venue
+    /// on random localhost ports
+    pub fn new(venue: Venue) -> Self {
+        Self {
+            venue,
+            rest_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            ws_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            rest_responses: venue.rest_responses(),
+            scripts: Vec::new(),
+            start_seq: 1000,
+        }
+    }
+
+    /// Sets the REST listen address
+    pub fn with_rest_addr(mut self, addr: SocketAddr) -> Self {
+        self.rest_addr = addr;
+        self
+    }
+
+    /// Sets the websocket listen address
+    pub fn with_ws_addr(mut self, addr: SocketAddr) -> Self {
+        self.ws_addr = addr;
+        self
+    }
+
+    /// Overrides REST response served on given path
+    pub fn with_rest_response<T: ToString>(mut self, path: &str, body: T) -> Self {
+        self.rest_responses
+            .insert(path.to_string(), body.to_string());
+        self
+    }
+
+    /// Adds script for the next websocket connection
+    pub fn with_ws_script(mut self, script: Script) -> Self {
+        self.scripts.push(script);
+        self
+    }
+
+    /// Sets the first sequence number used by `{seq}` placeholders
+    pub fn with_start_seq(mut self, start_seq: u64) -> Self {
+        self.start_seq = start_seq;
+        self
+    }
+
+    /// Binds the listeners and starts serving on background tasks
+    pub fn start(self) -> std::io::Result<MockExchangeHandle> {
+        let rest_listener = TcpListener::bind(self.rest_addr)?;
+        let ws_listener = TcpListener::bind(self.ws_addr)?;
+        let handle = MockExchangeHandle {
+            venue: self.venue,
+            rest_addr: rest_listener.local_addr()?,
+            ws_addr: ws_listener.local_addr()?,
+            connections: Arc::new(AtomicUsize::new(0)),
+        };
+
+        info!(
+            "mock {:?} listening: rest = {}, ws = {}",
+            self.venue, handle.rest_addr, handle.ws_addr
+        );
+
+        task::spawn(serve_rest(rest_listener, self.rest_responses));
+        task::spawn(serve_ws(
+            ws_listener.into(),
+            Arc::new(self.scripts),
+            self.start_seq,
+            handle.connections.clone(),
+        ));
+
+        Ok(handle)
+    }
+}
+
+/// Handle to running mock exchange
+#[derive(Clone, Debug)]
+pub struct MockExchangeHandle {
+    venue: Venue,
+    rest_addr: SocketAddr,
+    ws_addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
+}
+
+impl MockExchangeHandle {
+    /// Returns base URL of the REST API
+    pub fn rest_url(&self) -> String {
+        format!("http://{}", self.rest_addr)
+    }
+
+    /// Returns URL of the websocket endpoint
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}{}", self.ws_addr, self.venue.ws_path())
+    }
+
+    /// Returns number of websocket connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn serve_rest(listener: TcpListener, responses: HashMap<String, String>) {
+    let mut app = tide::new();
+
+    for (path, body) in responses {
+        app.at(&path).get(move |_| {
+            let body = body.clone();
+            async move {
+                Ok(Response::builder(200)
+                    .body(body)
+                    .content_type(mime::JSON)
+                    .build())
+            }
+        });
+    }
+
+    if let Err(e) = app.listen(listener).await {
+        error!("REST listener failed: {e}");
+    }
+}
+
+async fn serve_ws(
+    listener: async_std::net::TcpListener,
+    scripts: Arc<Vec<Script>>,
+    start_seq: u64,
+    connections: Arc<AtomicUsize>,
+) {
+    while let Ok((stream, peer)) = listener.accept().await {
+        let n = connections.fetch_add(1, Ordering::SeqCst);
+        let script = match scripts.get(n).or_else(|| scripts.last()) {
+            Some(script) => script.clone(),
+            None => Script::default(),
+        };
+
+        debug!("websocket connection {n} from {peer}");
+
+        task::spawn(async move {
+            if let Err(e) = play_script(stream, script, start_seq).await {
+                warn!("websocket connection {n} error: {e}");
+            }
+        });
+    }
+}
+
+/// Plays the script on accepted connection
+async fn play_script(
+    stream: TcpStream,
+    script: Script,
+    start_seq: u64,
+) -> Result<(), async_tungstenite::tungstenite::Error> {
+    let mut ws_stream = accept_async(stream).await?;
+    let mut seq = Sequence::new(start_seq);
+
+    for step in script.steps.iter() {
+        match step {
+            Step::Send(template) => {
+                ws_stream.send(Message::text(seq.render(template))).await?;
+            }
+            Step::Expect => {
+                let msg = ws_stream.next().await.transpose()?;
+                debug!("received {msg:?}");
+            }
+            Step::Sleep(ms) => task::sleep(Duration::from_millis(*ms)).await,
+            Step::Gap(n) => seq.gap(*n),
+            Step::Disconnect => {
+                debug!("dropping connection");
+                return Ok(());
+            }
+        }
+    }
+
+    drain(ws_stream).await
+}
+
+/// Reads incoming frames until the client disconnects
+async fn drain(
+    mut ws_stream: WebSocketStream<TcpStream>,
+) -> Result<(), async_tungstenite::tungstenite::Error> {
+    while let Some(msg) = ws_stream.next().await {
+        if let Message::Ping(payload) = msg? {
+            ws_stream.send(Message::Pong(payload)).await?;
+        }
+    }
+
+    Ok(())
+}
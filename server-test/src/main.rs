@@ -5,26 +5,35 @@ use async_std::net::TcpStream;
 use futures::prelude::*;
 use tracing::{debug, error, info};
 
+use botvana::clock::RealClock;
 use botvana::net::codec::BotvanaCodec;
 use botvana::net::msg::{BotId, Message};
 
 async fn test_client<A: ToSocketAddrs>(
     addr: A,
     bot_id: u16,
+    secret: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let stream = TcpStream::connect(addr).await.expect("Failed to connect");
-    let mut framed = Framed::new(stream, BotvanaCodec);
+    let mut framed = Framed::new(stream, BotvanaCodec::new());
 
     let msg = Message::hello(BotId(bot_id));
     if let Err(e) = framed.send(msg).await {
         error!("Error framing the message: {:?}", e);
     }
 
+    if let Some(Message::AuthChallenge(nonce)) = framed.next().await.transpose().unwrap() {
+        let msg = Message::auth_response(secret.as_bytes(), &BotId(bot_id), &nonce);
+        if let Err(e) = framed.send(msg).await {
+            error!("Error framing the message: {:?}", e);
+        }
+    }
+
     if let Some(msg) = framed.next().await.transpose().unwrap() {
         debug!("received from server = {:?}", msg);
     }
 
-    let msg = Message::ping();
+    let msg = Message::ping(&RealClock);
     if let Err(e) = framed.send(msg).await {
         error!("Error framing the message: {:?}", e);
     }
@@ -47,7 +56,9 @@ async fn run_parallel_clients(n_clients: u16) {
 
     for n in 1..n_clients {
         handles.push(async_std::task::spawn(async move {
-            test_client("127.0.0.1:7978", n)
+            let secret = std::env::var("BOT_SECRET").unwrap_or_default();
+
+            test_client("127.0.0.1:7978", n, &secret)
                 .await
                 .expect("failed client");
         }));
@@ -1,23 +1,29 @@
-use std::thread::spawn;
+use std::{
+    io::{Read, Write},
+    thread::spawn,
+};
 
 use crossbeam_channel::unbounded;
 use eframe::{egui, epi};
-use serde::Deserialize;
-use tracing::{debug, info};
-use tungstenite::{connect, Message};
+use tracing::{debug, info, warn};
+use tungstenite::{connect, Message, WebSocket};
 use url::Url;
 
-use botvana::market::{orderbook::*, MarketVec};
+use botvana::{
+    market::{orderbook::*, Market},
+    net::gateway::*,
+    state::BotInfo,
+};
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 pub struct StationApp {
-    bots: Vec<u16>,
-    markets: MarketVec,
+    bots: Vec<BotInfo>,
+    markets: Vec<Market>,
     _latencies: Vec<u64>,
     orderbooks: Vec<Orderbook<f64>>,
 
-    ws_rx: crossbeam_channel::Receiver<WebsocketMessage>,
-    ws_tx: crossbeam_channel::Sender<WebsocketMessage>,
+    ws_rx: crossbeam_channel::Receiver<ServerMessage>,
+    ws_tx: crossbeam_channel::Sender<ServerMessage>,
 }
 
 impl Default for StationApp {
@@ -26,7 +32,7 @@ impl Default for StationApp {
 
         Self {
             bots: vec![],
-            markets: MarketVec::new(),
+            markets: vec![],
             _latencies: vec![],
             orderbooks: vec![],
             ws_rx,
@@ -47,26 +53,63 @@ impl epi::App for StationApp {
         _frame: &epi::Frame,
         _storage: Option<&dyn epi::Storage>,
     ) {
-        let (mut socket, response) =
-            connect(Url::parse("ws://localhost:7979").unwrap()).expect("Can't connect");
+        let mut url = Url::parse("ws://localhost:7979").unwrap();
+
+        // API token is required when the gateway has authentication enabled
+        if let Ok(token) = std::env::var("BOTVANA_API_TOKEN") {
+            url.query_pairs_mut().append_pair("token", &token);
+        }
+
+        let (mut socket, response) = connect(url).expect("Can't connect");
 
         println!("response = {:?}", response);
 
         let ws_tx = self.ws_tx.clone();
 
         // Spawn thread to run the websocket connection loop on
-        spawn(move || loop {
-            let msg = socket.read_message().expect("Error reading message");
-
-            match msg {
-                Message::Text(msg) => {
-                    debug!("Received: {}", msg);
-                    let msg: WebsocketMessage = serde_json::from_str(&msg)
-                        .expect("failed to deserialize websocket message as json");
-                    ws_tx.send(msg).expect("failed to send websocket message");
-                }
-                _ => {
-                    info!("Received unknown {:?}", msg);
+        spawn(move || {
+            subscribe(&mut socket, Channel::Bots);
+            subscribe(&mut socket, Channel::Markets);
+
+            let mut orderbook_channels = vec![];
+
+            loop {
+                let msg = socket.read_message().expect("Error reading message");
+
+                match msg {
+                    Message::Text(msg) => {
+                        debug!("Received: {}", msg);
+                        let msg: ServerMessage = serde_json::from_str(&msg)
+                            .expect("failed to deserialize websocket message as json");
+
+                        // Subscribe to orderbooks of newly listed markets
+                        if let ServerMessage::Snapshot {
+                            data: ChannelData::Markets(markets),
+                            ..
+                        }
+                        | ServerMessage::Update {
+                            data: ChannelData::Markets(markets),
+                            ..
+                        } = &msg
+                        {
+                            for market in markets {
+                                let channel = Channel::Orderbook {
+                                    exchange: market.exchange,
+                                    market: Box::from(market.name.as_str()),
+                                };
+
+                                if !orderbook_channels.contains(&channel) {
+                                    orderbook_channels.push(channel.clone());
+                                    subscribe(&mut socket, channel);
+                                }
+                            }
+                        }
+
+                        ws_tx.send(msg).expect("failed to send websocket message");
+                    }
+                    _ => {
+                        info!("Received unknown {:?}", msg);
+                    }
                 }
             }
         });
@@ -75,14 +118,8 @@ impl epi::App for StationApp {
     /// Called each time the UI needs repainting, which may be many times per second.
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::CtxRef, _frame: &epi::Frame) {
-        let msg = self.ws_rx.try_recv();
-        match msg {
-            Ok(msg) => {
-                self.bots = msg.connected_bots;
-                self.markets = msg.markets;
-                self.orderbooks = msg.orderbooks;
-            }
-            _ => {}
+        while let Ok(msg) = self.ws_rx.try_recv() {
+            self.handle_message(msg);
         }
 
         egui::TopBottomPanel::top("my_panel").show(ctx, |ui| {
@@ -94,9 +131,9 @@ impl epi::App for StationApp {
 
             ui.with_layout(egui::Layout::top_down_justified(egui::Align::LEFT), |ui| {
                 for bot in self.bots.iter() {
-                    let label = ui.button(format!("Bot ID={}", bot.to_string()));
+                    let label = ui.button(format!("Bot ID={}", bot.bot_id.0));
                     if label.clicked() {
-                        info!("clicked {}", bot);
+                        info!("clicked {}", bot.bot_id.0);
                     }
                 }
             });
@@ -143,10 +180,59 @@ impl epi::App for StationApp {
     }
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "snake_case")]
-struct WebsocketMessage {
-    connected_bots: Vec<u16>,
-    markets: MarketVec,
-    orderbooks: Vec<Orderbook<f64>>,
+impl StationApp {
+    /// Applies snapshot or update received from the gateway
+    fn handle_message(&mut self, msg: ServerMessage) {
+        let (channel, data) = match msg {
+            ServerMessage::Snapshot { channel, data } | ServerMessage::Update { channel, data } => {
+                (channel, data)
+            }
+            ServerMessage::Error { message } => {
+                warn!("Gateway error: {}", message);
+                return;
+            }
+            _ => return,
+        };
+
+        match (channel, data) {
+            (_, ChannelData::Bots(bots)) => self.bots = bots,
+            (_, ChannelData::Markets(markets)) => self.markets = markets,
+            (Channel::Orderbook { exchange, market }, ChannelData::Orderbook(orderbook)) => {
+                self.orderbooks
+                    .retain(|existing| existing.exchange != exchange || existing.market != market);
+                self.orderbooks.push(Orderbook {
+                    bids: orderbook.bids,
+                    asks: orderbook.asks,
+                    time: orderbook.time,
+                    exchange,
+                    market,
+                });
+            }
+            (
+                Channel::Orderbook { exchange, market },
+                ChannelData::OrderbookDelta { bids, asks, time },
+            ) => {
+                if let Some(orderbook) = self
+                    .orderbooks
+                    .iter_mut()
+                    .find(|existing| existing.exchange == exchange && existing.market == market)
+                {
+                    orderbook.bids.update(&bids);
+                    orderbook.asks.update(&asks);
+                    orderbook.time = time;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends subscription to the gateway
+fn subscribe<S: Read + Write>(socket: &mut WebSocket<S>, channel: Channel) {
+    let msg = serde_json::to_string(&ClientMessage::subscribe(channel))
+        .expect("failed to serialize subscription");
+
+    socket
+        .write_message(Message::Text(msg))
+        .expect("Error writing message");
 }